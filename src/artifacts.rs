@@ -0,0 +1,144 @@
+use std::io;
+
+use crate::config::Remote;
+use crate::script::{self, quote};
+use crate::transport::Transport;
+
+/// Object storage configured with `artifact_store`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Store {
+    /// Amazon S3 or a compatible store like MinIO, uploaded to with the aws cli.
+    S3 {
+        bucket: String,
+        prefix: String,
+        endpoint: Option<String>,
+    },
+    /// Google Cloud Storage, uploaded to with gcloud.
+    Gcs { bucket: String, prefix: String },
+}
+
+impl Store {
+    pub fn parse(url: &str, endpoint: Option<&str>) -> Result<Self, &'static str> {
+        let (scheme, path) = url
+            .split_once("://")
+            .ok_or("`artifact_store` must start with s3:// or gs://")?;
+        let path = path.trim_matches('/');
+        let (bucket, prefix) = path.split_once('/').unwrap_or((path, ""));
+        if bucket.is_empty() {
+            return Err("`artifact_store` is missing the bucket");
+        }
+        let (bucket, prefix) = (bucket.to_owned(), prefix.to_owned());
+        match scheme {
+            "s3" => Ok(Store::S3 {
+                bucket,
+                prefix,
+                endpoint: endpoint.map(|endpoint| endpoint.trim_end_matches('/').to_owned()),
+            }),
+            "gs" if endpoint.is_some() => {
+                Err("`artifact_store_endpoint` is only supported for s3:// stores")
+            }
+            "gs" => Ok(Store::Gcs { bucket, prefix }),
+            _ => Err("`artifact_store` must start with s3:// or gs://"),
+        }
+    }
+
+    /// The configured store of `remote`, if any.
+    pub fn of(remote: &Remote) -> Option<Self> {
+        let url = remote.artifact_store.as_deref()?;
+        Self::parse(url, remote.artifact_store_endpoint.as_deref()).ok()
+    }
+
+    fn key(&self, file_name: &str) -> String {
+        let prefix = match self {
+            Store::S3 { prefix, .. } | Store::Gcs { prefix, .. } => prefix,
+        };
+        if prefix.is_empty() {
+            file_name.to_owned()
+        } else {
+            format!("{}/{}", prefix, file_name)
+        }
+    }
+
+    /// Remote command uploading `file_name`, a file or directory relative to the current
+    /// directory.
+    fn upload_command(&self, file_name: &str) -> String {
+        match self {
+            Store::S3 {
+                bucket, endpoint, ..
+            } => {
+                let mut aws = "aws s3 cp --only-show-errors".to_owned();
+                if let Some(endpoint) = endpoint {
+                    aws.push_str(&format!(" --endpoint-url {}", quote(endpoint)));
+                }
+                let destination = quote(&format!("s3://{}/{}", bucket, self.key(file_name)));
+                format!(
+                    "if [ -d {file} ]; then {aws} --recursive {file} {destination}; \
+                     else {aws} {file} {destination}; fi",
+                    file = quote(file_name),
+                    aws = aws,
+                    destination = destination
+                )
+            }
+            // `cp --recursive` would nest directories that already exist in the bucket
+            Store::Gcs { bucket, .. } => format!(
+                "if [ -d {file} ]; then gcloud storage rsync --recursive {file} {destination}; \
+                 else gcloud storage cp {file} {destination}; fi",
+                file = quote(file_name),
+                destination = quote(&format!("gs://{}/{}", bucket, self.key(file_name)))
+            ),
+        }
+    }
+
+    /// HTTPS URL of the object uploaded from `file_name`.
+    pub fn url(&self, file_name: &str) -> String {
+        let key = self.key(file_name);
+        match self {
+            Store::S3 {
+                bucket,
+                endpoint: Some(endpoint),
+                ..
+            } => format!("{}/{}/{}", endpoint, bucket, key),
+            Store::S3 { bucket, .. } => format!("https://{}.s3.amazonaws.com/{}", bucket, key),
+            Store::Gcs { bucket, .. } => {
+                format!("https://storage.googleapis.com/{}/{}", bucket, key)
+            }
+        }
+    }
+}
+
+/// Uploads `file_name` from the remote target dir in `build_path` to `store` directly from the
+/// remote, which needs the aws cli or gcloud and credentials for the store.
+///
+/// Returns the URLs of all uploaded files.
+pub fn publish(
+    transport: &Transport,
+    remote: &Remote,
+    host: &str,
+    build_path: &str,
+    store: &Store,
+    file_name: &str,
+) -> io::Result<Vec<String>> {
+    if file_name.is_empty() {
+        return Err(io::Error::other(
+            "uploading the whole target dir isn't supported, select the artifacts with -c=<path>",
+        ));
+    }
+    let target_dir = format!("{}{}", build_path, crate::REMOTE_TARGET_DIR);
+    let upload = format!(
+        "{}cd {} && {}",
+        script::source_env(remote),
+        target_dir,
+        store.upload_command(file_name)
+    );
+    let mut ssh = transport.remote_command(remote, host, &upload);
+    let status = transport.run(&mut ssh, "publish")?;
+    if !status.success() {
+        return Err(io::Error::other(format!("upload failed ({})", status)));
+    }
+    let files = transport.remote_output(
+        remote,
+        host,
+        &format!("cd {} && find {} -type f", target_dir, quote(file_name)),
+    )?;
+    Ok(files.lines().map(|file| store.url(file)).collect())
+}
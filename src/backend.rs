@@ -0,0 +1,1491 @@
+use std::env;
+use std::fmt::Debug;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::process::{Command, ExitStatus};
+
+use tracing::{info, warn};
+
+use crate::config::{
+    HostKeyChecking, Link, LockfileStrategy, OversizedFiles, Remote, Shell, Transfer,
+};
+use crate::hostkey;
+use crate::lockfile;
+use crate::process::{Input, Output};
+use crate::script::{self, quote};
+use crate::session;
+use crate::sync::FAST_INCLUDES;
+use crate::transport::Transport;
+use crate::windows;
+use crate::REMOTE_TARGET_DIR;
+
+/// Branch in the remote repository of the `git` transfer the commit is pushed to.
+const GIT_BRANCH: &str = "cargo-remote";
+
+/// Moves the sources to the remote and the results back, one implementation per [`Transfer`]
+/// of the config.
+///
+/// [`sync::upload`](crate::sync::upload), [`sync::fetch`](crate::sync::fetch) and
+/// [`sync::copy_back`](crate::sync::copy_back) pick the backend of the remote and handle
+/// everything common to all of them, like recording the build directory.
+pub trait SyncBackend: Debug + Send + Sync {
+    /// Uploads `project_dir` without the `excludes` into `build_path` on `host`. `fast` only
+    /// uploads the [`FAST_INCLUDES`], backends that can't filter ignore it.
+    #[allow(clippy::too_many_arguments)]
+    fn upload(
+        &self,
+        transport: &Transport,
+        remote: &Remote,
+        host: &str,
+        project_dir: &Path,
+        build_path: &str,
+        excludes: &[String],
+        fast: bool,
+    ) -> io::Result<ExitStatus>;
+
+    /// Transfers the remote `source` to the local `destination` like `rsync <host>:<source>
+    /// <destination>`. `delete` removes local files missing on the remote, if the backend can.
+    #[allow(clippy::too_many_arguments)]
+    fn fetch(
+        &self,
+        transport: &Transport,
+        remote: &Remote,
+        host: &str,
+        source: &str,
+        destination: &str,
+        delete: bool,
+        phase: &str,
+    ) -> io::Result<ExitStatus>;
+
+    /// Whether the backend fetches files with rsync, so the copy back can be planned with a
+    /// listing of the remote and files sent as a whole.
+    fn incremental(&self) -> bool;
+}
+
+/// rsync over ssh, the default.
+#[derive(Debug)]
+pub struct Rsync;
+
+/// The rsync daemon of the remote, with rsync over ssh as fallback.
+#[derive(Debug)]
+pub struct Rsyncd;
+
+/// tar archives streamed through the exec backend.
+#[derive(Debug)]
+pub struct Tar;
+
+/// git push for the committed sources, rsync for the rest.
+#[derive(Debug)]
+pub struct Git;
+
+/// Every file put and got with sftp batches, for servers without rsync. Like tar it doesn't
+/// delete files on the remote that were removed locally.
+#[derive(Debug)]
+pub struct Sftp;
+
+impl SyncBackend for Rsync {
+    fn upload(
+        &self,
+        transport: &Transport,
+        remote: &Remote,
+        host: &str,
+        project_dir: &Path,
+        build_path: &str,
+        excludes: &[String],
+        fast: bool,
+    ) -> io::Result<ExitStatus> {
+        rsync_upload(
+            transport,
+            remote,
+            host,
+            project_dir,
+            build_path,
+            excludes,
+            fast,
+            false,
+        )
+    }
+
+    fn fetch(
+        &self,
+        transport: &Transport,
+        remote: &Remote,
+        host: &str,
+        source: &str,
+        destination: &str,
+        delete: bool,
+        phase: &str,
+    ) -> io::Result<ExitStatus> {
+        rsync_fetch(
+            transport,
+            remote,
+            host,
+            source,
+            destination,
+            delete,
+            phase,
+            false,
+        )
+    }
+
+    fn incremental(&self) -> bool {
+        true
+    }
+}
+
+impl SyncBackend for Rsyncd {
+    fn upload(
+        &self,
+        transport: &Transport,
+        remote: &Remote,
+        host: &str,
+        project_dir: &Path,
+        build_path: &str,
+        excludes: &[String],
+        fast: bool,
+    ) -> io::Result<ExitStatus> {
+        rsync_upload(
+            transport,
+            remote,
+            host,
+            project_dir,
+            build_path,
+            excludes,
+            fast,
+            true,
+        )
+    }
+
+    fn fetch(
+        &self,
+        transport: &Transport,
+        remote: &Remote,
+        host: &str,
+        source: &str,
+        destination: &str,
+        delete: bool,
+        phase: &str,
+    ) -> io::Result<ExitStatus> {
+        rsync_fetch(
+            transport,
+            remote,
+            host,
+            source,
+            destination,
+            delete,
+            phase,
+            true,
+        )
+    }
+
+    fn incremental(&self) -> bool {
+        true
+    }
+}
+
+/// Uploads the project with rsync, through the rsync daemon of the remote with `daemon` if it
+/// has one.
+#[allow(clippy::too_many_arguments)]
+fn rsync_upload(
+    transport: &Transport,
+    remote: &Remote,
+    host: &str,
+    project_dir: &Path,
+    build_path: &str,
+    excludes: &[String],
+    fast: bool,
+    daemon: bool,
+) -> io::Result<ExitStatus> {
+    let daemon_destination = match daemon {
+        true => transport.rsyncd_url(remote, host, build_path),
+        false => None,
+    };
+    let mut rsync_to = match daemon_destination {
+        Some(_) => transport.rsyncd(remote, true),
+        None => transport.rsync(remote, true),
+    };
+    // `--fast` uploads too little to be worth splitting
+    let parallel = transport.transfer_jobs > 1 && !fast;
+    if parallel {
+        rsync_to.args(transport.total_progress_flag(remote));
+    } else if !fast {
+        rsync_to.args(transport.progress_flag(remote));
+    }
+    for exclude in excludes {
+        rsync_to.arg("--exclude").arg(exclude);
+    }
+    let per_remote_lock = remote.lockfile_strategy == LockfileStrategy::PerRemote;
+    if per_remote_lock {
+        rsync_to.arg("--exclude").arg("/Cargo.lock");
+    }
+    if fast {
+        // excluded files aren't deleted on the remote, everything else stays as it was synced
+        rsync_to.arg("--prune-empty-dirs");
+        for include in FAST_INCLUDES {
+            rsync_to.arg("--include").arg(include);
+        }
+        rsync_to.arg("--exclude").arg("*");
+    }
+
+    if let (Some(max_size), false) = (&remote.max_file_size, fast) {
+        let oversized = oversized_files(transport, remote, project_dir, excludes, max_size)
+            .unwrap_or_else(|e| {
+                warn!(
+                    "Failed to look for files larger than {} (error: {})",
+                    max_size, e
+                );
+                Vec::new()
+            });
+        let action = match remote.oversized_files {
+            OversizedFiles::Warn => "uploading it anyway",
+            OversizedFiles::Skip => {
+                rsync_to.arg(format!("--max-size={}", max_size));
+                "skipping it"
+            }
+        };
+        for (size, file) in oversized {
+            warn!(
+                "{} is larger than {} ({} MiB), {}.",
+                file,
+                max_size,
+                size / (1024 * 1024),
+                action
+            );
+        }
+    }
+
+    if remote.group.is_some() {
+        // files get the group of the setgid build dir and stay writable for it
+        rsync_to.arg("--no-group").arg("--chmod=g+rwX");
+    }
+
+    let destination = match daemon_destination {
+        Some(url) => {
+            // the daemon only creates the last directory and can't set its group
+            let mut ssh =
+                transport.remote_command(remote, host, &create_build_path(remote, build_path));
+            let status = transport.run(&mut ssh, "sync-mkdir")?;
+            if !status.success() {
+                return Ok(status);
+            }
+            url
+        }
+        None => {
+            rsync_to
+                .arg("--rsync-path")
+                .arg(script::rsync_path(remote, build_path));
+            format!("{}:{}", host, build_path)
+        }
+    };
+    let jobs = if parallel {
+        split_upload(&rsync_to, project_dir, excludes, transport.transfer_jobs)?
+    } else {
+        Vec::new()
+    };
+    let status = if jobs.len() > 1 {
+        info!("Uploading with {} rsync processes.", jobs.len());
+        let jobs = jobs
+            .into_iter()
+            .map(|mut job| {
+                job.arg(format!("{}/", project_dir.to_string_lossy()))
+                    .arg(&destination);
+                job
+            })
+            .collect();
+        transport.transfer_parallel(remote, jobs, "sync")?
+    } else {
+        rsync_to
+            .arg(format!("{}/", project_dir.to_string_lossy()))
+            .arg(destination);
+        transport.transfer(remote, &mut rsync_to, "sync")?
+    };
+    if !status.success() || !per_remote_lock {
+        return Ok(status);
+    }
+    upload_lock(transport, remote, host, project_dir, build_path).map(|lock| lock.unwrap_or(status))
+}
+
+/// Splits the upload of `rsync_to` into up to `jobs` copies of it, each uploading some of the
+/// top level directories of `project_dir` of about the same total size. The first one uploads
+/// everything the others don't, so it also deletes what was removed at the top level. Fewer
+/// than two commands if there is nothing to split.
+fn split_upload(
+    rsync_to: &Command,
+    project_dir: &Path,
+    excludes: &[String],
+    jobs: usize,
+) -> io::Result<Vec<Command>> {
+    let mut top_level_files = 0;
+    let mut dirs = Vec::new();
+    for entry in fs::read_dir(project_dir)? {
+        let entry = entry?;
+        let metadata = entry.metadata()?;
+        let name = match entry.file_name().into_string() {
+            Ok(name) => name,
+            Err(_) => continue,
+        };
+        let excluded = excludes
+            .iter()
+            .any(|exclude| exclude.trim_start_matches('/').trim_end_matches('/') == name);
+        // names that would be rsync patterns stay with the first job
+        if !metadata.is_dir() || excluded || name.contains(['*', '?', '[', '\\']) {
+            top_level_files += metadata.len();
+        } else {
+            dirs.push((tree_size(&entry.path()), name));
+        }
+    }
+    dirs.sort_unstable_by(|a, b| b.cmp(a));
+    let mut groups: Vec<(u64, Vec<String>)> = vec![(0, Vec::new()); jobs];
+    groups[0].0 = top_level_files;
+    for (size, name) in dirs {
+        let lightest = (0..jobs).min_by_key(|&i| groups[i].0).unwrap_or(0);
+        groups[lightest].0 += size;
+        // the first job gets everything that isn't excluded from it
+        if lightest != 0 {
+            groups[lightest].1.push(name);
+        }
+    }
+    let others: Vec<&String> = groups[1..].iter().flat_map(|(_, names)| names).collect();
+    if others.is_empty() {
+        return Ok(Vec::new());
+    }
+    let mut first = copy_command(rsync_to);
+    for name in others {
+        first.arg("--exclude").arg(format!("/{}", name));
+    }
+    let mut commands = vec![first];
+    for (_, names) in groups[1..].iter().filter(|(_, names)| !names.is_empty()) {
+        let mut command = copy_command(rsync_to);
+        for name in names {
+            command.arg("--include").arg(format!("/{}/***", name));
+        }
+        command.arg("--exclude").arg("/*");
+        commands.push(command);
+    }
+    Ok(commands)
+}
+
+/// Bytes of the files below `dir`, without following symlinks.
+fn tree_size(dir: &Path) -> u64 {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(_) => return 0,
+    };
+    entries
+        .filter_map(Result::ok)
+        .filter_map(|entry| Some((entry.path(), entry.metadata().ok()?)))
+        .map(|(path, metadata)| {
+            if metadata.is_dir() {
+                tree_size(&path)
+            } else {
+                metadata.len()
+            }
+        })
+        .sum()
+}
+
+/// A new command with the program, arguments, environment and working directory of `command`.
+fn copy_command(command: &Command) -> Command {
+    let mut copy = Command::new(command.get_program());
+    copy.args(command.get_args());
+    for (key, value) in command.get_envs() {
+        match value {
+            Some(value) => copy.env(key, value),
+            None => copy.env_remove(key),
+        };
+    }
+    if let Some(dir) = command.get_current_dir() {
+        copy.current_dir(dir);
+    }
+    copy
+}
+
+/// Uploads the Cargo.lock of the `per-remote` lockfile strategy, `None` if there is none.
+fn upload_lock(
+    transport: &Transport,
+    remote: &Remote,
+    host: &str,
+    project_dir: &Path,
+    build_path: &str,
+) -> io::Result<Option<ExitStatus>> {
+    match lock_file(remote, project_dir) {
+        Some(lock_file) => {
+            let mut rsync_lock = transport.rsync(remote, false);
+            rsync_lock
+                .arg("--rsync-path")
+                .arg(script::remote_rsync(remote))
+                .arg(&lock_file)
+                .arg(format!("{}:{}Cargo.lock", host, build_path));
+            transport
+                .transfer(remote, &mut rsync_lock, "sync-lock")
+                .map(Some)
+        }
+        None => Ok(None),
+    }
+}
+
+/// Fetches the remote `source` with rsync, through the rsync daemon of the remote with
+/// `daemon` if it has one.
+#[allow(clippy::too_many_arguments)]
+fn rsync_fetch(
+    transport: &Transport,
+    remote: &Remote,
+    host: &str,
+    source: &str,
+    destination: &str,
+    delete: bool,
+    phase: &str,
+    daemon: bool,
+) -> io::Result<ExitStatus> {
+    let daemon_source = match daemon {
+        true => transport.rsyncd_url(remote, host, source),
+        false => None,
+    };
+    let (mut rsync_back, source) = match daemon_source {
+        Some(url) => (transport.rsyncd(remote, delete), url),
+        None => {
+            let mut rsync = transport.rsync(remote, delete);
+            rsync.arg("--rsync-path").arg(script::remote_rsync(remote));
+            (rsync, format!("{}:{}", host, source))
+        }
+    };
+    rsync_back
+        .args(transport.progress_flag(remote))
+        .arg(source)
+        .arg(destination);
+    transport.transfer(remote, &mut rsync_back, phase)
+}
+
+/// The Cargo.lock uploaded with the `per-remote` lockfile strategy, `None` if there is none.
+fn lock_file(remote: &Remote, project_dir: &Path) -> Option<std::path::PathBuf> {
+    Some(lockfile::local_path(remote, project_dir))
+        .filter(|path| path.is_file())
+        .or_else(|| Some(project_dir.join("Cargo.lock")).filter(|path| path.is_file()))
+}
+
+/// Sizes and paths of the files in `project_dir` from `min_size` on that would be uploaded.
+///
+/// Lists them with a local rsync dry run, so the excludes are applied exactly like in the
+/// upload.
+fn oversized_files(
+    transport: &Transport,
+    remote: &Remote,
+    project_dir: &Path,
+    excludes: &[String],
+    min_size: &str,
+) -> io::Result<Vec<(u64, String)>> {
+    let mut rsync = Command::new(&remote.rsync_path_local);
+    rsync
+        .arg("-a")
+        .arg("--dry-run")
+        .arg(format!("--min-size={}", min_size))
+        .arg("--out-format=%l %n");
+    for exclude in excludes {
+        rsync.arg("--exclude").arg(exclude);
+    }
+    // nothing is written to the destination in a dry run
+    rsync
+        .arg(format!("{}/", project_dir.to_string_lossy()))
+        .arg(env::temp_dir().join("cargo-remote-size-check"));
+    let output = transport.output(&mut rsync, "size-check")?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "rsync failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .filter_map(|line| {
+            let (size, file) = line.split_once(' ')?;
+            Some((size.parse().ok()?, file.to_owned()))
+        })
+        .filter(|(_, file)| !file.ends_with('/'))
+        .collect())
+}
+
+impl SyncBackend for Tar {
+    /// Uploads the project as a tar archive streamed through ssh.
+    ///
+    /// Unlike rsync this always sends every file and doesn't delete files on the remote that were
+    /// removed locally.
+    fn upload(
+        &self,
+        transport: &Transport,
+        remote: &Remote,
+        host: &str,
+        project_dir: &Path,
+        build_path: &str,
+        excludes: &[String],
+        _fast: bool,
+    ) -> io::Result<ExitStatus> {
+        let tar = tar_command(remote, project_dir, excludes)?;
+
+        let mut extract = format!("mkdir -p {} && {}", build_path, script::umask(remote));
+        if let Some(group) = &remote.group {
+            extract.push_str(&format!(
+                "chgrp {group} {path} && chmod g+s {path} && ",
+                path = build_path,
+                group = quote(group)
+            ));
+        }
+        let (compress, decompress) = tar_compression(transport, remote);
+        extract.push_str(&format!("{}tar -C {} -xf -", decompress, build_path));
+        if remote.shell == Shell::PowerShell {
+            extract = windows::extract(build_path);
+        }
+
+        let mut pipeline = Command::new("sh");
+        pipeline.arg("-c").arg(format!(
+            "{} | {}{}",
+            tar,
+            compress,
+            exec_backend(remote).shell_line(
+                transport,
+                remote,
+                host,
+                &script::as_build_user(remote, &extract)
+            )
+        ));
+        let status = transport.run(&mut pipeline, "sync")?;
+        if !status.success() || remote.lockfile_strategy != LockfileStrategy::PerRemote {
+            return Ok(status);
+        }
+
+        match lock_file(remote, project_dir) {
+            Some(lock_file) => {
+                let mut upload_lock = Command::new("sh");
+                upload_lock.arg("-c").arg(format!(
+                    "{} < {}",
+                    exec_backend(remote).shell_line(
+                        transport,
+                        remote,
+                        host,
+                        &script::as_build_user(remote, &format!("cat > {}Cargo.lock", build_path))
+                    ),
+                    quote(&lock_file.to_string_lossy())
+                ));
+                transport.run(&mut upload_lock, "sync-lock")
+            }
+            None => Ok(status),
+        }
+    }
+
+    /// Fetches the remote `source` as a tar archive streamed through ssh.
+    ///
+    /// A `source` ending with `/` transfers the contents of the directory into `destination`,
+    /// like with rsync.
+    fn fetch(
+        &self,
+        transport: &Transport,
+        remote: &Remote,
+        host: &str,
+        source: &str,
+        destination: &str,
+        _delete: bool,
+        phase: &str,
+    ) -> io::Result<ExitStatus> {
+        let mut rename = String::new();
+        let (archive, extract_dir) = match source.strip_suffix('/') {
+            Some(directory) => (format!("tar -C {} -cf - .", directory), destination),
+            None => {
+                let (parent, name) = source.rsplit_once('/').unwrap_or((".", source));
+                let extract_dir = match destination.rsplit_once('/') {
+                    _ if destination.ends_with('/') => destination,
+                    Some((extract_dir, destination_name)) => {
+                        // tar can't rename, e.g. Cargo.lock to remote-Cargo.lock
+                        if destination_name != name {
+                            rename = format!(
+                                " && mv {} {}",
+                                quote(&format!("{}/{}", extract_dir, name)),
+                                quote(destination)
+                            );
+                        }
+                        extract_dir
+                    }
+                    None => ".",
+                };
+                (
+                    format!("tar -C {} -cf - {}", parent, script::word(name)),
+                    extract_dir,
+                )
+            }
+        };
+        let archive = match (remote.shell, source.strip_suffix('/')) {
+            (Shell::PowerShell, Some(directory)) => windows::archive(directory, "."),
+            (Shell::PowerShell, None) => {
+                let (parent, name) = source.rsplit_once('/').unwrap_or((".", source));
+                windows::archive(parent, name)
+            }
+            (Shell::Sh, _) => archive,
+        };
+        let (compress, decompress) = tar_compression(transport, remote);
+        let archive = match compress.strip_suffix(" | ") {
+            Some(compress) => format!("{} | {}", archive, compress),
+            None => archive,
+        };
+        let mut pipeline = Command::new("sh");
+        pipeline.arg("-c").arg(format!(
+            "mkdir -p {dir} && {archive} | {decompress}tar -C {dir} -xf -{rename}",
+            dir = quote(extract_dir),
+            archive = exec_backend(remote).shell_line(
+                transport,
+                remote,
+                host,
+                &script::as_build_user(remote, &archive)
+            ),
+            decompress = decompress,
+            rename = rename
+        ));
+        transport.run(&mut pipeline, phase)
+    }
+
+    fn incremental(&self) -> bool {
+        false
+    }
+}
+
+/// Local shell command archiving `project_dir` without the `excludes` to stdout.
+fn tar_command(remote: &Remote, project_dir: &Path, excludes: &[String]) -> io::Result<String> {
+    // archiving the entries instead of `.` keeps a `.*` exclude from matching the root
+    let mut tar = vec![format!(
+        "tar -C {} -cf -",
+        quote(&project_dir.to_string_lossy())
+    )];
+    let mut top_level_excludes = Vec::new();
+    for exclude in excludes {
+        match exclude.strip_prefix('/') {
+            Some(anchored) if !anchored.contains('/') => top_level_excludes.push(anchored),
+            Some(anchored) => tar.push(format!("--exclude={}", quote(anchored))),
+            None => tar.push(format!("--exclude={}", quote(exclude))),
+        }
+    }
+    if remote.lockfile_strategy == LockfileStrategy::PerRemote {
+        top_level_excludes.push("Cargo.lock");
+    }
+    let mut entries: Vec<String> = fs::read_dir(project_dir)?
+        .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+        .filter(|name| !top_level_excludes.contains(&name.as_str()))
+        .collect();
+    entries.sort();
+    tar.extend(entries.iter().map(|entry| quote(entry)));
+    Ok(tar.join(" "))
+}
+
+/// Stages compressing and decompressing tar streams with the compressor of `transport`, they
+/// end with a pipe. Empty on a LAN, where compressing takes longer than sending.
+fn tar_compression(transport: &Transport, remote: &Remote) -> (String, String) {
+    match transport.tar_compressor {
+        Some(compressor) if remote.link != Link::Lan => (
+            format!("{} -q -c | ", compressor),
+            format!("{} -q -d -c | ", compressor),
+        ),
+        _ => (String::new(), String::new()),
+    }
+}
+
+impl SyncBackend for Git {
+    /// Uploads the project by pushing the current commit into a git repository in `build_path`,
+    /// which only sends the objects the remote doesn't have yet, and transferring just the
+    /// uncommitted changes with rsync.
+    ///
+    /// Files ignored by git aren't uploaded. Falls back to a full rsync upload if the project
+    /// isn't the root of a git repository with at least one commit.
+    fn upload(
+        &self,
+        transport: &Transport,
+        remote: &Remote,
+        host: &str,
+        project_dir: &Path,
+        build_path: &str,
+        excludes: &[String],
+        _fast: bool,
+    ) -> io::Result<ExitStatus> {
+        let commit = match head_commit(project_dir) {
+            Some(commit) => commit,
+            None => {
+                warn!(
+                    "{:?} isn't the root of a git repository with a commit, uploading it with rsync.",
+                    project_dir
+                );
+                return Rsync.upload(
+                    transport,
+                    remote,
+                    host,
+                    project_dir,
+                    build_path,
+                    excludes,
+                    false,
+                );
+            }
+        };
+
+        let prepare = format!(
+            "{} && cd {} && {{ [ -d .git ] || git init -q; }}",
+            create_build_path(remote, build_path),
+            build_path
+        );
+        let mut ssh = transport.remote_command(remote, host, &prepare);
+        let status = transport.run(&mut ssh, "sync-git-init")?;
+        if !status.success() {
+            return Ok(status);
+        }
+
+        // git doesn't expand `~`, paths without a leading `/` are relative to the home directory
+        let repository = build_path.strip_prefix("~/").unwrap_or(build_path);
+        let mut push = Command::new("git");
+        push.arg("-C")
+            .arg(project_dir)
+            .env(
+                "GIT_SSH_COMMAND",
+                exec_backend(remote).remote_shell(transport, remote),
+            )
+            .args(["push", "--quiet", "--force"])
+            .arg(format!(
+                "--receive-pack={}git-receive-pack",
+                script::sudo(remote)
+            ))
+            .arg(format!("{}:{}", host, repository))
+            .arg(format!("{}:refs/heads/{}", commit, GIT_BRANCH));
+        let status = transport.run(&mut push, "sync-git-push")?;
+        if !status.success() {
+            return Ok(status);
+        }
+
+        let per_remote_lock = remote.lockfile_strategy == LockfileStrategy::PerRemote;
+        let (changed, deleted) = uncommitted_files(project_dir)?;
+        let mut checkout = format!(
+            "cd {} && git checkout --quiet --force --detach {} && git clean -q -fd -e /{}",
+            build_path, commit, REMOTE_TARGET_DIR
+        );
+        // new files uploaded before stay, so rsync only sends them again if they changed
+        for file in &changed {
+            checkout.push_str(&format!(" -e {}", quote(&format!("/{}", file))));
+        }
+        if !deleted.is_empty() {
+            let deleted: Vec<String> = deleted.iter().map(|file| quote(file)).collect();
+            checkout.push_str(&format!(" && rm -f -- {}", deleted.join(" ")));
+        }
+        let mut ssh = transport.remote_command(remote, host, &checkout);
+        let status = transport.run(&mut ssh, "sync-git-checkout")?;
+        if !status.success() {
+            return Ok(status);
+        }
+
+        let changed: Vec<String> = changed
+            .into_iter()
+            .filter(|file| !(per_remote_lock && file == "Cargo.lock"))
+            .collect();
+        let status = if changed.is_empty() {
+            status
+        } else {
+            let files_from =
+                env::temp_dir().join(format!("cargo-remote-changes-{}", std::process::id()));
+            fs::write(&files_from, changed.join("\0"))?;
+            let mut rsync_to = transport.rsync(remote, false);
+            rsync_to
+                .args(transport.progress_flag(remote))
+                .arg("--from0")
+                .arg(format!("--files-from={}", files_from.to_string_lossy()));
+            for exclude in excludes {
+                rsync_to.arg("--exclude").arg(exclude);
+            }
+            if remote.group.is_some() {
+                rsync_to.arg("--no-group").arg("--chmod=g+rwX");
+            }
+            rsync_to
+                .arg("--rsync-path")
+                .arg(script::remote_rsync(remote))
+                .arg(format!("{}/", project_dir.to_string_lossy()))
+                .arg(format!("{}:{}", host, build_path));
+            let status = transport.transfer(remote, &mut rsync_to, "sync");
+            let _ = fs::remove_file(&files_from);
+            status?
+        };
+        if !status.success() || !per_remote_lock {
+            return Ok(status);
+        }
+        upload_lock(transport, remote, host, project_dir, build_path)
+            .map(|lock| lock.unwrap_or(status))
+    }
+
+    fn fetch(
+        &self,
+        transport: &Transport,
+        remote: &Remote,
+        host: &str,
+        source: &str,
+        destination: &str,
+        delete: bool,
+        phase: &str,
+    ) -> io::Result<ExitStatus> {
+        Rsync.fetch(transport, remote, host, source, destination, delete, phase)
+    }
+
+    fn incremental(&self) -> bool {
+        true
+    }
+}
+
+/// Command creating `build_path` on the remote, owned by the `group` of `remote` if set.
+fn create_build_path(remote: &Remote, build_path: &str) -> String {
+    let mut create = format!("{}mkdir -p {}", script::umask(remote), build_path);
+    if let Some(group) = &remote.group {
+        create.push_str(&format!(
+            " && chgrp {group} {path} && chmod g+s {path}",
+            path = build_path,
+            group = quote(group)
+        ));
+    }
+    create
+}
+
+/// The commit checked out in `project_dir`, `None` if it isn't the root of a git repository or
+/// there is no commit yet.
+fn head_commit(project_dir: &Path) -> Option<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(project_dir)
+        .args(["rev-parse", "--show-toplevel", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())?;
+    let output = String::from_utf8_lossy(&output.stdout);
+    let mut lines = output.lines();
+    let top_level = fs::canonicalize(lines.next()?).ok()?;
+    if top_level != fs::canonicalize(project_dir).ok()? {
+        return None;
+    }
+    lines.next().map(str::to_owned)
+}
+
+/// Files of `project_dir` that differ from the checked out commit: the modified and new files
+/// not ignored by git, and the deleted ones.
+fn uncommitted_files(project_dir: &Path) -> io::Result<(Vec<String>, Vec<String>)> {
+    let git = |args: &[&str]| -> io::Result<String> {
+        let output = Command::new("git")
+            .arg("-C")
+            .arg(project_dir)
+            .args(args)
+            .output()?;
+        if !output.status.success() {
+            return Err(io::Error::other(format!(
+                "git {} failed ({}): {}",
+                args[0],
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    };
+    // staged and unstaged changes of tracked files, including deletions
+    let tracked = git(&["diff", "--name-only", "--no-renames", "-z", "HEAD"])?;
+    let untracked = git(&["ls-files", "-z", "--others", "--exclude-standard"])?;
+    let (changed, deleted) = tracked
+        .split('\0')
+        .chain(untracked.split('\0'))
+        .filter(|file| !file.is_empty())
+        .map(str::to_owned)
+        .partition(|file| project_dir.join(file).exists());
+    Ok((changed, deleted))
+}
+
+impl SyncBackend for Sftp {
+    /// Creates `build_path` with the exec backend and puts the files `tar` would archive, with
+    /// their modification times so cargo doesn't rebuild unchanged crates.
+    fn upload(
+        &self,
+        transport: &Transport,
+        remote: &Remote,
+        host: &str,
+        project_dir: &Path,
+        build_path: &str,
+        excludes: &[String],
+        _fast: bool,
+    ) -> io::Result<ExitStatus> {
+        let mut mkdir =
+            transport.remote_command(remote, host, &create_build_path(remote, build_path));
+        let status = transport.run(&mut mkdir, "sync-mkdir")?;
+        if !status.success() {
+            return Ok(status);
+        }
+
+        // the listing of an archive applies the excludes exactly like the tar transfer
+        let mut list = Command::new("sh");
+        list.arg("-c").arg(format!(
+            "{} | tar -tf -",
+            tar_command(remote, project_dir, excludes)?
+        ));
+        let output = transport.output(&mut list, "sync-list")?;
+        if !output.status.success() {
+            return Err(io::Error::other(format!(
+                "listing the files failed ({}): {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        let destination = sftp_path(build_path);
+        let mut batch = String::new();
+        for entry in String::from_utf8_lossy(&output.stdout).lines() {
+            match entry.strip_suffix('/') {
+                // existing directories are fine, `-` keeps the batch going
+                Some(dir) => batch.push_str(&format!(
+                    "-mkdir {}\n",
+                    sftp_quote(&format!("{}{}", destination, dir))
+                )),
+                None => batch.push_str(&format!(
+                    "put -p {} {}\n",
+                    sftp_quote(&project_dir.join(entry).to_string_lossy()),
+                    sftp_quote(&format!("{}{}", destination, entry))
+                )),
+            }
+        }
+        if remote.lockfile_strategy == LockfileStrategy::PerRemote {
+            if let Some(lock_file) = lock_file(remote, project_dir) {
+                batch.push_str(&format!(
+                    "put -p {} {}\n",
+                    sftp_quote(&lock_file.to_string_lossy()),
+                    sftp_quote(&format!("{}Cargo.lock", destination))
+                ));
+            }
+        }
+        let status = run_sftp(transport, remote, host, batch, "sync")?;
+        if !status.success() || remote.group.is_none() {
+            return Ok(status);
+        }
+        // the sftp server doesn't apply the umask of the remote, the group has to write, too
+        let mut chmod = transport.remote_command(
+            remote,
+            host,
+            &format!("chmod -R g+rwX {}", script::path(build_path)),
+        );
+        transport.run(&mut chmod, "sync-chmod")
+    }
+
+    /// Gets `source` with sftp, the contents of `source` if it ends with `/`. Files starting
+    /// with `.` directly in such a directory are left out.
+    fn fetch(
+        &self,
+        transport: &Transport,
+        remote: &Remote,
+        host: &str,
+        source: &str,
+        destination: &str,
+        _delete: bool,
+        phase: &str,
+    ) -> io::Result<ExitStatus> {
+        let batch = match source.strip_suffix('/') {
+            Some(directory) => {
+                if !transport.dry_run() {
+                    fs::create_dir_all(destination)?;
+                }
+                format!(
+                    "lcd {}\ncd {}\nget -pr *\n",
+                    sftp_quote(destination),
+                    sftp_quote(&sftp_path(directory))
+                )
+            }
+            None => format!(
+                "get -p {} {}\n",
+                sftp_quote(&sftp_path(source)),
+                sftp_quote(destination)
+            ),
+        };
+        run_sftp(transport, remote, host, batch, phase)
+    }
+
+    fn incremental(&self) -> bool {
+        false
+    }
+}
+
+impl Sftp {
+    /// A `sftp` command reading its batch from stdin, connecting through the ssh of `remote`
+    /// like [`Ssh::connection`]: the same ssh program, port, host key policy and session. The
+    /// host still has to be added.
+    pub fn connection(transport: &Transport, remote: &Remote) -> Command {
+        // an ssh installed elsewhere usually comes with its own sftp
+        let sibling = Path::new(&remote.ssh_path).with_file_name("sftp");
+        let mut sftp = if sibling.components().count() > 1 && sibling.is_file() {
+            Command::new(sibling)
+        } else {
+            Command::new("sftp")
+        };
+        if transport.debug {
+            sftp.arg("-vvv");
+        }
+        sftp.args(["-b", "-", "-P", &remote.ssh_port.to_string(), "-S"])
+            .arg(&remote.ssh_path)
+            .args(ssh_options(transport, remote));
+        sftp
+    }
+}
+
+/// Runs the sftp `batch` against `host`, stopping at the first failing command.
+fn run_sftp(
+    transport: &Transport,
+    remote: &Remote,
+    host: &str,
+    batch: String,
+    phase: &str,
+) -> io::Result<ExitStatus> {
+    let mut sftp = Sftp::connection(transport, remote);
+    sftp.arg(host);
+    transport.run_with(
+        &mut sftp,
+        phase,
+        Input::Bytes(batch.into_bytes()),
+        Output::Inherit,
+    )
+}
+
+/// `path` for sftp, which starts in the home directory and doesn't expand `~`.
+fn sftp_path(path: &str) -> String {
+    path.strip_prefix("~/").unwrap_or(path).to_owned()
+}
+
+/// `value` as one argument of an sftp batch command.
+fn sftp_quote(value: &str) -> String {
+    format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// The sync backend selected by the `transfer` of `remote`.
+pub fn sync_backend(remote: &Remote) -> &'static dyn SyncBackend {
+    match remote.transfer {
+        Transfer::Rsync => &Rsync,
+        Transfer::Rsyncd => &Rsyncd,
+        Transfer::Tar => &Tar,
+        Transfer::Git => &Git,
+        Transfer::Sftp => &Sftp,
+    }
+}
+
+/// How [`ExecBackend::command_with`] reaches the remote. Backends without an equivalent
+/// ignore an option.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ExecOptions {
+    /// A terminal on the remote, for interactive commands and the progress bars of cargo.
+    pub tty: bool,
+    /// Forward the ssh agent, see [`Remote::agent_forwarding`].
+    pub forward_agent: bool,
+    /// Seconds to wait for the connection, the default of the backend if unset.
+    pub connect_timeout: Option<u64>,
+    /// Notice a dropped connection while the command prints nothing, for commands following
+    /// a log.
+    pub keepalive: bool,
+}
+
+/// Runs shell scripts on the remote. Scripts are run as the build user and get the cargo
+/// environment from [`script`], the backend only reaches the host.
+pub trait ExecBackend: Debug + Send + Sync {
+    /// Local program reaching the remote, which has to be installed.
+    fn program<'a>(&self, remote: &'a Remote) -> &'a str;
+
+    /// Command running `script` on `host` as is, as the user the backend connects as.
+    fn command_with(
+        &self,
+        transport: &Transport,
+        remote: &Remote,
+        host: &str,
+        script: &str,
+        options: ExecOptions,
+    ) -> Command;
+
+    /// Local shell command line running `script` on `host`, for pipelines like the tar
+    /// transfers.
+    fn shell_line(
+        &self,
+        transport: &Transport,
+        remote: &Remote,
+        host: &str,
+        script: &str,
+    ) -> String;
+
+    /// Remote shell of rsync (`rsync -e`) and git (`GIT_SSH_COMMAND`), which append the host
+    /// and the command.
+    fn remote_shell(&self, transport: &Transport, remote: &Remote) -> String;
+
+    /// Command running `script` on `host` non-interactively as the build user.
+    fn command(&self, transport: &Transport, remote: &Remote, host: &str, script: &str) -> Command {
+        self.command_with(
+            transport,
+            remote,
+            host,
+            &script::as_build_user(remote, script),
+            ExecOptions::default(),
+        )
+    }
+
+    /// Command running the build `script` on `host` as the build user.
+    ///
+    /// With `tty` it gets a terminal on the remote, which enables the progress bars and colors
+    /// of cargo, except in CI mode where the output is parsed. Without a terminal stdin reaches
+    /// the build unaltered. With `agent_forwarding` the ssh agent is forwarded, but only to the
+    /// build.
+    fn build_command(
+        &self,
+        transport: &Transport,
+        remote: &Remote,
+        host: &str,
+        script: &str,
+        tty: bool,
+    ) -> Command {
+        self.command_with(
+            transport,
+            remote,
+            host,
+            &script::as_build_user(remote, script),
+            ExecOptions {
+                tty: tty && transport.ci.is_none(),
+                forward_agent: remote.agent_forwarding,
+                ..ExecOptions::default()
+            },
+        )
+    }
+}
+
+/// ssh, the default.
+#[derive(Debug)]
+pub struct Ssh;
+
+/// The `exec_command` of the remote, e.g. `docker exec -i` for a build container.
+#[derive(Debug)]
+pub struct ExecCommand;
+
+/// Detect a dead connection within half a minute instead of waiting for TCP to time out.
+const KEEPALIVE: &[&str] = &[
+    "-o",
+    "ServerAliveInterval=10",
+    "-o",
+    "ServerAliveCountMax=3",
+];
+
+impl Ssh {
+    /// A `ssh` command connecting to the port of `remote` with its host key policy, the host
+    /// still has to be added. Only for what needs ssh itself like sessions, commands are
+    /// created with [`ExecBackend::command_with`].
+    pub fn connection(transport: &Transport, remote: &Remote) -> Command {
+        let mut ssh = Command::new(&remote.ssh_path);
+        if transport.debug {
+            ssh.arg("-vvv");
+        }
+        ssh.args(["-p", &remote.ssh_port.to_string()])
+            .args(ssh_options(transport, remote));
+        ssh
+    }
+}
+
+impl ExecBackend for Ssh {
+    fn program<'a>(&self, remote: &'a Remote) -> &'a str {
+        &remote.ssh_path
+    }
+
+    fn command_with(
+        &self,
+        transport: &Transport,
+        remote: &Remote,
+        host: &str,
+        script: &str,
+        options: ExecOptions,
+    ) -> Command {
+        let mut ssh = Ssh::connection(transport, remote);
+        if let Some(timeout) = options.connect_timeout {
+            ssh.arg("-o").arg(format!("ConnectTimeout={}", timeout));
+        }
+        if options.keepalive {
+            ssh.args(KEEPALIVE);
+        }
+        if options.tty {
+            ssh.arg("-t");
+        }
+        if options.forward_agent {
+            ssh.arg("-A");
+        }
+        ssh.arg(host).arg(script);
+        ssh
+    }
+
+    fn shell_line(
+        &self,
+        transport: &Transport,
+        remote: &Remote,
+        host: &str,
+        script: &str,
+    ) -> String {
+        format!(
+            "{} {} {}",
+            self.remote_shell(transport, remote),
+            script::word(host),
+            quote(script)
+        )
+    }
+
+    fn remote_shell(&self, transport: &Transport, remote: &Remote) -> String {
+        let mut shell = vec![script::word(&remote.ssh_path)];
+        if transport.debug {
+            shell.push("-vvv".to_owned());
+        }
+        shell.push(format!("-p {}", remote.ssh_port));
+        shell.extend(
+            ssh_options(transport, remote)
+                .iter()
+                .map(|option| script::word(option)),
+        );
+        shell.join(" ")
+    }
+}
+
+impl ExecBackend for ExecCommand {
+    fn program<'a>(&self, remote: &'a Remote) -> &'a str {
+        remote.exec_command.first().map_or("", String::as_str)
+    }
+
+    /// `exec_command`, the host and `sh -c script`. The options have no equivalent.
+    fn command_with(
+        &self,
+        _transport: &Transport,
+        remote: &Remote,
+        host: &str,
+        script: &str,
+        _options: ExecOptions,
+    ) -> Command {
+        let mut command = Command::new(self.program(remote));
+        command
+            .args(remote.exec_command.iter().skip(1))
+            .arg(host)
+            .args(["sh", "-c", script]);
+        command
+    }
+
+    fn shell_line(
+        &self,
+        transport: &Transport,
+        remote: &Remote,
+        host: &str,
+        script: &str,
+    ) -> String {
+        format!(
+            "{} {} sh -c {}",
+            self.remote_shell(transport, remote),
+            script::word(host),
+            quote(script)
+        )
+    }
+
+    fn remote_shell(&self, _transport: &Transport, remote: &Remote) -> String {
+        remote
+            .exec_command
+            .iter()
+            .map(|word| script::word(word))
+            .collect::<Vec<_>>()
+            .join(" ")
+    }
+}
+
+/// The `-o` options for the host key policy of `remote`. With a `host_key_fingerprint` only the
+/// keys [`Transport::pin_host_keys`] pinned are accepted, whatever the connection is for.
+fn ssh_options(transport: &Transport, remote: &Remote) -> Vec<String> {
+    let (checking, known_hosts) = match remote.host_key_fingerprint {
+        Some(_) => {
+            transport.pin_host_keys(remote);
+            let known_hosts = hostkey::known_hosts(remote);
+            (
+                Some(HostKeyChecking::Yes),
+                Some(known_hosts.to_string_lossy().into_owned()),
+            )
+        }
+        None => (remote.strict_host_key_checking, remote.known_hosts.clone()),
+    };
+    let mut options = Vec::new();
+    if let Some(checking) = checking {
+        options.push("-o".to_owned());
+        options.push(format!("StrictHostKeyChecking={}", checking));
+    }
+    if let Some(known_hosts) = &known_hosts {
+        options.push("-o".to_owned());
+        options.push(format!("UserKnownHostsFile={}", known_hosts));
+    }
+    options.extend(session::ssh_options(remote));
+    options
+}
+
+/// The exec backend of `remote`: its `exec_command` if it has one, otherwise ssh.
+pub fn exec_backend(remote: &Remote) -> &'static dyn ExecBackend {
+    if remote.exec_command.is_empty() {
+        &Ssh
+    } else {
+        &ExecCommand
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::executor::{Executor, Scripted};
+    use std::sync::Arc;
+
+    fn transport(executor: &Arc<Scripted>) -> Transport {
+        Transport::with_executor(false, Arc::clone(executor) as Arc<dyn Executor>)
+    }
+
+    fn remote() -> Remote {
+        Remote {
+            host: "builder@build".to_owned(),
+            ssh_path: "ssh".to_owned(),
+            ssh_port: 2222,
+            temp_dir: "~/builds".to_owned(),
+            known_hosts: Some("/tmp/pinned".to_owned()),
+            transfer: Transfer::Sftp,
+            ..Remote::default()
+        }
+    }
+
+    #[test]
+    fn sftp_upload_puts_the_listed_files() {
+        let listing = "Cargo.toml\nsrc/\nsrc/main.rs\n";
+        let executor = Arc::new(Scripted::new([(0, ""), (0, listing), (0, "")]));
+        let project_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+        let status = Sftp
+            .upload(
+                &transport(&executor),
+                &remote(),
+                "build",
+                project_dir,
+                "~/builds/1/",
+                &[],
+                false,
+            )
+            .unwrap();
+        assert!(status.success());
+        let commands = executor.commands();
+        assert_eq!(commands.len(), 3);
+        assert_eq!(commands[0][0], "ssh");
+        let sftp = &commands[2];
+        assert_eq!(sftp[0], "sftp");
+        assert_eq!(sftp[1..7], ["-b", "-", "-P", "2222", "-S", "ssh"]);
+        assert!(sftp.contains(&"UserKnownHostsFile=/tmp/pinned".to_owned()));
+        assert_eq!(sftp.last().map(String::as_str), Some("build"));
+        let batch = &executor.inputs()[2];
+        let manifest = format!("{}/Cargo.toml", project_dir.display());
+        assert!(batch.contains(&format!(
+            "put -p \"{}\" \"builds/1/Cargo.toml\"\n",
+            manifest
+        )));
+        assert!(batch.contains("-mkdir \"builds/1/src\"\n"));
+        assert!(batch.contains("/src/main.rs\" \"builds/1/src/main.rs\"\n"));
+    }
+
+    #[test]
+    fn sftp_upload_stops_when_the_build_path_cant_be_created() {
+        let executor = Arc::new(Scripted::new([(1, "")]));
+        let status = Sftp
+            .upload(
+                &transport(&executor),
+                &remote(),
+                "build",
+                Path::new(env!("CARGO_MANIFEST_DIR")),
+                "~/builds/1/",
+                &[],
+                false,
+            )
+            .unwrap();
+        assert_eq!(status.code(), Some(1));
+        assert_eq!(executor.commands().len(), 1);
+    }
+
+    #[test]
+    fn sftp_fetch_gets_a_file_or_the_contents_of_a_directory() {
+        let executor = Arc::new(Scripted::new([]));
+        let transport = transport(&executor);
+        let destination = env::temp_dir().join(format!("cargo-remote-test-{}", std::process::id()));
+        let destination = destination.to_string_lossy().into_owned();
+        Sftp.fetch(
+            &transport,
+            &remote(),
+            "build",
+            "~/builds/1/Cargo.lock",
+            "/tmp/Cargo.lock",
+            false,
+            "copy-lock",
+        )
+        .unwrap();
+        Sftp.fetch(
+            &transport,
+            &remote(),
+            "build",
+            "~/builds/1/target/",
+            &destination,
+            false,
+            "copy-back",
+        )
+        .unwrap();
+        fs::remove_dir(&destination).unwrap();
+        assert_eq!(
+            executor.inputs(),
+            [
+                "get -p \"builds/1/Cargo.lock\" \"/tmp/Cargo.lock\"\n".to_owned(),
+                format!(
+                    "lcd \"{}\"\ncd \"builds/1/target\"\nget -pr *\n",
+                    destination
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn exec_command_runs_the_script_with_sh() {
+        let remote = Remote {
+            exec_command: vec!["docker".to_owned(), "exec".to_owned(), "-i".to_owned()],
+            transfer: Transfer::Tar,
+            ..remote()
+        };
+        let executor = Arc::new(Scripted::new([]));
+        let transport = transport(&executor);
+        let backend = exec_backend(&remote);
+        assert_eq!(backend.program(&remote), "docker");
+        let mut command = backend.command_with(
+            &transport,
+            &remote,
+            "builder",
+            "cargo build",
+            ExecOptions {
+                tty: true,
+                ..ExecOptions::default()
+            },
+        );
+        transport.run(&mut command, "build").unwrap();
+        assert_eq!(
+            executor.commands(),
+            [["docker", "exec", "-i", "builder", "sh", "-c", "cargo build"]]
+        );
+        assert_eq!(
+            backend.shell_line(&transport, &remote, "builder", "tar -xf -"),
+            "docker exec -i builder sh -c 'tar -xf -'"
+        );
+    }
+
+    #[test]
+    fn ssh_is_the_default_exec_backend() {
+        let remote = remote();
+        let executor = Arc::new(Scripted::new([]));
+        let command = exec_backend(&remote).command_with(
+            &transport(&executor),
+            &remote,
+            "build",
+            "true",
+            ExecOptions {
+                connect_timeout: Some(10),
+                ..ExecOptions::default()
+            },
+        );
+        let args: Vec<_> = command
+            .get_args()
+            .map(|arg| arg.to_string_lossy())
+            .collect();
+        assert_eq!(command.get_program(), "ssh");
+        assert!(args.contains(&"ConnectTimeout=10".into()));
+        assert_eq!(args[args.len() - 2..], ["build", "true"]);
+    }
+}
@@ -0,0 +1,205 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use tracing::{error, info};
+
+use crate::compat;
+use crate::config::Remote;
+use crate::probe;
+use crate::process::{Input, Output};
+use crate::script;
+use crate::sync;
+use crate::transport::Transport;
+
+/// Directory in the local target dir the sample crate is generated in.
+const SAMPLE_DIR: &str = "cargo-remote-benchmark";
+
+/// Data files synced along with the sample crate, and the size of each of them.
+const DATA_FILES: usize = 32;
+const DATA_FILE_BYTES: usize = 128 * 1024;
+
+/// Functions in the sample crate, each instantiated for several integer types.
+const SAMPLE_FUNCTIONS: usize = 1000;
+
+/// Result of benchmarking a single remote.
+#[derive(Debug)]
+struct Measurement {
+    host: String,
+    cpu: String,
+    cores: String,
+    sync: Duration,
+    build: Duration,
+}
+
+/// Syncs and builds a generated sample crate on every remote and prints how long each step
+/// took, along with the CPU of the remote.
+///
+/// Every remote starts from an empty build directory, so the whole tree is transferred and
+/// the crate is compiled from scratch. Returns whether all remotes could be measured.
+pub fn run(transport: &Transport, remotes: &[Remote], target_dir: &Path) -> bool {
+    let sample_dir = match write_sample(target_dir) {
+        Ok(sample_dir) => sample_dir,
+        Err(e) => {
+            error!("Failed to generate the sample crate (error: {})", e);
+            return false;
+        }
+    };
+
+    let mut results = Vec::new();
+    for remote in remotes {
+        info!("Benchmarking remote {}.", remote.name);
+        let result = measure(transport, remote, &sample_dir);
+        if let Err(e) = &result {
+            error!("Benchmarking remote {} failed (error: {})", remote.name, e);
+        }
+        results.push((remote, result));
+    }
+
+    println!();
+    println!(
+        "{:<16} {:<24} {:<32} {:>5} {:>8} {:>8}",
+        "remote", "host", "cpu", "cores", "sync", "build"
+    );
+    for (remote, result) in &results {
+        match result {
+            Ok(measurement) => println!(
+                "{:<16} {:<24} {:<32} {:>5} {:>7.1}s {:>7.1}s",
+                remote.name,
+                measurement.host,
+                measurement.cpu,
+                measurement.cores,
+                measurement.sync.as_secs_f64(),
+                measurement.build.as_secs_f64()
+            ),
+            Err(e) => println!("{:<16} failed: {}", remote.name, e),
+        }
+    }
+    let fastest = results
+        .iter()
+        .filter_map(|(remote, result)| Some((remote, result.as_ref().ok()?)))
+        .min_by_key(|(_, measurement)| measurement.sync + measurement.build);
+    if let Some((remote, _)) = fastest {
+        println!();
+        println!("fastest: {}", remote.name);
+    }
+    results.iter().all(|(_, result)| result.is_ok())
+}
+
+fn measure(transport: &Transport, remote: &Remote, sample_dir: &Path) -> io::Result<Measurement> {
+    let host = transport
+        .select_host(remote)
+        .ok_or_else(|| io::Error::other("none of the hosts is reachable"))?;
+    let mut remote = remote.clone();
+    let facts = probe::cached_or_probe(transport, &remote, &host)?;
+    compat::adapt(&mut remote, &facts);
+
+    let cpu_info = transport.remote_output(
+        &remote,
+        &host,
+        "(nproc || sysctl -n hw.ncpu) 2>/dev/null; \
+         if [ -r /proc/cpuinfo ]; then grep -m1 '^model name' /proc/cpuinfo | cut -d: -f2; \
+         else sysctl -n machdep.cpu.brand_string 2>/dev/null; fi; true",
+    )?;
+    let mut cpu_info = cpu_info.lines().map(str::trim);
+    let cores = cpu_info.next().unwrap_or_default().to_owned();
+    let cpu = cpu_info
+        .next()
+        .filter(|cpu| !cpu.is_empty())
+        .unwrap_or("unknown")
+        .to_owned();
+
+    let build_path = sync::build_path(&remote, sample_dir);
+    transport.remote_output(&remote, &host, &script::remove_dir(&remote, &build_path))?;
+
+    let started = Instant::now();
+    let status = sync::upload(
+        transport,
+        &remote,
+        &host,
+        sample_dir,
+        &build_path,
+        &[crate::REMOTE_TARGET_DIR.to_owned()],
+        false,
+    )?;
+    if !status.success() {
+        return Err(io::Error::other(format!("sync failed ({})", status)));
+    }
+    let sync = started.elapsed();
+
+    // the toolchain of the nix-shell may have to be downloaded first, which isn't measured
+    build(transport, &remote, &host, &build_path, "--version")?;
+    let started = Instant::now();
+    build(transport, &remote, &host, &build_path, "build --release")?;
+    let build = started.elapsed();
+
+    Ok(Measurement {
+        host,
+        cpu,
+        cores,
+        sync,
+        build,
+    })
+}
+
+fn build(
+    transport: &Transport,
+    remote: &Remote,
+    host: &str,
+    build_path: &str,
+    command: &str,
+) -> io::Result<()> {
+    let build_command = script::build_command(remote, build_path, &[command.to_owned()]);
+    let mut ssh = transport.remote_build_command(remote, host, &build_command, false);
+    let status = transport.run_with(&mut ssh, "benchmark", Input::Inherit, Output::Inherit)?;
+    if !status.success() {
+        return Err(io::Error::other(format!(
+            "cargo {} failed ({})",
+            command, status
+        )));
+    }
+    Ok(())
+}
+
+/// Generates the sample crate without dependencies, so building it needs no network access.
+fn write_sample(target_dir: &Path) -> io::Result<PathBuf> {
+    let sample_dir = target_dir.join(SAMPLE_DIR);
+    fs::create_dir_all(sample_dir.join("src"))?;
+    fs::create_dir_all(sample_dir.join("data"))?;
+    fs::write(
+        sample_dir.join("Cargo.toml"),
+        "[package]\nname = \"cargo-remote-benchmark\"\nversion = \"0.1.0\"\nedition = \"2018\"\n\n\
+         [workspace]\n",
+    )?;
+    fs::write(
+        sample_dir.join("shell.nix"),
+        "{ pkgs ? import <nixpkgs> {} }:\npkgs.mkShell {\n  nativeBuildInputs = [ pkgs.cargo pkgs.rustc ];\n}\n",
+    )?;
+
+    let mut main = String::new();
+    for i in 0..SAMPLE_FUNCTIONS {
+        main.push_str(&format!(
+            "fn f{i}<T: Copy + Into<u64>>(values: &[T]) -> u64 {{\n    \
+             values.iter().fold({i}, |acc: u64, value| acc.rotate_left({shift}) ^ (*value).into().wrapping_mul({factor}))\n}}\n\n",
+            i = i,
+            shift = i % 63 + 1,
+            factor = 2 * i + 1
+        ));
+    }
+    main.push_str("fn main() {\n    let mut sum = 0u64;\n");
+    for i in 0..SAMPLE_FUNCTIONS {
+        main.push_str(&format!(
+            "    sum ^= f{i}(&[1u8, 2, 3]) ^ f{i}(&[4u16, 5]) ^ f{i}(&[6u32]);\n",
+            i = i
+        ));
+    }
+    main.push_str("    println!(\"{}\", sum);\n}\n");
+    fs::write(sample_dir.join("src").join("main.rs"), main)?;
+
+    let data = probe::sample_bytes(DATA_FILES * DATA_FILE_BYTES);
+    for (i, chunk) in data.chunks(DATA_FILE_BYTES).enumerate() {
+        fs::write(sample_dir.join("data").join(format!("{}.bin", i)), chunk)?;
+    }
+    Ok(sample_dir)
+}
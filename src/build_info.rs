@@ -0,0 +1,119 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::Serialize;
+
+use crate::config::Remote;
+use crate::script::{self, quote};
+use crate::summary::unix_time;
+use crate::transport::Transport;
+
+/// Local file next to the copied back artifacts describing the environment that built them.
+const INFO_FILE: &str = "remote-build-info.json";
+
+/// Separates the sections in the output of the probe.
+const SECTION_SEPARATOR: &str = "--cargo-remote-section--";
+
+/// Prefixes of the environment variables that change what the build produces.
+const RELEVANT_ENV: &[&str] = &[
+    "CARGO_",
+    "RUST",
+    "CC",
+    "CXX",
+    "AR",
+    "CFLAGS",
+    "CXXFLAGS",
+    "LDFLAGS",
+    "PKG_CONFIG",
+    "SOURCE_DATE_EPOCH",
+    "NIX_",
+    "PATH",
+];
+
+/// Parts of variable names that hint at credentials, which are never recorded.
+const SENSITIVE_ENV: &[&str] = &["TOKEN", "PASSWORD", "SECRET", "CREDENTIAL"];
+
+/// Environment the remote build ran in, to tell what exactly built the artifacts.
+#[derive(Debug, Clone, Serialize)]
+pub struct BuildInfo {
+    pub host: String,
+    pub build_path: String,
+    /// Unix time the build finished.
+    pub built_at: u64,
+    pub commands: Vec<String>,
+    /// Fields of `rustc -vV` inside the build environment, e.g. `release` and `commit-hash`.
+    pub rustc: BTreeMap<String, String>,
+    /// `cargo -V` inside the build environment.
+    pub cargo: String,
+    /// `uname -a` of the remote.
+    pub uname: String,
+    /// Environment variables of the build that influence the output, without credentials.
+    pub env: BTreeMap<String, String>,
+}
+
+pub fn path(output_dir: &Option<PathBuf>, target_dir: &Path) -> PathBuf {
+    output_dir.as_deref().unwrap_or(target_dir).join(INFO_FILE)
+}
+
+/// Asks the remote for the toolchain and environment of the build in `build_path`.
+pub fn collect(
+    transport: &Transport,
+    remote: &Remote,
+    host: &str,
+    build_path: &str,
+    commands: &[String],
+) -> io::Result<BuildInfo> {
+    let probe = format!(
+        "rustc -vV; echo {sep}; cargo -V; echo {sep}; uname -a; echo {sep}; env",
+        sep = quote(SECTION_SEPARATOR)
+    );
+    let output = transport.remote_output(
+        remote,
+        host,
+        &script::build_script(remote, build_path, Some(&probe)),
+    )?;
+    let mut sections = output.split(SECTION_SEPARATOR).map(str::trim);
+    let rustc = sections
+        .next()
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| line.split_once(": "))
+        .map(|(key, value)| (key.to_owned(), value.trim().to_owned()))
+        .collect();
+    let cargo = sections.next().unwrap_or_default().to_owned();
+    let uname = sections.next().unwrap_or_default().to_owned();
+    let env = sections
+        .next()
+        .unwrap_or_default()
+        .lines()
+        .filter_map(|line| line.split_once('='))
+        .filter(|(name, _)| is_relevant(name))
+        .map(|(name, value)| (name.to_owned(), value.to_owned()))
+        .collect();
+    Ok(BuildInfo {
+        host: host.to_owned(),
+        build_path: build_path.to_owned(),
+        built_at: unix_time(std::time::SystemTime::now()),
+        commands: commands.to_vec(),
+        rustc,
+        cargo,
+        uname,
+        env,
+    })
+}
+
+impl BuildInfo {
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_vec_pretty(self)?)
+    }
+}
+
+fn is_relevant(name: &str) -> bool {
+    RELEVANT_ENV.iter().any(|prefix| name.starts_with(prefix))
+        && !SENSITIVE_ENV.iter().any(|part| name.contains(part))
+}
@@ -0,0 +1,80 @@
+use std::fs::{self, File, OpenOptions, TryLockError};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use tracing::info;
+
+use crate::config::Remote;
+
+/// Advisory lock held while a build of the project runs on a remote, so two builds started in
+/// different terminals don't sync into and build in the same directory at the same time.
+///
+/// The lock is released when the process exits, however it ends.
+#[derive(Debug)]
+pub struct BuildLock {
+    _file: File,
+}
+
+impl BuildLock {
+    /// Takes the lock of the project with `target_dir` on `remote`. If another build holds it,
+    /// waits for it to finish, or fails right away without `wait`.
+    pub fn acquire(target_dir: &Path, remote: &Remote, wait: bool) -> io::Result<Self> {
+        let path = lock_path(target_dir, remote);
+        fs::create_dir_all(target_dir)?;
+        let mut file = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(&path)?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                let holder = fs::read_to_string(&path).unwrap_or_default();
+                let holder = match holder.trim() {
+                    "" => String::new(),
+                    pid => format!(" (pid {})", pid),
+                };
+                if !wait {
+                    return Err(io::Error::new(
+                        io::ErrorKind::WouldBlock,
+                        format!(
+                            "another build of this project on {} is running{}",
+                            remote.name, holder
+                        ),
+                    ));
+                }
+                info!(
+                    "Waiting for another build of this project on {} to finish{}.",
+                    remote.name, holder
+                );
+                file.lock()?;
+            }
+            Err(TryLockError::Error(e)) => return Err(e),
+        }
+        file.set_len(0)?;
+        writeln!(file, "{}", process::id())?;
+        Ok(BuildLock { _file: file })
+    }
+}
+
+/// Lock file of the project with `target_dir` on `remote`, builds on other remotes don't
+/// share a build directory and may run at the same time.
+fn lock_path(target_dir: &Path, remote: &Remote) -> PathBuf {
+    let key = if remote.name.is_empty() {
+        remote.host.as_str()
+    } else {
+        remote.name.as_str()
+    };
+    let key: String = key
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "-_.".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    target_dir.join(format!("cargo-remote-{}.lock", key))
+}
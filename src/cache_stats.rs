@@ -0,0 +1,172 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::{CompilerCache, Remote};
+use crate::script;
+use crate::transport::Transport;
+
+/// File in the local target dir with the cache statistics of the last builds.
+const HISTORY_FILE: &str = "remote-cache-stats.json";
+
+/// Builds kept in the history, older ones are dropped.
+const HISTORY_LEN: usize = 50;
+
+/// Counters of a compiler cache at one point in time.
+#[derive(Debug, Clone, Default)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    /// Size of the cache as reported by the tool.
+    pub size: Option<String>,
+}
+
+/// Queries the statistics of `cache` on `host`.
+pub fn query(
+    transport: &Transport,
+    remote: &Remote,
+    host: &str,
+    cache: CompilerCache,
+) -> io::Result<CacheStats> {
+    let command = match cache {
+        CompilerCache::Sccache => "sccache --show-stats",
+        CompilerCache::Ccache => "ccache --print-stats",
+    };
+    let output = transport.remote_output(
+        remote,
+        host,
+        &format!("{}{}", script::source_env(remote), command),
+    )?;
+    Ok(match cache {
+        CompilerCache::Sccache => parse_sccache(&output),
+        CompilerCache::Ccache => parse_ccache(&output),
+    })
+}
+
+/// Parses the human readable `sccache --show-stats` output, e.g. `Cache hits    12`.
+fn parse_sccache(output: &str) -> CacheStats {
+    let value = |name: &str| {
+        output.lines().find_map(|line| {
+            line.strip_prefix(name)
+                .filter(|rest| rest.starts_with(char::is_whitespace))
+                .map(|rest| rest.trim().to_owned())
+        })
+    };
+    CacheStats {
+        hits: value("Cache hits")
+            .and_then(|hits| hits.parse().ok())
+            .unwrap_or_default(),
+        misses: value("Cache misses")
+            .and_then(|misses| misses.parse().ok())
+            .unwrap_or_default(),
+        size: value("Cache size"),
+    }
+}
+
+/// Parses the tab separated `ccache --print-stats` output, e.g. `direct_cache_hit<TAB>12`.
+fn parse_ccache(output: &str) -> CacheStats {
+    let value = |name: &str| -> u64 {
+        output
+            .lines()
+            .filter_map(|line| line.split_once('\t'))
+            .find(|(key, _)| *key == name)
+            .and_then(|(_, value)| value.trim().parse().ok())
+            .unwrap_or_default()
+    };
+    CacheStats {
+        hits: value("direct_cache_hit") + value("preprocessed_cache_hit"),
+        misses: value("cache_miss"),
+        size: Some(format!("{} KiB", value("cache_size_kibibyte"))),
+    }
+}
+
+/// One line summarizing what happened to the cache between `before` and `after`.
+pub fn summary(cache: CompilerCache, before: &CacheStats, after: &CacheStats) -> String {
+    let hits = after.hits.saturating_sub(before.hits);
+    let misses = after.misses.saturating_sub(before.misses);
+    format!(
+        "{}: {} hits, {} misses (hit rate {}), cache size {}",
+        cache,
+        hits,
+        misses,
+        hit_rate(hits, misses),
+        after.size.as_deref().unwrap_or("unknown")
+    )
+}
+
+/// Share of `hits` in all lookups, `n/a` without any.
+pub fn hit_rate(hits: u64, misses: u64) -> String {
+    if hits + misses == 0 {
+        "n/a".to_owned()
+    } else {
+        format!("{:.1} %", hits as f64 * 100.0 / (hits + misses) as f64)
+    }
+}
+
+/// What happened to the compiler cache during one build.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Run {
+    /// Unix time the build finished.
+    pub finished_at: u64,
+    pub host: String,
+    /// `sccache` or `ccache`.
+    pub cache: String,
+    pub commands: Vec<String>,
+    pub hits: u64,
+    pub misses: u64,
+    /// Size of the cache after the build.
+    pub size: Option<String>,
+}
+
+impl Run {
+    pub fn new(
+        cache: CompilerCache,
+        host: &str,
+        commands: &[String],
+        before: &CacheStats,
+        after: &CacheStats,
+        finished_at: u64,
+    ) -> Self {
+        Run {
+            finished_at,
+            host: host.to_owned(),
+            cache: cache.to_string(),
+            commands: commands.to_vec(),
+            hits: after.hits.saturating_sub(before.hits),
+            misses: after.misses.saturating_sub(before.misses),
+            size: after.size.clone(),
+        }
+    }
+}
+
+/// The cache statistics of the last builds into a local target dir, oldest first.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct History {
+    pub runs: Vec<Run>,
+}
+
+impl History {
+    pub fn load(target_dir: &Path) -> Self {
+        fs::read(target_dir.join(HISTORY_FILE))
+            .ok()
+            .and_then(|contents| serde_json::from_slice(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, target_dir: &Path) -> io::Result<()> {
+        fs::create_dir_all(target_dir)?;
+        fs::write(
+            target_dir.join(HISTORY_FILE),
+            serde_json::to_vec_pretty(self)?,
+        )
+    }
+
+    /// Adds `run`, dropping the oldest runs beyond [`HISTORY_LEN`].
+    pub fn record(&mut self, run: Run) {
+        self.runs.push(run);
+        let excess = self.runs.len().saturating_sub(HISTORY_LEN);
+        self.runs.drain(..excess);
+    }
+}
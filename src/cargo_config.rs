@@ -0,0 +1,158 @@
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// Names of the cargo config file in the `.cargo` directory of a project, the second one is
+/// the legacy name without extension.
+pub const FILE_NAMES: &[&str] = &["config.toml", "config"];
+
+/// The `.cargo/config.toml` (or `.cargo/config`) of `project_dir`, if it has one.
+pub fn path(project_dir: &Path) -> Option<PathBuf> {
+    FILE_NAMES
+        .iter()
+        .map(|name| project_dir.join(".cargo").join(name))
+        .find(|path| path.is_file())
+}
+
+/// Settings of the cargo config of `project_dir` that can't work on the remote as they are:
+/// paths outside of the project, local tools given by absolute path and credentials.
+pub fn remote_problems(project_dir: &Path) -> Vec<String> {
+    let path = match path(project_dir) {
+        Some(path) => path,
+        None => return Vec::new(),
+    };
+    let config: toml::Value = match fs::read_to_string(&path)
+        .ok()
+        .and_then(|contents| toml::from_str(&contents).ok())
+    {
+        Some(config) => config,
+        // cargo reports broken configs itself
+        None => return Vec::new(),
+    };
+    let mut problems = Vec::new();
+    for (name, source) in tables(&config, "source") {
+        for key in ["directory", "local-registry"] {
+            if let Some(dir) = source.get(key).and_then(toml::Value::as_str) {
+                if is_outside(dir) {
+                    problems.push(format!(
+                        "[source.{}] {} = {:?} points outside of the project",
+                        name, key, dir
+                    ));
+                }
+            }
+        }
+    }
+    for (registry, patches) in tables(&config, "patch") {
+        for (name, patch) in patches.as_table().into_iter().flatten() {
+            if let Some(dir) = patch.get("path").and_then(toml::Value::as_str) {
+                if is_outside(dir) {
+                    problems.push(format!(
+                        "[patch.{}] {} = {{ path = {:?} }} points outside of the project",
+                        registry, name, dir
+                    ));
+                }
+            }
+        }
+    }
+    for dir in config
+        .get("paths")
+        .and_then(toml::Value::as_array)
+        .into_iter()
+        .flatten()
+        .filter_map(toml::Value::as_str)
+    {
+        if is_outside(dir) {
+            problems.push(format!("paths = [{:?}] points outside of the project", dir));
+        }
+    }
+    for (target, settings) in tables(&config, "target") {
+        for key in ["linker", "runner", "ar"] {
+            if let Some(tool) = settings.get(key).and_then(program) {
+                if tool.starts_with('/') {
+                    problems.push(format!(
+                        "[target.{}] {} = {:?} has to exist on the remote",
+                        target, key, tool
+                    ));
+                }
+            }
+        }
+    }
+    if let Some(build) = config.get("build") {
+        for key in ["rustc", "rustc-wrapper", "rustdoc"] {
+            if let Some(tool) = build.get(key).and_then(toml::Value::as_str) {
+                if tool.starts_with('/') {
+                    problems.push(format!(
+                        "[build] {} = {:?} has to exist on the remote",
+                        key, tool
+                    ));
+                }
+            }
+        }
+        if build.get("target-dir").is_some() {
+            problems.push(
+                "[build] target-dir is ignored, the remote build uses its own target dir"
+                    .to_owned(),
+            );
+        }
+    }
+    if let Some(cainfo) = config
+        .get("http")
+        .and_then(|http| http.get("cainfo"))
+        .and_then(toml::Value::as_str)
+    {
+        if is_outside(cainfo) {
+            problems.push(format!(
+                "[http] cainfo = {:?} points outside of the project",
+                cainfo
+            ));
+        }
+    }
+    let tokens = config
+        .get("registry")
+        .and_then(|registry| registry.get("token"))
+        .is_some()
+        || tables(&config, "registries").any(|(_, registry)| registry.get("token").is_some());
+    if tokens {
+        problems.push(
+            "registry tokens are uploaded with the config, use registry credentials or secrets instead"
+                .to_owned(),
+        );
+    }
+    problems
+}
+
+/// The subtables of the table `key`, e.g. the sources of `[source.<name>]`.
+fn tables<'a>(
+    config: &'a toml::Value,
+    key: &str,
+) -> impl Iterator<Item = (&'a String, &'a toml::Value)> {
+    config
+        .get(key)
+        .and_then(toml::Value::as_table)
+        .into_iter()
+        .flatten()
+}
+
+/// The program of a tool setting, a string or an array of the program and its arguments.
+fn program(value: &toml::Value) -> Option<&str> {
+    match value {
+        toml::Value::String(program) => Some(program),
+        toml::Value::Array(command) => command.first()?.as_str(),
+        _ => None,
+    }
+}
+
+/// Whether the `path` of the config, relative to the project, leads out of it. Absolute paths
+/// always do, the remote build directory is somewhere else.
+fn is_outside(path: &str) -> bool {
+    let mut depth = 0;
+    for component in Path::new(path).components() {
+        match component {
+            Component::ParentDir if depth == 0 => return true,
+            Component::ParentDir => depth -= 1,
+            Component::Normal(_) => depth += 1,
+            Component::RootDir | Component::Prefix(_) => return true,
+            Component::CurDir => {}
+        }
+    }
+    false
+}
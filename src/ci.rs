@@ -0,0 +1,130 @@
+use std::time::SystemTime;
+
+use clap::ValueEnum;
+
+use crate::summary::unix_time;
+
+/// CI system the output is formatted for with `--ci`.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ci {
+    /// Log groups and `::error` annotations for GitHub Actions
+    Github,
+    /// Collapsible sections for GitLab CI
+    Gitlab,
+    /// No progress output, otherwise unchanged
+    Plain,
+}
+
+impl Ci {
+    /// Opens a collapsible group for `phase` in the job log.
+    pub fn start_group(self, phase: &str) {
+        match self {
+            Ci::Github => println!("::group::{}", phase),
+            Ci::Gitlab => println!(
+                "\x1b[0Ksection_start:{}:{}[collapsed=true]\r\x1b[0K{}",
+                unix_time(SystemTime::now()),
+                section(phase),
+                phase
+            ),
+            Ci::Plain => {}
+        }
+    }
+
+    pub fn end_group(self, phase: &str) {
+        match self {
+            Ci::Github => println!("::endgroup::"),
+            Ci::Gitlab => println!(
+                "\x1b[0Ksection_end:{}:{}\r\x1b[0K",
+                unix_time(SystemTime::now()),
+                section(phase)
+            ),
+            Ci::Plain => {}
+        }
+    }
+
+    /// Turns compiler diagnostics in the build output into annotations, if the CI system has
+    /// any.
+    pub fn annotator(self) -> Annotator {
+        Annotator {
+            ci: self,
+            pending: None,
+        }
+    }
+}
+
+/// Collects the diagnostics of the human readable cargo output line by line.
+#[derive(Debug)]
+pub struct Annotator {
+    ci: Ci,
+    /// Level and message of the last diagnostic whose location hasn't been seen yet.
+    pending: Option<(&'static str, String)>,
+}
+
+impl Annotator {
+    /// Feeds the next line of output, returns an annotation once a diagnostic is complete.
+    pub fn line(&mut self, line: &str) -> Option<String> {
+        if self.ci != Ci::Github {
+            return None;
+        }
+        if let Some(diagnostic) = parse_diagnostic(line) {
+            self.pending = Some(diagnostic);
+            return None;
+        }
+        let location = line.trim_start().strip_prefix("--> ")?;
+        let (level, message) = self.pending.take()?;
+        let mut parts = location.rsplitn(3, ':');
+        let column = parts.next()?;
+        let line = parts.next()?;
+        let file = parts.next()?;
+        Some(format!(
+            "::{} file={},line={},col={}::{}",
+            level,
+            escape_property(file),
+            line,
+            column,
+            escape_data(&message)
+        ))
+    }
+}
+
+/// Level and message of a line like `error[E0308]: mismatched types`.
+fn parse_diagnostic(line: &str) -> Option<(&'static str, String)> {
+    let (level, rest) = if let Some(rest) = line.strip_prefix("error") {
+        ("error", rest)
+    } else if let Some(rest) = line.strip_prefix("warning") {
+        ("warning", rest)
+    } else {
+        return None;
+    };
+    let rest = match rest.strip_prefix('[') {
+        Some(code) => code.split_once(']')?.1,
+        None => rest,
+    };
+    let message = rest.strip_prefix(": ")?;
+    Some((level, message.to_owned()))
+}
+
+fn escape_data(value: &str) -> String {
+    value
+        .replace('%', "%25")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+fn escape_property(value: &str) -> String {
+    escape_data(value).replace(':', "%3A").replace(',', "%2C")
+}
+
+/// GitLab only accepts some characters in section names.
+fn section(phase: &str) -> String {
+    phase
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "_.-".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
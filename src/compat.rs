@@ -0,0 +1,114 @@
+use std::process::Command;
+
+use tracing::{info, warn};
+
+use crate::config::{Link, Remote, Transfer};
+use crate::prerequisites;
+use crate::probe::{RemoteFacts, COMPRESSORS};
+
+/// Environment profile used on macOS if the default one wasn't changed, `/etc/profile` doesn't
+/// set up nix there.
+const DARWIN_NIX_PROFILE: &str = "/nix/var/nix/profiles/default/etc/profile.d/nix-daemon.sh";
+
+/// Bandwidth from which sending whole files uncompressed is faster than computing deltas.
+const LAN_BANDWIDTH_MBIT: u64 = 300;
+
+/// First line of the local `rsync --version`, empty if rsync can't be run.
+pub fn local_rsync_version(rsync: &str) -> String {
+    Command::new(rsync)
+        .arg("--version")
+        .output()
+        .map(|output| {
+            String::from_utf8_lossy(&output.stdout)
+                .lines()
+                .next()
+                .unwrap_or_default()
+                .to_owned()
+        })
+        .unwrap_or_default()
+}
+
+/// Whether the rsync with this `--version` line understands `--info` (rsync 3.1 and newer).
+///
+/// Unknown versions are assumed to be recent.
+pub fn supports_info_flag(version_line: &str) -> bool {
+    if version_line.contains("openrsync") {
+        return false;
+    }
+    match rsync_version(version_line) {
+        Some((major, minor)) => (major, minor) >= (3, 1),
+        None => true,
+    }
+}
+
+/// Whether the rsync with this `--version` line understands `--partial-dir`.
+fn supports_partial_dir(version_line: &str) -> bool {
+    !version_line.contains("openrsync")
+}
+
+/// `(major, minor)` from `rsync  version 3.2.7  protocol version 31`.
+fn rsync_version(version_line: &str) -> Option<(u32, u32)> {
+    let mut words = version_line.split_whitespace();
+    words.find(|&word| word == "version")?;
+    let mut version = words.next()?.split('.');
+    let major = version.next()?.parse().ok()?;
+    let minor = version.next()?.parse().ok()?;
+    Some((major, minor))
+}
+
+/// The preferred compressor installed both locally and on the remote, for tar transfers.
+pub fn tar_compressor(facts: &RemoteFacts) -> Option<&'static str> {
+    COMPRESSORS.iter().copied().find(|compressor| {
+        facts.compressors.iter().any(|remote| remote == compressor)
+            && prerequisites::is_installed(compressor)
+    })
+}
+
+/// Adapts the settings of `remote` to the operating system and tools found on it.
+pub fn adapt(remote: &mut Remote, facts: &RemoteFacts) {
+    if facts.os.as_deref() == Some("Darwin") && remote.env == Remote::default().env {
+        info!(
+            "{} runs macOS, using {} as environment profile.",
+            facts.host, DARWIN_NIX_PROFILE
+        );
+        remote.env = DARWIN_NIX_PROFILE.to_owned();
+    }
+
+    if let (Link::Auto, Some(bandwidth)) = (remote.link, facts.bandwidth_mbit) {
+        remote.link = if bandwidth >= LAN_BANDWIDTH_MBIT {
+            Link::Lan
+        } else {
+            Link::Wan
+        };
+        info!(
+            "Measured {} Mbit/s to {}, transferring like on a {}.",
+            bandwidth,
+            facts.host,
+            remote.link.to_string().to_uppercase()
+        );
+    }
+
+    if facts.rsync_version.is_none()
+        && matches!(
+            remote.transfer,
+            Transfer::Rsync | Transfer::Rsyncd | Transfer::Git
+        )
+    {
+        warn!(
+            "rsync isn't installed on {}, transferring with tar instead. For incremental transfers {}.",
+            facts.host,
+            prerequisites::remote_install_hint("rsync", facts.os.as_deref())
+        );
+        remote.transfer = Transfer::Tar;
+    }
+
+    if let Some(rsync_version) = &facts.rsync_version {
+        if !supports_partial_dir(rsync_version) && !remote.partial_dir.is_empty() {
+            info!(
+                "The rsync on {} doesn't support resuming transfers ({}).",
+                facts.host, rsync_version
+            );
+            remote.partial_dir.clear();
+        }
+    }
+}
@@ -1,21 +1,510 @@
+use std::convert::TryFrom;
+
+use std::collections::BTreeMap;
+
 use serde::Deserialize;
 
+use crate::artifacts::Store;
+use crate::deploy;
+use crate::projects::Project;
+use crate::provision;
+use crate::requirement::Requirement;
+use crate::secrets::Reference;
+
 #[derive(Debug, Clone)]
 pub struct Remote {
     pub name: String,
     pub host: String,
+    /// All hosts of this remote in order of preference, `host` is the first one.
+    pub hosts: Vec<String>,
     pub ssh_port: u16,
     pub temp_dir: String,
     pub env: String,
+    pub copy_back_delete: bool,
+    /// Directory for partially transferred files so interrupted transfers can resume, empty
+    /// to disable.
+    pub partial_dir: String,
+    /// How often an interrupted transfer is resumed before giving up.
+    pub transfer_retries: u32,
+    /// umask used for everything created on the remote, e.g. "002".
+    pub umask: Option<String>,
+    /// Group owning the build directory so several users can share it.
+    pub group: Option<String>,
+    /// Compiler cache whose statistics are shown after each build.
+    pub compiler_cache: Option<CompilerCache>,
+    /// Whether all remotes share the local Cargo.lock or each one gets its own.
+    pub lockfile_strategy: LockfileStrategy,
+    /// Object storage the artifacts selected with `--copy-back` are uploaded to from the
+    /// remote instead, e.g. "s3://bucket/prefix" or "gs://bucket/prefix".
+    pub artifact_store: Option<String>,
+    /// Endpoint of an S3 compatible store like MinIO, e.g. "https://minio.example.com:9000".
+    pub artifact_store_endpoint: Option<String>,
+    /// ssh binary used for every connection.
+    pub ssh_path: String,
+    /// Command reaching the remote instead of ssh, the host and `sh -c <script>` are appended,
+    /// e.g. `["docker", "exec", "-i"]`. ssh is used if empty.
+    pub exec_command: Vec<String>,
+    /// Local rsync binary.
+    pub rsync_path_local: String,
+    /// rsync binary on the remote, e.g. if it is installed outside of the `PATH` there.
+    pub rsync_path_remote: String,
+    /// ssh `StrictHostKeyChecking` policy, the ssh config decides if unset.
+    pub strict_host_key_checking: Option<HostKeyChecking>,
+    /// known_hosts file used instead of the one of the user.
+    pub known_hosts: Option<String>,
+    /// Fingerprint the host key has to match before anything is transferred, e.g.
+    /// "SHA256:...".
+    pub host_key_fingerprint: Option<String>,
+    /// Forward the ssh agent to the build (`ssh -A`), e.g. for git dependencies on private
+    /// repositories. Anyone with root on the remote can use the agent while the build runs.
+    pub agent_forwarding: bool,
+    /// Local environment variables passed to the build, e.g. registry tokens. `*` matches any
+    /// number of characters.
+    pub forward_env: Vec<String>,
+    /// Environment variables of the build with values from the keyring or encrypted sources,
+    /// by name, e.g. `CARGO_REGISTRY_TOKEN = "keyring:crates-io"`.
+    pub secrets: BTreeMap<String, String>,
+    /// Kind of network connection to the remote, chooses the rsync flags.
+    pub link: Link,
+    /// Parallel jobs of cargo, like `-j`. Cargo uses all cores if unset.
+    pub jobs: Option<u32>,
+    /// Niceness the build runs with, from -20 to 19, e.g. 10 on shared servers.
+    pub nice: Option<i32>,
+    /// I/O scheduling class of the build, only applied where `ionice` is available (Linux).
+    pub ionice_class: Option<IoniceClass>,
+    /// Size from which uploaded files are reported or skipped, in rsync notation like "100M".
+    pub max_file_size: Option<String>,
+    /// What happens to files larger than `max_file_size`.
+    pub oversized_files: OversizedFiles,
+    /// How symlinks in transferred trees are handled.
+    pub symlinks: Symlinks,
+    /// Keep the permissions of transferred files, otherwise new files get the default permissions
+    /// of the receiving side.
+    pub preserve_permissions: bool,
+    /// Keep owner and group of transferred files, which only works if the receiving side runs
+    /// as root.
+    pub preserve_owner: bool,
+    /// Permissions applied to every transferred file with rsync `--chmod`, e.g. "u+rwX,go+rX".
+    pub chmod: Option<String>,
+    /// Versions of cargo plugins installed on the remote when a command needs them, by
+    /// subcommand, e.g. `nextest = "0.9.72"`. "*" installs the latest version.
+    pub tools: BTreeMap<String, String>,
+    /// Seconds `cargo fuzz run` runs for on the remote, unlimited if unset.
+    pub fuzz_duration: Option<u64>,
+    /// Local command flashing the firmware copied back with `--flash`, `{artifact}` is replaced
+    /// by its path.
+    pub flash_command: Option<String>,
+    /// Tool building the image of `--container` on the remote.
+    pub container_tool: ContainerTool,
+    /// Dockerfile of `--container`, relative to the project.
+    pub dockerfile: String,
+    /// Compare the checksums of files instead of their size and modification time in both
+    /// transfers, for file systems with unreliable mtimes.
+    pub checksum: bool,
+    /// Program transferring the files. Switched to tar automatically if rsync is missing locally
+    /// or on the remote.
+    pub transfer: Transfer,
+    /// Module of the rsync daemon used by the `rsyncd` transfer, its path has to be the
+    /// `temp_dir`.
+    pub rsyncd_module: Option<String>,
+    /// Port of the rsync daemon.
+    pub rsyncd_port: u16,
+    /// User authenticating with the rsync daemon, the module is accessed anonymously if unset.
+    pub rsyncd_user: Option<String>,
+    /// Local file with the password of `rsyncd_user`, only readable by its owner. rsync also
+    /// reads it from `RSYNC_PASSWORD`.
+    pub rsyncd_password_file: Option<String>,
+    /// Shell of the remote: `sh`, or PowerShell on Windows build servers with OpenSSH, which
+    /// transfer with tar and build without nix.
+    pub shell: Shell,
+    /// Account everything in the build directory runs as instead of the ssh user, e.g. a
+    /// dedicated low-privilege build user, switched to with `sudo_command`.
+    pub remote_user: Option<String>,
+    /// Switch to root with `sudo_command` if no `remote_user` is set, e.g. for builds
+    /// installing system dependencies.
+    pub use_sudo: bool,
+    /// Command prefix switching to the build user on the remote, `{user}` is replaced by it.
+    pub sudo_command: String,
+    /// Remote `CARGO_TARGET_DIR` shared by all projects built on this remote, so dependencies
+    /// they have in common are only built once. Builds using it wait for each other.
+    pub shared_target_dir: Option<String>,
+    /// Shell globs relative to the remote target dir of the artifacts packed by
+    /// `--package-artifacts`, e.g. "release/myapp".
+    pub package_artifacts: Vec<String>,
+    pub package_format: PackageFormat,
+    /// Flags passed to every rustc invocation of the build, like `build.rustflags` of cargo.
+    pub rustflags: Vec<String>,
+    /// Linker of the host target of the remote, e.g. "clang" to link with mold.
+    pub linker: Option<String>,
+    /// Settings of specific target triples, like the `[target.<triple>]` of cargo.
+    pub target: BTreeMap<String, TargetConfig>,
+    /// Build with the dependencies vendored locally, for remotes without access to crates.io.
+    pub vendor: bool,
+    /// Address the other remotes reach this one at with `--distribute`, the host by default.
+    pub dist_address: Option<String>,
+}
+
+/// Build settings of one target triple of a remote, from `[remote.target.<triple>]`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct TargetConfig {
+    pub linker: Option<String>,
+    pub rustflags: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum CompilerCache {
+    Sccache,
+    Ccache,
+}
+
+impl std::fmt::Display for CompilerCache {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompilerCache::Sccache => write!(f, "sccache"),
+            CompilerCache::Ccache => write!(f, "ccache"),
+        }
+    }
+}
+
+/// How the Cargo.lock is kept in sync between the local project and a remote.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum LockfileStrategy {
+    /// The local Cargo.lock is uploaded and replaced with the remote one after the build.
+    Shared,
+    /// A separate `Cargo.lock.<remote>` is kept locally and used in place of the Cargo.lock,
+    /// e.g. for remotes using a different registry.
+    PerRemote,
+}
+
+impl std::fmt::Display for LockfileStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LockfileStrategy::Shared => write!(f, "shared"),
+            LockfileStrategy::PerRemote => write!(f, "per-remote"),
+        }
+    }
+}
+
+/// Network connection to a remote, see [`Remote::link`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Link {
+    /// Decided by the bandwidth measured when probing the remote.
+    Auto,
+    /// Fast local network: whole files are sent uncompressed, computing deltas costs more time
+    /// than it saves.
+    Lan,
+    /// Slow or metered connection: only deltas are sent, compressed.
+    Wan,
+}
+
+impl std::fmt::Display for Link {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Link::Auto => write!(f, "auto"),
+            Link::Lan => write!(f, "lan"),
+            Link::Wan => write!(f, "wan"),
+        }
+    }
+}
+
+/// Handling of uploaded files larger than [`Remote::max_file_size`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OversizedFiles {
+    /// Upload them anyway but list them.
+    Warn,
+    /// Leave them out of the upload (rsync `--max-size`) and list them.
+    Skip,
+}
+
+impl std::fmt::Display for OversizedFiles {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OversizedFiles::Warn => write!(f, "warn"),
+            OversizedFiles::Skip => write!(f, "skip"),
+        }
+    }
+}
+
+/// Whether rsync understands `size` as a file size, e.g. "500K", "100M" or "1.5GiB".
+/// Seconds given as a number or as a duration like "30m" or "1h30m".
+fn seconds<'de, D>(deserializer: D) -> Result<Option<u64>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Seconds {
+        Number(u64),
+        Duration(String),
+    }
+    match Option::<Seconds>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(Seconds::Number(secs)) => Ok(Some(secs)),
+        Some(Seconds::Duration(duration)) => crate::timeout::parse_duration(&duration)
+            .map(|duration| Some(duration.as_secs()))
+            .map_err(|e| {
+                serde::de::Error::custom(format!("invalid duration {:?}: {}", duration, e))
+            }),
+    }
+}
+
+fn is_size(size: &str) -> bool {
+    let number_end = size
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(size.len());
+    let (number, unit) = size.split_at(number_end);
+    number.parse::<f64>().is_ok()
+        && matches!(
+            unit.to_ascii_uppercase().as_str(),
+            "" | "B"
+                | "K"
+                | "M"
+                | "G"
+                | "T"
+                | "P"
+                | "KB"
+                | "MB"
+                | "GB"
+                | "TB"
+                | "PB"
+                | "KIB"
+                | "MIB"
+                | "GIB"
+                | "TIB"
+                | "PIB"
+        )
+}
+
+/// Handling of symlinks by rsync.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Symlinks {
+    /// Transfer symlinks as symlinks (`--links`).
+    Preserve,
+    /// Transfer the files and directories they point to instead (`--copy-links`).
+    Copy,
+    /// Only replace symlinks pointing outside of the transferred tree, which would dangle on
+    /// the other side (`--copy-unsafe-links`).
+    CopyUnsafe,
+}
+
+impl std::fmt::Display for Symlinks {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Symlinks::Preserve => write!(f, "preserve"),
+            Symlinks::Copy => write!(f, "copy"),
+            Symlinks::CopyUnsafe => write!(f, "copy-unsafe"),
+        }
+    }
+}
+
+/// Tool building container images on the remote.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ContainerTool {
+    Docker,
+    Podman,
+    Buildah,
+}
+
+impl std::fmt::Display for ContainerTool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ContainerTool::Docker => write!(f, "docker"),
+            ContainerTool::Podman => write!(f, "podman"),
+            ContainerTool::Buildah => write!(f, "buildah"),
+        }
+    }
+}
+
+/// Program transferring the files, see [`Remote::transfer`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Transfer {
+    /// Incremental transfers with rsync.
+    Rsync,
+    /// Whole trees as tar archives streamed through ssh, for hosts without rsync.
+    Tar,
+    /// The current commit pushed with git and only the uncommitted changes with rsync, for
+    /// large repositories.
+    Git,
+    /// Incremental transfers with the rsync daemon of the remote instead of rsync over ssh,
+    /// the build still runs over ssh.
+    Rsyncd,
+    /// Every file put with sftp, for hosts without rsync.
+    Sftp,
+}
+
+impl std::fmt::Display for Transfer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Transfer::Rsync => write!(f, "rsync"),
+            Transfer::Tar => write!(f, "tar"),
+            Transfer::Git => write!(f, "git"),
+            Transfer::Rsyncd => write!(f, "rsyncd"),
+            Transfer::Sftp => write!(f, "sftp"),
+        }
+    }
+}
+
+/// Shell the commands on the remote are written for, see [`Remote::shell`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Shell {
+    /// A POSIX shell with nix, on Linux, macOS and the BSDs.
+    Sh,
+    /// Windows PowerShell, started by the default shell of OpenSSH (cmd.exe).
+    PowerShell,
+}
+
+impl std::fmt::Display for Shell {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Shell::Sh => write!(f, "sh"),
+            Shell::PowerShell => write!(f, "powershell"),
+        }
+    }
+}
+
+/// Archive format of `--package-artifacts`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum PackageFormat {
+    #[serde(rename = "tar.zst")]
+    TarZst,
+    #[serde(rename = "tar.gz")]
+    TarGz,
+    #[serde(rename = "zip")]
+    Zip,
+}
+
+impl std::fmt::Display for PackageFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PackageFormat::TarZst => write!(f, "tar.zst"),
+            PackageFormat::TarGz => write!(f, "tar.gz"),
+            PackageFormat::Zip => write!(f, "zip"),
+        }
+    }
+}
+
+/// I/O scheduling class of the build, see ionice(1).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum IoniceClass {
+    /// Only gets disk time when no other program needs it.
+    Idle,
+    /// The default class of every process.
+    BestEffort,
+}
+
+impl IoniceClass {
+    /// Number of the class for `ionice -c`.
+    pub fn number(self) -> u8 {
+        match self {
+            IoniceClass::BestEffort => 2,
+            IoniceClass::Idle => 3,
+        }
+    }
+}
+
+impl std::fmt::Display for IoniceClass {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            IoniceClass::Idle => write!(f, "idle"),
+            IoniceClass::BestEffort => write!(f, "best-effort"),
+        }
+    }
+}
+
+/// Whether ssh accepts unknown or changed host keys, see `StrictHostKeyChecking` in
+/// ssh_config(5).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum HostKeyChecking {
+    /// Only connect to hosts whose key is already known.
+    Yes,
+    /// Add keys of new hosts, refuse changed keys.
+    AcceptNew,
+    /// Connect to any host.
+    No,
+}
+
+impl std::fmt::Display for HostKeyChecking {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            HostKeyChecking::Yes => write!(f, "yes"),
+            HostKeyChecking::AcceptNew => write!(f, "accept-new"),
+            HostKeyChecking::No => write!(f, "no"),
+        }
+    }
 }
 
 #[derive(Debug, Deserialize)]
 struct PartialRemote {
     pub name: Option<String>,
-    pub host: String,
+    pub host: Option<String>,
+    pub hosts: Option<Vec<String>>,
     pub ssh_port: Option<u16>,
     pub temp_dir: Option<String>,
     pub env: Option<String>,
+    pub copy_back_delete: Option<bool>,
+    pub partial_dir: Option<String>,
+    pub transfer_retries: Option<u32>,
+    pub umask: Option<String>,
+    pub group: Option<String>,
+    pub compiler_cache: Option<CompilerCache>,
+    pub lockfile_strategy: Option<LockfileStrategy>,
+    pub artifact_store: Option<String>,
+    pub artifact_store_endpoint: Option<String>,
+    pub ssh_path: Option<String>,
+    pub exec_command: Option<Vec<String>>,
+    pub rsync_path_local: Option<String>,
+    pub rsync_path_remote: Option<String>,
+    pub strict_host_key_checking: Option<HostKeyChecking>,
+    pub known_hosts: Option<String>,
+    pub host_key_fingerprint: Option<String>,
+    pub agent_forwarding: Option<bool>,
+    pub forward_env: Option<Vec<String>>,
+    pub secrets: Option<BTreeMap<String, String>>,
+    pub link: Option<Link>,
+    pub jobs: Option<u32>,
+    pub nice: Option<i32>,
+    pub ionice_class: Option<IoniceClass>,
+    pub max_file_size: Option<String>,
+    pub oversized_files: Option<OversizedFiles>,
+    pub symlinks: Option<Symlinks>,
+    pub preserve_permissions: Option<bool>,
+    pub preserve_owner: Option<bool>,
+    pub chmod: Option<String>,
+    pub tools: Option<BTreeMap<String, String>>,
+    #[serde(default, deserialize_with = "seconds")]
+    pub fuzz_duration: Option<u64>,
+    pub flash_command: Option<String>,
+    pub container_tool: Option<ContainerTool>,
+    pub dockerfile: Option<String>,
+    pub checksum: Option<bool>,
+    pub transfer: Option<Transfer>,
+    pub rsyncd_module: Option<String>,
+    pub rsyncd_port: Option<u16>,
+    pub rsyncd_user: Option<String>,
+    pub rsyncd_password_file: Option<String>,
+    pub shell: Option<Shell>,
+    pub remote_user: Option<String>,
+    pub use_sudo: Option<bool>,
+    pub sudo_command: Option<String>,
+    pub shared_target_dir: Option<String>,
+    pub package_artifacts: Option<Vec<String>>,
+    pub package_format: Option<PackageFormat>,
+    pub rustflags: Option<Vec<String>>,
+    pub linker: Option<String>,
+    pub target: Option<BTreeMap<String, TargetConfig>>,
+    pub vendor: Option<bool>,
+    pub dist_address: Option<String>,
 }
 
 impl Default for Remote {
@@ -23,27 +512,461 @@ impl Default for Remote {
         Self {
             name: String::new(),
             host: String::new(),
+            hosts: Vec::new(),
             ssh_port: 22,
             temp_dir: "~/remote-builds".to_string(),
             env: "/etc/profile".to_string(),
+            copy_back_delete: true,
+            partial_dir: ".rsync-partial".to_string(),
+            transfer_retries: 3,
+            umask: None,
+            group: None,
+            compiler_cache: None,
+            lockfile_strategy: LockfileStrategy::Shared,
+            artifact_store: None,
+            artifact_store_endpoint: None,
+            ssh_path: "ssh".to_string(),
+            exec_command: Vec::new(),
+            rsync_path_local: "rsync".to_string(),
+            rsync_path_remote: "rsync".to_string(),
+            strict_host_key_checking: None,
+            known_hosts: None,
+            host_key_fingerprint: None,
+            agent_forwarding: false,
+            forward_env: Vec::new(),
+            secrets: BTreeMap::new(),
+            link: Link::Auto,
+            jobs: None,
+            nice: None,
+            ionice_class: None,
+            max_file_size: None,
+            oversized_files: OversizedFiles::Warn,
+            symlinks: Symlinks::Preserve,
+            preserve_permissions: true,
+            preserve_owner: true,
+            chmod: None,
+            tools: BTreeMap::new(),
+            fuzz_duration: None,
+            flash_command: None,
+            container_tool: ContainerTool::Docker,
+            dockerfile: "Dockerfile".to_owned(),
+            checksum: false,
+            transfer: Transfer::Rsync,
+            rsyncd_module: None,
+            rsyncd_port: 873,
+            rsyncd_user: None,
+            rsyncd_password_file: None,
+            shell: Shell::Sh,
+            remote_user: None,
+            use_sudo: false,
+            sudo_command: "sudo -u {user} -H".to_owned(),
+            shared_target_dir: None,
+            package_artifacts: Vec::new(),
+            package_format: PackageFormat::TarZst,
+            rustflags: Vec::new(),
+            linker: None,
+            target: BTreeMap::new(),
+            vendor: false,
+            dist_address: None,
+        }
+    }
+}
+
+impl std::fmt::Display for Remote {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "[[remote]]")?;
+        writeln!(f, "name = {:?}", self.name)?;
+        if self.hosts.len() > 1 {
+            writeln!(f, "hosts = {:?}", self.hosts)?;
+        } else {
+            writeln!(f, "host = {:?}", self.host)?;
+        }
+        writeln!(f, "ssh_port = {}", self.ssh_port)?;
+        writeln!(f, "temp_dir = {:?}", self.temp_dir)?;
+        writeln!(f, "env = {:?}", self.env)?;
+        writeln!(f, "copy_back_delete = {}", self.copy_back_delete)?;
+        writeln!(f, "partial_dir = {:?}", self.partial_dir)?;
+        writeln!(f, "transfer_retries = {}", self.transfer_retries)?;
+        if let Some(umask) = &self.umask {
+            writeln!(f, "umask = {:?}", umask)?;
+        }
+        if let Some(group) = &self.group {
+            writeln!(f, "group = {:?}", group)?;
+        }
+        if let Some(compiler_cache) = &self.compiler_cache {
+            writeln!(f, "compiler_cache = \"{}\"", compiler_cache)?;
+        }
+        writeln!(f, "lockfile_strategy = \"{}\"", self.lockfile_strategy)?;
+        if let Some(artifact_store) = &self.artifact_store {
+            writeln!(f, "artifact_store = {:?}", artifact_store)?;
+        }
+        if let Some(artifact_store_endpoint) = &self.artifact_store_endpoint {
+            writeln!(f, "artifact_store_endpoint = {:?}", artifact_store_endpoint)?;
+        }
+        writeln!(f, "ssh_path = {:?}", self.ssh_path)?;
+        if !self.exec_command.is_empty() {
+            writeln!(f, "exec_command = {:?}", self.exec_command)?;
+        }
+        writeln!(f, "rsync_path_local = {:?}", self.rsync_path_local)?;
+        writeln!(f, "rsync_path_remote = {:?}", self.rsync_path_remote)?;
+        writeln!(f, "link = \"{}\"", self.link)?;
+        if !self.forward_env.is_empty() {
+            writeln!(f, "forward_env = {:?}", self.forward_env)?;
+        }
+        if !self.secrets.is_empty() {
+            let secrets: Vec<String> = self
+                .secrets
+                .iter()
+                .map(|(name, reference)| format!("{} = {:?}", name, reference))
+                .collect();
+            writeln!(f, "secrets = {{ {} }}", secrets.join(", "))?;
+        }
+        if let Some(strict_host_key_checking) = &self.strict_host_key_checking {
+            writeln!(
+                f,
+                "strict_host_key_checking = \"{}\"",
+                strict_host_key_checking
+            )?;
+        }
+        if let Some(known_hosts) = &self.known_hosts {
+            writeln!(f, "known_hosts = {:?}", known_hosts)?;
+        }
+        if let Some(host_key_fingerprint) = &self.host_key_fingerprint {
+            writeln!(f, "host_key_fingerprint = {:?}", host_key_fingerprint)?;
+        }
+        writeln!(f, "agent_forwarding = {}", self.agent_forwarding)?;
+        if let Some(jobs) = &self.jobs {
+            writeln!(f, "jobs = {}", jobs)?;
+        }
+        if let Some(nice) = &self.nice {
+            writeln!(f, "nice = {}", nice)?;
+        }
+        if let Some(ionice_class) = &self.ionice_class {
+            writeln!(f, "ionice_class = \"{}\"", ionice_class)?;
+        }
+        if let Some(max_file_size) = &self.max_file_size {
+            writeln!(f, "max_file_size = {:?}", max_file_size)?;
+        }
+        writeln!(f, "oversized_files = \"{}\"", self.oversized_files)?;
+        writeln!(f, "preserve_permissions = {}", self.preserve_permissions)?;
+        writeln!(f, "preserve_owner = {}", self.preserve_owner)?;
+        if let Some(chmod) = &self.chmod {
+            writeln!(f, "chmod = {:?}", chmod)?;
+        }
+        writeln!(f, "symlinks = \"{}\"", self.symlinks)?;
+        if !self.tools.is_empty() {
+            let tools: Vec<String> = self
+                .tools
+                .iter()
+                .map(|(tool, version)| format!("{} = {:?}", tool, version))
+                .collect();
+            writeln!(f, "tools = {{ {} }}", tools.join(", "))?;
+        }
+        if let Some(fuzz_duration) = &self.fuzz_duration {
+            writeln!(f, "fuzz_duration = {}", fuzz_duration)?;
+        }
+        if let Some(flash_command) = &self.flash_command {
+            writeln!(f, "flash_command = {:?}", flash_command)?;
         }
+        writeln!(f, "container_tool = \"{}\"", self.container_tool)?;
+        writeln!(f, "dockerfile = {:?}", self.dockerfile)?;
+        writeln!(f, "checksum = {}", self.checksum)?;
+        writeln!(f, "transfer = \"{}\"", self.transfer)?;
+        if let Some(rsyncd_module) = &self.rsyncd_module {
+            writeln!(f, "rsyncd_module = {:?}", rsyncd_module)?;
+            writeln!(f, "rsyncd_port = {}", self.rsyncd_port)?;
+        }
+        if let Some(rsyncd_user) = &self.rsyncd_user {
+            writeln!(f, "rsyncd_user = {:?}", rsyncd_user)?;
+        }
+        if let Some(rsyncd_password_file) = &self.rsyncd_password_file {
+            writeln!(f, "rsyncd_password_file = {:?}", rsyncd_password_file)?;
+        }
+        writeln!(f, "shell = \"{}\"", self.shell)?;
+        if let Some(remote_user) = &self.remote_user {
+            writeln!(f, "remote_user = {:?}", remote_user)?;
+        }
+        writeln!(f, "use_sudo = {}", self.use_sudo)?;
+        writeln!(f, "sudo_command = {:?}", self.sudo_command)?;
+        if let Some(shared_target_dir) = &self.shared_target_dir {
+            writeln!(f, "shared_target_dir = {:?}", shared_target_dir)?;
+        }
+        if !self.package_artifacts.is_empty() {
+            writeln!(f, "package_artifacts = {:?}", self.package_artifacts)?;
+        }
+        writeln!(f, "package_format = \"{}\"", self.package_format)?;
+        if !self.rustflags.is_empty() {
+            writeln!(f, "rustflags = {:?}", self.rustflags)?;
+        }
+        if let Some(linker) = &self.linker {
+            writeln!(f, "linker = {:?}", linker)?;
+        }
+        if !self.target.is_empty() {
+            let targets: Vec<String> = self
+                .target
+                .iter()
+                .map(|(triple, target)| {
+                    let linker = match &target.linker {
+                        Some(linker) => format!("linker = {:?}, ", linker),
+                        None => String::new(),
+                    };
+                    format!(
+                        "{:?} = {{ {}rustflags = {:?} }}",
+                        triple, linker, target.rustflags
+                    )
+                })
+                .collect();
+            writeln!(f, "target = {{ {} }}", targets.join(", "))?;
+        }
+        writeln!(f, "vendor = {}", self.vendor)?;
+        if let Some(dist_address) = &self.dist_address {
+            writeln!(f, "dist_address = {:?}", dist_address)?;
+        }
+        Ok(())
     }
 }
 
-impl From<PartialRemote> for Remote {
-    fn from(minimal_remote: PartialRemote) -> Self {
+impl TryFrom<PartialRemote> for Remote {
+    type Error = &'static str;
+
+    fn try_from(minimal_remote: PartialRemote) -> Result<Self, Self::Error> {
         let default = Remote::default();
+        let hosts = match (minimal_remote.host, minimal_remote.hosts) {
+            (Some(_), Some(_)) => return Err("only one of `host` and `hosts` may be set"),
+            (Some(host), None) => vec![host],
+            (None, Some(hosts)) if !hosts.is_empty() => hosts,
+            _ => return Err("missing field `host`"),
+        };
         let name = minimal_remote.name.unwrap_or(default.name);
         let ssh_port = minimal_remote.ssh_port.unwrap_or(default.ssh_port);
+        let shell = minimal_remote.shell.unwrap_or(default.shell);
         let temp_dir = minimal_remote.temp_dir.unwrap_or(default.temp_dir);
+        let temp_dir = match shell {
+            // `C:\builds` works as `C:/builds` everywhere on Windows
+            Shell::PowerShell => temp_dir.replace('\\', "/"),
+            Shell::Sh => temp_dir,
+        };
         let env = minimal_remote.env.unwrap_or(default.env);
-        Remote {
+        let copy_back_delete = minimal_remote
+            .copy_back_delete
+            .unwrap_or(default.copy_back_delete);
+        let partial_dir = minimal_remote.partial_dir.unwrap_or(default.partial_dir);
+        let transfer_retries = minimal_remote
+            .transfer_retries
+            .unwrap_or(default.transfer_retries);
+        if let Some(artifact_store) = &minimal_remote.artifact_store {
+            Store::parse(
+                artifact_store,
+                minimal_remote.artifact_store_endpoint.as_deref(),
+            )?;
+        }
+        if let Some(nice) = minimal_remote.nice {
+            if !(-20..=19).contains(&nice) {
+                return Err("`nice` must be between -20 and 19");
+            }
+        }
+        if let Some(max_file_size) = &minimal_remote.max_file_size {
+            if !is_size(max_file_size) {
+                return Err("`max_file_size` must be a size like \"100M\" or \"1.5G\"");
+            }
+        }
+        if shell == Shell::PowerShell {
+            if minimal_remote
+                .transfer
+                .is_some_and(|transfer| transfer != Transfer::Tar)
+            {
+                return Err("`shell = \"powershell\"` only works with `transfer = \"tar\"`");
+            }
+            if minimal_remote.remote_user.is_some()
+                || minimal_remote.use_sudo == Some(true)
+                || minimal_remote.group.is_some()
+            {
+                return Err(
+                    "`remote_user`, `use_sudo` and `group` don't work with `shell = \"powershell\"`",
+                );
+            }
+            if minimal_remote.lockfile_strategy == Some(LockfileStrategy::PerRemote) {
+                return Err(
+                    "`lockfile_strategy = \"per-remote\"` doesn't work with `shell = \"powershell\"`",
+                );
+            }
+        }
+        if minimal_remote
+            .exec_command
+            .as_ref()
+            .is_some_and(|exec_command| !exec_command.is_empty())
+        {
+            if minimal_remote.transfer != Some(Transfer::Tar) {
+                return Err("`exec_command` only works with `transfer = \"tar\"`");
+            }
+            if minimal_remote.host_key_fingerprint.is_some()
+                || minimal_remote.agent_forwarding == Some(true)
+            {
+                return Err(
+                    "`host_key_fingerprint` and `agent_forwarding` need ssh, they don't work with `exec_command`",
+                );
+            }
+        }
+        if minimal_remote.transfer == Some(Transfer::Sftp)
+            && (minimal_remote.remote_user.is_some() || minimal_remote.use_sudo == Some(true))
+        {
+            return Err(
+                "`transfer = \"sftp\"` puts the files as the ssh user, it doesn't work with `remote_user` and `use_sudo`",
+            );
+        }
+        if minimal_remote.transfer == Some(Transfer::Rsyncd)
+            && minimal_remote.rsyncd_module.is_none()
+        {
+            return Err("`transfer = \"rsyncd\"` needs the `rsyncd_module` to transfer to");
+        }
+        if (minimal_remote.remote_user.is_some() || minimal_remote.use_sudo == Some(true))
+            && temp_dir.starts_with('~')
+        {
+            return Err(
+                "`remote_user` and `use_sudo` need an absolute `temp_dir`, `~` would be the home of the ssh user",
+            );
+        }
+        if let Some(secrets) = &minimal_remote.secrets {
+            if secrets
+                .keys()
+                .any(|name| name.is_empty() || name.contains('='))
+            {
+                return Err("the names in `secrets` must be environment variable names");
+            }
+            if secrets
+                .values()
+                .any(|value| Reference::parse(value).is_none())
+            {
+                return Err(
+                    "`secrets` must reference the keyring (\"keyring:<name>\"), age (\"age:<ciphertext>\") or sops (\"sops:<file>#<key>\"), plaintext values leak with the config file",
+                );
+            }
+        }
+        if let Some(package_artifacts) = &minimal_remote.package_artifacts {
+            let is_glob = |pattern: &String| {
+                !pattern.is_empty()
+                    && !pattern.starts_with('/')
+                    && pattern
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || "-_./*?[]+@%,=".contains(c))
+            };
+            if !package_artifacts.iter().all(is_glob) {
+                return Err(
+                    "`package_artifacts` must be globs relative to the target dir without spaces or quotes",
+                );
+            }
+        }
+        let flags_are_words = |flags: &Vec<String>| {
+            flags
+                .iter()
+                .all(|flag| !flag.is_empty() && !flag.contains(char::is_whitespace))
+        };
+        if !minimal_remote
+            .rustflags
+            .iter()
+            .chain(
+                minimal_remote
+                    .target
+                    .iter()
+                    .flat_map(|t| t.values().map(|t| &t.rustflags)),
+            )
+            .all(flags_are_words)
+        {
+            return Err("`rustflags` must be single flags without whitespace, like `[\"-C\", \"target-cpu=native\"]`");
+        }
+        if let Some(target) = &minimal_remote.target {
+            if !target.keys().all(|triple| {
+                !triple.is_empty()
+                    && triple
+                        .chars()
+                        .all(|c| c.is_ascii_alphanumeric() || "-_.".contains(c))
+            }) {
+                return Err("`target` tables must be named after target triples, `cfg(...)` expressions aren't supported");
+            }
+        }
+        Ok(Remote {
             name,
-            host: minimal_remote.host,
+            host: hosts[0].clone(),
+            hosts,
             ssh_port,
             temp_dir,
             env,
-        }
+            copy_back_delete,
+            partial_dir,
+            transfer_retries,
+            umask: minimal_remote.umask,
+            group: minimal_remote.group,
+            compiler_cache: minimal_remote.compiler_cache,
+            lockfile_strategy: minimal_remote
+                .lockfile_strategy
+                .unwrap_or(default.lockfile_strategy),
+            artifact_store: minimal_remote.artifact_store,
+            artifact_store_endpoint: minimal_remote.artifact_store_endpoint,
+            ssh_path: minimal_remote.ssh_path.unwrap_or(default.ssh_path),
+            exec_command: minimal_remote.exec_command.unwrap_or(default.exec_command),
+            rsync_path_local: minimal_remote
+                .rsync_path_local
+                .unwrap_or(default.rsync_path_local),
+            rsync_path_remote: minimal_remote
+                .rsync_path_remote
+                .unwrap_or(default.rsync_path_remote),
+            strict_host_key_checking: minimal_remote.strict_host_key_checking,
+            known_hosts: minimal_remote.known_hosts,
+            host_key_fingerprint: minimal_remote.host_key_fingerprint,
+            agent_forwarding: minimal_remote
+                .agent_forwarding
+                .unwrap_or(default.agent_forwarding),
+            forward_env: minimal_remote.forward_env.unwrap_or(default.forward_env),
+            secrets: minimal_remote.secrets.unwrap_or(default.secrets),
+            link: minimal_remote.link.unwrap_or(default.link),
+            jobs: minimal_remote.jobs,
+            nice: minimal_remote.nice,
+            ionice_class: minimal_remote.ionice_class,
+            max_file_size: minimal_remote.max_file_size,
+            oversized_files: minimal_remote
+                .oversized_files
+                .unwrap_or(default.oversized_files),
+            symlinks: minimal_remote.symlinks.unwrap_or(default.symlinks),
+            preserve_permissions: minimal_remote
+                .preserve_permissions
+                .unwrap_or(default.preserve_permissions),
+            preserve_owner: minimal_remote
+                .preserve_owner
+                .unwrap_or(default.preserve_owner),
+            chmod: minimal_remote.chmod,
+            tools: minimal_remote.tools.unwrap_or(default.tools),
+            fuzz_duration: minimal_remote.fuzz_duration,
+            flash_command: minimal_remote.flash_command,
+            container_tool: minimal_remote
+                .container_tool
+                .unwrap_or(default.container_tool),
+            dockerfile: minimal_remote.dockerfile.unwrap_or(default.dockerfile),
+            checksum: minimal_remote.checksum.unwrap_or(default.checksum),
+            transfer: minimal_remote.transfer.unwrap_or(match shell {
+                Shell::PowerShell => Transfer::Tar,
+                Shell::Sh => default.transfer,
+            }),
+            rsyncd_module: minimal_remote.rsyncd_module,
+            rsyncd_port: minimal_remote.rsyncd_port.unwrap_or(default.rsyncd_port),
+            rsyncd_user: minimal_remote.rsyncd_user,
+            rsyncd_password_file: minimal_remote.rsyncd_password_file,
+            shell,
+            remote_user: minimal_remote.remote_user,
+            use_sudo: minimal_remote.use_sudo.unwrap_or(default.use_sudo),
+            sudo_command: minimal_remote.sudo_command.unwrap_or(default.sudo_command),
+            shared_target_dir: minimal_remote.shared_target_dir,
+            package_artifacts: minimal_remote
+                .package_artifacts
+                .unwrap_or(default.package_artifacts),
+            package_format: minimal_remote
+                .package_format
+                .unwrap_or(default.package_format),
+            rustflags: minimal_remote.rustflags.unwrap_or(default.rustflags),
+            linker: minimal_remote.linker,
+            target: minimal_remote.target.unwrap_or(default.target),
+            vendor: minimal_remote.vendor.unwrap_or(default.vendor),
+            dist_address: minimal_remote.dist_address,
+        })
     }
 }
 
@@ -52,7 +975,219 @@ impl<'de> Deserialize<'de> for Remote {
     where
         D: serde::Deserializer<'de>,
     {
-        PartialRemote::deserialize(deserializer).map(Self::from)
+        let partial = PartialRemote::deserialize(deserializer)?;
+        Self::try_from(partial).map_err(serde::de::Error::custom)
+    }
+}
+
+/// Keys allowed at the top level of a config file.
+pub const CONFIG_KEYS: &[&str] = &[
+    "remote",
+    "exclude",
+    "projects",
+    "alias",
+    "profiles",
+    "deploy",
+    "provision",
+    "required_cargo",
+    "required_rustc",
+];
+
+/// Keys allowed in a `[[remote]]` entry.
+pub const REMOTE_KEYS: &[&str] = &[
+    "name",
+    "host",
+    "hosts",
+    "ssh_port",
+    "temp_dir",
+    "env",
+    "copy_back_delete",
+    "partial_dir",
+    "transfer_retries",
+    "umask",
+    "group",
+    "compiler_cache",
+    "lockfile_strategy",
+    "artifact_store",
+    "artifact_store_endpoint",
+    "ssh_path",
+    "exec_command",
+    "rsync_path_local",
+    "rsync_path_remote",
+    "strict_host_key_checking",
+    "known_hosts",
+    "host_key_fingerprint",
+    "agent_forwarding",
+    "forward_env",
+    "secrets",
+    "link",
+    "jobs",
+    "nice",
+    "ionice_class",
+    "max_file_size",
+    "oversized_files",
+    "symlinks",
+    "preserve_permissions",
+    "preserve_owner",
+    "chmod",
+    "tools",
+    "fuzz_duration",
+    "flash_command",
+    "container_tool",
+    "dockerfile",
+    "checksum",
+    "transfer",
+    "rsyncd_module",
+    "rsyncd_port",
+    "rsyncd_user",
+    "rsyncd_password_file",
+    "shell",
+    "remote_user",
+    "use_sudo",
+    "sudo_command",
+    "shared_target_dir",
+    "package_artifacts",
+    "package_format",
+    "rustflags",
+    "linker",
+    "target",
+    "vendor",
+    "dist_address",
+];
+
+/// Keys allowed in a `[projects.<name>]` entry.
+pub const PROJECT_KEYS: &[&str] = &["path", "depends_on"];
+
+/// Keys allowed in a `[deploy.<name>]` entry.
+pub const DEPLOY_KEYS: &[&str] = &["host", "ssh_port", "path", "artifacts", "post_command"];
+
+/// Keys allowed in a `[profiles.<name>]` entry.
+pub const PROFILE_KEYS: &[&str] = &["remote", "cargo_flags", "copy_back", "env"];
+
+/// Directories and files that are almost never needed for a build, excluded from the upload
+/// unless the `exclude` list is set in the config.
+pub const DEFAULT_EXCLUDES: &[&str] = &[
+    "node_modules",
+    ".venv",
+    "venv",
+    ".idea",
+    ".vscode",
+    "__pycache__",
+    "*.pyc",
+    "*.o",
+    ".DS_Store",
+];
+
+/// Short aliases cargo itself knows, expanded when no alias with the same name is configured.
+pub const BUILTIN_ALIASES: &[(&str, &str)] =
+    &[("b", "build"), ("r", "run"), ("t", "test"), ("c", "check")];
+
+/// Command line an alias in the `[alias]` section expands to, either split at whitespace or
+/// given as a list of arguments, like the aliases of cargo.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum Alias {
+    Line(String),
+    Args(Vec<String>),
+}
+
+impl Alias {
+    fn args(&self) -> Vec<String> {
+        match self {
+            Alias::Line(line) => line.split_whitespace().map(str::to_owned).collect(),
+            Alias::Args(args) => args.clone(),
+        }
+    }
+}
+
+/// Artifacts a profile copies back: the whole target folder (`true`), nothing (`false`) or a
+/// specific file or folder from it.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(untagged)]
+pub enum CopyBack {
+    Enabled(bool),
+    Path(String),
+}
+
+impl CopyBack {
+    /// The value `--copy-back` would have for this setting.
+    pub fn as_flag(&self) -> Option<Option<String>> {
+        match self {
+            CopyBack::Enabled(true) => Some(None),
+            CopyBack::Enabled(false) => None,
+            CopyBack::Path(path) => Some(Some(path.clone())),
+        }
+    }
+}
+
+/// A named set of defaults in the `[profiles]` section, selected with `--profile`, e.g. to
+/// build with different setups during development and in CI.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct Profile {
+    /// Name of the remote to build on, unless one is chosen with `--remote`.
+    pub remote: Option<String>,
+    /// Arguments appended to every cargo command (before a `--`).
+    pub cargo_flags: Vec<String>,
+    /// Artifacts to copy back, unless `--copy-back` is given.
+    pub copy_back: Option<CopyBack>,
+    /// Environment variables set for the cargo commands on the remote.
+    pub env: BTreeMap<String, String>,
+}
+
+/// A remote given as `ssh://[user@]host[:port][/temp_dir]` on the command line, e.g.
+/// `ssh://user@builder:2222//srv/builds` for the absolute temp dir `/srv/builds`.
+#[derive(Debug)]
+pub struct RemoteUri {
+    pub host: String,
+    pub ssh_port: Option<u16>,
+    pub temp_dir: Option<String>,
+}
+
+impl RemoteUri {
+    /// The parts of `value`, `None` if it isn't a `ssh://` URI but a plain host.
+    pub fn parse(value: &str) -> Result<Option<Self>, String> {
+        let rest = match value.strip_prefix("ssh://") {
+            Some(rest) => rest,
+            None => return Ok(None),
+        };
+        let (authority, path) = match rest.split_once('/') {
+            Some((authority, path)) => (authority, Some(path)),
+            None => (rest, None),
+        };
+        let (user, host_port) = match authority.rsplit_once('@') {
+            Some((user, host_port)) => (Some(user), host_port),
+            None => (None, authority),
+        };
+        // IPv6 addresses are enclosed in brackets, as their colons would look like a port
+        let (host, port) = match host_port.strip_prefix('[') {
+            Some(bracketed) => match bracketed.split_once(']') {
+                Some((host, rest)) => (host, rest.strip_prefix(':')),
+                None => return Err(format!("unclosed bracket in {}", value)),
+            },
+            None => match host_port.rsplit_once(':') {
+                Some((host, port)) => (host, Some(port)),
+                None => (host_port, None),
+            },
+        };
+        if host.is_empty() {
+            return Err(format!("{} has no host", value));
+        }
+        let ssh_port = match port {
+            Some(port) => Some(
+                port.parse()
+                    .map_err(|_| format!("invalid port {:?} in {}", port, value))?,
+            ),
+            None => None,
+        };
+        Ok(Some(RemoteUri {
+            host: match user {
+                Some(user) => format!("{}@{}", user, host),
+                None => host.to_owned(),
+            },
+            ssh_port,
+            temp_dir: path.filter(|path| !path.is_empty()).map(str::to_owned),
+        }))
     }
 }
 
@@ -60,25 +1195,189 @@ impl<'de> Deserialize<'de> for Remote {
 pub struct Config {
     #[serde(rename = "remote")]
     remotes: Option<Vec<Remote>>,
+    exclude: Option<Vec<String>>,
+    projects: Option<BTreeMap<String, Project>>,
+    alias: Option<BTreeMap<String, Alias>>,
+    profiles: Option<BTreeMap<String, Profile>>,
+    deploy: Option<BTreeMap<String, deploy::Target>>,
+    provision: Option<BTreeMap<String, provision::Provider>>,
+    /// Version of cargo the remote needs to build the project, e.g. ">=1.70".
+    required_cargo: Option<Requirement>,
+    /// Version of rustc the remote needs to build the project.
+    required_rustc: Option<Requirement>,
 }
 
 impl Config {
     pub fn new(project_dir: &std::path::Path) -> Result<Self, config::ConfigError> {
         let mut conf = config::Config::new();
+        for config_file in Self::files(project_dir) {
+            conf.merge(config::File::from(config_file))?;
+        }
+        conf.try_into()
+    }
+
+    /// The existing config files, in the order they are merged (later ones take precedence).
+    pub fn files(project_dir: &std::path::Path) -> Vec<std::path::PathBuf> {
+        let mut files = Vec::new();
 
         if let Some(config_file) = xdg::BaseDirectories::with_prefix("cargo-remote")
             .ok()
             .and_then(|base| base.find_config_file("cargo-remote.toml"))
         {
-            conf.merge(config::File::from(config_file))?;
+            files.push(config_file);
         }
 
         let project_config = project_dir.join(".cargo-remote.toml");
         if project_config.is_file() {
-            conf.merge(config::File::from(project_config))?;
+            files.push(project_config);
         }
 
-        conf.try_into()
+        files
+    }
+
+    /// rsync exclude patterns for the source upload, replaces [`DEFAULT_EXCLUDES`] if configured.
+    pub fn excludes(&self) -> Vec<String> {
+        match &self.exclude {
+            Some(exclude) => exclude.clone(),
+            None => DEFAULT_EXCLUDES.iter().map(|&e| e.to_owned()).collect(),
+        }
+    }
+
+    /// The complete effective config in config file syntax.
+    pub fn describe(&self) -> String {
+        let mut description = String::new();
+        description.push_str(&format!("exclude = {:?}\n", self.excludes()));
+        for (tool, requirement) in self.required_versions() {
+            description.push_str(&format!(
+                "required_{} = {:?}\n",
+                tool,
+                requirement.to_string()
+            ));
+        }
+        if let Some(aliases) = &self.alias {
+            description.push_str("\n[alias]\n");
+            for (name, alias) in aliases {
+                description.push_str(&format!("{} = {:?}\n", name, alias.args()));
+            }
+        }
+        for (name, project) in self.projects() {
+            description.push_str(&format!(
+                "\n[projects.{}]\npath = {:?}\ndepends_on = {:?}\n",
+                name, project.path, project.depends_on
+            ));
+        }
+        for (name, profile) in self.profiles.iter().flatten() {
+            description.push_str(&format!("\n[profiles.{}]\n", name));
+            if let Some(remote) = &profile.remote {
+                description.push_str(&format!("remote = {:?}\n", remote));
+            }
+            description.push_str(&format!("cargo_flags = {:?}\n", profile.cargo_flags));
+            match &profile.copy_back {
+                Some(CopyBack::Enabled(enabled)) => {
+                    description.push_str(&format!("copy_back = {}\n", enabled))
+                }
+                Some(CopyBack::Path(path)) => {
+                    description.push_str(&format!("copy_back = {:?}\n", path))
+                }
+                None => {}
+            }
+            let env: Vec<String> = profile
+                .env
+                .iter()
+                .map(|(key, value)| format!("{} = {:?}", key, value))
+                .collect();
+            description.push_str(&format!("env = {{ {} }}\n", env.join(", ")));
+        }
+        for (name, target) in self.deploy.iter().flatten() {
+            description.push_str(&format!(
+                "\n[deploy.{}]\nhost = {:?}\npath = {:?}\nartifacts = {:?}\n",
+                name, target.host, target.path, target.artifacts
+            ));
+            if let Some(ssh_port) = target.ssh_port {
+                description.push_str(&format!("ssh_port = {}\n", ssh_port));
+            }
+            if let Some(post_command) = &target.post_command {
+                description.push_str(&format!("post_command = {:?}\n", post_command));
+            }
+        }
+        for remote in self.remotes.iter().flatten() {
+            description.push_str(&format!("\n{}", remote));
+        }
+        description
+    }
+
+    /// Replaces an alias in the first of `args` with its command line, also if it expands to
+    /// another alias. Configured aliases take precedence over [`BUILTIN_ALIASES`].
+    pub fn expand_alias(&self, mut args: Vec<String>) -> Vec<String> {
+        let mut expanded = Vec::new();
+        while let Some(first) = args.first().cloned() {
+            // aliases referring to each other in a cycle are only expanded once
+            if expanded.contains(&first) {
+                break;
+            }
+            let replacement = match self.alias.as_ref().and_then(|alias| alias.get(&first)) {
+                Some(alias) => alias.args(),
+                None => match BUILTIN_ALIASES.iter().find(|(name, _)| *name == first) {
+                    Some((_, command)) => vec![command.to_string()],
+                    None => break,
+                },
+            };
+            args.splice(..1, replacement);
+            expanded.push(first);
+        }
+        args
+    }
+
+    /// Related local projects built together by `build-all`, keyed by name.
+    pub fn projects(&self) -> BTreeMap<String, Project> {
+        self.projects.clone().unwrap_or_default()
+    }
+
+    /// The deployment target called `name` in the `[deploy]` section.
+    pub fn deploy_target(&self, name: &str) -> Option<&deploy::Target> {
+        self.deploy.as_ref().and_then(|deploy| deploy.get(name))
+    }
+
+    /// The cloud provider called `name` in the `[provision]` section.
+    pub fn provider(&self, name: &str) -> Option<&provision::Provider> {
+        self.provision
+            .as_ref()
+            .and_then(|provision| provision.get(name))
+    }
+
+    /// The `required_cargo` and `required_rustc` of the project, by tool.
+    pub fn required_versions(&self) -> Vec<(&'static str, &Requirement)> {
+        let cargo = self.required_cargo.iter().map(|r| ("cargo", r));
+        let rustc = self.required_rustc.iter().map(|r| ("rustc", r));
+        cargo.chain(rustc).collect()
+    }
+
+    /// The profile called `name` in the `[profiles]` section.
+    pub fn profile(&self, name: &str) -> Option<&Profile> {
+        self.profiles
+            .as_ref()
+            .and_then(|profiles| profiles.get(name))
+    }
+
+    /// The remote selected with `opts` if one is given, all configured remotes otherwise.
+    pub fn remotes(&self, opts: &crate::RemoteOpts) -> Vec<Remote> {
+        if opts.name.is_some() || opts.host.is_some() {
+            self.get_remote(opts).into_iter().collect()
+        } else {
+            self.remotes
+                .iter()
+                .flatten()
+                .map(|remote| Remote {
+                    ssh_port: opts.ssh_port.unwrap_or(remote.ssh_port),
+                    temp_dir: opts
+                        .temp_dir
+                        .clone()
+                        .unwrap_or_else(|| remote.temp_dir.clone()),
+                    env: opts.env.clone().unwrap_or_else(|| remote.env.clone()),
+                    ..remote.clone()
+                })
+                .collect()
+        }
     }
 
     pub fn get_remote(&self, opts: &crate::RemoteOpts) -> Option<Remote> {
@@ -96,12 +1395,19 @@ impl Config {
             (None, false) => return None,
         };
 
+        let hosts = match &opts.host {
+            Some(host) => vec![host.clone()],
+            None => blueprint_remote.hosts,
+        };
+
         Some(Remote {
             name: opts.name.clone().unwrap_or(blueprint_remote.name),
-            host: opts.host.clone().unwrap_or(blueprint_remote.host),
-            ssh_port: opts.ssh_port.clone().unwrap_or(blueprint_remote.ssh_port),
+            host: hosts[0].clone(),
+            hosts,
+            ssh_port: opts.ssh_port.unwrap_or(blueprint_remote.ssh_port),
             temp_dir: opts.temp_dir.clone().unwrap_or(blueprint_remote.temp_dir),
             env: opts.env.clone().unwrap_or(blueprint_remote.env),
+            ..blueprint_remote
         })
     }
 }
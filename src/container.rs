@@ -0,0 +1,112 @@
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+
+use tracing::info;
+
+use crate::config::{ContainerTool, Remote};
+use crate::process::{Input, Output};
+use crate::script::{self, quote};
+use crate::transport::Transport;
+
+/// Builds the image `tag` from the `dockerfile` of `remote` in the remote `build_path`, after
+/// the cargo build, so the Dockerfile can copy the artifacts from the target dir.
+pub fn build(
+    transport: &Transport,
+    remote: &Remote,
+    host: &str,
+    build_path: &str,
+    tag: &str,
+) -> io::Result<()> {
+    let build = match remote.container_tool {
+        ContainerTool::Docker | ContainerTool::Podman => "build",
+        ContainerTool::Buildah => "bud",
+    };
+    let script = format!(
+        "{}cd {}; {} {} -t {} -f {} .",
+        script::source_env(remote),
+        build_path,
+        remote.container_tool,
+        build,
+        quote(tag),
+        quote(&remote.dockerfile)
+    );
+    info!("Building image {} on {}.", tag, host);
+    run(transport, remote, host, &script, "container-build")
+}
+
+/// Pushes the image `tag` built on the remote to its registry.
+pub fn push(transport: &Transport, remote: &Remote, host: &str, tag: &str) -> io::Result<()> {
+    let script = format!(
+        "{}{} push {}",
+        script::source_env(remote),
+        remote.container_tool,
+        quote(tag)
+    );
+    info!("Pushing image {} from {}.", tag, host);
+    run(transport, remote, host, &script, "container-push")
+}
+
+/// Streams the image `tag` from the remote into the local docker daemon with `docker load`,
+/// without writing the archive to disk on either side.
+pub fn retrieve(transport: &Transport, remote: &Remote, host: &str, tag: &str) -> io::Result<()> {
+    let save = match remote.container_tool {
+        ContainerTool::Docker | ContainerTool::Podman => {
+            format!("{} save {}", remote.container_tool, quote(tag))
+        }
+        ContainerTool::Buildah => format!(
+            "buildah push {} docker-archive:/dev/stdout:{}",
+            quote(tag),
+            quote(tag)
+        ),
+    };
+    let script = format!("{}{}", script::source_env(remote), save);
+
+    info!("Loading image {} from {} into the local docker.", tag, host);
+    let mut load = Command::new("docker")
+        .arg("load")
+        .stdin(Stdio::piped())
+        .spawn()?;
+    let mut load_stdin = load
+        .stdin
+        .take()
+        .ok_or_else(|| io::Error::other("docker load has no stdin"))?;
+    let mut ssh = transport.remote_command(remote, host, &script);
+    let saved = transport.run_with(
+        &mut ssh,
+        "container-load",
+        Input::Inherit,
+        Output::Chunks(Box::new(move |chunk| load_stdin.write_all(chunk))),
+    );
+    // the hook and with it stdin of docker load are dropped once ssh finished
+    let loaded = load.wait()?;
+    let saved = saved?;
+    if !saved.success() {
+        return Err(io::Error::other(format!(
+            "{} failed on the remote ({})",
+            remote.container_tool, saved
+        )));
+    }
+    if !loaded.success() {
+        return Err(io::Error::other(format!("docker load failed ({})", loaded)));
+    }
+    Ok(())
+}
+
+fn run(
+    transport: &Transport,
+    remote: &Remote,
+    host: &str,
+    script: &str,
+    phase: &str,
+) -> io::Result<()> {
+    let mut ssh = transport.remote_command(remote, host, script);
+    let status = transport.run(&mut ssh, phase)?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!(
+            "{} failed ({})",
+            remote.container_tool, status
+        )))
+    }
+}
@@ -0,0 +1,87 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use clap::ValueEnum;
+
+/// Directory in the target dir the coverage reports are written to and copied back from.
+pub const COVERAGE_DIR: &str = "coverage";
+
+/// Name of the lcov report inside [`COVERAGE_DIR`].
+const LCOV_FILE: &str = "lcov.info";
+
+/// Cargo plugin measuring the coverage.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Tool {
+    /// cargo-llvm-cov, source based coverage of rustc
+    LlvmCov,
+    /// cargo-tarpaulin, Linux only
+    Tarpaulin,
+}
+
+/// Cargo commands running the tests with `tool` and writing an lcov report (and with `html`
+/// an HTML report) to [`COVERAGE_DIR`] in the remote target dir. `args` are passed on to the
+/// tool.
+pub fn commands(tool: Tool, html: bool, args: &str) -> Vec<String> {
+    let output_dir = format!("{}/{}", crate::REMOTE_TARGET_DIR, COVERAGE_DIR);
+    match tool {
+        Tool::LlvmCov => {
+            let mut commands = vec![format!(
+                "llvm-cov --lcov --output-path {}/{} {}",
+                output_dir, LCOV_FILE, args
+            )];
+            if html {
+                // reuses the profile data of the run above
+                commands.push(format!(
+                    "llvm-cov report --html --output-dir {}",
+                    output_dir
+                ));
+            }
+            commands
+        }
+        Tool::Tarpaulin => vec![format!(
+            "tarpaulin --out Lcov {}--output-dir {} {}",
+            if html { "--out Html " } else { "" },
+            output_dir,
+            args
+        )],
+    }
+}
+
+/// Rewrites the source paths in the lcov report copied back to `local_dir` from the remote
+/// `build_path` to the sources in `project_dir`, so local tools find them.
+///
+/// Returns the number of rewritten paths.
+pub fn remap_lcov(local_dir: &Path, build_path: &str, project_dir: &Path) -> io::Result<usize> {
+    let lcov = local_dir.join(LCOV_FILE);
+    let content = fs::read_to_string(&lcov)?;
+    // the remote home directory isn't known, only the directory of the build path is unique
+    let build_dir = format!(
+        "/{}/",
+        build_path
+            .trim_end_matches('/')
+            .rsplit('/')
+            .next()
+            .unwrap_or_default()
+    );
+    let mut remapped = 0;
+    let lines: Vec<String> = content
+        .lines()
+        .map(|line| {
+            let source = match line.strip_prefix("SF:") {
+                Some(source) => source,
+                None => return line.to_owned(),
+            };
+            match source.find(&build_dir) {
+                Some(start) => {
+                    remapped += 1;
+                    let relative = &source[start + build_dir.len()..];
+                    format!("SF:{}", project_dir.join(relative).to_string_lossy())
+                }
+                None => line.to_owned(),
+            }
+        })
+        .collect();
+    fs::write(&lcov, lines.join("\n") + "\n")?;
+    Ok(remapped)
+}
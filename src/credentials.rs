@@ -0,0 +1,99 @@
+use std::env;
+use std::io;
+
+use crate::config::Remote;
+use crate::process::Input;
+use crate::secrets::Reference;
+
+/// Local environment variables allowed by the `forward_env` list of `remote`, with their values,
+/// and the resolved `secrets` of the remote, which take precedence.
+///
+/// Entries may contain `*` wildcards, e.g. `CARGO_REGISTRIES_*_TOKEN`. Values can't contain
+/// line breaks, as they are sent line by line.
+pub fn collect(remote: &Remote) -> io::Result<Vec<(String, String)>> {
+    let mut forwarded: Vec<_> = env::vars()
+        .filter(|(name, _)| {
+            remote
+                .forward_env
+                .iter()
+                .any(|pattern| matches(pattern, name))
+        })
+        .filter(|(name, _)| !remote.secrets.contains_key(name))
+        .collect();
+    for (name, reference) in &remote.secrets {
+        let value = Reference::parse(reference)
+            .ok_or_else(|| io::Error::other(format!("invalid reference for {}", name)))?
+            .resolve()
+            .map_err(|e| io::Error::other(format!("can't resolve the secret {}: {}", name, e)))?;
+        forwarded.push((name.clone(), value));
+    }
+    forwarded.sort();
+    if let Some((name, _)) = forwarded.iter().find(|(_, value)| value.contains('\n')) {
+        return Err(io::Error::other(format!(
+            "{} contains a line break and can't be forwarded",
+            name
+        )));
+    }
+    Ok(forwarded)
+}
+
+/// The variable cargo reads the token of `registry` from (crates.io without one) and its value,
+/// taken from the local environment or else from the keyring entry named after the registry
+/// (`crates-io` for crates.io), see `cargo remote secret set`.
+pub fn registry_token(registry: Option<&str>) -> io::Result<(String, String)> {
+    let (name, keyring) = match registry {
+        Some(registry) => (
+            format!(
+                "CARGO_REGISTRIES_{}_TOKEN",
+                registry.to_uppercase().replace('-', "_")
+            ),
+            registry,
+        ),
+        None => ("CARGO_REGISTRY_TOKEN".to_owned(), "crates-io"),
+    };
+    if let Ok(token) = env::var(&name) {
+        return Ok((name, token));
+    }
+    match Reference::Keyring(keyring).resolve() {
+        Ok(token) if !token.is_empty() => Ok((name, token)),
+        _ => Err(io::Error::other(format!(
+            "no token for the registry, set {} or save it with `cargo remote secret set {}`",
+            name, keyring
+        ))),
+    }
+}
+
+/// Remote commands exporting the variables sent by [`input`] on stdin.
+///
+/// They only live in the environment of the build, nothing is written to the disk of the
+/// remote and the values don't show up in any command line.
+pub fn read_script() -> &'static str {
+    "while IFS= read -r cargo_remote_variable && [ -n \"$cargo_remote_variable\" ]; do \
+     export \"$cargo_remote_variable\"; done; unset cargo_remote_variable; "
+}
+
+/// stdin for the build session carrying `variables` to [`read_script`].
+pub fn input(variables: &[(String, String)]) -> Input {
+    let mut bytes = Vec::new();
+    for (name, value) in variables {
+        bytes.extend_from_slice(format!("{}={}\n", name, value).as_bytes());
+    }
+    bytes.push(b'\n');
+    Input::Bytes(bytes)
+}
+
+/// Whether `name` matches `pattern`, in which `*` stands for any number of characters.
+fn matches(pattern: &str, name: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == name,
+        Some((prefix, rest)) => {
+            let name = match name.strip_prefix(prefix) {
+                Some(name) => name,
+                None => return false,
+            };
+            (0..=name.len())
+                .filter(|&start| name.is_char_boundary(start))
+                .any(|start| matches(rest, &name[start..]))
+        }
+    }
+}
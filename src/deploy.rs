@@ -0,0 +1,93 @@
+use std::io;
+
+use serde::Deserialize;
+
+use crate::config::Remote;
+use crate::script::quote;
+use crate::transport::Transport;
+
+/// A host the artifacts are pushed to from the build server, from the `[deploy]` section of
+/// the config.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Target {
+    /// ssh destination as seen from the build server, e.g. `deploy@app1.internal`.
+    pub host: String,
+    #[serde(default)]
+    pub ssh_port: Option<u16>,
+    /// Directory on the target the artifacts are copied into.
+    pub path: String,
+    /// Shell globs relative to the remote target dir, e.g. "release/myapp".
+    pub artifacts: Vec<String>,
+    /// Command run on the target after the artifacts arrived, e.g. to restart a service.
+    #[serde(default)]
+    pub post_command: Option<String>,
+}
+
+impl Target {
+    /// Problems of the target, the artifacts end up unquoted in a shell script.
+    pub fn check(&self) -> Result<(), &'static str> {
+        let is_glob = |pattern: &String| {
+            !pattern.is_empty()
+                && !pattern.starts_with('/')
+                && pattern
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || "-_./*?[]+@%,=".contains(c))
+        };
+        if self.artifacts.is_empty() {
+            Err("`artifacts` is empty")
+        } else if !self.artifacts.iter().all(is_glob) {
+            Err("`artifacts` must be globs relative to the target dir without spaces or quotes")
+        } else if self.host.is_empty() || self.host.starts_with('-') || self.path.is_empty() {
+            Err("`host` and `path` must be set")
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Pushes the artifacts of `target` from the target dir in `build_path` straight from the
+/// build server to the deployment host with rsync over ssh, then runs its `post_command`.
+///
+/// The build server needs access to the deployment host, with a key of its own or the
+/// forwarded agent of `agent_forwarding`.
+pub fn push(
+    transport: &Transport,
+    remote: &Remote,
+    host: &str,
+    build_path: &str,
+    target: &Target,
+) -> io::Result<()> {
+    let ssh = match target.ssh_port {
+        Some(port) => format!("ssh -p {}", port),
+        None => "ssh".to_owned(),
+    };
+    let path = target.path.trim_end_matches('/');
+    let mut script = format!(
+        "cd {build_path}{target_dir} && {ssh} {host} {mkdir} && \
+         rsync -az -e {quoted_ssh} -- {artifacts} {destination}",
+        build_path = build_path,
+        target_dir = crate::REMOTE_TARGET_DIR,
+        ssh = ssh,
+        host = quote(&target.host),
+        mkdir = quote(&format!("mkdir -p {}", quote(path))),
+        quoted_ssh = quote(&ssh),
+        // expanded by the shell of the build server
+        artifacts = target.artifacts.join(" "),
+        destination = quote(&format!("{}:{}/", target.host, path))
+    );
+    if let Some(post_command) = &target.post_command {
+        script.push_str(&format!(
+            " && {} {} {}",
+            ssh,
+            quote(&target.host),
+            quote(post_command)
+        ));
+    }
+    let mut ssh = transport.remote_build_command(remote, host, &script, false);
+    let status = transport.run(&mut ssh, "deploy")?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!("deployment failed ({})", status)))
+    }
+}
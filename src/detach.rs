@@ -0,0 +1,423 @@
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+use tracing::{info, warn};
+
+use crate::artifacts::{self, Store};
+use crate::backend::ExecOptions;
+use crate::config::Remote;
+use crate::process::{Input, Output};
+use crate::script::{self, quote};
+use crate::sync;
+use crate::transport::Transport;
+
+/// Keeps the detached builds of the project inside the local target dir.
+const JOBS_FILE: &str = "remote-jobs.json";
+
+/// Exit code of ssh when the connection failed or dropped.
+const SSH_ERROR: i32 = 255;
+
+/// Pause before following the output of a build again after the connection dropped.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// Failed reconnects in a row after which streaming the output is given up, about five minutes
+/// without a connection.
+const RECONNECT_ATTEMPTS: u32 = 60;
+
+/// How the log is followed, dead connections are noticed instead of waiting for TCP to time out.
+const FOLLOW: ExecOptions = ExecOptions {
+    tty: false,
+    forward_agent: false,
+    connect_timeout: Some(10),
+    keepalive: true,
+};
+
+/// Artifacts to copy back, or upload to the `artifact_store`, once a detached build finished
+/// successfully.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CopyBack {
+    /// File in the remote target dir, the whole target dir if empty.
+    pub file_name: String,
+    pub destination: String,
+    pub delete: bool,
+    /// Only the files directly in a directory `file_name`, for `--output-dir`.
+    #[serde(default)]
+    pub flat: bool,
+}
+
+/// A build running on the remote without the client being connected.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Job {
+    pub id: u32,
+    /// Name of the remote in the config.
+    pub remote: String,
+    pub host: String,
+    pub build_path: String,
+    /// Process id of the shell running the build on the remote.
+    pub pid: Option<u32>,
+    /// Unix time the build started.
+    pub started_at: u64,
+    pub commands: Vec<String>,
+    /// Cleared once the artifacts were copied back.
+    pub copy_back: Option<CopyBack>,
+}
+
+/// State of a detached build on the remote.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Running,
+    Finished(i32),
+    /// Neither a log nor a tmux session exists, e.g. because the build dir was cleaned.
+    Unknown,
+}
+
+impl std::fmt::Display for Status {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Status::Running => write!(f, "running"),
+            Status::Finished(0) => write!(f, "finished"),
+            Status::Finished(code) => write!(f, "failed ({})", code),
+            Status::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+/// The detached builds of a project.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Jobs {
+    pub jobs: Vec<Job>,
+}
+
+impl Jobs {
+    fn path(target_dir: &Path) -> PathBuf {
+        target_dir.join(JOBS_FILE)
+    }
+
+    /// The jobs saved in `target_dir`, none if nothing was detached yet.
+    pub fn load(target_dir: &Path) -> io::Result<Self> {
+        match fs::read(Self::path(target_dir)) {
+            Ok(content) => Ok(serde_json::from_slice(&content)?),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e),
+        }
+    }
+
+    pub fn save(&self, target_dir: &Path) -> io::Result<()> {
+        fs::create_dir_all(target_dir)?;
+        fs::write(Self::path(target_dir), serde_json::to_vec_pretty(self)?)
+    }
+
+    /// Id for the next job.
+    pub fn next_id(&self) -> u32 {
+        self.jobs.iter().map(|job| job.id).max().unwrap_or(0) + 1
+    }
+
+    /// The job with `id`, or the last one started without an id.
+    pub fn get_mut(&mut self, id: Option<u32>) -> io::Result<&mut Job> {
+        let job = match id {
+            Some(id) => self.jobs.iter_mut().find(|job| job.id == id),
+            None => self.jobs.last_mut(),
+        };
+        job.ok_or_else(|| match id {
+            Some(id) => io::Error::other(format!("there is no detached build with id {}", id)),
+            None => io::Error::other("no build was started with --detach"),
+        })
+    }
+
+    pub fn remove(&mut self, id: u32) {
+        self.jobs.retain(|job| job.id != id);
+    }
+}
+
+impl Job {
+    /// Name of the tmux session, unique per build directory and job.
+    fn session(&self) -> String {
+        let directory = self
+            .build_path
+            .trim_end_matches('/')
+            .rsplit('/')
+            .next()
+            .unwrap_or_default();
+        format!("cargo-remote-{}-{}", directory, self.id)
+    }
+
+    fn file(&self, extension: &str) -> String {
+        format!(
+            "{}{}/cargo-remote-job-{}.{}",
+            self.build_path,
+            crate::REMOTE_TARGET_DIR,
+            self.id,
+            extension
+        )
+    }
+
+    /// The remote the job was started on, `fallback` if it isn't configured anymore.
+    pub fn remote<'a>(&self, remotes: &'a [Remote], fallback: &'a Remote) -> &'a Remote {
+        remotes
+            .iter()
+            .find(|remote| remote.name == self.remote)
+            .unwrap_or(fallback)
+    }
+
+    /// Starts `build_command` in a tmux session, or with nohup if tmux isn't installed, and
+    /// remembers the process id of the build.
+    ///
+    /// Without tmux the build gets its own process group if `setsid` is available, so it can
+    /// be cancelled as a whole.
+    pub fn launch(
+        &mut self,
+        transport: &Transport,
+        remote: &Remote,
+        build_command: &str,
+    ) -> io::Result<()> {
+        let log = self.file("log");
+        let status = self.file("status");
+        let build = quote(&format!(
+            "{{ {} echo $? > {}; }} 2>&1 | tee {}",
+            build_command, status, log
+        ));
+        let script = format!(
+            "mkdir -p {build_path}{target} && rm -f {status} && : > {log} && \
+             if command -v tmux >/dev/null 2>&1; then \
+             tmux new-session -d -P -F '#{{pane_pid}}' -s {session} sh -c {build}; \
+             else \
+             if command -v setsid >/dev/null 2>&1; then cargo_remote_setsid=setsid; fi; \
+             $cargo_remote_setsid nohup sh -c {build} >/dev/null 2>&1 </dev/null & echo $!; \
+             fi",
+            build_path = self.build_path,
+            target = crate::REMOTE_TARGET_DIR,
+            status = status,
+            log = log,
+            session = self.session(),
+            build = build
+        );
+        let output = transport.remote_output(remote, &self.host, &script)?;
+        self.pid = output.trim().parse().ok();
+        Ok(())
+    }
+
+    pub fn status(&self, transport: &Transport, remote: &Remote) -> io::Result<Status> {
+        let output = transport.remote_output(
+            remote,
+            &self.host,
+            &format!(
+                "if [ -f {status} ]; then echo finished $(cat {status}); \
+                 elif [ -f {log} ]; then echo running; else echo unknown; fi",
+                status = self.file("status"),
+                log = self.file("log")
+            ),
+        )?;
+        Ok(parse_status(&output))
+    }
+
+    /// Attaches to the tmux session of the build. Without tmux the log is followed instead.
+    pub fn attach(&self, transport: &Transport, remote: &Remote) -> io::Result<i32> {
+        let script = format!(
+            "if tmux has-session -t {session} 2>/dev/null; then exec tmux attach -t {session}; \
+             else {logs}; fi",
+            session = self.session(),
+            logs = self.logs_script(true)
+        );
+        let mut ssh = transport.remote_command_with(
+            remote,
+            &self.host,
+            &script::as_build_user(remote, &script),
+            ExecOptions {
+                tty: true,
+                ..ExecOptions::default()
+            },
+        );
+        Ok(transport.run(&mut ssh, "attach")?.code().unwrap_or(1))
+    }
+
+    /// Prints the log of the build. With `follow` new output is printed until the build finished.
+    pub fn logs(&self, transport: &Transport, remote: &Remote, follow: bool) -> io::Result<i32> {
+        let mut ssh = transport.remote_command(remote, &self.host, &self.logs_script(follow));
+        Ok(transport.run(&mut ssh, "logs")?.code().unwrap_or(1))
+    }
+
+    /// Streams the output of the build until it finished and returns its exit status.
+    ///
+    /// When the connection drops, the log is followed again from the first byte that wasn't
+    /// received yet, so nothing is lost or printed twice. The build itself isn't affected.
+    pub fn stream(&self, transport: &Transport, remote: &Remote) -> io::Result<ExitStatus> {
+        let received = Arc::new(AtomicU64::new(0));
+        let mut attempts = 0;
+        loop {
+            let offset = received.load(Ordering::Relaxed);
+            let follow = format!(
+                "{}; exit $(cat {})",
+                self.follow_script(offset),
+                self.file("status")
+            );
+            let mut ssh = transport.remote_command_with(
+                remote,
+                &self.host,
+                &script::as_build_user(remote, &follow),
+                FOLLOW,
+            );
+            let counter = received.clone();
+            let output = Output::Chunks(Box::new(move |chunk| {
+                let mut stdout = io::stdout();
+                stdout.write_all(chunk)?;
+                stdout.flush()?;
+                counter.fetch_add(chunk.len() as u64, Ordering::Relaxed);
+                Ok(())
+            }));
+            let status = transport.run_with(&mut ssh, "build", Input::Bytes(Vec::new()), output)?;
+            // the build itself may have failed with the exit code of ssh
+            if status.code() != Some(SSH_ERROR)
+                || matches!(
+                    self.status(transport, remote),
+                    Ok(Status::Finished(SSH_ERROR))
+                )
+            {
+                return Ok(status);
+            }
+            if received.load(Ordering::Relaxed) > offset {
+                attempts = 0;
+            }
+            attempts += 1;
+            if attempts > RECONNECT_ATTEMPTS {
+                return Err(io::Error::other(format!(
+                    "lost the connection to {}",
+                    self.host
+                )));
+            }
+            warn!(
+                "Lost the connection to {}, reconnecting in {}s ({}/{}).",
+                self.host,
+                RECONNECT_DELAY.as_secs(),
+                attempts,
+                RECONNECT_ATTEMPTS
+            );
+            thread::sleep(RECONNECT_DELAY);
+        }
+    }
+
+    fn logs_script(&self, follow: bool) -> String {
+        if follow {
+            self.follow_script(0)
+        } else {
+            format!("cat {}", self.file("log"))
+        }
+    }
+
+    /// Prints the log from byte `offset` on, and new output until the build finished.
+    fn follow_script(&self, offset: u64) -> String {
+        // `tail -f` never ends by itself, so stop it once the status file shows up
+        format!(
+            "tail -c +{from} -f {log} & cargo_remote_tail=$!; \
+             while [ ! -f {status} ]; do sleep 1; done; sleep 1; kill $cargo_remote_tail",
+            from = offset + 1,
+            log = self.file("log"),
+            status = self.file("status")
+        )
+    }
+
+    /// Stops the build and everything it started.
+    pub fn cancel(&self, transport: &Transport, remote: &Remote) -> io::Result<()> {
+        let mut script = format!("tmux kill-session -t {} 2>/dev/null", self.session());
+        if let Some(pid) = self.pid {
+            script.push_str(&format!(
+                " || kill -TERM -- -{pid} 2>/dev/null || kill -TERM {pid}",
+                pid = pid
+            ));
+        }
+        let mut ssh = transport.remote_command(remote, &self.host, &script);
+        let status = transport.run(&mut ssh, "cancel")?;
+        if !status.success() {
+            return Err(io::Error::other(format!(
+                "the build isn't running on {} anymore",
+                self.host
+            )));
+        }
+        Ok(())
+    }
+
+    /// Copies the artifacts back if the job finished successfully and asked for them.
+    ///
+    /// Returns whether anything was copied.
+    pub fn finish(
+        &mut self,
+        transport: &Transport,
+        remote: &Remote,
+        status: Status,
+    ) -> io::Result<bool> {
+        let copy_back = match (&self.copy_back, status) {
+            (Some(copy_back), Status::Finished(0)) => copy_back,
+            _ => return Ok(false),
+        };
+        if let Some(store) = Store::of(remote) {
+            info!(
+                "Uploading artifacts of detached build {} to {}.",
+                self.id,
+                remote.artifact_store.as_deref().unwrap_or_default()
+            );
+            let urls = artifacts::publish(
+                transport,
+                remote,
+                &self.host,
+                &self.build_path,
+                &store,
+                &copy_back.file_name,
+            )?;
+            for url in urls {
+                println!("{}", url);
+            }
+            self.copy_back = None;
+            return Ok(true);
+        }
+        info!(
+            "Transferring artifacts of detached build {} back to client.",
+            self.id
+        );
+        if copy_back.destination.ends_with('/') {
+            fs::create_dir_all(&copy_back.destination)?;
+        }
+        let transferred = if copy_back.flat {
+            sync::copy_back_flat(
+                transport,
+                remote,
+                &self.host,
+                &self.build_path,
+                &copy_back.file_name,
+                &copy_back.destination,
+            )?
+        } else {
+            sync::copy_back(
+                transport,
+                remote,
+                &self.host,
+                &self.build_path,
+                &copy_back.file_name,
+                &copy_back.destination,
+                copy_back.delete,
+            )?
+        };
+        if !transferred.success() {
+            return Err(io::Error::other(format!("rsync failed ({})", transferred)));
+        }
+        self.copy_back = None;
+        Ok(true)
+    }
+}
+
+fn parse_status(output: &str) -> Status {
+    let output = output.trim();
+    if output == "running" {
+        return Status::Running;
+    }
+    output
+        .strip_prefix("finished ")
+        .and_then(|code| code.trim().parse().ok())
+        .map(Status::Finished)
+        .unwrap_or(Status::Unknown)
+}
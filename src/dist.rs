@@ -0,0 +1,170 @@
+use std::fs;
+use std::io::{self, Read};
+
+use tracing::{info, warn};
+
+use crate::config::Remote;
+use crate::script::{self, quote};
+use crate::transport::Transport;
+
+/// Port the sccache-dist scheduler listens on, on the build server.
+const SCHEDULER_PORT: u16 = 10600;
+
+/// Port the sccache-dist build servers listen on, on the other remotes.
+const SERVER_PORT: u16 = 10501;
+
+/// Directory in the home of the build user on every remote with the sccache-dist configs and
+/// logs.
+const DIST_DIR: &str = ".cargo-remote-dist";
+
+/// File in the config dir with the token the scheduler, the servers and the client share.
+const TOKEN_FILE: &str = "dist-token";
+
+/// The token authenticating the parts of the build farm to each other, created on first use.
+pub fn token() -> io::Result<String> {
+    let dirs = xdg::BaseDirectories::with_prefix("cargo-remote")?;
+    if let Some(file) = dirs.find_config_file(TOKEN_FILE) {
+        return Ok(fs::read_to_string(file)?.trim().to_owned());
+    }
+    let mut bytes = [0; 16];
+    fs::File::open("/dev/urandom")?.read_exact(&mut bytes)?;
+    let token: String = bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+    fs::write(dirs.place_config_file(TOKEN_FILE)?, &token)?;
+    Ok(token)
+}
+
+/// Address the other remotes reach `remote` at: its `dist_address`, or the host without user.
+fn address(remote: &Remote, host: &str) -> String {
+    match &remote.dist_address {
+        Some(address) => address.clone(),
+        None => host.rsplit('@').next().unwrap_or(host).to_owned(),
+    }
+}
+
+/// Writes `contents` to the file `name` in [`DIST_DIR`], the shell words of `extra_lines` are
+/// appended as lines of their own.
+fn write_config(name: &str, contents: &str, extra_lines: &str) -> String {
+    format!(
+        "mkdir -p ~/{dir} && printf '%s\\n' {contents} {extra} > ~/{dir}/{name}; ",
+        dir = DIST_DIR,
+        contents = quote(contents),
+        extra = extra_lines,
+        name = name
+    )
+}
+
+/// Script starting the sccache-dist scheduler of `remote` unless it already runs, and writing
+/// the config of the sccache client of the build.
+fn scheduler_script(remote: &Remote, token: &str) -> String {
+    let scheduler = format!(
+        "public_addr = \"0.0.0.0:{port}\"\n\n\
+         [client_auth]\ntype = \"token\"\ntoken = \"{token}\"\n\n\
+         [server_auth]\ntype = \"token\"\ntoken = \"{token}\"",
+        port = SCHEDULER_PORT,
+        token = token
+    );
+    let client = format!(
+        "[dist]\nscheduler_url = \"http://127.0.0.1:{port}\"\ntoolchains = []\n\n\
+         [dist.auth]\ntype = \"token\"\ntoken = \"{token}\"",
+        port = SCHEDULER_PORT,
+        token = token
+    );
+    format!(
+        "{env}command -v sccache-dist >/dev/null || {{ echo 'sccache-dist is not installed' >&2; exit 1; }}; \
+         {scheduler}{client}\
+         if ! pgrep -f 'sccache-dist scheduler' >/dev/null; then \
+         nohup sccache-dist scheduler --config ~/{dir}/scheduler.conf > ~/{dir}/scheduler.log 2>&1 < /dev/null & fi",
+        env = script::source_env(remote),
+        scheduler = write_config("scheduler.conf", &scheduler, ""),
+        client = write_config("client.conf", &client, ""),
+        dir = DIST_DIR
+    )
+}
+
+/// Script starting an sccache-dist build server on a worker, which needs root and bubblewrap
+/// for its sandbox.
+fn server_script(worker: &Remote, host: &str, scheduler_address: &str, token: &str) -> String {
+    let server = format!(
+        "public_addr = \"{address}:{port}\"\n\
+         scheduler_url = \"http://{scheduler}:{scheduler_port}\"\n\
+         cache_dir = \"/tmp/cargo-remote-dist/toolchains\"\n\n\
+         [scheduler_auth]\ntype = \"token\"\ntoken = \"{token}\"\n\n\
+         [builder]\ntype = \"overlay\"\nbuild_dir = \"/tmp/cargo-remote-dist/build\"",
+        address = address(worker, host),
+        port = SERVER_PORT,
+        scheduler = scheduler_address,
+        scheduler_port = SCHEDULER_PORT,
+        token = token
+    );
+    format!(
+        "{env}command -v sccache-dist >/dev/null || {{ echo 'sccache-dist is not installed' >&2; exit 1; }}; \
+         bwrap=$(command -v bwrap) || {{ echo 'bubblewrap (bwrap) is not installed' >&2; exit 1; }}; \
+         {config}\
+         if [ \"$(id -u)\" = 0 ]; then root=; else root='sudo -n'; fi; \
+         if ! pgrep -f 'sccache-dist server' >/dev/null; then \
+         nohup $root \"$(command -v sccache-dist)\" server --config ~/{dir}/server.conf > ~/{dir}/server.log 2>&1 < /dev/null & \
+         sleep 1; pgrep -f 'sccache-dist server' >/dev/null || {{ cat ~/{dir}/server.log >&2; exit 1; }}; fi",
+        env = script::source_env(worker),
+        config = write_config("server.conf", &server, "\"bwrap_path = \\\"$bwrap\\\"\""),
+        dir = DIST_DIR
+    )
+}
+
+/// Turns the build server `remote` into the sccache-dist scheduler and every one of `workers`
+/// into a build server compiling for it. Workers that fail are left out with a warning.
+/// Returns how many workers joined.
+pub fn start(
+    transport: &Transport,
+    remote: &Remote,
+    host: &str,
+    workers: &[Remote],
+    token: &str,
+) -> io::Result<usize> {
+    run(
+        transport,
+        remote,
+        host,
+        &scheduler_script(remote, token),
+        token,
+    )?;
+    let scheduler_address = address(remote, host);
+    let mut joined = 0;
+    for worker in workers {
+        let script = server_script(worker, &worker.host, &scheduler_address, token);
+        match run(transport, worker, &worker.host, &script, token) {
+            Ok(_) => {
+                info!("{} compiles for the build on {}.", worker.host, host);
+                joined += 1;
+            }
+            Err(e) => warn!(
+                "Failed to start the sccache-dist server on {} (error: {})",
+                worker.host, e
+            ),
+        }
+    }
+    Ok(joined)
+}
+
+/// Runs `script` on `host`, failures show it without the `token`.
+fn run(
+    transport: &Transport,
+    remote: &Remote,
+    host: &str,
+    script: &str,
+    token: &str,
+) -> io::Result<()> {
+    transport
+        .remote_output(remote, host, script)
+        .map(drop)
+        .map_err(|e| io::Error::other(e.to_string().replace(token, "<token>")))
+}
+
+/// Exports run before the cargo commands, compiling through the sccache client of the build
+/// farm. The sccache server is restarted to pick up its config.
+pub fn client_script() -> String {
+    format!(
+        "export RUSTC_WRAPPER=sccache SCCACHE_CONF=\"$HOME\"/{}/client.conf; \
+         sccache --stop-server >/dev/null 2>&1; ",
+        DIST_DIR
+    )
+}
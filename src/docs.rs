@@ -0,0 +1,147 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+
+use tracing::{info, warn};
+
+/// Directory in the target dir cargo writes the documentation to.
+pub const DOC_DIR: &str = "doc";
+
+/// Page of the documented crate the URL points to, the first crate with an index page in
+/// `doc_dir` if there are several.
+pub fn start_page(doc_dir: &Path) -> Option<String> {
+    let mut crates: Vec<String> = fs::read_dir(doc_dir)
+        .ok()?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.path().join("index.html").is_file())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| name != "src" && name != "static.files")
+        .collect();
+    crates.sort();
+    crates
+        .into_iter()
+        .next()
+        .map(|name| format!("{}/index.html", name))
+}
+
+/// Opens `url` in the default browser.
+pub fn open(url: &str) {
+    let opener = if cfg!(target_os = "macos") {
+        "open"
+    } else {
+        "xdg-open"
+    };
+    if let Err(e) = Command::new(opener).arg(url).status() {
+        warn!("Failed to open {} with {} (error: {})", url, opener, e);
+    }
+}
+
+/// Serves the static files in `doc_dir` on `port` of the loopback interface until the process
+/// is interrupted, calling `ready` with the URL of the start page once it listens.
+pub fn serve(doc_dir: &Path, port: u16, ready: impl FnOnce(&str)) -> io::Result<()> {
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    let page = start_page(doc_dir).unwrap_or_default();
+    let url = format!(
+        "http://127.0.0.1:{}/{}",
+        listener.local_addr()?.port(),
+        page
+    );
+    info!("Serving {:?} on {}, stop with Ctrl-C.", doc_dir, url);
+    ready(&url);
+    for stream in listener.incoming() {
+        let result = stream.and_then(|stream| respond(doc_dir, stream));
+        if let Err(e) = result {
+            warn!("Failed to answer a request (error: {})", e);
+        }
+    }
+    Ok(())
+}
+
+/// Answers a single GET request with the file it asks for.
+fn respond(doc_dir: &Path, mut stream: TcpStream) -> io::Result<()> {
+    let mut request_line = String::new();
+    BufReader::new(&stream).read_line(&mut request_line)?;
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = (parts.next(), parts.next().unwrap_or("/"));
+    if method != Some("GET") {
+        return write_response(&mut stream, "405 Method Not Allowed", "text/plain", b"");
+    }
+    let file = match resolve(doc_dir, target) {
+        Some(file) => file,
+        None => return write_response(&mut stream, "404 Not Found", "text/plain", b"not found"),
+    };
+    match fs::read(&file) {
+        Ok(content) => write_response(&mut stream, "200 OK", content_type(&file), &content),
+        Err(_) => write_response(&mut stream, "404 Not Found", "text/plain", b"not found"),
+    }
+}
+
+/// The file in `doc_dir` requested by the URL path `target`, `None` if it would leave
+/// `doc_dir`.
+fn resolve(doc_dir: &Path, target: &str) -> Option<PathBuf> {
+    let path = target.split(['?', '#']).next().unwrap_or_default();
+    let path = decode(path)?;
+    let relative = Path::new(path.trim_start_matches('/'));
+    if relative
+        .components()
+        .any(|component| !matches!(component, Component::Normal(_)))
+    {
+        return None;
+    }
+    let file = doc_dir.join(relative);
+    if file.is_dir() {
+        Some(file.join("index.html"))
+    } else {
+        Some(file)
+    }
+}
+
+/// Decodes the `%XX` escapes of a URL path.
+fn decode(path: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(path.len());
+    let mut rest = path.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = std::str::from_utf8(tail.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+fn content_type(file: &Path) -> &'static str {
+    match file.extension().and_then(|extension| extension.to_str()) {
+        Some("html") => "text/html; charset=utf-8",
+        Some("css") => "text/css",
+        Some("js") => "text/javascript",
+        Some("json") => "application/json",
+        Some("svg") => "image/svg+xml",
+        Some("png") => "image/png",
+        Some("woff2") => "font/woff2",
+        Some("woff") => "font/woff",
+        Some("txt") | Some("md") => "text/plain; charset=utf-8",
+        _ => "application/octet-stream",
+    }
+}
+
+fn write_response(
+    stream: &mut TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &[u8],
+) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        status,
+        content_type,
+        body.len()
+    )?;
+    stream.write_all(body)
+}
@@ -0,0 +1,159 @@
+use std::path::Path;
+
+use crate::backend;
+use crate::compat;
+use crate::config::Remote;
+use crate::hostkey;
+use crate::prerequisites;
+use crate::probe::{self, RemoteFacts};
+use crate::script;
+use crate::transport::Transport;
+use crate::validate;
+
+/// Collects the results of the individual checks.
+#[derive(Debug, Default)]
+struct Report {
+    errors: usize,
+}
+
+impl Report {
+    fn ok(&mut self, message: &str) {
+        println!("ok       {}", message);
+    }
+
+    fn warning(&mut self, message: &str) {
+        println!("warning  {}", message);
+    }
+
+    fn error(&mut self, message: &str) {
+        println!("error    {}", message);
+        self.errors += 1;
+    }
+}
+
+/// Checks the config, the local tools and every host of `remote` and prints the results.
+///
+/// Returns 0 if nothing would prevent a build and 1 otherwise.
+pub fn run(transport: &Transport, remote: &Remote, project_dir: &Path) -> i32 {
+    let mut report = Report::default();
+
+    let problems = validate::validate_files(project_dir);
+    if problems.is_empty() {
+        report.ok("config files are valid");
+    }
+    for problem in &problems {
+        report.error(&format!("config: {}", problem));
+    }
+
+    let exec_program = backend::exec_backend(remote).program(remote);
+    if prerequisites::is_installed(exec_program) {
+        report.ok(&format!("local {}", exec_program));
+    } else {
+        let tool = Path::new(exec_program)
+            .file_name()
+            .map_or(exec_program.into(), |name| name.to_string_lossy());
+        report.error(&format!(
+            "{} isn't installed locally, {}",
+            exec_program,
+            prerequisites::local_install_hint(&tool)
+        ));
+    }
+    let local_rsync = compat::local_rsync_version(&remote.rsync_path_local);
+    if local_rsync.is_empty() {
+        report.warning(&format!(
+            "{} isn't installed locally, files are transferred with tar instead, {}",
+            remote.rsync_path_local,
+            prerequisites::local_install_hint("rsync")
+        ));
+    } else {
+        report.ok(&format!("local {}", local_rsync));
+    }
+    let local_triple = probe::local_host_triple();
+
+    for host in &remote.hosts {
+        if let Some(expected) = &remote.host_key_fingerprint {
+            match hostkey::verify(transport, remote, host) {
+                Ok(_) => report.ok(&format!("{}: host key matches {}", host, expected)),
+                Err(e) => {
+                    report.error(&format!("{}: {}", host, e));
+                    continue;
+                }
+            }
+        }
+        let facts = match RemoteFacts::probe(transport, remote, host) {
+            Ok(facts) => facts,
+            Err(e) => {
+                report.error(&format!("{}: can't connect ({})", host, e));
+                continue;
+            }
+        };
+        report.ok(&format!(
+            "{}: reachable on port {}, {}",
+            host,
+            remote.ssh_port,
+            facts.os.as_deref().unwrap_or("unknown os")
+        ));
+        if let Err(e) = facts.save(remote) {
+            report.warning(&format!("{}: failed to cache the probe ({})", host, e));
+        }
+
+        match &facts.rsync_version {
+            Some(version) => report.ok(&format!("{}: {}", host, version)),
+            None => report.warning(&format!(
+                "{}: rsync isn't installed, files are transferred with tar instead, {}",
+                host,
+                prerequisites::remote_install_hint("rsync", facts.os.as_deref())
+            )),
+        }
+
+        let mut adapted = remote.clone();
+        compat::adapt(&mut adapted, &facts);
+        if let Some(bandwidth) = facts.bandwidth_mbit {
+            report.ok(&format!(
+                "{}: {} Mbit/s upload, transferring like on a {}",
+                host,
+                bandwidth,
+                adapted.link.to_string().to_uppercase()
+            ));
+        }
+        let nix = transport.remote_output(
+            &adapted,
+            host,
+            &format!("{}command -v nix-shell", script::source_env(&adapted)),
+        );
+        match nix {
+            Ok(path) => report.ok(&format!("{}: nix-shell at {}", host, path.trim())),
+            Err(_) => report.error(&format!(
+                "{}: nix-shell isn't available after sourcing {}",
+                host, adapted.env
+            )),
+        }
+
+        match (&facts.host_triple, &local_triple) {
+            (Some(remote_triple), Some(local_triple)) if remote_triple != local_triple => report
+                .warning(&format!(
+                    "{}: builds for {} but this machine is {}, copied back binaries won't run here",
+                    host, remote_triple, local_triple
+                )),
+            (Some(remote_triple), _) => report.ok(&format!(
+                "{}: rustc {} for {}",
+                host,
+                facts.rustc_version.as_deref().unwrap_or("unknown"),
+                remote_triple
+            )),
+            (None, _) => report.warning(&format!(
+                "{}: rustc isn't available outside of nix-shell, the host triple is unknown",
+                host
+            )),
+        }
+    }
+
+    println!();
+    if report.errors == 0 {
+        println!("No problems found.");
+        0
+    } else {
+        println!("{} problem(s) found.", report.errors);
+        1
+    }
+}
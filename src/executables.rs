@@ -0,0 +1,103 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::config::Remote;
+use crate::outdirs;
+use crate::sync;
+use crate::transport::Transport;
+use crate::REMOTE_TARGET_DIR;
+
+/// Kinds of cargo targets whose executables can be copied back by name.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Example,
+    Bench,
+}
+
+impl fmt::Display for Kind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Kind::Example => write!(f, "example"),
+            Kind::Bench => write!(f, "bench"),
+        }
+    }
+}
+
+/// Copies back the executables of the `wanted` examples and benches built by `commands`, which
+/// already ran in `build_path`. Cargo names them, bench executables get a hash, so they are
+/// looked up in the JSON messages of a replay. Every executable goes to the same place below
+/// `target_dir`, or into `output_dir` under the name of its target. Returns the local
+/// executables.
+#[allow(clippy::too_many_arguments)]
+pub fn copy_back(
+    transport: &Transport,
+    remote: &Remote,
+    host: &str,
+    build_path: &str,
+    commands: &[String],
+    wanted: &[(Kind, String)],
+    target_dir: &Path,
+    output_dir: Option<&Path>,
+) -> io::Result<Vec<PathBuf>> {
+    let (build_dir, messages) = outdirs::replay(transport, remote, host, build_path, commands)?;
+    let remote_target_dir = format!("{}/{}/", build_dir, REMOTE_TARGET_DIR);
+    let mut local = Vec::new();
+    for (kind, name) in wanted {
+        let executable = messages
+            .iter()
+            .filter_map(|line| serde_json::from_str::<serde_json::Value>(line).ok())
+            .filter(|message| {
+                message["reason"] == "compiler-artifact"
+                    && message["target"]["name"] == name.as_str()
+                    && message["target"]["kind"]
+                        .as_array()
+                        .is_some_and(|kinds| kinds.iter().any(|k| *k == kind.to_string()))
+            })
+            .find_map(|message| message["executable"].as_str().map(str::to_owned))
+            .ok_or_else(|| {
+                io::Error::other(format!(
+                    "the commands built no {} named {:?}, e.g. add --{} {} to them",
+                    kind, name, kind, name
+                ))
+            })?;
+        let relative = executable.strip_prefix(&remote_target_dir).ok_or_else(|| {
+            // e.g. a CARGO_TARGET_DIR set by the environment profile
+            io::Error::other(format!(
+                "the {} {} was built outside of the target dir, in {}",
+                kind, name, executable
+            ))
+        })?;
+        let destination = match output_dir {
+            Some(output_dir) => {
+                let mut file_name = PathBuf::from(name);
+                if let Some(extension) = Path::new(relative).extension() {
+                    file_name.set_extension(extension);
+                }
+                output_dir.join(file_name)
+            }
+            None => target_dir.join(relative),
+        };
+        if let Some(parent) = destination.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let status = sync::copy_back(
+            transport,
+            remote,
+            host,
+            build_path,
+            relative,
+            &destination.to_string_lossy(),
+            false,
+        )?;
+        if !status.success() {
+            return Err(io::Error::other(format!(
+                "transferring the {} {} failed ({})",
+                kind, name, status
+            )));
+        }
+        local.push(destination);
+    }
+    Ok(local)
+}
@@ -0,0 +1,212 @@
+use std::io;
+use std::process::{Command, ExitStatus};
+
+use tracing::{info, trace};
+
+use crate::process::{Input, Output, Process};
+use crate::transport::redact_key_paths;
+
+/// Runs the local processes `cargo remote` is built on, most of them ssh and rsync reaching out
+/// to the remote.
+///
+/// Everything above it (retrying transfers, choosing hosts, parsing probes) only builds
+/// commands and interprets their results, so it can be exercised with another implementation
+/// than [`System`], like [`DryRun`].
+pub trait Executor: std::fmt::Debug + Send + Sync {
+    /// Runs `command` with its stdin from `stdin` and inherited stderr, handing its stdout to
+    /// `stdout`, and waits for it to exit. `phase` tags the command in the logs.
+    fn run(
+        &self,
+        command: &mut Command,
+        phase: &str,
+        stdin: Input,
+        stdout: Output,
+    ) -> io::Result<ExitStatus>;
+
+    /// Runs `command` without stdin and returns its exit status and captured output.
+    fn output(&self, command: &mut Command, phase: &str) -> io::Result<std::process::Output>;
+
+    /// Whether the commands only pretend to run, so their output tells nothing about the remote.
+    fn dry_run(&self) -> bool {
+        false
+    }
+}
+
+/// Spawns the commands on this machine.
+#[derive(Debug)]
+pub struct System {
+    /// Route the stderr of every command into the trace log.
+    pub debug: bool,
+}
+
+impl Executor for System {
+    fn run(
+        &self,
+        command: &mut Command,
+        phase: &str,
+        stdin: Input,
+        stdout: Output,
+    ) -> io::Result<ExitStatus> {
+        if !self.debug {
+            return Process::new(command).stdin(stdin).stdout(stdout).run();
+        }
+
+        trace!(
+            "[{}] {}",
+            phase,
+            redact_key_paths(&format!("{:?}", command))
+        );
+        let phase = phase.to_owned();
+        Process::new(command)
+            .stdin(stdin)
+            .stdout(stdout)
+            .stderr(Output::Lines(Box::new(move |line| {
+                trace!("[{}] {}", phase, redact_key_paths(line));
+                Ok(())
+            })))
+            .run()
+    }
+
+    fn output(&self, command: &mut Command, phase: &str) -> io::Result<std::process::Output> {
+        trace!(
+            "[{}] {}",
+            phase,
+            redact_key_paths(&format!("{:?}", command))
+        );
+        let output = command.stdin(std::process::Stdio::null()).output()?;
+        if self.debug {
+            for line in String::from_utf8_lossy(&output.stderr).lines() {
+                trace!("[{}] {}", phase, redact_key_paths(line));
+            }
+        }
+        Ok(output)
+    }
+}
+
+/// Only logs the commands and pretends they succeeded without any output.
+#[derive(Debug)]
+pub struct DryRun;
+
+impl Executor for DryRun {
+    fn run(
+        &self,
+        command: &mut Command,
+        phase: &str,
+        _stdin: Input,
+        _stdout: Output,
+    ) -> io::Result<ExitStatus> {
+        info!(
+            "[{}] would run: {}",
+            phase,
+            redact_key_paths(&format!("{:?}", command))
+        );
+        Ok(ExitStatus::default())
+    }
+
+    fn output(&self, command: &mut Command, phase: &str) -> io::Result<std::process::Output> {
+        self.run(command, phase, Input::Inherit, Output::Inherit)?;
+        Ok(std::process::Output {
+            status: ExitStatus::default(),
+            stdout: Vec::new(),
+            stderr: Vec::new(),
+        })
+    }
+
+    fn dry_run(&self) -> bool {
+        true
+    }
+}
+
+/// Answers the commands with scripted exit codes and stdout instead of running them and
+/// records what was run, for tests of the logic above the executor.
+#[cfg(test)]
+#[derive(Debug, Default)]
+pub struct Scripted {
+    /// Exit code and stdout of the next commands in order, commands beyond them succeed
+    /// without output.
+    responses: std::sync::Mutex<std::collections::VecDeque<(i32, String)>>,
+    /// Program and arguments of every command run so far.
+    commands: std::sync::Mutex<Vec<Vec<String>>>,
+    /// What every command run so far got on stdin, empty if it was inherited.
+    inputs: std::sync::Mutex<Vec<String>>,
+}
+
+#[cfg(test)]
+impl Scripted {
+    pub fn new<'a>(responses: impl IntoIterator<Item = (i32, &'a str)>) -> Self {
+        Scripted {
+            responses: std::sync::Mutex::new(
+                responses
+                    .into_iter()
+                    .map(|(code, stdout)| (code, stdout.to_owned()))
+                    .collect(),
+            ),
+            commands: std::sync::Mutex::default(),
+            inputs: std::sync::Mutex::default(),
+        }
+    }
+
+    /// Program and arguments of the commands run so far.
+    pub fn commands(&self) -> Vec<Vec<String>> {
+        self.commands.lock().unwrap().clone()
+    }
+
+    /// What the commands run so far got on stdin, empty where it was inherited.
+    pub fn inputs(&self) -> Vec<String> {
+        self.inputs.lock().unwrap().clone()
+    }
+
+    fn answer(&self, command: &Command, stdin: &Input) -> (ExitStatus, String) {
+        let mut words = vec![command.get_program().to_string_lossy().into_owned()];
+        words.extend(
+            command
+                .get_args()
+                .map(|arg| arg.to_string_lossy().into_owned()),
+        );
+        self.commands.lock().unwrap().push(words);
+        self.inputs.lock().unwrap().push(match stdin {
+            Input::Inherit => String::new(),
+            Input::Bytes(bytes) => String::from_utf8_lossy(bytes).into_owned(),
+        });
+        let (code, stdout) = self
+            .responses
+            .lock()
+            .unwrap()
+            .pop_front()
+            .unwrap_or_default();
+        let status = std::os::unix::process::ExitStatusExt::from_raw(code << 8);
+        (status, stdout)
+    }
+}
+
+#[cfg(test)]
+impl Executor for Scripted {
+    fn run(
+        &self,
+        command: &mut Command,
+        _phase: &str,
+        stdin: Input,
+        stdout: Output,
+    ) -> io::Result<ExitStatus> {
+        let (status, output) = self.answer(command, &stdin);
+        match stdout {
+            Output::Inherit => {}
+            Output::Chunks(mut hook) => hook(output.as_bytes())?,
+            Output::Lines(mut hook) => {
+                for line in output.lines() {
+                    hook(line)?;
+                }
+            }
+        }
+        Ok(status)
+    }
+
+    fn output(&self, command: &mut Command, _phase: &str) -> io::Result<std::process::Output> {
+        let (status, stdout) = self.answer(command, &Input::Inherit);
+        Ok(std::process::Output {
+            status,
+            stdout: stdout.into_bytes(),
+            stderr: Vec::new(),
+        })
+    }
+}
@@ -0,0 +1,79 @@
+use std::io;
+use std::path::Path;
+use std::process::{Command, ExitStatus};
+
+use crate::script::quote;
+
+/// Placeholder in `flash_command` replaced by the path of the copied back firmware.
+const ARTIFACT_PLACEHOLDER: &str = "{artifact}";
+
+/// The firmware built by a `cargo build --target <triple>` for an embedded device.
+#[derive(Debug)]
+pub struct Firmware {
+    triple: String,
+    /// Directory of the profile in the target dir, e.g. `release`.
+    profile_dir: String,
+    /// Binary or `examples/<example>` inside the profile directory.
+    binary: String,
+}
+
+impl Firmware {
+    /// The firmware built by the cargo arguments `args`. Without `--bin` or `--example` the
+    /// project needs to have exactly one of `binaries`.
+    pub fn parse(args: &[String], binaries: &[String]) -> Result<Self, String> {
+        if !matches!(args.first().map(String::as_str), Some("build" | "b")) {
+            return Err("--flash needs a `build --target <triple>` command".to_owned());
+        }
+        let mut triple = None;
+        let mut profile_dir = "debug".to_owned();
+        let mut binary = None;
+        let mut args = args[1..].iter();
+        while let Some(arg) = args.next() {
+            let (option, inline_value) = match arg.split_once('=') {
+                Some((option, value)) => (option, Some(value.to_owned())),
+                None => (arg.as_str(), None),
+            };
+            let mut value = || inline_value.clone().or_else(|| args.next().cloned());
+            match option {
+                "--" => break,
+                "--release" | "-r" => profile_dir = "release".to_owned(),
+                "--profile" => {
+                    profile_dir = match value().as_deref() {
+                        Some("dev" | "test") => "debug".to_owned(),
+                        Some("bench") => "release".to_owned(),
+                        Some(profile) => profile.to_owned(),
+                        None => return Err("--profile is missing a value".to_owned()),
+                    }
+                }
+                "--target" => triple = value(),
+                "--bin" => binary = value(),
+                "--example" => binary = value().map(|example| format!("examples/{}", example)),
+                _ => {}
+            }
+        }
+        let triple = triple.ok_or("--flash needs the `--target` of the device")?;
+        let binary = match (binary, binaries) {
+            (Some(binary), _) => binary,
+            (None, [binary]) => binary.clone(),
+            (None, _) => {
+                return Err("the project has several binaries, choose one with --bin".to_owned())
+            }
+        };
+        Ok(Firmware {
+            triple,
+            profile_dir,
+            binary,
+        })
+    }
+
+    /// The firmware relative to the target dir, copied back from the remote.
+    pub fn file_name(&self) -> String {
+        format!("{}/{}/{}", self.triple, self.profile_dir, self.binary)
+    }
+}
+
+/// Runs the local `flash_command` with the firmware at `artifact`.
+pub fn flash(flash_command: &str, artifact: &Path) -> io::Result<ExitStatus> {
+    let command = flash_command.replace(ARTIFACT_PLACEHOLDER, &quote(&artifact.to_string_lossy()));
+    Command::new("sh").arg("-c").arg(command).status()
+}
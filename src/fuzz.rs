@@ -0,0 +1,137 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use tracing::{info, warn};
+
+use crate::backend;
+use crate::config::Remote;
+use crate::transport::Transport;
+
+/// Options of `cargo fuzz run` that take a value, which isn't the fuzz target then.
+const OPTIONS_WITH_VALUE: &[&str] = &[
+    "--sanitizer",
+    "-s",
+    "--features",
+    "--target",
+    "--triple",
+    "--fuzz-dir",
+    "--jobs",
+    "-j",
+    "--target-dir",
+];
+
+/// rsync exit code if some source files are missing, e.g. no artifacts were written.
+const PARTIAL_TRANSFER: i32 = 23;
+
+/// A `cargo fuzz run` invocation.
+#[derive(Debug)]
+pub struct FuzzRun {
+    pub target: String,
+    /// Directory of the fuzz crate relative to the project, `fuzz` by default.
+    pub fuzz_dir: String,
+}
+
+impl FuzzRun {
+    /// The fuzz run started by the cargo arguments `args`, if they are `fuzz run <target>`.
+    pub fn parse(args: &[String]) -> Option<Self> {
+        if args.len() < 3 || args[0] != "fuzz" || args[1] != "run" {
+            return None;
+        }
+        let mut fuzz_dir = "fuzz".to_owned();
+        let mut args = args[2..].iter();
+        while let Some(arg) = args.next() {
+            if arg == "--" {
+                return None;
+            }
+            if let Some(dir) = arg.strip_prefix("--fuzz-dir=") {
+                fuzz_dir = dir.to_owned();
+            } else if arg == "--fuzz-dir" {
+                fuzz_dir = args.next()?.clone();
+            } else if OPTIONS_WITH_VALUE.contains(&arg.as_str()) {
+                args.next();
+            } else if !arg.starts_with('-') {
+                return Some(FuzzRun {
+                    target: arg.clone(),
+                    fuzz_dir: fuzz_dir.trim_end_matches('/').to_owned(),
+                });
+            }
+        }
+        None
+    }
+
+    /// Adds the libFuzzer time limit of `duration` seconds to the cargo arguments `args`,
+    /// unless they already set one.
+    pub fn limit_duration(args: Vec<String>, duration: Option<u64>) -> Vec<String> {
+        let duration = match duration {
+            Some(duration) if !args.iter().any(|arg| arg.starts_with("-max_total_time")) => {
+                duration
+            }
+            _ => return args,
+        };
+        let mut args = args;
+        if !args.iter().any(|arg| arg == "--") {
+            args.push("--".to_owned());
+        }
+        args.push(format!("-max_total_time={}", duration));
+        args
+    }
+
+    /// Directories of the fuzz target synced back, relative to the project.
+    fn directories(&self) -> [String; 2] {
+        [
+            format!("{}/corpus/{}", self.fuzz_dir, self.target),
+            format!("{}/artifacts/{}", self.fuzz_dir, self.target),
+        ]
+    }
+
+    /// Merges the corpus and the crash artifacts found on the remote into the local project,
+    /// with the sync backend of `remote`.
+    ///
+    /// Nothing is deleted locally, so the corpus only grows. A dry run doesn't touch the
+    /// project.
+    pub fn sync_back(
+        &self,
+        transport: &Transport,
+        remote: &Remote,
+        host: &str,
+        build_path: &str,
+        project_dir: &Path,
+    ) -> io::Result<()> {
+        for directory in &self.directories() {
+            let local = project_dir.join(directory);
+            if !transport.dry_run() {
+                fs::create_dir_all(&local)?;
+            }
+            let status = backend::sync_backend(remote).fetch(
+                transport,
+                remote,
+                host,
+                &format!("{}{}/", build_path, directory),
+                &format!("{}/", local.to_string_lossy()),
+                false,
+                "fuzz-sync-back",
+            )?;
+            if !status.success() && status.code() != Some(PARTIAL_TRANSFER) {
+                return Err(io::Error::other(format!(
+                    "transferring {} failed ({})",
+                    directory, status
+                )));
+            }
+        }
+        if transport.dry_run() {
+            return Ok(());
+        }
+
+        let [corpus, artifacts] = self.directories();
+        info!("Merged the corpus into {:?}.", project_dir.join(corpus));
+        let crashes = fs::read_dir(project_dir.join(&artifacts))?.count();
+        if crashes > 0 {
+            warn!(
+                "{} has {} crash artifacts, reproduce them with `cargo fuzz run {} {}/<artifact>`.",
+                artifacts, crashes, self.target, artifacts
+            );
+        }
+        Ok(())
+    }
+}
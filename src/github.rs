@@ -0,0 +1,148 @@
+use std::io::{self, Write};
+use std::process::{Command, Stdio};
+
+use serde_json::{json, Value};
+
+/// Environment variables the GitHub token is read from, in order.
+pub const TOKEN_VARIABLES: &[&str] = &["GITHUB_TOKEN", "GH_TOKEN"];
+
+const API: &str = "https://api.github.com";
+
+/// `owner/repo` of the GitHub remote `origin` of the repository in the current directory.
+pub fn origin_repo() -> Option<String> {
+    let output = Command::new("git")
+        .args(["remote", "get-url", "origin"])
+        .output()
+        .ok()?;
+    let url = String::from_utf8_lossy(&output.stdout).trim().to_owned();
+    let path = url
+        .strip_prefix("git@github.com:")
+        .or_else(|| url.strip_prefix("ssh://git@github.com/"))
+        .or_else(|| url.strip_prefix("https://github.com/"))?;
+    Some(
+        path.trim_end_matches('/')
+            .trim_end_matches(".git")
+            .to_owned(),
+    )
+}
+
+fn token() -> io::Result<String> {
+    TOKEN_VARIABLES
+        .iter()
+        .find_map(|variable| std::env::var(variable).ok().filter(|t| !t.is_empty()))
+        .ok_or_else(|| {
+            io::Error::other(format!(
+                "no GitHub token, set one of {}",
+                TOKEN_VARIABLES.join(", ")
+            ))
+        })
+}
+
+/// Calls the GitHub API with curl, the token is passed on stdin to keep it out of the
+/// process list.
+fn request(method: &str, url: &str, body: Option<&Value>) -> io::Result<Value> {
+    request_with_headers(method, url, body).map(|(_, value)| value)
+}
+
+/// Like [`request`], but also returns the response headers.
+fn request_with_headers(
+    method: &str,
+    url: &str,
+    body: Option<&Value>,
+) -> io::Result<(String, Value)> {
+    let token = token()?;
+    let mut curl = Command::new("curl");
+    curl.args(["--silent", "--show-error", "--fail", "--request", method])
+        .args(["--dump-header", "-"])
+        .args(["--header", "@-"])
+        .args(["--header", "Accept: application/vnd.github+json"]);
+    if let Some(body) = body {
+        curl.args(["--header", "Content-Type: application/json"])
+            .args(["--data", &body.to_string()]);
+    }
+    let mut child = curl
+        .arg(url)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        writeln!(stdin, "Authorization: Bearer {}", token)?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "{} {} failed ({})",
+            method, url, output.status
+        )));
+    }
+    // the headers of the last response count, proxies may answer first
+    let response = String::from_utf8_lossy(&output.stdout);
+    let (mut headers, mut body) = ("", response.as_ref());
+    while body.starts_with("HTTP/") {
+        match body.split_once("\r\n\r\n") {
+            Some((next_headers, rest)) => (headers, body) = (next_headers, rest),
+            None => break,
+        }
+    }
+    Ok((headers.to_owned(), serde_json::from_str(body)?))
+}
+
+/// URL of the next page of a paginated response, from its `Link` header.
+fn next_page(headers: &str) -> Option<String> {
+    let (_, links) = headers.lines().find_map(|line| {
+        line.split_once(':')
+            .filter(|(name, _)| name.eq_ignore_ascii_case("link"))
+    })?;
+    links.split(',').find_map(|link| {
+        let (url, params) = link.split_once(';')?;
+        params
+            .split(';')
+            .any(|param| param.trim() == "rel=\"next\"")
+            .then(|| {
+                url.trim()
+                    .trim_start_matches('<')
+                    .trim_end_matches('>')
+                    .to_owned()
+            })
+    })
+}
+
+/// Updates the comment containing `marker` on pull request `pr` of `repo` or creates it.
+///
+/// Returns the URL of the comment.
+pub fn upsert_comment(repo: &str, pr: u64, marker: &str, body: &str) -> io::Result<String> {
+    let mut page = Some(format!(
+        "{}/repos/{}/issues/{}/comments?per_page=100",
+        API, repo, pr
+    ));
+    let mut existing = None;
+    while let Some(url) = page.take() {
+        let (headers, comments) = request_with_headers("GET", &url, None)?;
+        existing = comments.as_array().and_then(|comments| {
+            comments.iter().find_map(|comment| {
+                comment["body"]
+                    .as_str()
+                    .is_some_and(|body| body.contains(marker))
+                    .then(|| comment["id"].as_u64())
+                    .flatten()
+            })
+        });
+        if existing.is_none() {
+            page = next_page(&headers);
+        }
+    }
+    let body = json!({ "body": body });
+    let comment = match existing {
+        Some(id) => request(
+            "PATCH",
+            &format!("{}/repos/{}/issues/comments/{}", API, repo, id),
+            Some(&body),
+        )?,
+        None => request(
+            "POST",
+            &format!("{}/repos/{}/issues/{}/comments", API, repo, pr),
+            Some(&body),
+        )?,
+    };
+    Ok(comment["html_url"].as_str().unwrap_or_default().to_owned())
+}
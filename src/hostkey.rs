@@ -0,0 +1,179 @@
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process::{self, Command};
+
+use base64::engine::general_purpose::{STANDARD, STANDARD_NO_PAD};
+use base64::Engine;
+use sha2::{Digest, Sha256};
+
+use crate::config::Remote;
+use crate::transport::Transport;
+
+/// Seconds `ssh-keyscan` waits for the host.
+const KEYSCAN_TIMEOUT: &str = "10";
+
+/// Checks that `host` presents the `host_key_fingerprint` pinned for `remote`, if any, and
+/// returns the matching keys as known_hosts lines.
+///
+/// The keys are fetched with `ssh-keyscan`, so this works for hosts which aren't in the
+/// known_hosts file yet and never prompts. Hosts behind a `ProxyJump` or `ProxyCommand` can't
+/// be scanned directly, their key is taken from an ssh connection through the proxy instead.
+pub fn verify(transport: &Transport, remote: &Remote, host: &str) -> io::Result<Vec<String>> {
+    let expected = match &remote.host_key_fingerprint {
+        Some(expected) => expected,
+        None => return Ok(Vec::new()),
+    };
+    let target = resolve(transport, remote, host)?;
+    let scanned = if target.proxied {
+        connect_and_record(transport, remote, host)?
+    } else {
+        let mut keyscan = Command::new("ssh-keyscan");
+        keyscan
+            .args(["-T", KEYSCAN_TIMEOUT, "-p", &target.port])
+            .arg(&target.hostname);
+        let output = transport.output(&mut keyscan, "host-key")?;
+        String::from_utf8_lossy(&output.stdout).into_owned()
+    };
+    let keys: Vec<(String, String)> = scanned
+        .lines()
+        .filter_map(|line| Some((fingerprint(line)?, line.to_owned())))
+        .collect();
+    if keys.is_empty() {
+        return Err(io::Error::other(format!(
+            "no host keys received from {}:{}",
+            target.hostname, target.port
+        )));
+    }
+    let matching: Vec<String> = keys
+        .iter()
+        .filter(|(fingerprint, _)| fingerprint == expected)
+        .filter_map(|(_, line)| target.known_hosts_line(line))
+        .collect();
+    if matching.is_empty() {
+        Err(io::Error::other(format!(
+            "the host key of {} doesn't match {}, it has {}",
+            host,
+            expected,
+            keys.iter()
+                .map(|(fingerprint, _)| fingerprint.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        )))
+    } else {
+        Ok(matching)
+    }
+}
+
+/// Verifies `host` like [`verify`] and adds the matching keys to the [`known_hosts`] file of
+/// `remote`, the only keys every connection to the remote accepts.
+pub fn pin(transport: &Transport, remote: &Remote, host: &str) -> io::Result<()> {
+    let lines = verify(transport, remote, host)?;
+    if lines.is_empty() {
+        return Ok(());
+    }
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(known_hosts(remote))?;
+    for line in lines {
+        writeln!(file, "{}", line)?;
+    }
+    Ok(())
+}
+
+/// Temporary known_hosts file with the pinned keys of `remote`, removed at exit.
+pub fn known_hosts(remote: &Remote) -> PathBuf {
+    env::temp_dir().join(format!(
+        "cargo-remote-{}-{}-known-hosts",
+        process::id(),
+        remote.name
+    ))
+}
+
+/// Where ssh connects to for a host, which may be an alias from the ssh config.
+struct Target {
+    hostname: String,
+    port: String,
+    /// `HostKeyAlias` ssh looks the key up by instead of the hostname.
+    alias: Option<String>,
+    /// Whether the connection goes through a `ProxyJump` or `ProxyCommand`.
+    proxied: bool,
+}
+
+impl Target {
+    /// `line` of a scanned or recorded key under the name ssh looks it up by.
+    fn known_hosts_line(&self, line: &str) -> Option<String> {
+        let mut fields = line.split_whitespace().skip(1);
+        let (key_type, key) = (fields.next()?, fields.next()?);
+        let name = self.alias.as_deref().unwrap_or(&self.hostname);
+        let name = if self.port == "22" {
+            name.to_owned()
+        } else {
+            format!("[{}]:{}", name, self.port)
+        };
+        Some(format!("{} {} {}", name, key_type, key))
+    }
+}
+
+fn resolve(transport: &Transport, remote: &Remote, host: &str) -> io::Result<Target> {
+    let mut ssh = Command::new(&remote.ssh_path);
+    ssh.args(["-G", "-p", &remote.ssh_port.to_string()])
+        .arg(host);
+    let output = transport.output(&mut ssh, "host-key")?;
+    let config = String::from_utf8_lossy(&output.stdout);
+    let option = |name: &str| {
+        config.lines().find_map(|line| {
+            let (key, value) = line.split_once(' ')?;
+            let value = value.trim();
+            (key == name && value != "none").then(|| value.to_owned())
+        })
+    };
+    Ok(Target {
+        hostname: option("hostname")
+            .unwrap_or_else(|| host.rsplit('@').next().unwrap_or(host).to_owned()),
+        port: option("port").unwrap_or_else(|| remote.ssh_port.to_string()),
+        alias: option("hostkeyalias"),
+        proxied: option("proxyjump").is_some() || option("proxycommand").is_some(),
+    })
+}
+
+/// Connects to `host` through its proxy with a scratch known_hosts file and returns the key
+/// ssh recorded there. Only the key type ssh negotiates is received.
+fn connect_and_record(transport: &Transport, remote: &Remote, host: &str) -> io::Result<String> {
+    let scratch = env::temp_dir().join(format!(
+        "cargo-remote-{}-{}-scanned-hosts",
+        process::id(),
+        remote.name
+    ));
+    let _ = fs::remove_file(&scratch);
+    let mut ssh = Command::new(&remote.ssh_path);
+    ssh.args(["-p", &remote.ssh_port.to_string()])
+        .args(["-o", "BatchMode=yes"])
+        .args(["-o", &format!("ConnectTimeout={}", KEYSCAN_TIMEOUT)])
+        .args(["-o", "ControlPath=none"])
+        .args(["-o", "StrictHostKeyChecking=accept-new"])
+        .args(["-o", "GlobalKnownHostsFile=/dev/null"])
+        .arg("-o")
+        .arg(format!("UserKnownHostsFile={}", scratch.display()))
+        .args([host, "exit"]);
+    // the key is recorded during the key exchange, before authentication may fail
+    transport.output(&mut ssh, "host-key")?;
+    let recorded = fs::read_to_string(&scratch).unwrap_or_default();
+    let _ = fs::remove_file(&scratch);
+    Ok(recorded)
+}
+
+/// SHA256 fingerprint like `ssh-keygen -l` prints it of a `ssh-keyscan` or known_hosts line.
+fn fingerprint(line: &str) -> Option<String> {
+    if line.starts_with('#') {
+        return None;
+    }
+    let key = line.split_whitespace().nth(2)?;
+    let blob = STANDARD.decode(key).ok()?;
+    Some(format!(
+        "SHA256:{}",
+        STANDARD_NO_PAD.encode(Sha256::digest(&blob))
+    ))
+}
@@ -0,0 +1,182 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use tracing::info;
+
+use crate::config::Remote;
+use crate::script::{self, quote};
+use crate::transport::Transport;
+
+/// Separates the sections of the probe output.
+const SECTION_SEPARATOR: &str = "--- cargo-remote ---";
+
+/// Native libraries that are part of every base image and need no extra package.
+const SYSTEM_LIBS: &[&str] = &[
+    "c", "m", "dl", "rt", "pthread", "util", "gcc_s", "gcc", "stdc++", "c++", "resolv",
+];
+
+/// Build environment of a remote as found by [`probe`].
+#[derive(Debug, Default)]
+pub struct BuildEnv {
+    pub rust_version: String,
+    pub host_triple: String,
+    pub components: BTreeSet<String>,
+    pub targets: BTreeSet<String>,
+    /// Native libraries linked by build scripts of recent builds.
+    pub native_libs: BTreeSet<String>,
+}
+
+/// Collects the toolchain of the remote and the native libraries linked by the build scripts
+/// of previous builds in `build_path`.
+pub fn probe(
+    transport: &Transport,
+    remote: &Remote,
+    host: &str,
+    build_path: &str,
+) -> io::Result<BuildEnv> {
+    let probe = format!(
+        "{env}rustc -vV; echo {sep}; rustup component list --installed 2>/dev/null; \
+         echo {sep}; rustup target list --installed 2>/dev/null; echo {sep}; \
+         cat {path}{target}/*/build/*/output {path}{target}/*/*/build/*/output 2>/dev/null \
+         | grep '^cargo:rustc-link-lib=' | sort -u; true",
+        env = script::source_env(remote),
+        sep = quote(SECTION_SEPARATOR),
+        path = build_path,
+        target = crate::REMOTE_TARGET_DIR,
+    );
+    let output = transport.remote_output(remote, host, &probe)?;
+    Ok(parse_probe(&output))
+}
+
+fn parse_probe(output: &str) -> BuildEnv {
+    let mut sections = output.split(SECTION_SEPARATOR).map(str::lines);
+    let mut env = BuildEnv::default();
+
+    for line in sections.next().into_iter().flatten() {
+        if let Some(release) = line.strip_prefix("release: ") {
+            env.rust_version = release.trim().to_owned();
+        } else if let Some(host) = line.strip_prefix("host: ") {
+            env.host_triple = host.trim().to_owned();
+        }
+    }
+
+    let host_suffix = format!("-{}", env.host_triple);
+    for line in sections.next().into_iter().flatten() {
+        let component = line.trim().trim_end_matches(&host_suffix);
+        // part of every toolchain
+        if !matches!(component, "" | "cargo" | "rustc" | "rust-std" | "rust-docs") {
+            env.components.insert(component.to_owned());
+        }
+    }
+
+    for line in sections.next().into_iter().flatten() {
+        let target = line.trim();
+        if !target.is_empty() && target != env.host_triple {
+            env.targets.insert(target.to_owned());
+        }
+    }
+
+    for line in sections.next().into_iter().flatten() {
+        // cargo:rustc-link-lib=[KIND[:MODIFIERS]=]NAME[:RENAME]
+        let name = line.rsplit('=').next().unwrap_or_default();
+        let name = name.split(':').next().unwrap_or_default().trim();
+        if !name.is_empty() && !SYSTEM_LIBS.contains(&name) {
+            env.native_libs.insert(name.to_owned());
+        }
+    }
+
+    env
+}
+
+/// Debian package providing the headers of a native library, a best guess for unknown ones.
+fn dev_package(lib: &str) -> String {
+    match lib {
+        "ssl" | "crypto" => "libssl-dev".to_owned(),
+        "z" => "zlib1g-dev".to_owned(),
+        "bz2" => "libbz2-dev".to_owned(),
+        "lzma" => "liblzma-dev".to_owned(),
+        "udev" => "libudev-dev".to_owned(),
+        "dbus-1" => "libdbus-1-dev".to_owned(),
+        lib => format!("lib{}-dev", lib),
+    }
+}
+
+impl BuildEnv {
+    /// A Dockerfile reproducing this build environment on top of the official rust image.
+    pub fn dockerfile(&self) -> String {
+        let mut dockerfile = format!(
+            "# Generated by cargo-remote from a {} build server\nFROM rust:{}\n",
+            self.host_triple, self.rust_version
+        );
+        let packages: BTreeSet<_> = self
+            .native_libs
+            .iter()
+            .map(|lib| dev_package(lib))
+            .collect();
+        if !packages.is_empty() {
+            dockerfile.push_str(&format!(
+                "RUN apt-get update \\\n    && apt-get install -y --no-install-recommends pkg-config {} \\\n    && rm -rf /var/lib/apt/lists/*\n",
+                packages.into_iter().collect::<Vec<_>>().join(" ")
+            ));
+        }
+        if !self.components.is_empty() {
+            dockerfile.push_str(&format!(
+                "RUN rustup component add {}\n",
+                self.components
+                    .iter()
+                    .cloned()
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            ));
+        }
+        if !self.targets.is_empty() {
+            dockerfile.push_str(&format!(
+                "RUN rustup target add {}\n",
+                self.targets.iter().cloned().collect::<Vec<_>>().join(" ")
+            ));
+        }
+        dockerfile
+    }
+}
+
+/// Builds the image `tag` from `dockerfile` on the remote with docker and optionally pushes it.
+pub fn build(
+    transport: &Transport,
+    remote: &Remote,
+    host: &str,
+    dockerfile: &str,
+    tag: &str,
+    push: bool,
+) -> io::Result<()> {
+    // the Dockerfile doesn't copy anything, so an empty context is enough
+    let mut script = format!(
+        "{}printf %s {} | docker build -t {} -",
+        script::source_env(remote),
+        quote(dockerfile),
+        quote(tag)
+    );
+    if push {
+        script.push_str(&format!(" && docker push {}", quote(tag)));
+    }
+
+    info!("Building image {} on {}.", tag, host);
+    let mut ssh = transport.remote_command(remote, host, &script);
+    let status = transport.run(&mut ssh, "image")?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!("docker failed ({})", status)))
+    }
+}
+
+/// Keeps the Dockerfile in `<target_dir>/build-env/` so it can be inspected and versioned.
+pub fn save_dockerfile(target_dir: &Path, dockerfile: &str) -> io::Result<PathBuf> {
+    let path = target_dir.join("build-env").join("Dockerfile");
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(&path, dockerfile)?;
+    Ok(path)
+}
@@ -0,0 +1,168 @@
+use std::fs::OpenOptions;
+use std::io::{self, BufRead, Write};
+use std::path::{Path, PathBuf};
+
+use crate::config::{ContainerTool, Remote};
+use crate::script;
+use crate::transport::Transport;
+
+/// Asks `question` on the terminal and returns the trimmed answer.
+pub fn read_answer(question: &str) -> io::Result<String> {
+    print!("{} ", question);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    if io::stdin().lock().read_line(&mut answer)? == 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::UnexpectedEof,
+            "input ended before all questions were answered",
+        ));
+    }
+    Ok(answer.trim().to_owned())
+}
+
+/// Asks `question`, an empty answer selects `default`.
+fn ask(question: &str, default: &str) -> io::Result<String> {
+    let answer = if default.is_empty() {
+        read_answer(&format!("{}:", question))?
+    } else {
+        read_answer(&format!("{} [{}]:", question, default))?
+    };
+    Ok(if answer.is_empty() {
+        default.to_owned()
+    } else {
+        answer
+    })
+}
+
+pub fn ask_yes(question: &str, default: bool) -> io::Result<bool> {
+    let hint = if default { "[Y/n]" } else { "[y/N]" };
+    loop {
+        match read_answer(&format!("{} {}", question, hint))?
+            .to_lowercase()
+            .as_str()
+        {
+            "" => return Ok(default),
+            "y" | "yes" => return Ok(true),
+            "n" | "no" => return Ok(false),
+            _ => println!("Please answer yes or no."),
+        }
+    }
+}
+
+/// Interactively creates a remote, tests the connection to it and appends it to the project or
+/// the global config file. Returns the path of the written config file.
+pub fn run(project_dir: &Path) -> io::Result<PathBuf> {
+    let default = Remote::default();
+    let host = loop {
+        let host = ask("Build server (user@host or ssh config entry)", "")?;
+        if !host.is_empty() {
+            break host;
+        }
+    };
+    let name = ask("Name of the remote", &host)?;
+    let ssh_port = loop {
+        match ask("ssh port", &default.ssh_port.to_string())?.parse() {
+            Ok(port) => break port,
+            Err(e) => println!("Invalid port: {}", e),
+        }
+    };
+    let temp_dir = ask("Directory for the builds on the server", &default.temp_dir)?;
+    let env = ask(
+        "File sourced before building, sets up PATH for nix-shell",
+        &default.env,
+    )?;
+    let forward_env = ask(
+        "Local environment variables passed to the build, comma separated, `*` matches anything",
+        "",
+    )?
+    .split(',')
+    .map(str::trim)
+    .filter(|name| !name.is_empty())
+    .map(str::to_owned)
+    .collect();
+    let mut remote = Remote {
+        name,
+        host: host.clone(),
+        hosts: vec![host.clone()],
+        ssh_port,
+        temp_dir,
+        env,
+        forward_env,
+        ..default
+    };
+
+    println!("Testing the connection to {}.", host);
+    let check = format!(
+        "{}for tool in nix-shell docker podman buildah; do command -v $tool >/dev/null && echo $tool; done; true",
+        script::source_env(&remote)
+    );
+    let tools = match Transport::new(false).remote_output(&remote, &host, &check) {
+        Ok(tools) => {
+            let tools: Vec<String> = tools.lines().map(|tool| tool.trim().to_owned()).collect();
+            if tools.iter().any(|tool| tool == "nix-shell") {
+                println!("Connected, nix-shell is available.");
+            } else {
+                println!(
+                    "Connected, but nix-shell wasn't found after sourcing {}, builds will fail \
+                     until nix is installed or another env file is configured.",
+                    remote.env
+                );
+            }
+            if tools.iter().any(|tool| tool == "docker") {
+                println!(
+                    "docker is available, `cargo remote image` can create an image of the build \
+                     environment."
+                );
+            }
+            tools
+        }
+        Err(e) => {
+            println!("Connecting failed: {}", e);
+            if !ask_yes("Write the config anyway?", false)? {
+                return Err(io::Error::other("no config written"));
+            }
+            Vec::new()
+        }
+    };
+    // builds always run in the nix-shell, the container tool only packages them with --container
+    let detected = ["docker", "podman", "buildah"]
+        .iter()
+        .copied()
+        .find(|tool| tools.iter().any(|found| found == tool))
+        .unwrap_or("nix");
+    remote.container_tool = loop {
+        match ask(
+            "Build in nix-shell only, or also package --container images with docker, podman or \
+             buildah",
+            detected,
+        )?
+        .to_lowercase()
+        .as_str()
+        {
+            "nix" | "nix-shell" | "docker" => break ContainerTool::Docker,
+            "podman" => break ContainerTool::Podman,
+            "buildah" => break ContainerTool::Buildah,
+            _ => println!("Please answer nix, docker, podman or buildah."),
+        }
+    };
+
+    let path = if ask_yes(
+        &format!(
+            "Save in the project config {:?} (no: global config)?",
+            project_dir.join(".cargo-remote.toml")
+        ),
+        true,
+    )? {
+        project_dir.join(".cargo-remote.toml")
+    } else {
+        xdg::BaseDirectories::with_prefix("cargo-remote")?.place_config_file("cargo-remote.toml")?
+    };
+
+    // remotes are an array of tables, so appending keeps existing entries intact
+    let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+    if file.metadata()?.len() > 0 {
+        writeln!(file)?;
+    }
+    write!(file, "{}", remote)?;
+    Ok(path)
+}
@@ -0,0 +1,120 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+use crate::config::{LockfileStrategy, Remote, Shell};
+use crate::script;
+use crate::transport::Transport;
+use crate::windows;
+
+/// Name of the file the remote Cargo.lock is downloaded to before it replaces the local one.
+const FETCHED_LOCK_FILE: &str = "remote-Cargo.lock";
+
+/// Content of the local Cargo.lock at the time the sources were uploaded.
+#[derive(Debug)]
+pub struct LockSnapshot {
+    path: PathBuf,
+    contents: Option<Vec<u8>>,
+}
+
+/// What happened to the local Cargo.lock after the remote one was fetched.
+#[derive(Debug, PartialEq, Eq)]
+pub enum LockUpdate {
+    /// The remote build didn't change the lock file, nothing was written.
+    Unchanged,
+    /// The local lock file was replaced with the remote one.
+    Updated,
+    /// The local lock file was modified while the build was running and was left alone.
+    Conflict,
+}
+
+/// The local lock file used for builds on `remote`, `Cargo.lock` or `Cargo.lock.<remote>`
+/// depending on its `lockfile_strategy`.
+pub fn local_path(remote: &Remote, project_dir: &Path) -> PathBuf {
+    match remote.lockfile_strategy {
+        LockfileStrategy::Shared => project_dir.join("Cargo.lock"),
+        LockfileStrategy::PerRemote => {
+            let name = if remote.name.is_empty() {
+                &remote.host
+            } else {
+                &remote.name
+            };
+            let name: String = name
+                .chars()
+                .map(|c| if c == '/' || c == '\\' { '_' } else { c })
+                .collect();
+            project_dir.join(format!("Cargo.lock.{}", name))
+        }
+    }
+}
+
+impl LockSnapshot {
+    pub fn take(remote: &Remote, project_dir: &Path) -> Self {
+        let path = local_path(remote, project_dir);
+        let contents = fs::read(&path).ok();
+        LockSnapshot { path, contents }
+    }
+
+    /// The local lock file the snapshot was taken of.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Where the remote Cargo.lock should be downloaded to.
+    pub fn fetch_path(target_dir: &Path) -> PathBuf {
+        target_dir.join(FETCHED_LOCK_FILE)
+    }
+
+    /// Whether the Cargo.lock in `build_path` on `host` is still the uploaded one, compared by
+    /// checksum, so the build didn't change it and it doesn't have to be transferred back.
+    /// `false` if the checksum can't be determined.
+    pub fn unchanged_on(
+        &self,
+        transport: &Transport,
+        remote: &Remote,
+        host: &str,
+        build_path: &str,
+    ) -> bool {
+        let uploaded = match &self.contents {
+            Some(contents) => format!("{:x}", Sha256::digest(contents)),
+            None => return false,
+        };
+        let lock_file = format!("{}Cargo.lock", build_path);
+        let command = match remote.shell {
+            Shell::Sh => format!("{} {}", script::SHA256, lock_file),
+            Shell::PowerShell => windows::file_hash(&lock_file),
+        };
+        transport
+            .remote_output(remote, host, &command)
+            .map(|output| {
+                output
+                    .split_whitespace()
+                    .next()
+                    .is_some_and(|checksum| checksum.eq_ignore_ascii_case(&uploaded))
+            })
+            .unwrap_or(false)
+    }
+
+    /// Replaces the local lock file with `fetched` if it differs.
+    ///
+    /// Refuses to do so if the local lock file changed since the snapshot was taken, unless
+    /// `force` is set.
+    pub fn apply(&self, fetched: &Path, force: bool) -> io::Result<LockUpdate> {
+        let remote = fs::read(fetched)?;
+        let local = fs::read(&self.path).ok();
+
+        if local.as_deref() == Some(remote.as_slice()) {
+            fs::remove_file(fetched)?;
+            return Ok(LockUpdate::Unchanged);
+        }
+        if local != self.contents && !force {
+            return Ok(LockUpdate::Conflict);
+        }
+        // the target dir may live on another file system, so don't rename
+        fs::copy(fetched, &self.path)?;
+        fs::remove_file(fetched)?;
+        Ok(LockUpdate::Updated)
+    }
+}
@@ -1,102 +1,982 @@
-use simple_logger::SimpleLogger;
-use std::collections::hash_map::DefaultHasher;
-use std::hash::{Hash, Hasher};
+use clap::{ArgAction, Args, Parser, Subcommand, ValueEnum};
+use std::io::{IsTerminal, Read};
 use std::path::PathBuf;
-use std::process::{exit, Command, Stdio};
-use structopt::StructOpt;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use log::{error, info};
+use tracing::{error, info, warn};
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::EnvFilter;
 
+mod artifacts;
+mod backend;
+mod benchmark;
+mod build_info;
+mod build_lock;
+mod cache_stats;
+mod cargo_config;
+mod ci;
+mod compat;
 mod config;
+mod container;
+mod coverage;
+mod credentials;
+mod deploy;
+mod detach;
+mod dist;
+mod docs;
+mod doctor;
+mod executables;
+mod executor;
+mod firmware;
+mod fuzz;
+mod github;
+mod hostkey;
+mod image;
+mod init;
+mod lockfile;
+mod manifest;
+mod monitor;
+mod nextest;
+mod outdirs;
+mod output_cache;
+mod package;
+mod picker;
+mod prerequisites;
+mod probe;
+mod process;
+mod projects;
+mod provenance;
+mod provision;
+mod record;
+mod registry;
+mod regression;
+mod requirement;
+mod script;
+mod secrets;
+mod session;
+mod signals;
+mod summary;
+mod sync;
+mod sysroot;
+mod targets;
+mod timeout;
+mod tools;
+mod transport;
+mod validate;
+mod vendor;
+mod wasm;
+mod watch;
+mod windows;
 
-const PROGRESS_FLAG: &str = "--info=progress2";
+/// Target directory used by the remote cargo, relative to the build path.
+const REMOTE_TARGET_DIR: &str = "target";
 
-#[derive(StructOpt, Debug)]
+/// Work left for the end of the process however it ends, like deleting the build directory of
+/// `--ephemeral` or the instance of `--provision`.
+static AT_EXIT: Mutex<Vec<Box<dyn FnOnce() + Send>>> = Mutex::new(Vec::new());
+
+/// Adds `work` to [`AT_EXIT`], it runs before the work added earlier.
+fn at_exit(work: impl FnOnce() + Send + 'static) {
+    if let Ok(mut at_exit) = AT_EXIT.lock() {
+        at_exit.push(Box::new(work));
+    }
+}
+
+thread_local! {
+    /// Whether this thread is running [`AT_EXIT`], so a panic in there doesn't wait for itself.
+    static RUNNING_AT_EXIT: std::cell::Cell<bool> = const { std::cell::Cell::new(false) };
+}
+
+/// Runs and clears [`AT_EXIT`]. The lock is held meanwhile, so an [`exit`] on another thread,
+/// e.g. of a signal arriving during the teardown, waits for it instead of cutting it short.
+fn run_at_exit() {
+    if RUNNING_AT_EXIT.with(|running| running.replace(true)) {
+        return;
+    }
+    if let Ok(mut at_exit) = AT_EXIT.lock() {
+        // the build directory is deleted before the instance it is on
+        for work in std::mem::take(&mut *at_exit).into_iter().rev() {
+            work();
+        }
+    }
+    RUNNING_AT_EXIT.with(|running| running.set(false));
+}
+
+/// [`std::process::exit`] after running [`AT_EXIT`].
+fn exit(code: i32) -> ! {
+    run_at_exit();
+    std::process::exit(code)
+}
+
+/// Runs [`AT_EXIT`] when the process is interrupted, with the exit code of a shell.
+fn interrupted(signal: i32) {
+    warn!("Interrupted by signal {}, cleaning up.", signal);
+    exit(128 + signal)
+}
+
+/// Runs [`AT_EXIT`] when the main thread panics, after the default hook printed the panic.
+fn run_at_exit_on_panic() {
+    let default_hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        // panics of helper threads are reported to the main thread, which goes on
+        if std::thread::current().name() == Some("main") {
+            run_at_exit();
+        }
+    }));
+}
+
+#[derive(Args, Debug, Clone)]
 pub struct RemoteOpts {
     /// The name of the remote specified in the config
-    #[structopt(short = "r", long = "remote")]
+    #[arg(short = 'r', long = "remote", env = "CARGO_REMOTE_NAME", global = true)]
     name: Option<String>,
 
     /// Remote ssh build server with user or the name of the ssh entry
-    #[structopt(short = "H", long = "remote-host")]
+    #[arg(
+        short = 'H',
+        long = "remote-host",
+        env = "CARGO_REMOTE_HOST",
+        global = true
+    )]
     host: Option<String>,
 
     /// The ssh port to communicate with the build server
-    #[structopt(short = "p", long = "remote-ssh-port")]
+    #[arg(
+        short = 'p',
+        long = "remote-ssh-port",
+        env = "CARGO_REMOTE_PORT",
+        global = true
+    )]
     ssh_port: Option<u16>,
 
     /// The directory where cargo builds the project
-    #[structopt(short, long = "remote-temp-dir")]
+    #[arg(
+        short,
+        long = "remote-temp-dir",
+        env = "CARGO_REMOTE_TEMP_DIR",
+        global = true
+    )]
     temp_dir: Option<String>,
 
-    #[structopt(
-        short = "e",
+    #[arg(
+        short = 'e',
         long = "env",
+        env = "CARGO_REMOTE_ENV",
+        global = true,
         help = "Environment profile. default_value = /etc/profile"
     )]
     env: Option<String>,
 }
 
-#[derive(StructOpt, Debug)]
-#[structopt(name = "cargo-nix", bin_name = "cargo")]
+#[derive(Parser, Debug)]
+#[command(name = "cargo", bin_name = "cargo")]
 enum Opts {
-    #[structopt(name = "nix")]
-    Remote {
-        #[structopt(flatten)]
-        remote_opts: RemoteOpts,
-
-        #[structopt(
-            short = "c",
-            long = "copy-back",
-            help = "Transfer the target folder or specific file from that folder back to the local machine"
+    #[command(
+        name = "nix",
+        version,
+        about = "Build rust projects on a remote server",
+        // -h is --transfer-hidden
+        disable_help_flag = true
+    )]
+    Remote(Cli),
+}
+
+#[derive(Args, Debug)]
+struct Cli {
+    #[command(flatten)]
+    remote_opts: RemoteOpts,
+
+    // not global, after a subcommand --profile belongs to cargo
+    #[arg(
+        long = "profile",
+        env = "CARGO_REMOTE_PROFILE",
+        help = "Use the remote, cargo flags, copy back setting and environment of a profile from the config"
+    )]
+    profile: Option<String>,
+
+    #[arg(
+        short = 'c',
+        long = "copy-back",
+        global = true,
+        num_args = 0..=1,
+        require_equals = true,
+        group = "copy_back_any",
+        help = "Transfer the target folder or specific file from that folder back to the local machine"
+    )]
+    copy_back: Option<Option<String>>,
+
+    #[arg(
+        short = 'o',
+        long = "output-dir",
+        global = true,
+        help = "Copy the artifacts flat into this local directory instead of the target folder, keeping the files already in it",
+        requires = "copy_back_any"
+    )]
+    output_dir: Option<PathBuf>,
+
+    #[arg(
+        long = "copy-back-no-delete",
+        global = true,
+        help = "Merge the artifacts into the local directory instead of deleting files that don't exist remotely"
+    )]
+    copy_back_no_delete: bool,
+
+    #[arg(
+        long = "copy-back-outdirs",
+        global = true,
+        conflicts_with = "detach",
+        help = "Copy back the OUT_DIRs of build scripts (generated code) into the local target folder, e.g. for rust-analyzer"
+    )]
+    copy_back_outdirs: bool,
+
+    #[arg(
+        long = "copy-back-example",
+        value_name = "NAME",
+        global = true,
+        group = "copy_back_any",
+        conflicts_with = "detach",
+        help = "Copy back only the executable of this example, can be repeated"
+    )]
+    copy_back_example: Vec<String>,
+
+    #[arg(
+        long = "copy-back-bench",
+        value_name = "NAME",
+        global = true,
+        group = "copy_back_any",
+        conflicts_with = "detach",
+        help = "Copy back only the executable of this bench, built with bench --no-run, can be repeated"
+    )]
+    copy_back_bench: Vec<String>,
+
+    #[arg(
+        long = "copy-back-profile",
+        value_name = "PROFILE",
+        global = true,
+        conflicts_with = "fast",
+        help = "Copy back only the target folder of this cargo profile (dev, release or a custom one), implies --copy-back"
+    )]
+    copy_back_profile: Option<String>,
+
+    #[arg(
+        long = "package-artifacts",
+        global = true,
+        conflicts_with = "detach",
+        help = "Pack the artifacts matching package_artifacts from the config into one archive on the remote and copy back only that"
+    )]
+    package_artifacts: bool,
+
+    #[arg(
+        long = "deploy",
+        value_name = "TARGET",
+        global = true,
+        conflicts_with = "detach",
+        help = "After a successful build, push the artifacts of this [deploy] target from the config from the build server to its host (can be repeated)"
+    )]
+    deploy: Vec<String>,
+
+    #[arg(
+        long = "allow-triple-mismatch",
+        global = true,
+        help = "Copy back artifacts even if the build server has a different host triple than this machine"
+    )]
+    allow_triple_mismatch: bool,
+
+    #[arg(
+        long = "no-copy-lock",
+        global = true,
+        help = "don't transfer the Cargo.lock file back to the local machine"
+    )]
+    no_copy_lock: bool,
+
+    #[arg(
+        long = "force-lock",
+        global = true,
+        help = "Overwrite the local Cargo.lock with the remote one even if it was modified during the build"
+    )]
+    force_lock: bool,
+
+    #[arg(
+        long = "manifest-path",
+        global = true,
+        help = "Path to the manifest to execute",
+        default_value = "Cargo.toml"
+    )]
+    manifest_path: PathBuf,
+
+    #[arg(
+        long = "target-dir",
+        global = true,
+        help = "Local directory for all generated artifacts (defaults to the one cargo uses locally)"
+    )]
+    target_dir: Option<PathBuf>,
+
+    #[arg(
+        short = 'h',
+        long = "transfer-hidden",
+        global = true,
+        help = "Transfer hidden files and directories to the build server"
+    )]
+    hidden: bool,
+
+    #[arg(
+        long = "print-effective-config",
+        global = true,
+        help = "Print the remote resolved from flags, environment and config files, then exit"
+    )]
+    print_effective_config: bool,
+
+    #[arg(
+        long = "i-know-what-i-am-doing",
+        global = true,
+        help = "Run even if the temp_dir or shared_target_dir of the remote is a system or home directory"
+    )]
+    i_know_what_i_am_doing: bool,
+
+    #[arg(
+        long = "ephemeral",
+        global = true,
+        conflicts_with = "detach",
+        help = "Delete the remote build directory after the build and copy back, whether they succeed or not"
+    )]
+    ephemeral: bool,
+
+    #[arg(
+        long = "provision",
+        global = true,
+        value_name = "PROVIDER[:TYPE]",
+        conflicts_with_all = ["detach", "reconnect"],
+        help = "Build on a new instance of a cloud provider of the config, which is destroyed after the build and copy back"
+    )]
+    provision: Option<String>,
+
+    #[arg(
+        long = "show-filters",
+        global = true,
+        help = "Print the exclude filters applied when uploading the sources, then exit"
+    )]
+    show_filters: bool,
+
+    #[arg(
+        long = "debug-transport",
+        global = true,
+        help = "Run ssh and rsync verbosely and write their (redacted) stderr to the trace log"
+    )]
+    debug_transport: bool,
+
+    #[arg(
+        long = "record",
+        global = true,
+        num_args = 0..=1,
+        require_equals = true,
+        help = "Record the remote session in asciicast format, by default into <target dir>/remote-sessions/"
+    )]
+    record: Option<Option<String>>,
+
+    #[arg(
+        long = "json-summary",
+        global = true,
+        num_args = 0..=1,
+        require_equals = true,
+        help = "Write the results of the cargo commands as JSON, by default into <target dir>/remote-summary.json"
+    )]
+    json_summary: Option<Option<String>>,
+
+    #[arg(
+        long = "dry-run",
+        global = true,
+        help = "Log the ssh and rsync commands instead of running them"
+    )]
+    dry_run: bool,
+
+    #[arg(
+        long = "fast",
+        global = true,
+        help = "Only upload sources and manifests and copy nothing back, for a quick edit-check loop (e.g. `cargo remote check --fast`)"
+    )]
+    fast: bool,
+
+    #[arg(
+        long = "no-install",
+        global = true,
+        help = "Don't install missing cargo plugins like nextest on the remote"
+    )]
+    no_install: bool,
+
+    #[arg(
+        long = "checksum",
+        global = true,
+        help = "Find changed files by checksum instead of size and modification time, for unreliable mtimes (Docker volumes, CI caches)"
+    )]
+    checksum: bool,
+
+    #[arg(
+        long = "no-wait",
+        global = true,
+        help = "Fail right away if another build of the project is running on the remote instead of waiting for it"
+    )]
+    no_wait: bool,
+
+    #[arg(
+        long = "flash",
+        global = true,
+        help = "Copy back the firmware of `build --target <triple>` and flash it with the local `flash_command` of the remote"
+    )]
+    flash: bool,
+
+    #[arg(
+        long = "container",
+        global = true,
+        value_name = "TAG",
+        help = "After a successful build, build the image TAG from the `dockerfile` of the remote and load it into the local docker"
+    )]
+    container: Option<String>,
+
+    #[arg(
+        long = "push-container",
+        global = true,
+        requires = "container",
+        help = "Push the image of --container to its registry instead of loading it locally"
+    )]
+    push_container: bool,
+
+    #[arg(
+        long = "shard",
+        global = true,
+        help = "Split `nextest run` into one partition per configured remote and run them all at the same time"
+    )]
+    shard: bool,
+
+    #[arg(
+        long = "junit",
+        global = true,
+        requires = "shard",
+        help = "Merge the JUnit reports of all shards into this file"
+    )]
+    junit: Option<PathBuf>,
+
+    #[arg(
+        long = "detach",
+        global = true,
+        help = "Start the build on the remote in the background and return immediately, see attach, status and logs"
+    )]
+    detach: bool,
+
+    #[arg(
+        long = "reconnect",
+        global = true,
+        conflicts_with_all = ["detach", "record", "ci", "timeout"],
+        help = "Keep the build running on the remote when the connection drops and resume its output after reconnecting"
+    )]
+    reconnect: bool,
+
+    #[arg(
+        long = "timeout",
+        global = true,
+        value_name = "DURATION",
+        value_parser = timeout::parse_duration,
+        conflicts_with = "detach",
+        help = "Stop the build and everything it started on the remote if it runs longer than this, e.g. 90s, 30m or 1h30m"
+    )]
+    timeout: Option<Duration>,
+
+    #[arg(
+        long = "timeout-copy-back",
+        global = true,
+        requires = "timeout",
+        help = "After a timeout, copy back the logs of the build scripts and what --copy-back selects before exiting"
+    )]
+    timeout_copy_back: bool,
+
+    #[arg(
+        long = "monitor",
+        global = true,
+        value_name = "SECONDS",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "10",
+        conflicts_with = "detach",
+        help = "Log the load, memory and disk usage of the remote every SECONDS (10 by default) during the build"
+    )]
+    monitor: Option<u64>,
+
+    #[arg(
+        long = "fail-on-size-increase",
+        global = true,
+        value_name = "PERCENT",
+        help = "Fail if a copied back artifact grew by more than PERCENT since the last build of the same commands"
+    )]
+    fail_on_size_increase: Option<f64>,
+
+    #[arg(
+        long = "transfer-jobs",
+        global = true,
+        value_name = "N",
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u16).range(1..),
+        help = "Upload the top level directories of the project with up to N rsync processes at once, for fast links with a high latency"
+    )]
+    transfer_jobs: u16,
+
+    #[arg(
+        long = "no-cache",
+        global = true,
+        help = "Build again even if an earlier `build` of the same sources, commands and toolchain left artifacts to copy back"
+    )]
+    no_cache: bool,
+
+    #[arg(
+        long = "vendor",
+        global = true,
+        help = "Vendor the dependencies locally and build offline with them, for remotes without access to crates.io"
+    )]
+    vendor: bool,
+
+    #[arg(
+        long = "distribute",
+        global = true,
+        help = "Experimental: compile on all configured remotes with sccache-dist, scheduled by the build server"
+    )]
+    distribute: bool,
+
+    #[arg(
+        long = "ci",
+        global = true,
+        value_enum,
+        help = "Format the output for a CI system: log groups, error annotations and no progress output"
+    )]
+    ci: Option<ci::Ci>,
+
+    #[arg(
+        long = "log-format",
+        global = true,
+        value_enum,
+        default_value_t = LogFormat::Text,
+        help = "Format of the log written to stderr"
+    )]
+    log_format: LogFormat,
+
+    #[arg(long = "help", global = true, action = ArgAction::Help, help = "Print help")]
+    help: Option<bool>,
+
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+enum ConfigAction {
+    /// Print the config of the remote with the file, environment variable or flag of every setting
+    Show {
+        /// Also print the settings left at their defaults
+        #[arg(long)]
+        defaults: bool,
+    },
+}
+
+#[derive(Subcommand, Debug)]
+enum SecretAction {
+    /// Save a secret in the system keyring, or encrypt it with age, and print its config line
+    Set {
+        /// Name of the secret, also the environment variable in the printed config line
+        name: String,
+
+        #[arg(
+            long = "age",
+            value_name = "RECIPIENT",
+            help = "Encrypt the value read from stdin for this age recipient instead"
+        )]
+        age: Option<String>,
+    },
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+enum LogFormat {
+    Text,
+    /// One JSON object per line, for log aggregation systems
+    Json,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run several cargo commands one after another in the same remote session
+    Run {
+        #[arg(
+            value_name = "CARGO_COMMAND",
+            required = true,
+            help = "Complete cargo command lines, e.g. 'clippy -- -D warnings' test"
+        )]
+        commands: Vec<String>,
+    },
+
+    /// Run a cargo command once for every set of features and summarize which of them pass
+    Matrix {
+        /// Comma separated features of one combination, repeat for every combination. An empty
+        /// set builds without --features
+        #[arg(long = "features-set", value_name = "FEATURES", required = true)]
+        features_sets: Vec<String>,
+        /// Disable the default features in every combination
+        #[arg(long)]
+        no_default_features: bool,
+        /// Run all combinations at the same time, each with its own target dir
+        #[arg(long)]
+        parallel: bool,
+        /// Cargo command run for every combination, e.g. `-- clippy --all-targets`
+        #[arg(
+            value_name = "CARGO_COMMAND",
+            default_value = "check",
+            trailing_var_arg = true,
+            allow_hyphen_values = true
+        )]
+        command: Vec<String>,
+    },
+
+    /// Measure the test coverage on the remote and copy back only the reports into <target dir>/coverage
+    Coverage {
+        /// Cargo plugin measuring the coverage, installed on the remote if it is missing
+        #[arg(long, value_enum, default_value_t = coverage::Tool::LlvmCov)]
+        tool: coverage::Tool,
+        /// Also generate an HTML report
+        #[arg(long)]
+        html: bool,
+        /// Arguments passed on to the tool, e.g. `-- --workspace`
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+
+    /// Build the library for wasm with wasm-bindgen and copy back only the package into <target dir>/pkg
+    Wasm {
+        /// Kind of JavaScript module generated
+        #[arg(long, value_enum, default_value_t = wasm::Target::Web)]
+        target: wasm::Target,
+        /// Build with the dev profile instead of the release profile
+        #[arg(long)]
+        dev: bool,
+        /// Arguments passed on to cargo build, e.g. `-- --features web`
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+
+    /// Build the documentation on the remote and copy it back into <target dir>/doc
+    Doc {
+        /// Serve the copied back docs on this local port until interrupted
+        #[arg(
+            long,
+            value_name = "PORT",
+            num_args = 0..=1,
+            default_missing_value = "8000"
+        )]
+        serve: Option<u16>,
+        /// Open the docs in the browser
+        #[arg(long)]
+        open: bool,
+        /// Arguments passed on to cargo doc, e.g. `-- --no-deps --document-private-items`
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+
+    /// Run cargo package on the remote and copy back <target dir>/package with the .crate files
+    Package {
+        /// Arguments passed on to cargo package, e.g. `-- --allow-dirty`
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+
+    /// Run cargo publish on the remote with the registry token from the local environment or keyring
+    Publish {
+        /// Registry to publish to, crates.io by default
+        #[arg(long)]
+        registry: Option<String>,
+        /// Arguments passed on to cargo publish, e.g. `-- --dry-run`
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+
+    /// Check Cargo.lock for security advisories with cargo-audit on the remote, installed there if needed
+    Audit {
+        /// Arguments passed on to cargo audit, e.g. `-- --deny warnings`
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+
+    /// Run cargo-deny on the remote, installed there if needed, `check` by default
+    Deny {
+        /// Arguments passed on to cargo deny, e.g. `check advisories`
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+
+    /// Open an interactive nix-shell in the remote build directory (the default without a command)
+    Shell,
+
+    /// Delete the remote build directory of the project
+    Clean,
+
+    /// Check the config, the local tools and the connection to the remote
+    Doctor,
+
+    /// Open a persistent ssh session to the remote, used by all commands until `disconnect`
+    Connect,
+
+    /// Close the ssh sessions to the remote opened with `connect`
+    Disconnect,
+
+    /// List the configured remotes and what is known about them, without connecting
+    List,
+
+    /// Forget what is known about the remotes and probe them again
+    Refresh,
+
+    /// Delete build directories on the remote that weren't used for a while
+    Gc {
+        #[arg(
+            long = "older-than",
+            default_value_t = 30,
+            help = "Minimum age in days"
+        )]
+        older_than: u32,
+
+        #[arg(
+            long = "orphans",
+            help = "Delete the build directories of local projects that no longer exist instead, regardless of their age"
         )]
-        copy_back: Option<Option<String>>,
+        orphans: bool,
 
-        #[structopt(
-            long = "no-copy-lock",
-            help = "don't transfer the Cargo.lock file back to the local machine"
+        #[arg(
+            long = "dry-run",
+            help = "Only list the directories that would be deleted"
         )]
-        no_copy_lock: bool,
+        dry_run: bool,
+    },
+
+    /// Interactively set up a remote, test the connection and save it in the project or global config
+    Init,
+
+    /// Check the config files for errors and unknown keys and print the merged config
+    ValidateConfig,
+
+    /// Show the effective config and where every setting comes from
+    Config {
+        #[command(subcommand)]
+        action: ConfigAction,
+    },
+
+    /// Manage the secrets referenced by the `secrets` of the remotes
+    Secret {
+        #[command(subcommand)]
+        action: SecretAction,
+    },
+
+    /// Check the config and the cached remote probe without any network access
+    VersionCheck,
+
+    /// List the installed targets, cross tools and linkers of every remote and where the given targets can be built
+    Targets {
+        #[arg(value_name = "TARGET")]
+        targets: Vec<String>,
+    },
+
+    /// Sync and build a sample crate on every remote and compare the times and CPUs
+    Benchmark,
+
+    /// Keep syncing the sources to the remote whenever they weren't changed for a few seconds
+    Watch {
+        #[arg(
+            long = "idle",
+            default_value_t = 2,
+            help = "Seconds without changes before syncing"
+        )]
+        idle: u64,
+    },
+
+    /// Sync and build all [projects] from the config in dependency order
+    BuildAll {
+        #[arg(
+            value_name = "COMMAND",
+            help = "cargo commands for every project, `build` by default"
+        )]
+        commands: Vec<String>,
+    },
+
+    /// Build a container image reproducing the build environment of the remote
+    Image {
+        tag: String,
+
+        #[arg(long = "push", help = "Push the image after building it")]
+        push: bool,
+    },
+
+    /// Show the sccache or ccache statistics recorded by the last builds with a compiler_cache
+    CacheStats,
+
+    /// Post the summary written by --json-summary as a comment on a GitHub pull request, or update it
+    Comment {
+        #[arg(
+            long = "github-pr",
+            value_name = "NUMBER",
+            help = "Number of the pull request"
+        )]
+        github_pr: u64,
+
+        #[arg(
+            long = "repo",
+            env = "GITHUB_REPOSITORY",
+            help = "owner/repo on GitHub, by default the one of the git remote origin"
+        )]
+        repo: Option<String>,
 
-        #[structopt(
-            long = "manifest-path",
-            help = "Path to the manifest to execute",
-            default_value = "Cargo.toml",
-            parse(from_os_str)
+        #[arg(
+            long = "summary",
+            help = "Summary to post, by default the one of the last build"
         )]
-        manifest_path: PathBuf,
+        summary: Option<PathBuf>,
+    },
+
+    /// Attach to a build started with --detach, the last one by default
+    Attach {
+        #[arg(value_name = "ID")]
+        id: Option<u32>,
+    },
 
-        #[structopt(
-            short = "h",
-            long = "transfer-hidden",
-            help = "Transfer hidden files and directories to the build server"
+    /// Show whether a build started with --detach is still running, the last one by default
+    Status {
+        #[arg(value_name = "ID")]
+        id: Option<u32>,
+    },
+
+    /// Print the log of a build started with --detach, the last one by default
+    Logs {
+        #[arg(value_name = "ID")]
+        id: Option<u32>,
+
+        #[arg(
+            short = 'f',
+            long = "follow",
+            help = "Keep printing new output until the build finished"
         )]
-        hidden: bool,
+        follow: bool,
+    },
+
+    /// List the builds started with --detach and copy back the artifacts of finished ones
+    Jobs,
+
+    /// Stop a build started with --detach
+    Cancel {
+        #[arg(value_name = "ID")]
+        id: u32,
     },
+
+    /// Any cargo command, e.g. `cargo remote build --release`
+    #[command(external_subcommand)]
+    Cargo(Vec<String>),
 }
 
 fn main() {
-    SimpleLogger::new()
-        .with_level(log::LevelFilter::Info)
-        .with_utc_timestamps()
-        .env()
-        .init()
-        .unwrap();
-
-    let Opts::Remote {
-        remote_opts,
+    let Opts::Remote(Cli {
+        mut remote_opts,
+        profile,
         copy_back,
+        output_dir,
+        copy_back_no_delete,
+        copy_back_outdirs,
+        copy_back_example,
+        copy_back_bench,
+        copy_back_profile,
+        package_artifacts,
+        deploy,
+        allow_triple_mismatch,
         no_copy_lock,
+        force_lock,
         manifest_path,
+        target_dir,
         hidden,
-    } = Opts::from_args();
+        print_effective_config,
+        i_know_what_i_am_doing,
+        ephemeral,
+        provision,
+        show_filters,
+        debug_transport,
+        record,
+        json_summary,
+        dry_run,
+        mut fast,
+        no_install,
+        checksum,
+        no_wait,
+        flash,
+        container,
+        push_container,
+        shard,
+        junit,
+        detach,
+        reconnect,
+        timeout,
+        timeout_copy_back,
+        monitor,
+        fail_on_size_increase,
+        transfer_jobs,
+        no_cache,
+        vendor,
+        distribute,
+        ci,
+        log_format,
+        help: _,
+        mut command,
+    }) = Opts::parse_from(join_copy_back_path(std::env::args_os()));
+
+    init_logging(debug_transport, log_format);
+    apply_remote_uri(&mut remote_opts);
+
+    fast |= take_fast_flag(&mut command);
+    // the checks only read the manifests and Cargo.lock and produce no artifacts
+    if matches!(command, Some(Command::Audit { .. } | Command::Deny { .. })) {
+        fast = true;
+    }
+    let copy_back = if fast && copy_back.is_some() {
+        warn!("--fast doesn't copy back any artifacts, ignoring --copy-back.");
+        None
+    } else {
+        copy_back
+    };
+    let flash = if fast && flash {
+        warn!("--fast doesn't copy back any artifacts, ignoring --flash.");
+        false
+    } else {
+        flash
+    };
+    let coverage = matches!(command, Some(Command::Coverage { .. }));
+    let wasm = matches!(command, Some(Command::Wasm { .. }));
+    let (doc, serve_docs, open_docs) = match &command {
+        Some(Command::Doc { serve, open, .. }) => (true, *serve, *open),
+        _ => (false, None, false),
+    };
+    let copy_back = command_output(&command).or(copy_back);
+
+    run_config_command(&command, &manifest_path, &remote_opts, profile.as_deref());
 
+    let manifest_path = std::fs::canonicalize(&manifest_path).unwrap_or(manifest_path);
     let mut metadata_cmd = cargo_metadata::MetadataCommand::new();
-    metadata_cmd.manifest_path(manifest_path).no_deps();
+    metadata_cmd.manifest_path(&manifest_path).no_deps();
 
     let project_metadata = metadata_cmd.exec().unwrap();
     let project_dir = project_metadata.workspace_root;
     info!("Project dir: {:?}", project_dir);
+    // respects CARGO_TARGET_DIR and build.target-dir from the local cargo config
+    let target_dir = target_dir.unwrap_or(project_metadata.target_directory);
+    info!("Target dir: {:?}", target_dir);
+    // the sources are still synced from the workspace root so path dependencies resolve
+    let member = project_metadata
+        .packages
+        .iter()
+        .find(|package| {
+            package.manifest_path == manifest_path
+                && manifest_path != project_dir.join("Cargo.toml")
+        })
+        .map(|package| package.name.clone());
+    if let Some(member) = &member {
+        info!("Building only the workspace member {}.", member);
+    }
 
     let conf = match config::Config::new(&project_dir) {
         Ok(conf) => conf,
@@ -105,128 +985,3093 @@ fn main() {
             exit(-3);
         }
     };
+    for problem in validate::unknown_keys(&project_dir) {
+        warn!("{}, it is ignored", problem);
+    }
 
-    let remote = match conf.get_remote(&remote_opts) {
-        Some(remote) => remote,
+    let profile = load_profile(&conf, profile.as_deref());
+    if remote_opts.name.is_none() && remote_opts.host.is_none() {
+        remote_opts.name = profile.remote.clone();
+    }
+    let copy_back = match (&copy_back, &profile.copy_back) {
+        (None, Some(profile_copy_back)) if !fast => profile_copy_back.as_flag(),
+        _ => copy_back,
+    };
+    if let Some(Command::Cargo(args)) = &mut command {
+        let end = args
+            .iter()
+            .position(|arg| arg == "--")
+            .unwrap_or(args.len());
+        args.splice(end..end, profile.cargo_flags.iter().cloned());
+    }
+
+    let packages = &project_metadata.packages;
+    let firmware = flash.then(|| parse_firmware(&command, packages, &conf));
+    let copy_back = match &firmware {
+        Some(firmware) => Some(Some(firmware.file_name())),
+        None => copy_back,
+    };
+
+    if let Some(Command::CacheStats) = &command {
+        show_cache_stats(&target_dir);
+        exit(0);
+    }
+    if let Some(Command::Comment {
+        github_pr,
+        repo,
+        summary,
+    }) = &command
+    {
+        exit(post_comment(
+            &target_dir,
+            *github_pr,
+            repo.clone(),
+            summary.clone(),
+        ));
+    }
+
+    let excludes = sync::source_excludes(&conf, &project_dir, &target_dir, hidden);
+    for problem in cargo_config::remote_problems(&project_dir) {
+        warn!(
+            "The cargo config of the project won't work remotely: {}",
+            problem
+        );
+    }
+    if show_filters {
+        print_filters(&excludes, fast);
+        exit(0);
+    }
+
+    let provisioning = provision
+        .as_deref()
+        .map(|provision| provider(&conf, provision));
+    let configured = conf.remotes(&remote_opts);
+    let explicit = remote_opts.name.is_some() || remote_opts.host.is_some() || provision.is_some();
+    let picked = if explicit {
+        None
+    } else {
+        pick_remote(&configured, &project_dir, ci)
+    };
+    let explicit = explicit || picked.is_some();
+    let remote = match picked.or_else(|| {
+        select_remote(
+            &conf,
+            &remote_opts,
+            provisioning.as_ref().map(|(provider, _)| provider),
+            provision.as_deref(),
+        )
+    }) {
+        Some(remote) => config::Remote {
+            checksum: remote.checksum || checksum,
+            ..remote
+        },
         None => {
             error!("No remote build server was defined (use config file or the --remote flags)");
             exit(4);
         }
     };
 
-    let build_server = remote.host;
+    if print_effective_config {
+        print!("{}", remote);
+        exit(0);
+    }
+    let check_paths = |remote: &config::Remote| {
+        check_build_path(remote, &project_dir, i_know_what_i_am_doing);
+    };
+    check_paths(&remote);
+    if package_artifacts && remote.package_artifacts.is_empty() {
+        error!("--package-artifacts needs the package_artifacts of the remote in the config");
+        exit(-24);
+    }
+    let deploy_targets = deploy_targets(&conf, &deploy);
 
-    // generate a unique build path by using the hashed project dir as folder on the remote machine
-    let mut hasher = DefaultHasher::new();
-    project_dir.hash(&mut hasher);
-    let build_path = format!("{}/{}/", remote.temp_dir, hasher.finish());
+    let mut transport = if dry_run {
+        transport::Transport::with_executor(debug_transport, Arc::new(executor::DryRun))
+    } else {
+        transport::Transport::new(debug_transport)
+    };
+    transport.ci = ci;
+    transport.transfer_jobs = usize::from(transfer_jobs);
+    match &command {
+        Some(Command::Targets { targets }) => {
+            let supported = targets::report(&transport, &conf.remotes(&remote_opts), targets);
+            exit(if supported { 0 } else { 1 });
+        }
+        Some(Command::Doctor) => {
+            exit(doctor::run(&transport, &remote, &project_dir));
+        }
+        Some(Command::Disconnect) => exit(disconnect(&transport, &remote)),
+        Some(Command::Cargo(args)) if shard => {
+            let args = sharded_nextest_args(conf.expand_alias(args.clone()), member.as_deref());
+            let remotes: Vec<_> = conf
+                .remotes(&remote_opts)
+                .into_iter()
+                .map(|remote| config::Remote {
+                    checksum: remote.checksum || checksum,
+                    ..remote
+                })
+                .collect();
+            remotes.iter().for_each(check_paths);
+            let passed = nextest::run_sharded(
+                &transport,
+                &remotes,
+                &project_dir,
+                &target_dir,
+                &excludes,
+                &args,
+                junit.as_deref(),
+            );
+            exit(if passed { 0 } else { 1 });
+        }
+        Some(Command::Refresh) => {
+            exit(refresh_remotes(&transport, &conf.remotes(&remote_opts)));
+        }
+        Some(Command::Benchmark) => {
+            let remotes = conf.remotes(&remote_opts);
+            remotes.iter().for_each(check_paths);
+            let measured = benchmark::run(&transport, &remotes, &target_dir);
+            exit(if measured { 0 } else { 1 });
+        }
+        Some(
+            command @ (Command::Attach { .. }
+            | Command::Status { .. }
+            | Command::Logs { .. }
+            | Command::Jobs
+            | Command::Cancel { .. }),
+        ) => {
+            exit(manage_jobs(
+                &transport,
+                &conf.remotes(&remote_opts),
+                &remote,
+                &target_dir,
+                command,
+            ));
+        }
+        _ => {}
+    }
 
-    info!("Transferring sources to build server.");
-    // transfer project to build server
-    let mut rsync_to = Command::new("rsync");
-    rsync_to
-        .arg("-a".to_owned())
-        .arg("--delete")
-        .arg("--compress")
-        .arg("-e")
-        .arg(format!("ssh -p {}", remote.ssh_port))
-        .arg(PROGRESS_FLAG)
-        .arg("--exclude")
-        .arg("target");
-
-    if !hidden {
-        rsync_to.arg("--exclude").arg(".*");
-    }
-
-    rsync_to
-        .arg("--rsync-path")
-        .arg("mkdir -p rust && rsync")
-        .arg(format!("{}/", project_dir.to_string_lossy()))
-        .arg(format!("{}:{}", build_server, build_path))
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit())
-        .stdin(Stdio::inherit())
-        .output()
-        .unwrap_or_else(|e| {
-            error!("Failed to transfer project to build server (error: {})", e);
-            exit(-4);
-        });
-    info!("Environment profile: {:?}", remote.env);
-    info!("Build path: {:?}", build_path);
-    let build_command = format!("source {}; cd {}; nix-shell;", remote.env, build_path,);
-
-    info!("Starting build process.");
-    let output = Command::new("ssh")
-        .args(&["-p", &remote.ssh_port.to_string()])
-        .arg("-t")
-        .arg(&build_server)
-        .arg(build_command)
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit())
-        .stdin(Stdio::inherit())
-        .output()
-        .unwrap_or_else(|e| {
-            error!("Failed to run cargo command remotely (error: {})", e);
-            exit(-5);
-        });
+    check_exec_program(&remote);
 
-    if let Some(file_name) = copy_back {
-        info!("Transferring artifacts back to client.");
-        let file_name = file_name.unwrap_or_else(String::new);
-        Command::new("rsync")
-            .arg("-a")
-            .arg("--delete")
-            .arg("--compress")
-            .arg("-e")
-            .arg(format!("ssh -p {}", remote.ssh_port))
-            .arg(PROGRESS_FLAG)
-            .arg(format!(
-                "{}:{}target/{}",
-                build_server, build_path, file_name
-            ))
-            .arg(format!(
-                "{}/target/{}",
-                project_dir.to_string_lossy(),
-                file_name
-            ))
-            .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit())
-            .stdin(Stdio::inherit())
-            .output()
-            .unwrap_or_else(|e| {
-                error!(
-                    "Failed to transfer target back to local machine (error: {})",
-                    e
-                );
-                exit(-6);
-            });
+    let remote = match &provisioning {
+        Some((provider, instance_type)) => provision_remote(
+            &transport,
+            remote,
+            provider,
+            instance_type.as_deref(),
+            dry_run,
+        ),
+        None => remote,
+    };
+    let remote = if dry_run {
+        remote
+    } else {
+        match fallback_remote(&transport, &conf, &remote, &configured, explicit) {
+            Some(other) => {
+                check_paths(other);
+                config::Remote {
+                    checksum: other.checksum || checksum,
+                    ..other.clone()
+                }
+            }
+            None => remote,
+        }
+    };
+    let mut build_server = match transport.select_host(&remote) {
+        Some(host) => host,
+        None => {
+            error!(
+                "None of the hosts of the remote is reachable: {:?}",
+                remote.hosts
+            );
+            exit(-9);
+        }
+    };
+    info!("Using build server {}", build_server);
+    if !transport.pin_host_key(&remote, &build_server) {
+        exit(-15);
+    }
+    let mut remote = remote;
+    if let Some(Command::Connect) = command {
+        exit(connect(&transport, &remote, &build_server));
+    }
+    let facts = adapt_to_host(&mut transport, &mut remote, &build_server);
+    if firmware.is_some() && remote.flash_command.is_none() {
+        error!(
+            "--flash needs a `flash_command` for remote {}, e.g. \"probe-rs run --chip <chip> {{artifact}}\"",
+            remote.name
+        );
+        exit(-18);
     }
 
-    if !no_copy_lock {
-        info!("Transferring Cargo.lock file back to client.");
-        Command::new("rsync")
-            .arg("-a")
-            .arg("--delete")
-            .arg("--compress")
-            .arg("-e")
-            .arg(format!("ssh -p {}", remote.ssh_port))
-            .arg(PROGRESS_FLAG)
-            .arg(format!("{}:{}Cargo.lock", build_server, build_path))
-            .arg(format!("{}/Cargo.lock", project_dir.to_string_lossy()))
-            .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit())
-            .stdin(Stdio::inherit())
-            .output()
-            .unwrap_or_else(|e| {
-                error!(
-                    "Failed to transfer Cargo.lock back to local machine (error: {})",
-                    e
+    let build_path = sync::build_path(&remote, &project_dir);
+
+    let parallel = matches!(command, Some(Command::Matrix { parallel: true, .. }));
+    let Plan {
+        commands,
+        fuzz_run,
+        mut publish_token,
+    } = plan_commands(
+        command,
+        &conf,
+        &Build {
+            transport: &transport,
+            remote: &remote,
+            host: &build_server,
+            build_path: &build_path,
+            project_dir: &project_dir,
+            target_dir: &target_dir,
+        },
+        &excludes,
+        hidden,
+        detach || reconnect,
+    );
+    let commands = match &member {
+        Some(member) => commands
+            .iter()
+            .map(|command| script::select_package(command, member))
+            .collect(),
+        None => commands,
+    };
+    let copy_back = match &copy_back_profile {
+        Some(profile) => Some(Some(profile_copy_back(
+            profile,
+            copy_back,
+            &project_dir,
+            &commands,
+        ))),
+        None => copy_back,
+    };
+
+    // reports and documentation can be used on any machine
+    if copy_back.is_some() && !coverage && !doc {
+        check_triple(facts.as_ref(), &commands, allow_triple_mismatch);
+    }
+
+    // held until the process exits
+    let _build_lock = if dry_run {
+        None
+    } else {
+        match build_lock::BuildLock::acquire(&target_dir, &remote, !no_wait) {
+            Ok(build_lock) => Some(build_lock),
+            Err(e) => {
+                error!("Failed to lock the build (error: {})", e);
+                exit(-22);
+            }
+        }
+    };
+
+    if remote.shell == config::Shell::PowerShell {
+        // these run POSIX shell scripts or rsync on the remote
+        refuse_on_windows(&[
+            (detach, "--detach"),
+            (reconnect, "--reconnect"),
+            (parallel, "matrix --parallel"),
+            (distribute, "--distribute"),
+            (timeout.is_some(), "--timeout"),
+            (monitor.is_some(), "--monitor"),
+            (vendor || remote.vendor, "--vendor"),
+            (container.is_some(), "--container"),
+            (package_artifacts, "--package-artifacts"),
+            (!deploy.is_empty(), "--deploy"),
+            (copy_back_outdirs, "--copy-back-outdirs"),
+            (
+                !copy_back_example.is_empty() || !copy_back_bench.is_empty(),
+                "--copy-back-example and --copy-back-bench",
+            ),
+        ]);
+    }
+
+    let sysroot = sysroot::Sysroot::prepare(&commands, &project_dir).unwrap_or_else(|e| {
+        error!("{}", e);
+        exit(-27);
+    });
+    let commands = sysroot.rewrite(commands);
+
+    let vendor_dir = (vendor || remote.vendor).then(|| {
+        vendor::prepare(&transport, &project_dir, &target_dir).unwrap_or_else(|e| {
+            error!("Failed to vendor the dependencies (error: {})", e);
+            exit(-29);
+        })
+    });
+    let mut excludes = excludes;
+    if vendor_dir.is_some() {
+        // keeps the upload of the sources from deleting the vendored dependencies
+        excludes.push(format!("/{}", vendor::REMOTE_DIR));
+    }
+
+    let lock_snapshot = lockfile::LockSnapshot::take(&remote, &project_dir);
+
+    if ephemeral {
+        delete_at_exit(&transport, &remote, &build_server, &build_path, dry_run);
+    }
+
+    // returns whether the connection to `host` was lost, so the build can fail over
+    let upload_sources = |remote: &config::Remote, host: &str| -> bool {
+        upload_sources(
+            &Build {
+                transport: &transport,
+                remote,
+                host,
+                build_path: &build_path,
+                project_dir: &project_dir,
+                target_dir: &target_dir,
+            },
+            &excludes,
+            fast,
+            vendor_dir.as_deref(),
+        )
+    };
+    let mut failed_hosts = Vec::new();
+    let fail_over =
+        |remote: &config::Remote, failed_hosts: &mut Vec<String>, lost: &str| -> String {
+            failed_hosts.push(lost.to_owned());
+            match next_host(&transport, remote, failed_hosts, lost) {
+                Some(host) => {
+                    if ephemeral {
+                        delete_at_exit(&transport, remote, &host, &build_path, dry_run);
+                    }
+                    host
+                }
+                None => {
+                    error!(
+                        "Lost the connection to {} and no other host of the remote is reachable.",
+                        lost
+                    );
+                    exit(-4);
+                }
+            }
+        };
+
+    let sync_started = Instant::now();
+    let transferred_before = transport.transferred_bytes();
+    while upload_sources(&remote, &build_server) {
+        build_server = fail_over(&remote, &mut failed_hosts, &build_server);
+    }
+    let mut timings = summary::Timings {
+        sync_secs: sync_started.elapsed().as_secs_f64(),
+        sync_bytes: backend::sync_backend(&remote)
+            .incremental()
+            .then(|| transport.transferred_bytes() - transferred_before),
+        ..summary::Timings::default()
+    };
+    info!("Environment profile: {:?}", remote.env);
+    info!("Build path: {:?}", build_path);
+    let distributed = distribute
+        && !dry_run
+        && !commands.is_empty()
+        && start_distributed(&transport, &conf, &remote, &build_server);
+    let build_command = if remote.shell == config::Shell::PowerShell {
+        windows::build_command(&remote, &build_path, &commands)
+    } else if commands.is_empty() {
+        script::build_command(&remote, &build_path, &commands)
+    } else {
+        let cargo_script = cargo_script(
+            &remote,
+            &commands,
+            &sysroot,
+            &profile.env,
+            vendor_dir.is_some(),
+            parallel,
+            !no_install,
+            wasm,
+            distributed,
+        );
+        script::build_script(&remote, &build_path, Some(&cargo_script))
+    };
+    for variable in tools::variables(&commands) {
+        if !remote.forward_env.contains(&variable) {
+            remote.forward_env.push(variable);
+        }
+    }
+
+    let cache_entry = match &copy_back {
+        Some(Some(file_name))
+            if !no_cache
+                && output_cache::cacheable(&commands)
+                && !copy_back_outdirs
+                && !dry_run
+                && !detach
+                && !commands.is_empty()
+                && !file_name.is_empty()
+                && remote.artifact_store.is_none() =>
+        {
+            output_cache::Entry::lookup(
+                &transport,
+                &remote,
+                &build_server,
+                &build_path,
+                &commands,
+                file_name,
+                &profile.env,
+            )
+            .map_err(|e| warn!("Failed to look up the cached artifacts (error: {})", e))
+            .ok()
+        }
+        _ => None,
+    };
+
+    let cache_stats_before = remote.compiler_cache.and_then(|cache| {
+        cache_stats::query(&transport, &remote, &build_server, cache)
+            .map_err(|e| warn!("Failed to query {} statistics (error: {})", cache, e))
+            .ok()
+    });
+
+    let started_at = std::time::SystemTime::now();
+    if detach {
+        let copy_back = copy_back.map(|file_name| {
+            let file_name = file_name.unwrap_or_default();
+            detach::CopyBack {
+                destination: copy_back_destination(&output_dir, &target_dir, &file_name),
+                file_name,
+                delete: remote.copy_back_delete && !copy_back_no_delete,
+                flat: output_dir.is_some(),
+            }
+        });
+        let build = Build {
+            transport: &transport,
+            remote: &remote,
+            host: &build_server,
+            build_path: &build_path,
+            project_dir: &project_dir,
+            target_dir: &target_dir,
+        };
+        exit(build.detach(commands, copy_back, &build_command, started_at));
+    }
+    let cache_hit = cache_entry.as_ref().is_some_and(|entry| entry.hit);
+    let mut timed_out = false;
+    let build_status = if cache_hit {
+        info!("The sources were built with the same commands before, using the cached artifacts.");
+        std::process::ExitStatus::default()
+    } else {
+        info!("Starting build process.");
+        if reconnect && commands.is_empty() {
+            error!("--reconnect needs a cargo command, an interactive shell can't be resumed");
+            exit(-13);
+        }
+        let forwarded = forwarded_variables(&remote, &commands, reconnect, publish_token.take());
+        let mut remote_command = build_command.clone();
+        if !forwarded.is_empty() {
+            let names: Vec<_> = forwarded.iter().map(|(name, _)| name.as_str()).collect();
+            info!("Forwarding {} to the build.", names.join(", "));
+            remote_command.insert_str(0, credentials::read_script());
+        }
+        if ci.is_some() && remote.shell == config::Shell::Sh {
+            // the diagnostics of cargo go to stderr, which ssh only merges into stdout with a tty
+            remote_command = format!("{{ {} }} 2>&1", remote_command);
+        }
+        if timeout.is_some() {
+            remote_command.insert_str(0, &timeout::record_pid(&build_path));
+        }
+        let build_started = Instant::now();
+        let build_status = loop {
+            let build = Build {
+                transport: &transport,
+                remote: &remote,
+                host: &build_server,
+                build_path: &build_path,
+                project_dir: &project_dir,
+                target_dir: &target_dir,
+            };
+            let watchdog = timeout
+                .filter(|_| !dry_run)
+                .map(|timeout| build.watchdog(timeout));
+            let monitor = monitor.filter(|_| !dry_run).map(|interval| {
+                monitor::Monitor::start(
+                    &transport,
+                    &remote,
+                    &build_server,
+                    &build_path,
+                    Duration::from_secs(interval.max(1)),
+                )
+            });
+            let build_status = if reconnect {
+                reconnecting_build(
+                    &transport,
+                    &remote,
+                    &build_server,
+                    &build_path,
+                    &build_command,
+                    &commands,
+                    &target_dir,
+                    started_at,
+                )
+            } else {
+                build.run(&remote_command, &forwarded, record.as_ref(), &build_command)
+            }
+            .unwrap_or_else(|e| {
+                error!("Failed to run cargo command remotely (error: {})", e);
+                exit(-5);
+            });
+            if let Some(monitor) = monitor {
+                monitor.finish();
+            }
+            if watchdog.is_some_and(timeout::Watchdog::finish) {
+                error!(
+                    "The build was stopped after the timeout of {}s.",
+                    timeout.unwrap_or_default().as_secs()
+                );
+                if !timeout_copy_back {
+                    exit(-23);
+                }
+                build.fetch_build_script_logs();
+                // exits after the copy back
+                timed_out = true;
+                break build_status;
+            }
+            // a reconnecting build resumes the same job instead
+            if reconnect || !transport::connection_lost(build_status) {
+                break build_status;
+            }
+            build_server = fail_over(&remote, &mut failed_hosts, &build_server);
+            while upload_sources(&remote, &build_server) {
+                build_server = fail_over(&remote, &mut failed_hosts, &build_server);
+            }
+        };
+        timings.build_secs = build_started.elapsed().as_secs_f64();
+        if let (Some(entry), true) = (&cache_entry, build_status.success()) {
+            entry.store(&transport, &remote, &build_server, &build_path);
+        }
+        build_status
+    };
+
+    let build = Build {
+        transport: &transport,
+        remote: &remote,
+        host: &build_server,
+        build_path: &build_path,
+        project_dir: &project_dir,
+        target_dir: &target_dir,
+    };
+    let built = build_status.success() && !dry_run && !commands.is_empty();
+    if let (Some(cache), Some(before)) = (remote.compiler_cache, &cache_stats_before) {
+        build.record_cache_stats(cache, before, &commands);
+    }
+
+    let artifact_store = artifacts::Store::of(&remote);
+    if let (Some(file_name), Some(store)) = (&copy_back, &artifact_store) {
+        if build_status.success() {
+            build.publish_artifacts(store, file_name.as_deref().unwrap_or_default());
+        } else {
+            warn!("The build failed, the artifacts aren't uploaded.");
+        }
+    } else if let Some(file_name) = copy_back {
+        info!("Transferring artifacts back to client.");
+        let file_name = file_name.unwrap_or_default();
+        let destination = copy_back_destination(&output_dir, &target_dir, &file_name);
+        let file_name = match &cache_entry {
+            Some(entry) if cache_hit => entry.file_name(),
+            _ => file_name,
+        };
+        // rsync only creates the last component of the destination
+        let create_parent = firmware.is_some() || copy_back_profile.is_some();
+        let copy_back_started = Instant::now();
+        let transferred_before = transport.transferred_bytes();
+        build.copy_back(
+            &file_name,
+            &destination,
+            output_dir.as_deref(),
+            create_parent,
+            remote.copy_back_delete && !copy_back_no_delete,
+        );
+        timings.copy_back_secs = Some(copy_back_started.elapsed().as_secs_f64());
+        timings.copy_back_bytes = backend::sync_backend(&remote)
+            .incremental()
+            .then(|| transport.transferred_bytes() - transferred_before);
+        if built {
+            build.record_build_info(&commands, &build_info::path(&output_dir, &target_dir));
+        }
+        if built && !cache_hit {
+            build.check_regressions(
+                &commands,
+                timings.build_secs,
+                &destination,
+                fail_on_size_increase,
+            );
+        }
+        if coverage && !dry_run {
+            build.remap_coverage(&destination);
+        }
+    }
+    if timed_out {
+        exit(-23);
+    }
+
+    if package_artifacts && !dry_run && !commands.is_empty() {
+        if build_status.success() {
+            build.package_artifacts(output_dir.as_deref());
+        } else {
+            warn!("The build failed, the artifacts aren't packaged.");
+        }
+    }
+
+    if !deploy_targets.is_empty() && !dry_run && !commands.is_empty() {
+        if build_status.success() {
+            build.deploy(&deploy_targets);
+        } else {
+            warn!("The build failed, nothing is deployed.");
+        }
+    }
+
+    if copy_back_outdirs && built {
+        if parallel {
+            warn!("The OUT_DIRs of builds with --parallel aren't copied back.");
+        } else {
+            build.copy_back_outdirs(&commands);
+        }
+    }
+
+    let executables: Vec<(executables::Kind, String)> = copy_back_example
+        .into_iter()
+        .map(|name| (executables::Kind::Example, name))
+        .chain(
+            copy_back_bench
+                .into_iter()
+                .map(|name| (executables::Kind::Bench, name)),
+        )
+        .collect();
+    if !executables.is_empty() && built {
+        if parallel {
+            warn!("The examples and benches of builds with --parallel aren't copied back.");
+        } else {
+            build.copy_back_executables(&commands, &executables, output_dir.as_deref());
+        }
+    }
+
+    if !dry_run && !commands.is_empty() {
+        info!("Timings: {}", timings);
+    }
+
+    if let Some(summary_path) = json_summary {
+        let summary_path = summary_path
+            .map(PathBuf::from)
+            .unwrap_or_else(|| summary::default_path(&target_dir));
+        build.write_summary(
+            &summary_path,
+            &commands,
+            cache_hit,
+            started_at,
+            build_status,
+            &timings,
+        );
+    }
+
+    if let Some(fuzz_run) = &fuzz_run {
+        info!(
+            "Transferring the corpus of {} back to client.",
+            fuzz_run.target
+        );
+        if let Err(e) = fuzz_run.sync_back(
+            &transport,
+            &remote,
+            &build_server,
+            &build_path,
+            &project_dir,
+        ) {
+            error!(
+                "Failed to transfer the corpus back to local machine (error: {})",
+                e
+            );
+        }
+    }
+
+    if !no_copy_lock && !fast {
+        build.copy_lock(&lock_snapshot, force_lock, dry_run);
+    }
+
+    if !build_status.success() {
+        exit(build_status.code().unwrap_or(1))
+    }
+
+    if (serve_docs.is_some() || open_docs) && !dry_run {
+        let doc_dir = match &output_dir {
+            Some(output_dir) => output_dir.clone(),
+            None => target_dir.join(docs::DOC_DIR),
+        };
+        show_docs(&doc_dir, serve_docs, open_docs);
+    }
+
+    if let Some(tag) = &container {
+        if dry_run {
+            info!("Would build image {} with {}.", tag, remote.container_tool);
+        } else {
+            build.deliver_container(tag, push_container);
+        }
+    }
+
+    if let (Some(firmware), Some(flash_command)) = (&firmware, &remote.flash_command) {
+        let artifact = PathBuf::from(copy_back_destination(
+            &output_dir,
+            &target_dir,
+            &firmware.file_name(),
+        ));
+        let artifact = match &output_dir {
+            // the file keeps its name in the output directory
+            Some(output_dir) => output_dir.join(artifact.file_name().unwrap_or_default()),
+            None => artifact,
+        };
+        if dry_run {
+            info!("Would flash {:?} with `{}`.", artifact, flash_command);
+            exit(0);
+        }
+        flash_firmware(flash_command, &artifact);
+    }
+    // runs AT_EXIT
+    exit(0)
+}
+
+/// Logs to stderr, `RUST_LOG` takes precedence over `--debug-transport`, and runs the `AT_EXIT`
+/// work when cargo remote is interrupted or panics.
+fn init_logging(debug_transport: bool, log_format: LogFormat) {
+    // RUST_LOG takes precedence, e.g. RUST_LOG=cargo_nix::transport=trace
+    let filter = EnvFilter::try_from_default_env()
+        .unwrap_or_else(|_| EnvFilter::new(if debug_transport { "trace" } else { "info" }));
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_span_events(FmtSpan::CLOSE)
+        .with_ansi(std::io::stderr().is_terminal())
+        .with_writer(std::io::stderr);
+    match log_format {
+        LogFormat::Text => subscriber.init(),
+        LogFormat::Json => subscriber.json().init(),
+    }
+    run_at_exit_on_panic();
+    if let Err(e) = signals::install(interrupted) {
+        warn!(
+            "Failed to handle signals, an interrupted build leaves its remote resources behind (error: {})",
+            e
+        );
+    }
+}
+
+/// Turns `-c <path>` and `--copy-back <path>` into `-c=<path>`, clap only takes the optional
+/// value with `=` to tell it from the cargo command. A path is told apart by a `/` or `.` in
+/// it, which no cargo command has.
+fn join_copy_back_path(
+    args: impl IntoIterator<Item = std::ffi::OsString>,
+) -> Vec<std::ffi::OsString> {
+    let mut joined = Vec::new();
+    let mut args = args.into_iter().peekable();
+    while let Some(mut arg) = args.next() {
+        if arg == "--" {
+            joined.push(arg);
+            joined.extend(args);
+            break;
+        }
+        if arg == "-c" || arg == "--copy-back" {
+            let path = args.next_if(|next| {
+                next.to_str()
+                    .is_some_and(|next| !next.starts_with('-') && next.contains(['/', '.']))
+            });
+            if let Some(path) = path {
+                arg.push("=");
+                arg.push(path);
+            }
+        }
+        joined.push(arg);
+    }
+    joined
+}
+
+/// Removes a `--fast` given after an external subcommand, cargo has no such flag.
+fn take_fast_flag(command: &mut Option<Command>) -> bool {
+    let Some(Command::Cargo(args)) = command else {
+        return false;
+    };
+    let flag = args
+        .iter()
+        .take_while(|arg| *arg != "--")
+        .position(|arg| arg == "--fast");
+    match flag {
+        Some(flag) => {
+            args.remove(flag);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Splits a `--remote-host` given as `ssh://user@host:port/temp_dir` into the separate flags.
+fn apply_remote_uri(remote_opts: &mut RemoteOpts) {
+    let Some(host) = &remote_opts.host else {
+        return;
+    };
+    match config::RemoteUri::parse(host) {
+        Ok(Some(uri)) => {
+            // the separate flags take precedence over the parts of the URI
+            remote_opts.host = Some(uri.host);
+            remote_opts.ssh_port = remote_opts.ssh_port.or(uri.ssh_port);
+            remote_opts.temp_dir = remote_opts.temp_dir.take().or(uri.temp_dir);
+        }
+        Ok(None) => {}
+        Err(e) => {
+            error!("Invalid remote host: {}", e);
+            exit(-3);
+        }
+    }
+}
+
+/// The directory of the target dir that commands with their own output copy back.
+fn command_output(command: &Option<Command>) -> Option<Option<String>> {
+    let dir = match command {
+        Some(Command::Coverage { .. }) => coverage::COVERAGE_DIR,
+        Some(Command::Doc { .. }) => docs::DOC_DIR,
+        Some(Command::Wasm { .. }) => wasm::PKG_DIR,
+        Some(Command::Package { .. }) => "package",
+        _ => return None,
+    };
+    Some(Some(format!("{}/", dir)))
+}
+
+/// Runs the commands that only need the config files and don't invoke cargo, and exits.
+fn run_config_command(
+    command: &Option<Command>,
+    manifest_path: &std::path::Path,
+    remote_opts: &RemoteOpts,
+    profile: Option<&str>,
+) {
+    match command {
+        Some(Command::VersionCheck) => {
+            exit(version_check_fast_path(manifest_path, remote_opts));
+        }
+        Some(Command::Init) => match init::run(&find_project_dir(manifest_path)) {
+            Ok(path) => {
+                println!("Saved the remote in {:?}.", path);
+                exit(0);
+            }
+            Err(e) => {
+                error!("{}", e);
+                exit(1);
+            }
+        },
+        Some(Command::ValidateConfig) => exit(validate_config_files(manifest_path)),
+        Some(Command::Config {
+            action: ConfigAction::Show { defaults },
+        }) => exit(show_config(manifest_path, remote_opts, profile, *defaults)),
+        Some(Command::Secret {
+            action: SecretAction::Set { name, age },
+        }) => exit(set_secret(name, age.as_deref())),
+        Some(Command::List) => exit(list_remotes(manifest_path, remote_opts)),
+        _ => {}
+    }
+}
+
+/// The profile `name` of the config, or the empty one without `--profile`.
+fn load_profile(conf: &config::Config, name: Option<&str>) -> config::Profile {
+    match name.map(|name| (name, conf.profile(name))) {
+        Some((_, Some(profile))) => profile.clone(),
+        Some((name, None)) => {
+            error!("The profile {} isn't defined in the config", name);
+            exit(-3);
+        }
+        None => config::Profile::default(),
+    }
+}
+
+/// The firmware `--flash` copies back and flashes, from the `build --target` command.
+fn parse_firmware(
+    command: &Option<Command>,
+    packages: &[cargo_metadata::Package],
+    conf: &config::Config,
+) -> firmware::Firmware {
+    let Some(Command::Cargo(args)) = command else {
+        error!("--flash needs a `build --target <triple>` command");
+        exit(-18);
+    };
+    let binaries: Vec<String> = packages
+        .iter()
+        .flat_map(|package| package.targets.iter())
+        .filter(|target| target.kind.iter().any(|kind| kind == "bin"))
+        .map(|target| target.name.clone())
+        .collect();
+    firmware::Firmware::parse(&conf.expand_alias(args.clone()), &binaries).unwrap_or_else(|e| {
+        error!("{}", e);
+        exit(-18);
+    })
+}
+
+/// Prints the rsync filters of the upload for `--show-filters`.
+fn print_filters(excludes: &[String], fast: bool) {
+    for exclude in excludes {
+        println!("- {}", exclude);
+    }
+    if fast {
+        for include in sync::FAST_INCLUDES {
+            println!("+ {}", include);
+        }
+        println!("- *");
+    }
+}
+
+/// The provider and instance type of `--provision <provider>[:<instance type>]`.
+fn provider(conf: &config::Config, provision: &str) -> (provision::Provider, Option<String>) {
+    let (name, instance_type) = match provision.split_once(':') {
+        Some((name, instance_type)) => (name, Some(instance_type)),
+        None => (provision, None),
+    };
+    match conf.provider(name).map(|p| (p, p.check())) {
+        Some((provider, Ok(()))) => (
+            provider.clone(),
+            instance_type
+                .map(str::to_owned)
+                .or_else(|| provider.instance_type.clone()),
+        ),
+        Some((_, Err(e))) => {
+            error!("The provider {} is invalid: {}", name, e);
+            exit(-33);
+        }
+        None => {
+            error!("The provider {} isn't defined in the config", name);
+            exit(-33);
+        }
+    }
+}
+
+/// Lets the user pick one of several `configured` remotes in a terminal, elsewhere the first is
+/// used.
+fn pick_remote(
+    configured: &[config::Remote],
+    project_dir: &std::path::Path,
+    ci: Option<ci::Ci>,
+) -> Option<config::Remote> {
+    if configured.len() < 2 {
+        return None;
+    }
+    if ci.is_none() && std::io::stdin().is_terminal() && std::io::stdout().is_terminal() {
+        match picker::pick(configured, project_dir) {
+            Ok(remote) => Some(remote),
+            Err(e) => {
+                error!("No remote picked ({})", e);
+                exit(4);
+            }
+        }
+    } else {
+        info!(
+            "Using the first of {} configured remotes, pick another one with --remote.",
+            configured.len()
+        );
+        None
+    }
+}
+
+/// The remote of the flags and the config. Instances of a `provider` get the settings of the
+/// remote named by it, the host is replaced.
+fn select_remote(
+    conf: &config::Config,
+    remote_opts: &RemoteOpts,
+    provider: Option<&provision::Provider>,
+    provision: Option<&str>,
+) -> Option<config::Remote> {
+    match provider {
+        Some(provider) => conf.get_remote(&RemoteOpts {
+            name: provider.remote.clone(),
+            host: provider
+                .remote
+                .is_none()
+                .then(|| provision.unwrap_or_default().to_owned()),
+            ..remote_opts.clone()
+        }),
+        None => conf.get_remote(remote_opts),
+    }
+}
+
+/// Refuses remotes whose build path would let rsync --delete, clean and gc delete files outside
+/// of the builds. With `--i-know-what-i-am-doing` only paths the remote shell would interpret.
+fn check_build_path(
+    remote: &config::Remote,
+    project_dir: &std::path::Path,
+    i_know_what_i_am_doing: bool,
+) {
+    if i_know_what_i_am_doing {
+        if let Err(e) = sync::check_remote_characters(remote) {
+            error!("Refusing to run on {}: {}. Fix the config.", remote.host, e);
+            exit(-25);
+        }
+        return;
+    }
+    let build_path = sync::build_path(remote, project_dir);
+    if let Err(e) = sync::check_remote_paths(remote, &build_path) {
+        error!(
+            "Refusing to run on {}: {}. Fix the config or pass --i-know-what-i-am-doing.",
+            remote.host, e
+        );
+        exit(-25);
+    }
+}
+
+/// The targets of `--deploy`, checked before anything is built.
+fn deploy_targets<'a>(
+    conf: &'a config::Config,
+    names: &'a [String],
+) -> Vec<(&'a String, &'a deploy::Target)> {
+    names
+        .iter()
+        .map(
+            |name| match conf.deploy_target(name).map(|t| (t, t.check())) {
+                Some((target, Ok(()))) => (name, target),
+                Some((_, Err(e))) => {
+                    error!("The deploy target {} is invalid: {}", name, e);
+                    exit(-26);
+                }
+                None => {
+                    error!("The deploy target {} isn't defined in the config", name);
+                    exit(-26);
+                }
+            },
+        )
+        .collect()
+}
+
+/// Closes the shared connections to `remote` opened by `connect`.
+fn disconnect(transport: &transport::Transport, remote: &config::Remote) -> i32 {
+    match session::disconnect(transport, remote) {
+        Ok(closed) => {
+            if closed.is_empty() {
+                info!("No session to {} is open.", remote.name);
+            }
+            0
+        }
+        Err(e) => {
+            error!("Failed to close the session (error: {})", e);
+            1
+        }
+    }
+}
+
+/// The `nextest run` arguments of `--shard`, limited to the workspace `member`.
+fn sharded_nextest_args(mut args: Vec<String>, member: Option<&str>) -> Vec<String> {
+    if args.len() < 2 || args[0] != "nextest" || args[1] != "run" {
+        error!("--shard only works with `nextest run`");
+        exit(-17);
+    }
+    if let (Some(member), Some(position)) = (member, script::package_position(&args)) {
+        args.splice(position..position, ["-p".to_owned(), member.to_owned()]);
+    }
+    args
+}
+
+/// Exits if the program running the commands of `remote`, ssh or its `exec_command`, is
+/// missing locally.
+fn check_exec_program(remote: &config::Remote) {
+    let program = backend::exec_backend(remote).program(remote);
+    if prerequisites::is_installed(program) {
+        return;
+    }
+    let tool = std::path::Path::new(program)
+        .file_name()
+        .map_or(program.into(), |name| name.to_string_lossy());
+    error!(
+        "{} isn't installed locally, {}.",
+        program,
+        prerequisites::local_install_hint(&tool)
+    );
+    exit(-21);
+}
+
+/// Provisions an instance of `provider` for `--provision`, `template` with the host of the
+/// instance. The instance is destroyed when cargo remote exits.
+fn provision_remote(
+    transport: &transport::Transport,
+    template: config::Remote,
+    provider: &provision::Provider,
+    instance_type: Option<&str>,
+    dry_run: bool,
+) -> config::Remote {
+    let instance = if dry_run {
+        info!(
+            "Would provision a build server{}.",
+            instance_type
+                .map(|instance_type| format!(" of type {}", instance_type))
+                .unwrap_or_default()
+        );
+        let instance = provision::Instance::placeholder(provider);
+        let destroyed = (transport.clone(), instance.clone());
+        at_exit(move || {
+            let (transport, instance) = destroyed;
+            instance.destroy(&transport);
+        });
+        instance
+    } else {
+        info!("Provisioning a build server.");
+        // destroyed at exit by `create` itself, as soon as it is known
+        provision::Instance::create(transport, provider, instance_type).unwrap_or_else(|e| {
+            error!("Failed to provision a build server (error: {})", e);
+            exit(-33);
+        })
+    };
+    let remote = instance.remote(template);
+    if !dry_run {
+        // a pinned host key can only be checked once the instance accepts connections
+        let booting = config::Remote {
+            host_key_fingerprint: None,
+            ..remote.clone()
+        };
+        if let Err(e) = instance.wait(transport, &booting, provider.boot_timeout) {
+            error!("The provisioned build server isn't usable (error: {})", e);
+            exit(-33);
+        }
+    }
+    remote
+}
+
+/// The `configured` remote to use instead of `remote` when it doesn't have the tool versions the
+/// config requires, None to keep `remote`. An `explicit` remote is never replaced.
+fn fallback_remote<'a>(
+    transport: &transport::Transport,
+    conf: &config::Config,
+    remote: &config::Remote,
+    configured: &'a [config::Remote],
+    explicit: bool,
+) -> Option<&'a config::Remote> {
+    if conf.required_versions().is_empty() {
+        return None;
+    }
+    let unmet = unmet_requirements(transport, conf, remote);
+    if unmet.is_empty() {
+        return None;
+    }
+    if explicit {
+        error!(
+            "{} can't build the project: {}",
+            remote.host,
+            unmet.join(", ")
+        );
+        exit(-31);
+    }
+    let other = configured
+        .iter()
+        .filter(|other| other.host != remote.host || other.name != remote.name)
+        .find(|other| unmet_requirements(transport, conf, other).is_empty());
+    match other {
+        Some(other) => {
+            warn!(
+                "{} can't build the project ({}), using {} instead.",
+                remote.host,
+                unmet.join(", "),
+                other.host
+            );
+            Some(other)
+        }
+        None => {
+            error!(
+                "{} can't build the project ({}) and no other configured remote can either.",
+                remote.host,
+                unmet.join(", ")
+            );
+            exit(-31);
+        }
+    }
+}
+
+/// Opens a shared connection to `host` that later invocations reuse.
+fn connect(transport: &transport::Transport, remote: &config::Remote, host: &str) -> i32 {
+    match session::connect(transport, remote, host) {
+        Ok(true) => info!(
+            "Connected to {}, close the session with `disconnect`.",
+            host
+        ),
+        Ok(false) => info!("A session to {} is already open.", host),
+        Err(e) => {
+            error!("Failed to connect to {} (error: {})", host, e);
+            return 1;
+        }
+    }
+    0
+}
+
+/// Probes `host` and adapts `remote` to what it has, transfers with tar without a local rsync.
+fn adapt_to_host(
+    transport: &mut transport::Transport,
+    remote: &mut config::Remote,
+    host: &str,
+) -> Option<probe::RemoteFacts> {
+    let facts = probe::cached_or_probe(transport, remote, host)
+        .map_err(|e| warn!("Failed to probe {} (error: {})", host, e))
+        .ok();
+    if let Some(facts) = &facts {
+        compat::adapt(remote, facts);
+        transport.tar_compressor = compat::tar_compressor(facts);
+    }
+    if backend::sync_backend(remote).incremental()
+        && !prerequisites::is_installed(&remote.rsync_path_local)
+    {
+        warn!(
+            "{} isn't installed locally, transferring with tar instead. For incremental transfers {}.",
+            remote.rsync_path_local,
+            prerequisites::local_install_hint("rsync")
+        );
+        remote.transfer = config::Transfer::Tar;
+    }
+    facts
+}
+
+/// What an invocation runs on the build server.
+struct Plan {
+    /// The cargo commands, without the `cargo` and empty for an interactive shell.
+    commands: Vec<String>,
+    fuzz_run: Option<fuzz::FuzzRun>,
+    /// The variable of the registry token for `publish`.
+    publish_token: Option<(String, String)>,
+}
+
+/// The cargo commands of `command`. Commands that manage the build server instead, like `clean`
+/// and `gc`, are run here and exit.
+fn plan_commands(
+    command: Option<Command>,
+    conf: &config::Config,
+    build: &Build,
+    excludes: &[String],
+    hidden: bool,
+    background: bool,
+) -> Plan {
+    let Build {
+        transport,
+        remote,
+        host,
+        build_path,
+        project_dir,
+        target_dir,
+    } = *build;
+    let mut fuzz_run = None;
+    let mut publish_token = None;
+    let commands = match command {
+        Some(Command::Watch { idle }) => watch::run(
+            transport,
+            remote,
+            host,
+            project_dir,
+            build_path,
+            excludes,
+            Duration::from_secs(idle),
+        ),
+        Some(Command::Image { tag, push }) => {
+            let build_env = image::probe(transport, remote, host, build_path).unwrap_or_else(|e| {
+                error!("Failed to inspect the build environment (error: {})", e);
+                exit(-10);
+            });
+            let dockerfile = build_env.dockerfile();
+            match image::save_dockerfile(target_dir, &dockerfile) {
+                Ok(path) => info!("Generated {:?}:\n{}", path, dockerfile),
+                Err(e) => warn!("Failed to save the Dockerfile locally (error: {})", e),
+            }
+            if let Err(e) = image::build(transport, remote, host, &dockerfile, &tag, push) {
+                error!("Failed to build image {} (error: {})", tag, e);
+                exit(-10);
+            }
+            exit(0);
+        }
+        Some(Command::BuildAll { commands }) => {
+            match projects::build_all(
+                transport,
+                conf,
+                remote,
+                host,
+                project_dir,
+                &commands,
+                hidden,
+            ) {
+                Ok(true) => exit(0),
+                Ok(false) => exit(1),
+                Err(e) => {
+                    error!("{}", e);
+                    exit(-12);
+                }
+            }
+        }
+        Some(Command::Clean) => {
+            info!("Deleting {} on {}.", build_path, host);
+            let mut ssh =
+                transport.remote_command(remote, host, &script::remove_dir(remote, build_path));
+            match transport.run(&mut ssh, "clean") {
+                Ok(status) => exit(status.code().unwrap_or(1)),
+                Err(e) => {
+                    error!("Failed to delete the remote build directory (error: {})", e);
+                    exit(1);
+                }
+            }
+        }
+        Some(Command::Gc { .. }) if remote.shell == config::Shell::PowerShell => {
+            error!(
+                "gc can't be used with Windows remotes (shell = \"powershell\"), \
+                 use `cargo remote clean` in the projects instead"
+            );
+            exit(-32);
+        }
+        Some(Command::Gc {
+            orphans: true,
+            dry_run,
+            ..
+        }) => exit(gc_orphans(transport, remote, host, dry_run)),
+        Some(Command::Gc {
+            older_than,
+            dry_run,
+            ..
+        }) => {
+            let mut ssh =
+                transport.remote_command(remote, host, &script::gc(remote, older_than, dry_run));
+            match transport.run(&mut ssh, "gc") {
+                Ok(status) => exit(status.code().unwrap_or(1)),
+                Err(e) => {
+                    error!("Failed to clean up {} (error: {})", remote.temp_dir, e);
+                    exit(1);
+                }
+            }
+        }
+        Some(Command::Cargo(args)) => {
+            let args = conf.expand_alias(args);
+            fuzz_run = fuzz::FuzzRun::parse(&args);
+            let args = match &fuzz_run {
+                Some(_) => fuzz::FuzzRun::limit_duration(args, remote.fuzz_duration),
+                None => args,
+            };
+            vec![script::join_args(&args)]
+        }
+        Some(Command::Run { commands }) => commands
+            .into_iter()
+            .map(|command| {
+                let (first, rest) = command.split_once(' ').unwrap_or((&command, ""));
+                let expanded = script::join_args(&conf.expand_alias(vec![first.to_owned()]));
+                if rest.is_empty() {
+                    expanded
+                } else {
+                    format!("{} {}", expanded, rest)
+                }
+            })
+            .collect(),
+        Some(Command::Matrix {
+            features_sets,
+            no_default_features,
+            command,
+            ..
+        }) => script::matrix_commands(
+            &script::join_args(&conf.expand_alias(command)),
+            &features_sets,
+            no_default_features,
+        ),
+        Some(Command::Coverage { tool, html, args }) => {
+            coverage::commands(tool, html, &script::join_args(&args))
+        }
+        Some(Command::Doc { args, .. }) => {
+            vec![format!("doc {}", script::join_args(&args))]
+        }
+        Some(Command::Audit { args }) => {
+            vec![format!("audit {}", script::join_args(&args))]
+        }
+        Some(Command::Deny { args }) if args.is_empty() => vec!["deny check".to_owned()],
+        Some(Command::Deny { args }) => {
+            vec![format!("deny {}", script::join_args(&args))]
+        }
+        Some(Command::Package { args }) => {
+            vec![format!("package {}", script::join_args(&args))]
+        }
+        Some(Command::Publish { registry, args }) => {
+            if background {
+                error!("The registry token is sent on stdin, publish can't be combined with --detach or --reconnect");
+                exit(-16);
+            }
+            publish_token = match credentials::registry_token(registry.as_deref()) {
+                Ok(token) => Some(token),
+                Err(e) => {
+                    error!("{}", e);
+                    exit(-16);
+                }
+            };
+            match registry {
+                Some(registry) => vec![format!(
+                    "publish --registry {} {}",
+                    script::word(&registry),
+                    script::join_args(&args)
+                )],
+                None => vec![format!("publish {}", script::join_args(&args))],
+            }
+        }
+        Some(Command::Wasm { target, dev, args }) => {
+            wasm::commands(target, dev, &script::join_args(&args))
+        }
+        Some(Command::Shell) | None => Vec::new(),
+        Some(
+            Command::VersionCheck
+            | Command::Init
+            | Command::ValidateConfig
+            | Command::Config { .. }
+            | Command::Secret { .. }
+            | Command::List
+            | Command::Refresh
+            | Command::Targets { .. }
+            | Command::Doctor
+            | Command::Connect
+            | Command::Disconnect
+            | Command::Benchmark
+            | Command::CacheStats
+            | Command::Comment { .. }
+            | Command::Attach { .. }
+            | Command::Status { .. }
+            | Command::Logs { .. }
+            | Command::Jobs
+            | Command::Cancel { .. },
+        ) => unreachable!("handled above"),
+    };
+    Plan {
+        commands,
+        fuzz_run,
+        publish_token,
+    }
+}
+
+/// The directory `--copy-back-profile` copies back, for the target of the `commands`.
+fn profile_copy_back(
+    profile: &str,
+    copy_back: Option<Option<String>>,
+    project_dir: &std::path::Path,
+    commands: &[String],
+) -> String {
+    if let Some(Some(_)) = copy_back {
+        error!("--copy-back-profile can't be combined with a file for --copy-back or a command copying back its own output");
+        exit(-3);
+    }
+    match cargo_profile_dir(project_dir, profile) {
+        Ok(profile_dir) => match target_triple(commands) {
+            Some(triple) => format!("{}/{}/", triple, profile_dir),
+            None => format!("{}/", profile_dir),
+        },
+        Err(e) => {
+            error!("{}", e);
+            exit(-3);
+        }
+    }
+}
+
+/// Refuses to copy back binaries that can't run on this machine, unless they are built with
+/// `--target` or `--allow-triple-mismatch` is given.
+fn check_triple(facts: Option<&probe::RemoteFacts>, commands: &[String], allow_mismatch: bool) {
+    if commands.iter().any(|command| command.contains("--target")) {
+        return;
+    }
+    let remote_triple = facts.and_then(|facts| facts.host_triple.as_deref());
+    let local_triple = probe::local_host_triple();
+    if let (Some(remote_triple), Some(local_triple)) = (remote_triple, local_triple) {
+        if remote_triple != local_triple {
+            let message = format!(
+                "The build server compiles for {} but this machine is {}, copied back binaries won't run here. \
+                 Build with --target {} or pass --allow-triple-mismatch.",
+                remote_triple, local_triple, local_triple
+            );
+            // interactive sessions may still pass --target to cargo themselves
+            if allow_mismatch || commands.is_empty() {
+                warn!("{}", message);
+            } else {
+                error!("{}", message);
+                exit(-11);
+            }
+        }
+    }
+}
+
+/// Exits if any of the `options` that need a POSIX shell on the remote is used.
+fn refuse_on_windows(options: &[(bool, &str)]) {
+    let unsupported: Vec<&str> = options
+        .iter()
+        .filter(|(used, _)| *used)
+        .map(|(_, option)| *option)
+        .collect();
+    if !unsupported.is_empty() {
+        error!(
+            "{} can't be used with Windows remotes (shell = \"powershell\")",
+            unsupported.join(", ")
+        );
+        exit(-32);
+    }
+}
+
+/// Uploads the sources and the vendored dependencies, returns whether the connection to the
+/// build server was lost so the build can fail over.
+fn upload_sources(
+    build: &Build,
+    excludes: &[String],
+    fast: bool,
+    vendor_dir: Option<&std::path::Path>,
+) -> bool {
+    info!("Transferring sources to build server.");
+    match sync::upload(
+        build.transport,
+        build.remote,
+        build.host,
+        build.project_dir,
+        build.build_path,
+        excludes,
+        fast,
+    ) {
+        Ok(status) if status.success() => {}
+        Ok(status) if transport::connection_lost(status) => return true,
+        Ok(status) => {
+            error!("Failed to transfer project to build server ({})", status);
+            exit(-4);
+        }
+        Err(e) => {
+            error!("Failed to transfer project to build server (error: {})", e);
+            exit(-4);
+        }
+    }
+    if let Some(vendor_dir) = vendor_dir {
+        info!("Transferring the vendored dependencies to build server.");
+        match vendor::upload(
+            build.transport,
+            build.remote,
+            build.host,
+            vendor_dir,
+            build.build_path,
+        ) {
+            Ok(status) if status.success() => {}
+            Ok(status) if transport::connection_lost(status) => return true,
+            Ok(status) => {
+                error!("Failed to transfer the vendored dependencies ({})", status);
+                exit(-4);
+            }
+            Err(e) => {
+                error!(
+                    "Failed to transfer the vendored dependencies (error: {})",
+                    e
+                );
+                exit(-4);
+            }
+        }
+    }
+    false
+}
+
+/// Starts sccache-dist on `host` and the other configured remotes for `--distribute`, returns
+/// whether any of them joined.
+fn start_distributed(
+    transport: &transport::Transport,
+    conf: &config::Config,
+    remote: &config::Remote,
+    host: &str,
+) -> bool {
+    let all = RemoteOpts {
+        name: None,
+        host: None,
+        ssh_port: None,
+        temp_dir: None,
+        env: None,
+    };
+    let workers: Vec<config::Remote> = conf
+        .remotes(&all)
+        .into_iter()
+        .filter(|worker| {
+            worker.host != host && (remote.name.is_empty() || worker.name != remote.name)
+        })
+        .collect();
+    if workers.is_empty() {
+        warn!(
+            "--distribute needs other configured remotes, building on {} alone.",
+            host
+        );
+        return false;
+    }
+    info!("Starting sccache-dist on {} remotes.", workers.len() + 1);
+    match dist::token().and_then(|token| dist::start(transport, remote, host, &workers, &token)) {
+        Ok(0) => {
+            warn!("No other remote joined, building on {} alone.", host);
+            false
+        }
+        Ok(_) => true,
+        Err(e) => {
+            error!("Failed to start the sccache-dist scheduler (error: {})", e);
+            exit(-30);
+        }
+    }
+}
+
+/// The script running the cargo `commands` in the build path, after installing the tools and
+/// components they need with `install`.
+#[allow(clippy::too_many_arguments)]
+fn cargo_script(
+    remote: &config::Remote,
+    commands: &[String],
+    sysroot: &sysroot::Sysroot,
+    env: &std::collections::BTreeMap<String, String>,
+    vendored: bool,
+    parallel: bool,
+    install: bool,
+    wasm: bool,
+    distributed: bool,
+) -> String {
+    let cargo_commands = if vendored {
+        vendor::rewrite(commands.to_vec())
+    } else {
+        commands.to_vec()
+    };
+    let mut cargo_script = if parallel {
+        script::parallel_cargo_commands(&cargo_commands)
+    } else {
+        script::cargo_commands(&cargo_commands)
+    };
+    if install {
+        let tools = tools::required(remote, commands);
+        cargo_script.insert_str(0, &tools::install_script(&tools));
+        cargo_script.insert_str(0, &tools::component_script(commands));
+        if wasm {
+            cargo_script.insert_str(0, &wasm::setup_script());
+        }
+    }
+    cargo_script.insert_str(0, &sysroot.setup_script(commands, install));
+    if distributed {
+        cargo_script.insert_str(0, &dist::client_script());
+    }
+    cargo_script.insert_str(0, &script::exports(env));
+    cargo_script
+}
+
+/// The variables sent to the build on stdin, from forward_env, the secrets and the registry
+/// token of `publish`. An interactive shell needs stdin for itself.
+fn forwarded_variables(
+    remote: &config::Remote,
+    commands: &[String],
+    reconnect: bool,
+    publish_token: Option<(String, String)>,
+) -> Vec<(String, String)> {
+    let unused = !remote.forward_env.is_empty() || !remote.secrets.is_empty();
+    if reconnect {
+        if unused {
+            warn!("The variables in forward_env and secrets aren't passed to builds with --reconnect.");
+        }
+        return Vec::new();
+    }
+    if commands.is_empty() {
+        if unused {
+            warn!("The variables in forward_env and secrets aren't passed to interactive shells.");
+        }
+        return Vec::new();
+    }
+    if remote.shell == config::Shell::PowerShell {
+        if unused {
+            warn!("The variables in forward_env and secrets aren't passed to builds on Windows.");
+        }
+        return Vec::new();
+    }
+    let mut forwarded = credentials::collect(remote).unwrap_or_else(|e| {
+        error!("Failed to forward the environment (error: {})", e);
+        exit(-16);
+    });
+    if let Some((name, token)) = publish_token {
+        forwarded.retain(|(forwarded, _)| *forwarded != name);
+        forwarded.push((name, token));
+    }
+    forwarded
+}
+
+/// Serves or opens the documentation copied back into `doc_dir`.
+fn show_docs(doc_dir: &std::path::Path, serve: Option<u16>, open: bool) {
+    match serve {
+        Some(port) => {
+            let served = docs::serve(doc_dir, port, |url| {
+                println!("{}", url);
+                if open {
+                    docs::open(url);
+                }
+            });
+            if let Err(e) = served {
+                error!("Failed to serve the docs on port {} (error: {})", port, e);
+                exit(-20);
+            }
+        }
+        None => {
+            let page = docs::start_page(doc_dir).unwrap_or_default();
+            docs::open(&format!("file://{}", doc_dir.join(page).to_string_lossy()));
+        }
+    }
+}
+
+/// Flashes the copied back `artifact` with the `flash_command` of the remote.
+fn flash_firmware(flash_command: &str, artifact: &std::path::Path) {
+    info!("Flashing {:?}.", artifact);
+    match firmware::flash(flash_command, artifact) {
+        Ok(status) if status.success() => {}
+        Ok(status) => {
+            error!("Flashing failed ({})", status);
+            exit(status.code().unwrap_or(1));
+        }
+        Err(e) => {
+            error!("Failed to run the flash command (error: {})", e);
+            exit(-18);
+        }
+    }
+}
+
+/// The build of an invocation: the build server it runs on and the local project it builds.
+/// The phases before and after the build run on it.
+#[derive(Clone, Copy)]
+struct Build<'a> {
+    transport: &'a transport::Transport,
+    remote: &'a config::Remote,
+    host: &'a str,
+    build_path: &'a str,
+    project_dir: &'a std::path::Path,
+    target_dir: &'a std::path::Path,
+}
+
+impl Build<'_> {
+    /// Starts the build as a detached job for `--detach`, to be checked on with `jobs`.
+    fn detach(
+        &self,
+        commands: Vec<String>,
+        copy_back: Option<detach::CopyBack>,
+        build_command: &str,
+        started_at: std::time::SystemTime,
+    ) -> i32 {
+        if commands.is_empty() {
+            error!(
+                "--detach needs a cargo command, an interactive shell can't run in the background"
+            );
+            return -13;
+        }
+        if !self.remote.forward_env.is_empty() {
+            warn!("The variables in forward_env aren't passed to detached builds.");
+        }
+        let mut jobs = detach::Jobs::load(self.target_dir).unwrap_or_else(|e| {
+            error!("Failed to read the detached builds (error: {})", e);
+            exit(-13);
+        });
+        let mut job = detach::Job {
+            id: jobs.next_id(),
+            remote: self.remote.name.clone(),
+            host: self.host.to_owned(),
+            build_path: self.build_path.to_owned(),
+            pid: None,
+            started_at: summary::unix_time(started_at),
+            commands,
+            copy_back,
+        };
+        if let Err(e) = job.launch(self.transport, self.remote, build_command) {
+            error!("Failed to start the detached build (error: {})", e);
+            return -13;
+        }
+        let id = job.id;
+        jobs.jobs.push(job);
+        if let Err(e) = jobs.save(self.target_dir) {
+            error!("Failed to save the detached build (error: {})", e);
+            return -13;
+        }
+        info!(
+            "Started build {} on {}. Use `cargo remote jobs`, `cargo remote attach`, \
+             `cargo remote logs -f` or `cargo remote cancel {}` to check on it.",
+            id, self.host, id
+        );
+        0
+    }
+
+    /// Stops the build after `timeout` for `--timeout`.
+    fn watchdog(&self, timeout: Duration) -> timeout::Watchdog {
+        timeout::Watchdog::start(
+            self.transport,
+            self.remote,
+            self.host,
+            self.build_path,
+            self.target_dir,
+            timeout,
+        )
+    }
+
+    /// Runs `remote_command` on the build server with the `forwarded` variables on stdin,
+    /// recording the session for `--record` and annotating the diagnostics in CI.
+    fn run(
+        &self,
+        remote_command: &str,
+        forwarded: &[(String, String)],
+        record: Option<&Option<String>>,
+        build_command: &str,
+    ) -> std::io::Result<std::process::ExitStatus> {
+        let transport = self.transport;
+        let stdin = if forwarded.is_empty() {
+            process::Input::Inherit
+        } else {
+            credentials::input(forwarded)
+        };
+        let mut ssh = transport.remote_build_command(
+            self.remote,
+            self.host,
+            remote_command,
+            forwarded.is_empty(),
+        );
+        if let Some(record_path) = record {
+            let record_path = record_path
+                .as_deref()
+                .map(PathBuf::from)
+                .unwrap_or_else(|| record::default_path(self.target_dir));
+            info!("Recording remote session to {:?}", record_path);
+            let title = format!("cargo remote on {} in {}", self.host, self.build_path);
+            return record::recorder(&record_path, &title, build_command)
+                .and_then(|recorder| transport.run_with(&mut ssh, "build", stdin, recorder));
+        }
+        match transport.ci {
+            Some(ci) => {
+                let mut annotator = ci.annotator();
+                let annotate = process::Output::Lines(Box::new(move |line| {
+                    println!("{}", line);
+                    if let Some(annotation) = annotator.line(line) {
+                        println!("{}", annotation);
+                    }
+                    Ok(())
+                }));
+                transport.run_with(&mut ssh, "build", stdin, annotate)
+            }
+            None => transport.run_with(&mut ssh, "build", stdin, process::Output::Inherit),
+        }
+    }
+
+    /// Copies back the logs of the build scripts of a build stopped by `--timeout`.
+    fn fetch_build_script_logs(&self) {
+        match timeout::fetch_logs(
+            self.transport,
+            self.remote,
+            self.host,
+            self.build_path,
+            self.target_dir,
+        ) {
+            Ok(logs) => info!("Copied the logs of the build scripts to {:?}.", logs),
+            Err(e) => warn!(
+                "Failed to copy back the logs of the build scripts (error: {})",
+                e
+            ),
+        }
+    }
+
+    /// Logs what the compiler cache did during the build and adds it to the history shown by
+    /// `cache-stats`.
+    fn record_cache_stats(
+        &self,
+        cache: config::CompilerCache,
+        before: &cache_stats::CacheStats,
+        commands: &[String],
+    ) {
+        match cache_stats::query(self.transport, self.remote, self.host, cache) {
+            Ok(after) => {
+                info!("{}", cache_stats::summary(cache, before, &after));
+                let mut history = cache_stats::History::load(self.target_dir);
+                history.record(cache_stats::Run::new(
+                    cache,
+                    self.host,
+                    commands,
+                    before,
+                    &after,
+                    summary::unix_time(std::time::SystemTime::now()),
+                ));
+                if let Err(e) = history.save(self.target_dir) {
+                    warn!("Failed to save the {} statistics (error: {})", cache, e);
+                }
+            }
+            Err(e) => warn!("Failed to query {} statistics (error: {})", cache, e),
+        }
+    }
+
+    /// Uploads `file_name` of the target dir to the artifact_store and prints the URLs.
+    fn publish_artifacts(&self, store: &artifacts::Store, file_name: &str) {
+        info!(
+            "Uploading artifacts to {}.",
+            self.remote.artifact_store.as_deref().unwrap_or_default()
+        );
+        match artifacts::publish(
+            self.transport,
+            self.remote,
+            self.host,
+            self.build_path,
+            store,
+            file_name,
+        ) {
+            Ok(urls) => {
+                for url in urls {
+                    println!("{}", url);
+                }
+            }
+            Err(e) => {
+                error!("Failed to upload the artifacts (error: {})", e);
+                exit(-14);
+            }
+        }
+    }
+
+    /// Copies `file_name` of the target dir back to `destination`, or its files into the
+    /// `output_dir`.
+    fn copy_back(
+        &self,
+        file_name: &str,
+        destination: &str,
+        output_dir: Option<&std::path::Path>,
+        create_parent: bool,
+        delete: bool,
+    ) {
+        if let Some(output_dir) = output_dir {
+            std::fs::create_dir_all(output_dir).unwrap_or_else(|e| {
+                error!(
+                    "Failed to create output directory {:?} (error: {})",
+                    output_dir, e
+                );
+                exit(-6);
+            });
+        }
+        if create_parent {
+            if let Some(parent) = std::path::Path::new(destination).parent() {
+                std::fs::create_dir_all(parent).unwrap_or_else(|e| {
+                    error!("Failed to create directory {:?} (error: {})", parent, e);
+                    exit(-6);
+                });
+            }
+        }
+        let copied_back = match output_dir {
+            Some(_) => sync::copy_back_flat(
+                self.transport,
+                self.remote,
+                self.host,
+                self.build_path,
+                file_name,
+                destination,
+            ),
+            None => sync::copy_back(
+                self.transport,
+                self.remote,
+                self.host,
+                self.build_path,
+                file_name,
+                destination,
+                delete,
+            ),
+        };
+        copied_back.unwrap_or_else(|e| {
+            error!(
+                "Failed to transfer target back to local machine (error: {})",
+                e
+            );
+            exit(-6);
+        });
+    }
+
+    /// Writes the remote build environment of the `commands` to `info_path`.
+    fn record_build_info(&self, commands: &[String], info_path: &std::path::Path) {
+        match build_info::collect(
+            self.transport,
+            self.remote,
+            self.host,
+            self.build_path,
+            commands,
+        )
+        .and_then(|info| info.save(info_path))
+        {
+            Ok(()) => info!("Wrote the remote build environment to {:?}", info_path),
+            Err(e) => warn!(
+                "Failed to record the remote build environment (error: {})",
+                e
+            ),
+        }
+    }
+
+    /// Compares the build time and the artifacts in `destination` with the last build of the
+    /// `commands`, exits if an artifact grew more than `fail_on_size_increase` percent.
+    fn check_regressions(
+        &self,
+        commands: &[String],
+        build_secs: f64,
+        destination: &str,
+        fail_on_size_increase: Option<f64>,
+    ) {
+        let regressions =
+            regression::Run::measure(self.host, build_secs, std::path::Path::new(destination))
+                .and_then(|run| {
+                    let mut history = regression::History::load(self.target_dir);
+                    let previous = history.record(commands, run.clone());
+                    history.save(self.target_dir)?;
+                    Ok(previous.map(|previous| regression::Report::new(&previous, &run)))
+                });
+        match regressions {
+            Ok(Some(report)) if !report.is_empty() => {
+                info!(
+                    "Changes since the last build of these commands:\n{}",
+                    report
+                );
+                let increases =
+                    report.increases_over(fail_on_size_increase.unwrap_or(f64::INFINITY));
+                if !increases.is_empty() {
+                    for (artifact, increase) in increases {
+                        error!(
+                            "{} grew by {:.1}%, more than the {}% allowed by --fail-on-size-increase",
+                            artifact,
+                            increase,
+                            fail_on_size_increase.unwrap_or_default()
+                        );
+                    }
+                    exit(-28);
+                }
+            }
+            Ok(_) => {}
+            Err(e) => warn!(
+                "Failed to compare the artifacts with the last build (error: {})",
+                e
+            ),
+        }
+    }
+
+    /// Maps the remote source paths of the lcov report in `destination` to the project dir.
+    fn remap_coverage(&self, destination: &str) {
+        match coverage::remap_lcov(
+            std::path::Path::new(destination),
+            self.build_path,
+            self.project_dir,
+        ) {
+            Ok(remapped) => info!(
+                "Coverage reports are in {}, {} source paths mapped to {:?}.",
+                destination, remapped, self.project_dir
+            ),
+            Err(e) => warn!("Failed to map the paths of the lcov report (error: {})", e),
+        }
+    }
+
+    /// Packages the package_artifacts of the remote in an archive and copies it back into the
+    /// `output_dir` or the target dir.
+    fn package_artifacts(&self, output_dir: Option<&std::path::Path>) {
+        info!("Packaging the artifacts on the remote.");
+        let project = self
+            .project_dir
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_else(|| "artifacts".to_owned());
+        let file_name = package::file_name(self.remote, &project);
+        let destination = output_dir.unwrap_or(self.target_dir).join(&file_name);
+        let packaged = package::create(
+            self.transport,
+            self.remote,
+            self.host,
+            self.build_path,
+            &file_name,
+        )
+        .and_then(|archive| {
+            if let Some(parent) = destination.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            sync::copy_back(
+                self.transport,
+                self.remote,
+                self.host,
+                self.build_path,
+                &archive,
+                &destination.to_string_lossy(),
+                false,
+            )
+        });
+        match packaged {
+            Ok(status) if status.success() => {
+                info!("Copied back the artifacts in {:?}.", destination)
+            }
+            Ok(status) => {
+                error!("Failed to transfer the archive back ({})", status);
+                exit(-24);
+            }
+            Err(e) => {
+                error!("Failed to package the artifacts (error: {})", e);
+                exit(-24);
+            }
+        }
+    }
+
+    /// Pushes the artifacts from the build server to the `--deploy` targets.
+    fn deploy(&self, targets: &[(&String, &deploy::Target)]) {
+        for (name, target) in targets {
+            info!("Deploying to {} ({}).", name, target.host);
+            if let Err(e) = deploy::push(
+                self.transport,
+                self.remote,
+                self.host,
+                self.build_path,
+                target,
+            ) {
+                error!("Failed to deploy to {} (error: {})", name, e);
+                exit(-26);
+            }
+            info!("Deployed to {}.", name);
+        }
+    }
+
+    /// Copies the OUT_DIRs of the build scripts of the `commands` into the local target dir.
+    fn copy_back_outdirs(&self, commands: &[String]) {
+        info!("Transferring the OUT_DIRs of build scripts back to client.");
+        let copied = outdirs::OutDirs::find(
+            self.transport,
+            self.remote,
+            self.host,
+            self.build_path,
+            commands,
+        )
+        .and_then(|out_dirs| {
+            out_dirs.copy_back(
+                self.transport,
+                self.remote,
+                self.host,
+                self.project_dir,
+                self.target_dir,
+            )
+        });
+        match copied {
+            Ok(out_dirs) => info!(
+                "Copied back {} OUT_DIRs into {:?}.",
+                out_dirs.len(),
+                self.target_dir
+            ),
+            Err(e) => {
+                error!("Failed to copy back the OUT_DIRs (error: {})", e);
+                exit(-6);
+            }
+        }
+    }
+
+    /// Copies back the executables of the examples and benches built by the `commands`.
+    fn copy_back_executables(
+        &self,
+        commands: &[String],
+        executables: &[(executables::Kind, String)],
+        output_dir: Option<&std::path::Path>,
+    ) {
+        info!("Transferring the executables of examples and benches back to client.");
+        match executables::copy_back(
+            self.transport,
+            self.remote,
+            self.host,
+            self.build_path,
+            commands,
+            executables,
+            self.target_dir,
+            output_dir,
+        ) {
+            Ok(copied) => {
+                for executable in copied {
+                    info!("Copied back {:?}.", executable);
+                }
+            }
+            Err(e) => {
+                error!(
+                    "Failed to copy back the examples and benches (error: {})",
+                    e
+                );
+                exit(-6);
+            }
+        }
+    }
+
+    /// Writes the `--json-summary` of the build to `summary_path`.
+    fn write_summary(
+        &self,
+        summary_path: &std::path::Path,
+        commands: &[String],
+        cache_hit: bool,
+        started_at: std::time::SystemTime,
+        build_status: std::process::ExitStatus,
+        timings: &summary::Timings,
+    ) {
+        let results = if commands.is_empty() {
+            Ok(Vec::new())
+        } else if cache_hit {
+            Ok(commands
+                .iter()
+                .map(|command| summary::CommandResult {
+                    command: command.clone(),
+                    exit_code: 0,
+                })
+                .collect())
+        } else {
+            summary::fetch_results(self.transport, self.remote, self.host, self.build_path)
+        };
+        let written = results.and_then(|commands| {
+            summary::Summary {
+                host: self.host.to_owned(),
+                build_path: self.build_path.to_owned(),
+                started_at: summary::unix_time(started_at),
+                duration_secs: started_at.elapsed().unwrap_or_default().as_secs(),
+                exit_code: build_status.code(),
+                commands,
+                timings: timings.clone(),
+            }
+            .save(summary_path)
+        });
+        match written {
+            Ok(()) => info!("Wrote build summary to {:?}", summary_path),
+            Err(e) => warn!("Failed to write the build summary (error: {})", e),
+        }
+    }
+
+    /// Copies Cargo.lock back if the build changed it, keeping local changes made during the
+    /// build unless `force` is given.
+    fn copy_lock(&self, lock_snapshot: &lockfile::LockSnapshot, force: bool, dry_run: bool) {
+        if !dry_run
+            && lock_snapshot.unchanged_on(self.transport, self.remote, self.host, self.build_path)
+        {
+            info!("{:?} is unchanged.", lock_snapshot.path());
+            return;
+        }
+        info!("Transferring Cargo.lock file back to client.");
+        let fetched_lock = lockfile::LockSnapshot::fetch_path(self.target_dir);
+        let status = std::fs::create_dir_all(self.target_dir)
+            .and_then(|_| {
+                sync::fetch(
+                    self.transport,
+                    self.remote,
+                    self.host,
+                    &format!("{}Cargo.lock", self.build_path),
+                    &fetched_lock.to_string_lossy(),
+                    false,
+                    "copy-lock",
+                )
+            })
+            .unwrap_or_else(|e| {
+                error!(
+                    "Failed to transfer Cargo.lock back to local machine (error: {})",
+                    e
                 );
                 exit(-7);
             });
+        if dry_run {
+            // Nothing was fetched, leave the local lockfile alone.
+        } else if status.success() {
+            let update = lock_snapshot
+                .apply(&fetched_lock, force)
+                .unwrap_or_else(|e| {
+                    error!("Failed to update {:?} (error: {})", lock_snapshot.path(), e);
+                    exit(-7);
+                });
+            match update {
+                lockfile::LockUpdate::Unchanged => {
+                    info!("{:?} is unchanged.", lock_snapshot.path())
+                }
+                lockfile::LockUpdate::Updated => info!("Updated {:?}.", lock_snapshot.path()),
+                lockfile::LockUpdate::Conflict => {
+                    error!(
+                        "{:?} was modified locally during the build, the remote version was saved to {:?}. \
+                         Use --force-lock to overwrite the local file anyway.",
+                        lock_snapshot.path(),
+                        fetched_lock
+                    );
+                    exit(-8);
+                }
+            }
+        } else {
+            warn!("Failed to transfer Cargo.lock back to local machine, keeping the local one.");
+        }
+    }
+
+    /// Builds the `--container` image on the build server, then pushes it or loads it locally.
+    fn deliver_container(&self, tag: &str, push: bool) {
+        let delivered =
+            container::build(self.transport, self.remote, self.host, self.build_path, tag)
+                .and_then(|_| {
+                    if push {
+                        container::push(self.transport, self.remote, self.host, tag)
+                    } else {
+                        container::retrieve(self.transport, self.remote, self.host, tag)
+                    }
+                });
+        if let Err(e) = delivered {
+            error!("Failed to build image {} (error: {})", tag, e);
+            exit(-19);
+        }
+    }
+}
+
+/// Deletes `build_path` on `host` when cargo remote exits, for `--ephemeral`.
+fn delete_at_exit(
+    transport: &transport::Transport,
+    remote: &config::Remote,
+    host: &str,
+    build_path: &str,
+    dry_run: bool,
+) {
+    let (transport, remote, host, build_path) = (
+        transport.clone(),
+        remote.clone(),
+        host.to_owned(),
+        build_path.to_owned(),
+    );
+    at_exit(move || {
+        if dry_run {
+            info!("Would delete {} on {}.", build_path, host);
+            return;
+        }
+        info!("Deleting {} on {}.", build_path, host);
+        let mut ssh =
+            transport.remote_command(&remote, &host, &script::remove_dir(&remote, &build_path));
+        match transport.run(&mut ssh, "clean") {
+            Ok(status) if status.success() => {}
+            Ok(status) => warn!("Failed to delete the remote build directory ({})", status),
+            Err(e) => warn!("Failed to delete the remote build directory (error: {})", e),
+        }
+    });
+}
+
+/// The host of `remote` to continue on after the connection to `lost` broke, skipping the
+/// `failed` ones. Its host key is checked like that of the first host.
+fn next_host(
+    transport: &transport::Transport,
+    remote: &config::Remote,
+    failed: &[String],
+    lost: &str,
+) -> Option<String> {
+    let host = transport.select_host_except(remote, failed)?;
+    warn!("Lost the connection to {}, failing over to {}.", lost, host);
+    if !transport.pin_host_key(remote, &host) {
+        exit(-15);
+    }
+    Some(host)
+}
+
+/// Runs the build like `--detach` and streams its output until it finished, reconnecting when
+/// the connection drops. The build stays a detached job until then, so it can still be attached
+/// to if the connection can't be restored.
+#[allow(clippy::too_many_arguments)]
+fn reconnecting_build(
+    transport: &transport::Transport,
+    remote: &config::Remote,
+    host: &str,
+    build_path: &str,
+    build_command: &str,
+    commands: &[String],
+    target_dir: &std::path::Path,
+    started_at: std::time::SystemTime,
+) -> std::io::Result<std::process::ExitStatus> {
+    let mut jobs = detach::Jobs::load(target_dir)?;
+    let mut job = detach::Job {
+        id: jobs.next_id(),
+        remote: remote.name.clone(),
+        host: host.to_owned(),
+        build_path: build_path.to_owned(),
+        pid: None,
+        started_at: summary::unix_time(started_at),
+        commands: commands.to_vec(),
+        copy_back: None,
+    };
+    job.launch(transport, remote, build_command)?;
+    let id = job.id;
+    jobs.jobs.push(job.clone());
+    jobs.save(target_dir)?;
+    let status = job.stream(transport, remote).map_err(|e| {
+        std::io::Error::other(format!(
+            "{}, the build continues as job {}, see `cargo remote attach {}`",
+            e, id, id
+        ))
+    })?;
+    jobs.remove(id);
+    jobs.save(target_dir)?;
+    Ok(status)
+}
+
+/// Directory of the cargo `profile` in the target dir, custom profiles have to be defined in
+/// the manifest or the cargo config of the workspace.
+fn cargo_profile_dir(project_dir: &std::path::Path, profile: &str) -> Result<String, String> {
+    match profile {
+        "dev" | "debug" | "test" => return Ok("debug".to_owned()),
+        "release" | "bench" => return Ok("release".to_owned()),
+        _ => {}
+    }
+    let defined = [
+        project_dir.join("Cargo.toml"),
+        project_dir.join(".cargo").join("config.toml"),
+        project_dir.join(".cargo").join("config"),
+    ]
+    .iter()
+    .filter_map(|path| std::fs::read_to_string(path).ok())
+    .filter_map(|contents| toml::from_str::<toml::Value>(&contents).ok())
+    .any(|manifest| {
+        manifest
+            .get("profile")
+            .and_then(|profiles| profiles.get(profile))
+            .is_some()
+    });
+    if defined {
+        Ok(profile.to_owned())
+    } else {
+        Err(format!(
+            "The cargo profile {} isn't defined in the Cargo.toml or .cargo/config.toml of {:?}",
+            profile, project_dir
+        ))
+    }
+}
+
+/// The triple of the first `--target` option in `commands`, the profile dirs are below it then.
+fn target_triple(commands: &[String]) -> Option<String> {
+    let target = commands
+        .iter()
+        .find_map(|command| sysroot::target(command))?;
+    // the target dir of a custom target is named after its spec file
+    Some(match target.strip_suffix(".json") {
+        Some(spec) => spec.rsplit('/').next().unwrap_or(spec).to_owned(),
+        None => target.to_owned(),
+    })
+}
+
+/// Local destination of `--copy-back` for `file_name`.
+fn copy_back_destination(
+    output_dir: &Option<PathBuf>,
+    target_dir: &std::path::Path,
+    file_name: &str,
+) -> String {
+    match output_dir {
+        Some(output_dir) => format!("{}/", output_dir.to_string_lossy()),
+        None => format!("{}/{}", target_dir.to_string_lossy(), file_name),
+    }
+}
+
+/// Runs `attach`, `status`, `logs`, `jobs` or `cancel` for the builds started with `--detach`.
+///
+/// Finished builds found along the way get their artifacts copied back.
+fn manage_jobs(
+    transport: &transport::Transport,
+    remotes: &[config::Remote],
+    fallback: &config::Remote,
+    target_dir: &std::path::Path,
+    command: &Command,
+) -> i32 {
+    let mut jobs = match detach::Jobs::load(target_dir) {
+        Ok(jobs) => jobs,
+        Err(e) => {
+            error!("Failed to read the detached builds (error: {})", e);
+            return 1;
+        }
+    };
+    let code = match command {
+        Command::Jobs => {
+            list_jobs(transport, remotes, fallback, &mut jobs);
+            0
+        }
+        Command::Cancel { id } => match jobs.get_mut(Some(*id)) {
+            Ok(job) => match job.cancel(transport, job.remote(remotes, fallback)) {
+                Ok(()) => {
+                    println!("Cancelled build {} on {}.", id, job.host);
+                    jobs.remove(*id);
+                    0
+                }
+                Err(e) => {
+                    error!("Failed to cancel build {} (error: {})", id, e);
+                    1
+                }
+            },
+            Err(e) => {
+                error!("{}", e);
+                1
+            }
+        },
+        Command::Attach { id } | Command::Status { id } | Command::Logs { id, .. } => {
+            let job = match jobs.get_mut(*id) {
+                Ok(job) => job,
+                Err(e) => {
+                    error!("{}", e);
+                    return 1;
+                }
+            };
+            let remote = job.remote(remotes, fallback);
+            let result = match command {
+                Command::Attach { .. } => job.attach(transport, remote),
+                Command::Logs { follow, .. } => job.logs(transport, remote, *follow),
+                _ => Ok(0),
+            }
+            .and_then(|code| {
+                let status = job.status(transport, remote)?;
+                if let Command::Status { .. } = command {
+                    println!("{} on {}: {}", status, job.host, job.commands.join("; "));
+                }
+                job.finish(transport, remote, status)?;
+                Ok(match (command, status) {
+                    (Command::Status { .. }, detach::Status::Unknown) => 1,
+                    _ => code,
+                })
+            });
+            result.unwrap_or_else(|e| {
+                error!("Failed to check on build {} (error: {})", job.id, e);
+                1
+            })
+        }
+        _ => unreachable!("not a job command"),
+    };
+    if let Err(e) = jobs.save(target_dir) {
+        error!("Failed to save the detached builds (error: {})", e);
+        return 1;
+    }
+    code
+}
+
+/// Prints the compiler cache statistics of the last builds into `target_dir`, oldest first.
+fn show_cache_stats(target_dir: &std::path::Path) {
+    let history = cache_stats::History::load(target_dir);
+    if history.runs.is_empty() {
+        println!("No build with a compiler_cache recorded cache statistics yet.");
+        return;
+    }
+    println!(
+        "{:<12} {:<20} {:<8} {:>6} {:>6} {:>8} {:<12} COMMAND",
+        "FINISHED", "HOST", "CACHE", "HITS", "MISSES", "RATE", "SIZE"
+    );
+    let now = summary::unix_time(std::time::SystemTime::now());
+    for run in &history.runs {
+        println!(
+            "{:<12} {:<20} {:<8} {:>6} {:>6} {:>8} {:<12} {}",
+            format!("{}m ago", now.saturating_sub(run.finished_at) / 60),
+            run.host,
+            run.cache,
+            run.hits,
+            run.misses,
+            cache_stats::hit_rate(run.hits, run.misses),
+            run.size.as_deref().unwrap_or("unknown"),
+            run.commands.join("; ")
+        );
+    }
+}
+
+/// Prints every detached build with its state. Builds that left no trace on the remote are
+/// forgotten.
+fn list_jobs(
+    transport: &transport::Transport,
+    remotes: &[config::Remote],
+    fallback: &config::Remote,
+    jobs: &mut detach::Jobs,
+) {
+    if jobs.jobs.is_empty() {
+        println!("No builds were started with --detach.");
+        return;
+    }
+    println!(
+        "{:<4} {:<12} {:<20} {:<12} COMMAND",
+        "ID", "STATE", "HOST", "STARTED"
+    );
+    let now = summary::unix_time(std::time::SystemTime::now());
+    let mut forgotten = Vec::new();
+    for job in &mut jobs.jobs {
+        let remote = job.remote(remotes, fallback);
+        let status = job.status(transport, remote);
+        let state = match &status {
+            Ok(status) => status.to_string(),
+            Err(e) => {
+                warn!("Failed to reach {} (error: {})", job.host, e);
+                "unreachable".to_owned()
+            }
+        };
+        println!(
+            "{:<4} {:<12} {:<20} {:<12} {}",
+            job.id,
+            state,
+            job.host,
+            format!("{}m ago", now.saturating_sub(job.started_at) / 60),
+            job.commands.join("; ")
+        );
+        match status {
+            Ok(detach::Status::Unknown) => forgotten.push(job.id),
+            Ok(status) => {
+                if let Err(e) = job.finish(transport, remote, status) {
+                    warn!(
+                        "Failed to copy back the artifacts of build {} (error: {})",
+                        job.id, e
+                    );
+                }
+            }
+            Err(_) => {}
+        }
+    }
+    for id in forgotten {
+        jobs.remove(id);
+    }
+}
+
+/// Exit codes of `version-check`
+const VERSION_CHECK_NO_REMOTE: i32 = 1;
+const VERSION_CHECK_STALE_PROBE: i32 = 2;
+
+/// Validates the config and the cached probe of the remote without running cargo or connecting
+/// to the remote, so editor integrations can call it cheaply.
+///
+/// Returns 0 if a remote is configured and its probe is fresh, [`VERSION_CHECK_NO_REMOTE`] if
+/// no remote is usable and [`VERSION_CHECK_STALE_PROBE`] if the remote wasn't probed recently.
+fn version_check_fast_path(manifest_path: &std::path::Path, remote_opts: &RemoteOpts) -> i32 {
+    println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
+
+    let project_dir = find_project_dir(manifest_path);
+    let remote = match config::Config::new(&project_dir) {
+        Ok(conf) => conf.get_remote(remote_opts),
+        Err(error) => {
+            println!("config: {}", error);
+            return VERSION_CHECK_NO_REMOTE;
+        }
+    };
+    let remote = match remote {
+        Some(remote) => remote,
+        None => {
+            println!("remote: none");
+            return VERSION_CHECK_NO_REMOTE;
+        }
+    };
+    println!("remote: {} ({})", remote.name, remote.host);
+
+    match probe::RemoteFacts::load(&remote, &remote.host) {
+        Some(facts) => {
+            println!(
+                "probe: {} ({}s old), rustc {}, host {}",
+                if facts.is_fresh() { "fresh" } else { "stale" },
+                facts.age(),
+                facts.rustc_version.as_deref().unwrap_or("unknown"),
+                facts.host_triple.as_deref().unwrap_or("unknown"),
+            );
+            if facts.is_fresh() {
+                0
+            } else {
+                VERSION_CHECK_STALE_PROBE
+            }
+        }
+        None => {
+            println!("probe: missing");
+            VERSION_CHECK_STALE_PROBE
+        }
+    }
+}
+
+/// Prints every configured remote together with the cached facts about its hosts, without
+/// invoking cargo or connecting to the remotes.
+fn list_remotes(manifest_path: &std::path::Path, remote_opts: &RemoteOpts) -> i32 {
+    let project_dir = find_project_dir(manifest_path);
+    let conf = match config::Config::new(&project_dir) {
+        Ok(conf) => conf,
+        Err(error) => {
+            error!("{}", error);
+            return -3;
+        }
+    };
+    let remotes = conf.remotes(remote_opts);
+    if remotes.is_empty() {
+        println!("No remotes configured.");
+        return 4;
+    }
+    for remote in &remotes {
+        println!(
+            "{} (port {}, builds in {})",
+            if remote.name.is_empty() {
+                "<unnamed>"
+            } else {
+                &remote.name
+            },
+            remote.ssh_port,
+            remote.temp_dir
+        );
+        for host in &remote.hosts {
+            match probe::RemoteFacts::load(remote, host) {
+                Some(facts) => println!(
+                    "  {}: {}, probed {}s ago",
+                    host,
+                    describe_facts(&facts),
+                    facts.age()
+                ),
+                None => println!("  {}: not probed yet", host),
+            }
+        }
+    }
+    0
+}
+
+/// The facts about a host shown by `list` and `refresh`.
+fn describe_facts(facts: &probe::RemoteFacts) -> String {
+    let mut description = format!(
+        "rustc {}, {}",
+        facts.rustc_version.as_deref().unwrap_or("unknown"),
+        facts.host_triple.as_deref().unwrap_or("unknown host"),
+    );
+    if let Some(cpus) = facts.cpus {
+        description.push_str(&format!(
+            ", {} CPU{}",
+            cpus,
+            if cpus == 1 { "" } else { "s" }
+        ));
+    }
+    if !facts.compressors.is_empty() {
+        description.push_str(&format!(", {}", facts.compressors.join(" and ")));
+    }
+    description
+}
+
+/// Deletes the cached facts about every host of `remotes` and probes them again. Hosts that
+/// can't be reached are probed on their next use.
+fn refresh_remotes(transport: &transport::Transport, remotes: &[config::Remote]) -> i32 {
+    if remotes.is_empty() {
+        error!("No remote build server was defined (use config file or the --remote flags)");
+        return 4;
+    }
+    let mut failed = false;
+    for remote in remotes {
+        for host in &remote.hosts {
+            if let Err(e) = probe::RemoteFacts::forget(remote, host) {
+                warn!(
+                    "Failed to delete the cached facts about {} (error: {})",
+                    host, e
+                );
+            }
+            let probed = probe::RemoteFacts::probe(transport, remote, host)
+                .and_then(|facts| facts.save(remote).map(|_| facts));
+            match probed {
+                Ok(facts) => println!("{}: {}", host, describe_facts(&facts)),
+                Err(e) => {
+                    error!("Failed to probe {} (error: {})", host, e);
+                    failed = true;
+                }
+            }
+        }
+    }
+    if failed {
+        1
+    } else {
+        0
+    }
+}
+
+/// Posts the summary of the last build (or `summary`) to pull request `pr`, updating the
+/// comment of a previous run.
+fn post_comment(
+    target_dir: &std::path::Path,
+    pr: u64,
+    repo: Option<String>,
+    summary: Option<PathBuf>,
+) -> i32 {
+    let summary_path = summary.unwrap_or_else(|| summary::default_path(target_dir));
+    let summary = match summary::Summary::load(&summary_path) {
+        Ok(summary) => summary,
+        Err(e) => {
+            error!(
+                "Failed to read {:?}, run a build with --json-summary first (error: {})",
+                summary_path, e
+            );
+            return 1;
+        }
+    };
+    let repo = match repo.or_else(github::origin_repo) {
+        Some(repo) => repo,
+        None => {
+            error!("Can't tell the GitHub repository from the git remote origin, use --repo");
+            return 1;
+        }
+    };
+    match github::upsert_comment(&repo, pr, summary::COMMENT_MARKER, &summary.markdown()) {
+        Ok(url) => {
+            info!("Posted the build summary: {}", url);
+            0
+        }
+        Err(e) => {
+            error!("Failed to post the build summary (error: {})", e);
+            1
+        }
+    }
+}
+
+/// Reports problems in every config file and prints the merged config, without invoking cargo.
+///
+/// Returns 0 if the config is valid and 1 otherwise.
+fn validate_config_files(manifest_path: &std::path::Path) -> i32 {
+    let project_dir = find_project_dir(manifest_path);
+    let files = config::Config::files(&project_dir);
+    if files.is_empty() {
+        println!("No config files found.");
+    }
+    for file in &files {
+        println!("Config file: {}", file.display());
+    }
+
+    let problems = validate::validate_files(&project_dir);
+    for problem in &problems {
+        println!("error: {}", problem);
+    }
+
+    match config::Config::new(&project_dir) {
+        Ok(conf) if problems.is_empty() => {
+            println!();
+            print!("{}", conf.describe());
+            0
+        }
+        Ok(_) => 1,
+        // already reported per file
+        Err(_) if !problems.is_empty() => 1,
+        Err(error) => {
+            println!("error: {}", error);
+            1
+        }
+    }
+}
+
+/// Saves the secret `name` in the system keyring, or encrypts it for the age `recipient`, and
+/// prints how to reference it in the config.
+///
+/// Returns 0 on success and 1 otherwise.
+fn set_secret(name: &str, recipient: Option<&str>) -> i32 {
+    let recipient = match recipient {
+        Some(recipient) => recipient,
+        None => {
+            if let Err(e) = secrets::set_keyring(name) {
+                error!("Failed to save {} in the keyring (error: {})", name, e);
+                return 1;
+            }
+            println!(
+                "Saved {} in the keyring, reference it in a remote with:",
+                name
+            );
+            println!("secrets = {{ {} = \"keyring:{}\" }}", name, name);
+            return 0;
+        }
+    };
+    if std::io::stdin().is_terminal() {
+        eprint!("Value of {}: ", name);
+    }
+    let mut value = String::new();
+    if let Err(e) = std::io::stdin().read_to_string(&mut value) {
+        error!("Failed to read the value of {} (error: {})", name, e);
+        return 1;
+    }
+    let value = value.strip_suffix('\n').unwrap_or(&value);
+    match secrets::encrypt_age(recipient, value) {
+        Ok(ciphertext) => {
+            println!("[remote.secrets]");
+            println!("{} = \"\"\"age:{}\"\"\"", name, ciphertext);
+            0
+        }
+        Err(e) => {
+            error!("Failed to encrypt {} (error: {})", name, e);
+            1
+        }
+    }
+}
+
+/// The `required_cargo` and `required_rustc` of the project the toolchain of `remote` doesn't
+/// meet. Versions from the cache are probed again before they count as unmet, versions the
+/// remote doesn't report (e.g. outside of nix-shell) aren't checked.
+fn unmet_requirements(
+    transport: &transport::Transport,
+    conf: &config::Config,
+    remote: &config::Remote,
+) -> Vec<String> {
+    let check = |facts: &probe::RemoteFacts| {
+        let mut unmet = Vec::new();
+        let mut unknown = Vec::new();
+        for (tool, requirement) in conf.required_versions() {
+            let version = match tool {
+                "cargo" => &facts.cargo_version,
+                _ => &facts.rustc_version,
+            };
+            match version {
+                Some(version) if requirement.matches(version) => {}
+                Some(version) => unmet.push(format!(
+                    "{} {} doesn't meet required_{} = \"{}\"",
+                    tool, version, tool, requirement
+                )),
+                None => unknown.push(tool),
+            }
+        }
+        (unmet, unknown)
+    };
+    let facts = match probe::cached_or_probe(transport, remote, &remote.host) {
+        Ok(facts) => facts,
+        Err(e) => {
+            warn!("Failed to probe {} (error: {})", remote.host, e);
+            return Vec::new();
+        }
+    };
+    let (mut unmet, mut unknown) = check(&facts);
+    if (!unmet.is_empty() || !unknown.is_empty()) && facts.age() > 0 {
+        match probe::RemoteFacts::probe(transport, remote, &remote.host) {
+            Ok(facts) => {
+                if let Err(e) = facts.save(remote) {
+                    warn!(
+                        "Failed to cache the facts about {} (error: {})",
+                        remote.host, e
+                    );
+                }
+                (unmet, unknown) = check(&facts);
+            }
+            Err(e) => warn!("Failed to probe {} (error: {})", remote.host, e),
+        }
+    }
+    for tool in unknown {
+        warn!(
+            "{} doesn't have {} outside of nix-shell, its version can't be checked.",
+            remote.host, tool
+        );
+    }
+    unmet
+}
+
+/// Lists the build directories of deleted local projects on `remote` and offers to delete
+/// them. Directories of projects this machine doesn't know are only listed.
+fn gc_orphans(
+    transport: &transport::Transport,
+    remote: &config::Remote,
+    host: &str,
+    dry_run: bool,
+) -> i32 {
+    let mut registry = match registry::Registry::load() {
+        Ok(registry) => registry,
+        Err(e) => {
+            error!("Failed to load the build directory registry (error: {})", e);
+            return 1;
+        }
+    };
+    let orphans = match registry::orphans(transport, remote, host, &registry) {
+        Ok(orphans) => orphans,
+        Err(e) => {
+            error!("Failed to list {} (error: {})", remote.temp_dir, e);
+            return 1;
+        }
+    };
+    let mut deleted = Vec::new();
+    for orphan in &orphans {
+        match orphan {
+            registry::Orphan::Deleted { dir_name, project } => {
+                println!("{}/{}  {:?} (deleted)", remote.temp_dir, dir_name, project);
+                deleted.push(dir_name.as_str());
+            }
+            registry::Orphan::Unknown { dir_name } => {
+                println!(
+                    "{}/{}  not built from this machine, kept",
+                    remote.temp_dir, dir_name
+                )
+            }
+        }
+    }
+    if deleted.is_empty() {
+        info!("No build directories of deleted projects on {}.", host);
+        return 0;
+    }
+    if dry_run {
+        return 0;
+    }
+    match init::ask_yes(
+        &format!("Delete {} orphaned build directories?", deleted.len()),
+        false,
+    ) {
+        Ok(true) => {}
+        Ok(false) => return 0,
+        Err(e) => {
+            error!("{}", e);
+            return 1;
+        }
+    }
+    let mut ssh =
+        transport.remote_command(remote, host, &registry::delete_script(remote, &deleted));
+    match transport.run(&mut ssh, "gc") {
+        Ok(status) if status.success() => {
+            for dir_name in deleted {
+                registry.forget(dir_name);
+            }
+            if let Err(e) = registry.save() {
+                warn!(
+                    "Failed to update the build directory registry (error: {})",
+                    e
+                );
+            }
+            0
+        }
+        Ok(status) => status.code().unwrap_or(1),
+        Err(e) => {
+            error!("Failed to clean up {} (error: {})", remote.temp_dir, e);
+            1
+        }
     }
+}
 
-    if !output.status.success() {
-        exit(output.status.code().unwrap_or(1))
+/// Prints the config of the remote selected with `remote_opts` (or the remote of `profile`)
+/// with the source of every setting, only the ones that are set somewhere unless `defaults`.
+///
+/// Returns 0 on success and the exit code of the error otherwise.
+fn show_config(
+    manifest_path: &std::path::Path,
+    remote_opts: &RemoteOpts,
+    profile: Option<&str>,
+    defaults: bool,
+) -> i32 {
+    let project_dir = find_project_dir(manifest_path);
+    let conf = match config::Config::new(&project_dir) {
+        Ok(conf) => conf,
+        Err(error) => {
+            error!("{}", error);
+            return -3;
+        }
+    };
+    for problem in validate::unknown_keys(&project_dir) {
+        warn!("{}, it is ignored", problem);
+    }
+    let mut opts = remote_opts.clone();
+    let profile_remote = match profile.and_then(|name| conf.profile(name)) {
+        Some(profile) if opts.name.is_none() && opts.host.is_none() => profile.remote.clone(),
+        _ => None,
+    };
+    let mut provenance = provenance::Provenance::of_files(
+        &project_dir,
+        opts.name.as_deref().or(profile_remote.as_deref()),
+    );
+    if let (Some(name), Some(profile)) = (&profile_remote, profile) {
+        provenance.set("remote.name", &format!("profile {}", profile));
+        opts.name = Some(name.clone());
+    }
+    let overrides = [
+        (
+            "name",
+            remote_opts.name.clone(),
+            "--remote",
+            "CARGO_REMOTE_NAME",
+        ),
+        (
+            "host",
+            remote_opts.host.clone(),
+            "--remote-host",
+            "CARGO_REMOTE_HOST",
+        ),
+        (
+            "ssh_port",
+            remote_opts.ssh_port.map(|port| port.to_string()),
+            "--remote-ssh-port",
+            "CARGO_REMOTE_PORT",
+        ),
+        (
+            "temp_dir",
+            remote_opts.temp_dir.clone(),
+            "--remote-temp-dir",
+            "CARGO_REMOTE_TEMP_DIR",
+        ),
+        ("env", remote_opts.env.clone(), "--env", "CARGO_REMOTE_ENV"),
+    ];
+    for (key, value, flag, variable) in overrides {
+        let source = match value {
+            Some(value) if std::env::var(variable).ok().as_ref() == Some(&value) => {
+                format!("environment variable {}", variable)
+            }
+            Some(_) => flag.to_owned(),
+            None => continue,
+        };
+        provenance.set(&format!("remote.{}", key), &source);
     }
+    let remote = match conf.get_remote(&opts) {
+        Some(remote) => remote,
+        None => {
+            error!("No remote build server was defined (use config file or the --remote flags)");
+            return 4;
+        }
+    };
+    print!(
+        "{}",
+        provenance.annotate(
+            &format!("exclude = {:?}\n\n{}", conf.excludes(), remote),
+            defaults
+        )
+    );
+    0
+}
+
+/// Finds the workspace root containing `manifest_path` without invoking cargo.
+fn find_project_dir(manifest_path: &std::path::Path) -> PathBuf {
+    let manifest_path =
+        std::fs::canonicalize(manifest_path).unwrap_or_else(|_| manifest_path.into());
+    let package_dir = manifest_path
+        .parent()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| PathBuf::from("."));
+    package_dir
+        .ancestors()
+        .find(|dir| {
+            std::fs::read_to_string(dir.join("Cargo.toml"))
+                .map(|manifest| manifest.contains("[workspace]"))
+                .unwrap_or(false)
+        })
+        .map(PathBuf::from)
+        .unwrap_or(package_dir)
 }
@@ -0,0 +1,107 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use crate::config::Remote;
+use crate::summary::size;
+use crate::transport::Transport;
+
+/// Changed files from this size on are sent as deltas, smaller ones aren't worth the checksums.
+const DELTA_MIN_SIZE: u64 = 1024 * 1024;
+
+/// A file in the remote directory being copied back.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    /// Path relative to the copied directory.
+    pub path: String,
+    pub size: u64,
+    /// Modification time in seconds since the epoch.
+    pub mtime: u64,
+}
+
+/// Lists the files in the remote directory `source` with their sizes and modification times.
+/// Needs GNU find on the remote.
+pub fn list(
+    transport: &Transport,
+    remote: &Remote,
+    host: &str,
+    source: &str,
+) -> io::Result<Vec<Entry>> {
+    let output = transport.remote_output(
+        remote,
+        host,
+        &format!("cd {} && find . -type f -printf '%s %T@ %P\\n'", source),
+    )?;
+    Ok(output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, ' ');
+            let size = fields.next()?.parse().ok()?;
+            // fractions of seconds aren't compared by rsync either
+            let mtime = fields.next()?.split('.').next()?.parse().ok()?;
+            let path = fields.next()?.to_owned();
+            Some(Entry { path, size, mtime })
+        })
+        .collect())
+}
+
+/// What copying back a remote directory into a local one has to transfer, decided per file by
+/// comparing the remote listing with the local files like rsync does, by size and mtime.
+#[derive(Debug, Default)]
+pub struct Plan {
+    /// Files missing locally or too small for deltas, sent as a whole.
+    pub whole: Vec<Entry>,
+    /// Larger files that changed, sent as deltas against the local version.
+    pub delta: Vec<Entry>,
+    pub unchanged: usize,
+}
+
+impl Plan {
+    pub fn new(entries: Vec<Entry>, destination: &Path) -> Self {
+        let mut plan = Plan::default();
+        for entry in entries {
+            let local = fs::metadata(destination.join(&entry.path)).ok();
+            let local_mtime = local
+                .as_ref()
+                .and_then(|metadata| metadata.modified().ok())
+                .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+                .map(|since_epoch| since_epoch.as_secs());
+            match local {
+                Some(local) if local.len() == entry.size && local_mtime == Some(entry.mtime) => {
+                    plan.unchanged += 1
+                }
+                Some(local) if local.is_file() && entry.size >= DELTA_MIN_SIZE => {
+                    plan.delta.push(entry)
+                }
+                _ => plan.whole.push(entry),
+            }
+        }
+        plan
+    }
+
+    /// Bytes to download at most, deltas are usually much smaller than their files.
+    pub fn bytes(&self) -> u64 {
+        self.whole
+            .iter()
+            .chain(&self.delta)
+            .map(|entry| entry.size)
+            .sum()
+    }
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} files, {} to download",
+            self.whole.len() + self.delta.len(),
+            size(self.bytes())
+        )?;
+        if !self.delta.is_empty() {
+            write!(f, " at most ({} changed files as deltas)", self.delta.len())?;
+        }
+        write!(f, ", {} files unchanged", self.unchanged)
+    }
+}
@@ -0,0 +1,299 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use tracing::{info, warn};
+
+use crate::config::Remote;
+use crate::summary::size;
+use crate::transport::Transport;
+
+/// Share of the CPU time from which a build counts as CPU-bound.
+const CPU_BOUND: f64 = 0.8;
+
+/// Share of the CPU time spent waiting for IO from which a build counts as IO-bound.
+const IO_BOUND: f64 = 0.2;
+
+/// Growth of the used swap from which a build counts as swapping.
+const SWAPPING_KB: u64 = 100 * 1024;
+
+/// Resource usage of the remote at one point in time, read from `/proc` and `df`. Fields the
+/// remote doesn't provide (e.g. without `/proc`) are missing.
+#[derive(Debug, Default, Clone)]
+struct Sample {
+    cpus: Option<u32>,
+    load: Option<f64>,
+    mem_total_kb: Option<u64>,
+    mem_available_kb: Option<u64>,
+    swap_used_kb: Option<u64>,
+    disk_free_kb: Option<u64>,
+    /// Fields of the `cpu` line of `/proc/stat`: user, nice, system, idle, iowait, ...
+    cpu_times: Vec<u64>,
+}
+
+impl Sample {
+    fn script(build_path: &str) -> String {
+        format!(
+            "echo cpus=$(nproc 2>/dev/null); echo load=$(cut -d ' ' -f 1 /proc/loadavg 2>/dev/null); \
+             awk '/^(MemTotal|MemAvailable|SwapTotal|SwapFree):/ {{ sub(\":\", \"\", $1); print $1 \"=\" $2 }}' /proc/meminfo 2>/dev/null; \
+             echo cpu=$(head -n 1 /proc/stat 2>/dev/null | cut -d ' ' -f 2-); \
+             df -Pk {} 2>/dev/null | awk 'NR == 2 {{ print \"disk_free=\" $4 }}'",
+            build_path
+        )
+    }
+
+    fn parse(output: &str) -> Self {
+        let fields: BTreeMap<&str, &str> = output
+            .lines()
+            .filter_map(|line| line.split_once('='))
+            .map(|(key, value)| (key, value.trim()))
+            .collect();
+        let number = |key: &str| fields.get(key).and_then(|value| value.parse::<u64>().ok());
+        Sample {
+            cpus: fields.get("cpus").and_then(|cpus| cpus.parse().ok()),
+            load: fields.get("load").and_then(|load| load.parse().ok()),
+            mem_total_kb: number("MemTotal"),
+            mem_available_kb: number("MemAvailable"),
+            swap_used_kb: number("SwapTotal")
+                .zip(number("SwapFree"))
+                .map(|(total, free)| total.saturating_sub(free)),
+            disk_free_kb: number("disk_free"),
+            cpu_times: fields
+                .get("cpu")
+                .map(|times| {
+                    times
+                        .split_whitespace()
+                        .filter_map(|t| t.parse().ok())
+                        .collect()
+                })
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Shares of the CPU time between `previous` and this sample spent busy and waiting for IO.
+    fn cpu_usage(&self, previous: &Sample) -> Option<(f64, f64)> {
+        if self.cpu_times.len() < 5 || previous.cpu_times.len() != self.cpu_times.len() {
+            return None;
+        }
+        let delta: Vec<u64> = self
+            .cpu_times
+            .iter()
+            .zip(&previous.cpu_times)
+            .map(|(now, before)| now.saturating_sub(*before))
+            .collect();
+        let total: u64 = delta.iter().sum();
+        if total == 0 {
+            return None;
+        }
+        let (idle, iowait) = (delta[3], delta[4]);
+        Some((
+            total.saturating_sub(idle + iowait) as f64 / total as f64,
+            iowait as f64 / total as f64,
+        ))
+    }
+}
+
+/// One line of the status of the remote, e.g. `load 15.2 on 16 cpus, cpu 93% busy, 2% iowait,
+/// memory 12.1 GiB of 31.3 GiB used, swap 0 B, 120.0 GiB free on the disk`.
+struct Status<'a> {
+    sample: &'a Sample,
+    cpu_usage: Option<(f64, f64)>,
+}
+
+impl fmt::Display for Status<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut parts = Vec::new();
+        match (self.sample.load, self.sample.cpus) {
+            (Some(load), Some(cpus)) => parts.push(format!("load {:.1} on {} cpus", load, cpus)),
+            (Some(load), None) => parts.push(format!("load {:.1}", load)),
+            _ => {}
+        }
+        if let Some((busy, iowait)) = self.cpu_usage {
+            parts.push(format!(
+                "cpu {:.0}% busy, {:.0}% iowait",
+                busy * 100.0,
+                iowait * 100.0
+            ));
+        }
+        if let (Some(total), Some(available)) =
+            (self.sample.mem_total_kb, self.sample.mem_available_kb)
+        {
+            parts.push(format!(
+                "memory {} of {} used",
+                size(total.saturating_sub(available) * 1024),
+                size(total * 1024)
+            ));
+        }
+        if let Some(swap_used) = self.sample.swap_used_kb {
+            parts.push(format!("swap {}", size(swap_used * 1024)));
+        }
+        if let Some(disk_free) = self.sample.disk_free_kb {
+            parts.push(format!("{} free on the disk", size(disk_free * 1024)));
+        }
+        if parts.is_empty() {
+            write!(f, "no resource usage available")
+        } else {
+            write!(f, "{}", parts.join(", "))
+        }
+    }
+}
+
+/// Extremes and averages of the samples taken during a build.
+#[derive(Debug, Default)]
+struct Peaks {
+    samples: u32,
+    busy_sum: f64,
+    iowait_sum: f64,
+    cpu_samples: u32,
+    max_load: f64,
+    min_swap_used_kb: Option<u64>,
+    max_swap_used_kb: Option<u64>,
+    min_disk_free_kb: Option<u64>,
+}
+
+impl Peaks {
+    fn add(&mut self, sample: &Sample, cpu_usage: Option<(f64, f64)>) {
+        self.samples += 1;
+        if let Some((busy, iowait)) = cpu_usage {
+            self.busy_sum += busy;
+            self.iowait_sum += iowait;
+            self.cpu_samples += 1;
+        }
+        self.max_load = self.max_load.max(sample.load.unwrap_or_default());
+        if let Some(swap_used) = sample.swap_used_kb {
+            self.min_swap_used_kb = Some(
+                self.min_swap_used_kb
+                    .map_or(swap_used, |m| m.min(swap_used)),
+            );
+            self.max_swap_used_kb = Some(
+                self.max_swap_used_kb
+                    .map_or(swap_used, |m| m.max(swap_used)),
+            );
+        }
+        if let Some(disk_free) = sample.disk_free_kb {
+            self.min_disk_free_kb = Some(
+                self.min_disk_free_kb
+                    .map_or(disk_free, |m| m.min(disk_free)),
+            );
+        }
+    }
+
+    /// What most likely held the build back.
+    fn verdict(&self) -> &'static str {
+        let swapped = self
+            .max_swap_used_kb
+            .zip(self.min_swap_used_kb)
+            .is_some_and(|(max, min)| max - min >= SWAPPING_KB);
+        let average = |sum: f64| sum / f64::from(self.cpu_samples.max(1));
+        if swapped {
+            "the remote was swapping, the build needs more memory or fewer jobs"
+        } else if self.cpu_samples == 0 {
+            "the remote doesn't report its CPU usage"
+        } else if average(self.iowait_sum) >= IO_BOUND {
+            "the build was mostly waiting for IO"
+        } else if average(self.busy_sum) >= CPU_BOUND {
+            "the build was CPU-bound"
+        } else {
+            "neither CPU, memory nor IO were saturated, the build waited on something else (e.g. a single-threaded step, the network or a lock)"
+        }
+    }
+}
+
+impl fmt::Display for Peaks {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} samples, peak load {:.1}",
+            self.samples, self.max_load
+        )?;
+        if self.cpu_samples > 0 {
+            let samples = f64::from(self.cpu_samples);
+            write!(
+                f,
+                ", cpu {:.0}% busy and {:.0}% iowait on average",
+                self.busy_sum / samples * 100.0,
+                self.iowait_sum / samples * 100.0
+            )?;
+        }
+        if let Some(max_swap_used) = self.max_swap_used_kb {
+            write!(f, ", up to {} swap", size(max_swap_used * 1024))?;
+        }
+        if let Some(min_disk_free) = self.min_disk_free_kb {
+            write!(
+                f,
+                ", down to {} free on the disk",
+                size(min_disk_free * 1024)
+            )?;
+        }
+        write!(f, ": {}", self.verdict())
+    }
+}
+
+/// Samples the load, memory and disk usage of the remote over a connection of its own while the
+/// build runs and logs them, so a slow build can be told apart as CPU-bound, swapping or
+/// waiting for IO.
+pub struct Monitor {
+    stop: Sender<()>,
+    thread: JoinHandle<Peaks>,
+}
+
+impl Monitor {
+    pub fn start(
+        transport: &Transport,
+        remote: &Remote,
+        host: &str,
+        build_path: &str,
+        interval: Duration,
+    ) -> Self {
+        let (stop, stopped) = mpsc::channel();
+        let (transport, remote, host) = (transport.clone(), remote.clone(), host.to_owned());
+        let script = Sample::script(build_path);
+        let thread = thread::spawn(move || {
+            let mut peaks = Peaks::default();
+            let mut previous: Option<Sample> = None;
+            let mut warned = false;
+            // the first sample right away is the baseline of the CPU usage
+            let mut wait = Duration::ZERO;
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(wait) {
+                wait = interval;
+                let sample = match transport.remote_output(&remote, &host, &script) {
+                    Ok(output) => Sample::parse(&output),
+                    Err(e) => {
+                        // the build goes on, a flaky side connection isn't worth more than one warning
+                        if !warned {
+                            warn!("Failed to sample the resources of {} (error: {})", host, e);
+                            warned = true;
+                        }
+                        continue;
+                    }
+                };
+                let cpu_usage = previous
+                    .as_ref()
+                    .and_then(|previous| sample.cpu_usage(previous));
+                info!(
+                    "{}: {}",
+                    host,
+                    Status {
+                        sample: &sample,
+                        cpu_usage,
+                    }
+                );
+                peaks.add(&sample, cpu_usage);
+                previous = Some(sample);
+            }
+            peaks
+        });
+        Monitor { stop, thread }
+    }
+
+    /// Ends the sampling after the build and logs a summary of the resource usage.
+    pub fn finish(self) {
+        let _ = self.stop.send(());
+        match self.thread.join() {
+            Ok(peaks) if peaks.samples > 0 => info!("Remote resources during the build: {}", peaks),
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,299 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::thread;
+
+use tracing::{error, info, warn};
+
+use crate::compat;
+use crate::config::Remote;
+use crate::credentials;
+use crate::probe;
+use crate::process::Output;
+use crate::script;
+use crate::sync;
+use crate::tools;
+use crate::transport::Transport;
+
+/// Outcome of the shard run on one remote.
+#[derive(Debug)]
+struct Shard {
+    remote: String,
+    host: Option<String>,
+    result: io::Result<bool>,
+    /// Local copy of the JUnit report of the shard.
+    junit: Option<PathBuf>,
+}
+
+/// Everything the shards have in common.
+struct ShardedRun<'a> {
+    transport: Transport,
+    project_dir: &'a Path,
+    target_dir: &'a Path,
+    excludes: &'a [String],
+    /// Arguments of `cargo nextest run`, without `nextest run`.
+    args: &'a [String],
+    /// Number of shards.
+    count: usize,
+    /// Whether the JUnit reports are fetched.
+    junit: bool,
+    profile: String,
+}
+
+/// Runs `cargo nextest run` with `args` on all `remotes` at the same time, each with its own
+/// `--partition count:<i>/<number of remotes>`, and prints a summary of all shards.
+///
+/// With `junit` the JUnit reports of the shards are fetched and merged into that file, which
+/// requires the nextest profile to write them to `junit.xml`. Returns whether every shard
+/// passed.
+pub fn run_sharded(
+    transport: &Transport,
+    remotes: &[Remote],
+    project_dir: &Path,
+    target_dir: &Path,
+    excludes: &[String],
+    args: &[String],
+    junit: Option<&Path>,
+) -> bool {
+    let run = ShardedRun {
+        // rsync progress of several transfers would be drawn over each other
+        transport: transport.without_progress(),
+        project_dir,
+        target_dir,
+        excludes,
+        args: &args[2..],
+        count: remotes.len(),
+        junit: junit.is_some(),
+        profile: profile(args),
+    };
+    info!("Running nextest in {} shards.", run.count);
+
+    let shards: Vec<Shard> = thread::scope(|scope| {
+        let handles: Vec<_> = remotes
+            .iter()
+            .enumerate()
+            .map(|(i, remote)| {
+                let run = &run;
+                scope.spawn(move || run.shard(i + 1, remote))
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("shard thread panicked"))
+            .collect()
+    });
+
+    println!();
+    println!("cargo remote nextest summary:");
+    for (i, shard) in shards.iter().enumerate() {
+        let shard_name = format!(
+            "shard {}/{} on {} ({})",
+            i + 1,
+            run.count,
+            shard.remote,
+            shard.host.as_deref().unwrap_or("unreachable")
+        );
+        match &shard.result {
+            Ok(true) => println!("  ok      {}", shard_name),
+            Ok(false) => println!("  FAILED  {}", shard_name),
+            Err(e) => println!("  FAILED  {}: {}", shard_name, e),
+        }
+    }
+
+    if let Some(junit) = junit {
+        let reports: Vec<&PathBuf> = shards
+            .iter()
+            .filter_map(|shard| shard.junit.as_ref())
+            .collect();
+        match merge_junit(&reports, junit) {
+            Ok(()) => info!(
+                "Merged the JUnit reports of {} shards into {:?}",
+                reports.len(),
+                junit
+            ),
+            Err(e) => error!("Failed to merge the JUnit reports (error: {})", e),
+        }
+    }
+    shards.iter().all(|shard| matches!(shard.result, Ok(true)))
+}
+
+impl ShardedRun<'_> {
+    /// Runs shard number `index` on `remote`.
+    fn shard(&self, index: usize, remote: &Remote) -> Shard {
+        let mut shard = Shard {
+            remote: remote.name.clone(),
+            host: None,
+            result: Ok(false),
+            junit: None,
+        };
+        shard.result = self.run(index, remote, &mut shard);
+        shard
+    }
+
+    /// Syncs the project to `remote` and runs partition `index` of the tests there, with every
+    /// line of output prefixed by the remote name. Returns whether all tests of the partition
+    /// passed, the selected host and the fetched JUnit report are recorded in `shard`.
+    fn run(&self, index: usize, remote: &Remote, shard: &mut Shard) -> io::Result<bool> {
+        let transport = &self.transport;
+        let host = transport
+            .select_host(remote)
+            .ok_or_else(|| io::Error::other("none of the hosts is reachable"))?;
+        shard.host = Some(host.clone());
+        let mut remote = remote.clone();
+        match probe::cached_or_probe(transport, &remote, &host) {
+            Ok(facts) => compat::adapt(&mut remote, &facts),
+            Err(e) => warn!("Failed to probe {} (error: {})", host, e),
+        }
+
+        let build_path = sync::build_path(&remote, self.project_dir);
+        let status = sync::upload(
+            transport,
+            &remote,
+            &host,
+            self.project_dir,
+            &build_path,
+            self.excludes,
+            false,
+        )?;
+        if !status.success() {
+            return Err(io::Error::other(format!("sync failed ({})", status)));
+        }
+
+        let commands = [format!(
+            "nextest run --partition count:{}/{} {}",
+            index,
+            self.count,
+            script::join_args(self.args)
+        )];
+        let mut cargo_script = tools::install_script(&tools::required(&remote, &commands));
+        cargo_script.push_str(&script::cargo_commands(&commands));
+        let mut build_command = format!(
+            "{{ {} }} 2>&1",
+            script::build_script(&remote, &build_path, Some(&cargo_script))
+        );
+        let forwarded = credentials::collect(&remote)?;
+        if !forwarded.is_empty() {
+            build_command.insert_str(0, credentials::read_script());
+        }
+
+        let mut ssh = transport.remote_build_command(&remote, &host, &build_command, false);
+        let prefix = format!("[{}] ", remote.name);
+        let status = transport.run_with(
+            &mut ssh,
+            "nextest",
+            // the shards must not compete for the terminal
+            credentials::input(&forwarded),
+            Output::Lines(Box::new(move |line| {
+                println!("{}{}", prefix, line);
+                Ok(())
+            })),
+        )?;
+        if self.junit {
+            shard.junit = fetch_junit(
+                transport,
+                &remote,
+                &host,
+                &build_path,
+                &self.profile,
+                &self.target_dir.join(format!("nextest-shard-{}.xml", index)),
+            );
+        }
+        Ok(status.success())
+    }
+}
+
+/// The nextest profile selected in `args`, whose reports are written to
+/// `target/nextest/<profile>`.
+fn profile(args: &[String]) -> String {
+    let mut args = args.iter();
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        }
+        if let Some(profile) = arg.strip_prefix("--profile=") {
+            return profile.to_owned();
+        }
+        if arg == "--profile" || arg == "-P" {
+            if let Some(profile) = args.next() {
+                return profile.clone();
+            }
+        }
+    }
+    "default".to_owned()
+}
+
+fn fetch_junit(
+    transport: &Transport,
+    remote: &Remote,
+    host: &str,
+    build_path: &str,
+    profile: &str,
+    destination: &Path,
+) -> Option<PathBuf> {
+    let fetched = fs::create_dir_all(destination.parent()?).and_then(|_| {
+        sync::copy_back(
+            transport,
+            remote,
+            host,
+            build_path,
+            &format!("nextest/{}/junit.xml", profile),
+            &destination.to_string_lossy(),
+            false,
+        )
+    });
+    match fetched {
+        Ok(status) if status.success() => Some(destination.to_owned()),
+        Ok(status) => {
+            warn!(
+                "No JUnit report found on {} ({}), is `junit.path` set for the nextest profile {}?",
+                host, status, profile
+            );
+            None
+        }
+        Err(e) => {
+            warn!(
+                "Failed to fetch the JUnit report from {} (error: {})",
+                host, e
+            );
+            None
+        }
+    }
+}
+
+/// Writes the test suites of all `reports` into a single `<testsuites>` element in
+/// `destination`, summing up the test counts.
+fn merge_junit(reports: &[&PathBuf], destination: &Path) -> io::Result<()> {
+    let mut suites = String::new();
+    let mut totals = [("tests", 0u64), ("failures", 0), ("errors", 0)];
+    for report in reports {
+        let content = fs::read_to_string(report)?;
+        let invalid = || io::Error::other(format!("{:?} isn't a nextest JUnit report", report));
+        let start = content.find("<testsuites").ok_or_else(invalid)?;
+        let open_end = start + content[start..].find('>').ok_or_else(invalid)?;
+        let close = content.rfind("</testsuites>").ok_or_else(invalid)?;
+        let attributes = &content[start..open_end];
+        for (name, total) in totals.iter_mut() {
+            *total += attribute(attributes, name).unwrap_or(0);
+        }
+        suites.push_str(content[open_end + 1..close].trim_matches('\n'));
+        suites.push('\n');
+    }
+    let totals: Vec<String> = totals
+        .iter()
+        .map(|(name, total)| format!("{}=\"{}\"", name, total))
+        .collect();
+    fs::write(
+        destination,
+        format!(
+            "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n<testsuites name=\"nextest-run\" {}>\n{}</testsuites>\n",
+            totals.join(" "),
+            suites
+        ),
+    )
+}
+
+/// Numeric attribute `name` of an XML tag.
+fn attribute(tag: &str, name: &str) -> Option<u64> {
+    let value = tag.split(&format!(" {}=\"", name)).nth(1)?;
+    value.split('"').next()?.parse().ok()
+}
@@ -0,0 +1,176 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::config::Remote;
+use crate::script;
+use crate::sync;
+use crate::transport::Transport;
+use crate::REMOTE_TARGET_DIR;
+
+/// Local file in the target dir with the `build-script-executed` messages of the remote build,
+/// rewritten to the local paths.
+const MESSAGES_FILE: &str = "remote-build-scripts.json";
+
+/// Marks the line with the absolute build path in the output of the replay, `~` isn't expanded
+/// in the paths of cargo.
+const BUILD_DIR_MARKER: &str = "cargo-remote-build-dir=";
+
+/// Build scripts run by the remote build, with the OUT_DIRs of their generated code.
+#[derive(Debug)]
+pub struct OutDirs {
+    /// Absolute build path on the remote, without a trailing `/`.
+    build_dir: String,
+    /// OUT_DIRs on the remote.
+    remote: BTreeSet<String>,
+    /// The `build-script-executed` messages of cargo, one JSON object per line.
+    messages: Vec<String>,
+}
+
+/// The cargo commands replaying `commands` with JSON messages, without running anything but
+/// the build. Commands that don't build (e.g. `fmt`) are left out.
+///
+/// The build is already done, so cargo only reports the fresh units.
+fn replay_commands(commands: &[String]) -> Vec<String> {
+    commands
+        .iter()
+        .filter_map(|command| {
+            let (subcommand, args) = command.split_once(' ').unwrap_or((command, ""));
+            let replay = match subcommand {
+                "build" | "b" | "check" | "c" | "clippy" => subcommand.to_owned(),
+                "test" | "t" | "bench" if args.split(' ').any(|arg| arg == "--no-run") => {
+                    subcommand.to_owned()
+                }
+                "test" | "t" | "bench" => format!("{} --no-run", subcommand),
+                "run" | "r" => "build".to_owned(),
+                _ => return None,
+            };
+            Some(format!("{} --message-format=json {}", replay, args))
+        })
+        .collect()
+}
+
+/// Replays `commands`, which already ran in `build_path`, on the remote. Returns the absolute
+/// build path without a trailing `/` and the JSON messages of cargo, one per line.
+pub fn replay(
+    transport: &Transport,
+    remote: &Remote,
+    host: &str,
+    build_path: &str,
+    commands: &[String],
+) -> io::Result<(String, Vec<String>)> {
+    let mut replay = vec![format!("echo {}\"$PWD\"", BUILD_DIR_MARKER)];
+    replay.extend(
+        replay_commands(commands)
+            .iter()
+            .map(|command| format!("cargo {} 2>/dev/null", command)),
+    );
+    // a failed replay still reports the units that were built
+    replay.push("true".to_owned());
+    let output = transport.remote_output(
+        remote,
+        host,
+        &script::build_script(remote, build_path, Some(&replay.join("; "))),
+    )?;
+    let build_dir = output
+        .lines()
+        .find_map(|line| line.strip_prefix(BUILD_DIR_MARKER))
+        .unwrap_or(build_path)
+        .trim_end_matches('/')
+        .to_owned();
+    let messages = output
+        .lines()
+        .filter(|line| line.starts_with('{'))
+        .map(str::to_owned)
+        .collect();
+    Ok((build_dir, messages))
+}
+
+impl OutDirs {
+    /// Asks cargo on the remote for the build scripts of `commands`, which already ran in
+    /// `build_path`.
+    pub fn find(
+        transport: &Transport,
+        remote: &Remote,
+        host: &str,
+        build_path: &str,
+        commands: &[String],
+    ) -> io::Result<Self> {
+        let (build_dir, lines) = replay(transport, remote, host, build_path, commands)?;
+        let mut out_dirs = OutDirs {
+            build_dir,
+            remote: BTreeSet::new(),
+            messages: Vec::new(),
+        };
+        for line in &lines {
+            let message: serde_json::Value = match serde_json::from_str(line) {
+                Ok(message) => message,
+                Err(_) => continue,
+            };
+            if message["reason"] != "build-script-executed" {
+                continue;
+            }
+            if let Some(out_dir) = message["out_dir"].as_str() {
+                if out_dirs.remote.insert(out_dir.to_owned()) {
+                    out_dirs.messages.push(line.to_owned());
+                }
+            }
+        }
+        Ok(out_dirs)
+    }
+
+    /// Copies every OUT_DIR into the same place below the local `target_dir` and saves the
+    /// messages with the local paths. Returns the local OUT_DIRs.
+    pub fn copy_back(
+        &self,
+        transport: &Transport,
+        remote: &Remote,
+        host: &str,
+        project_dir: &Path,
+        target_dir: &Path,
+    ) -> io::Result<Vec<PathBuf>> {
+        let remote_target_dir = format!("{}/{}/", self.build_dir, REMOTE_TARGET_DIR);
+        let mut local = Vec::new();
+        for out_dir in &self.remote {
+            let relative = match out_dir.strip_prefix(&remote_target_dir) {
+                Some(relative) => relative,
+                // e.g. a CARGO_TARGET_DIR set by the environment profile
+                None => continue,
+            };
+            let destination = target_dir.join(relative);
+            fs::create_dir_all(&destination)?;
+            let status = sync::fetch(
+                transport,
+                remote,
+                host,
+                &format!("{}/", out_dir),
+                &format!("{}/", destination.to_string_lossy()),
+                true,
+                "copy-back-outdirs",
+            )?;
+            if !status.success() {
+                return Err(io::Error::other(format!(
+                    "transferring {} failed ({})",
+                    out_dir, status
+                )));
+            }
+            local.push(destination);
+        }
+
+        // the target dir first, it may be outside of the project
+        let local_target_dir = format!("{}/", target_dir.to_string_lossy());
+        let local_project_dir = project_dir.to_string_lossy();
+        let messages: Vec<String> = self
+            .messages
+            .iter()
+            .map(|message| {
+                message
+                    .replace(&remote_target_dir, &local_target_dir)
+                    .replace(&self.build_dir, &local_project_dir)
+            })
+            .collect();
+        fs::write(target_dir.join(MESSAGES_FILE), messages.join("\n") + "\n")?;
+        Ok(local)
+    }
+}
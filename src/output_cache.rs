@@ -0,0 +1,140 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::io;
+
+use sha2::{Digest, Sha256};
+use tracing::warn;
+
+use crate::config::Remote;
+use crate::script::{self, quote};
+use crate::transport::Transport;
+use crate::REMOTE_TARGET_DIR;
+
+/// Directory in the remote target dir with the cached artifacts, relative to the target dir.
+const CACHE_DIR: &str = "cargo-remote-cache";
+
+/// Marks a complete entry, written after all artifacts were copied into it.
+const COMPLETE_MARKER: &str = ".complete";
+
+/// Entries kept per project, older ones are deleted when a new one is added.
+const MAX_ENTRIES: usize = 5;
+
+/// Cargo commands whose only outcome are the artifacts, the others (e.g. `test`) have to run
+/// again even if their artifacts are cached.
+const CACHEABLE_COMMANDS: &[&str] = &["build", "b"];
+
+/// Whether the results of `commands` can be taken from the cache.
+pub fn cacheable(commands: &[String]) -> bool {
+    !commands.is_empty()
+        && commands.iter().all(|command| {
+            command
+                .split_whitespace()
+                .next()
+                .is_some_and(|first| CACHEABLE_COMMANDS.contains(&first))
+        })
+}
+
+/// Artifacts of a successful build on the remote, keyed by the hash of the synced sources, the
+/// cargo commands, the copied back file, the toolchain and the build settings.
+#[derive(Debug)]
+pub struct Entry {
+    key: String,
+    file_name: String,
+    /// Whether the artifacts are already cached.
+    pub hit: bool,
+}
+
+impl Entry {
+    /// Hashes the sources in `build_path` (everything but the target dir and `.git`) together
+    /// with `commands`, `file_name`, the `rustc -vV` of the build environment and the settings
+    /// the build gets, like the exported `variables` (see [`settings_digest`]), and looks up the
+    /// entry for it.
+    #[allow(clippy::too_many_arguments)]
+    pub fn lookup(
+        transport: &Transport,
+        remote: &Remote,
+        host: &str,
+        build_path: &str,
+        commands: &[String],
+        file_name: &str,
+        variables: &BTreeMap<String, String>,
+    ) -> io::Result<Self> {
+        let mut inputs: Vec<String> = commands.iter().map(|command| quote(command)).collect();
+        inputs.push(quote(file_name));
+        inputs.push(settings_digest(remote, variables));
+        // runs in the build environment, whose rustc is the one building
+        let lookup = format!(
+            "cargo_remote_hash={hash} && \
+             cargo_remote_key=$({{ find . \\( -path ./{target} -o -path ./.git \\) -prune -o -type f \
+             -exec $cargo_remote_hash {{}} + | LC_ALL=C sort -k 2; printf '%s\\n' {inputs}; \
+             rustc -vV 2>&1; }} | $cargo_remote_hash | cut -c 1-32) && \
+             if [ -e {target}/{cache}/$cargo_remote_key/{marker} ]; then echo hit $cargo_remote_key; \
+             else echo miss $cargo_remote_key; fi",
+            hash = script::SHA256,
+            target = REMOTE_TARGET_DIR,
+            inputs = inputs.join(" "),
+            cache = CACHE_DIR,
+            marker = COMPLETE_MARKER
+        );
+        let script = script::build_script(remote, build_path, Some(&lookup));
+        let output = transport.remote_output(remote, host, &script)?;
+        // the environment may print something before
+        let result = output.lines().last().unwrap_or_default();
+        match result.split_whitespace().collect::<Vec<_>>()[..] {
+            [result, key] if key.len() == 32 => Ok(Entry {
+                key: key.to_owned(),
+                file_name: file_name.to_owned(),
+                hit: result == "hit",
+            }),
+            _ => Err(io::Error::other(format!(
+                "unexpected output {:?}",
+                output.trim()
+            ))),
+        }
+    }
+
+    /// The cached artifacts relative to the remote target dir, to copy back instead of
+    /// `file_name`.
+    pub fn file_name(&self) -> String {
+        format!("{}/{}/{}", CACHE_DIR, self.key, self.file_name)
+    }
+
+    /// Copies the artifacts of the build into the entry and removes the oldest entries.
+    pub fn store(&self, transport: &Transport, remote: &Remote, host: &str, build_path: &str) {
+        let artifact = self.file_name.trim_end_matches('/');
+        let cached = self.file_name().trim_end_matches('/').to_owned();
+        let script = format!(
+            "cd {build_path}{target} && rm -rf {cache}/{key} && \
+             mkdir -p \"$(dirname {cached})\" && cp -a {artifact} {cached} && \
+             : > {cache}/{key}/{marker} && \
+             cd {cache} && ls -t | tail -n +{keep} | xargs rm -rf",
+            build_path = build_path,
+            target = REMOTE_TARGET_DIR,
+            cache = CACHE_DIR,
+            key = self.key,
+            cached = quote(&cached),
+            artifact = quote(artifact),
+            marker = COMPLETE_MARKER,
+            keep = MAX_ENTRIES + 1
+        );
+        if let Err(e) = transport.remote_output(remote, host, &script) {
+            warn!("Failed to cache the artifacts (error: {})", e);
+        }
+    }
+}
+
+/// Hash of what the build gets besides the sources and the commands: the `rustflags`, `linker`
+/// and `[target]` settings of `remote`, the `variables`, the values of the `forward_env`
+/// variables and the names of the `secrets`. Hashed locally, the values stay on this machine.
+fn settings_digest(remote: &Remote, variables: &BTreeMap<String, String>) -> String {
+    let mut settings = script::rust_env(remote);
+    settings.push_str(&script::exports(variables));
+    for name in &remote.forward_env {
+        let value = env::var(name).unwrap_or_default();
+        settings.push_str(&format!("{}={}\n", name, value));
+    }
+    for name in remote.secrets.keys() {
+        settings.push_str(&format!("secret {}\n", name));
+    }
+    format!("{:x}", Sha256::digest(settings))
+}
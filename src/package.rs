@@ -0,0 +1,59 @@
+use std::io;
+
+use crate::config::{PackageFormat, Remote};
+use crate::script;
+use crate::transport::Transport;
+
+/// Directory in the remote target dir the archive is created in, relative to the target dir.
+const PACKAGE_DIR: &str = "cargo-remote-package";
+
+/// Name of the archive of `project`, e.g. `myapp.tar.zst`.
+pub fn file_name(remote: &Remote, project: &str) -> String {
+    format!("{}.{}", project, remote.package_format)
+}
+
+/// Packs the artifacts matching the `package_artifacts` of `remote` in the target dir of
+/// `build_path` into a single archive on the remote.
+///
+/// Returns the path of the archive relative to the remote target dir.
+pub fn create(
+    transport: &Transport,
+    remote: &Remote,
+    host: &str,
+    build_path: &str,
+    file_name: &str,
+) -> io::Result<String> {
+    let archive = format!("{}/{}", PACKAGE_DIR, file_name);
+    let files = format!("{}/files", PACKAGE_DIR);
+    let pack = match remote.package_format {
+        PackageFormat::TarZst => format!(
+            "tar -cf - -T {files} | zstd -q -T0 -o {archive}",
+            files = files,
+            archive = archive
+        ),
+        PackageFormat::TarGz => format!("tar -czf {} -T {}", archive, files),
+        PackageFormat::Zip => format!("zip -q -r {} -@ < {}", archive, files),
+    };
+    // the patterns are expanded by the shell, unmatched ones are left out
+    let script = format!(
+        "{env}cd {build_path}{target} && rm -rf {dir} && mkdir -p {dir} && \
+         for cargo_remote_file in {patterns}; do [ -e \"$cargo_remote_file\" ] && \
+         printf '%s\\n' \"$cargo_remote_file\"; done > {files}; \
+         if [ ! -s {files} ]; then echo 'No artifacts match package_artifacts.' >&2; exit 1; fi; \
+         {pack}",
+        env = script::source_env(remote),
+        build_path = build_path,
+        target = crate::REMOTE_TARGET_DIR,
+        dir = PACKAGE_DIR,
+        patterns = remote.package_artifacts.join(" "),
+        files = files,
+        pack = pack
+    );
+    let mut ssh = transport.remote_command(remote, host, &script);
+    let status = transport.run(&mut ssh, "package")?;
+    if status.success() {
+        Ok(archive)
+    } else {
+        Err(io::Error::other(format!("packaging failed ({})", status)))
+    }
+}
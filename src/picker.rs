@@ -0,0 +1,111 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::config::Remote;
+use crate::init;
+use crate::sync;
+
+/// Cache dir of the remote last picked for every project, in files named after its build
+/// directory.
+const LAST_REMOTE_DIR: &str = "last-remote";
+
+/// Lets the user pick one of several configured `remotes` on the terminal. The remote last
+/// picked for `project_dir` is preselected, and remembered again for the next time.
+pub fn pick(remotes: &[Remote], project_dir: &Path) -> io::Result<Remote> {
+    let last = last_used(project_dir);
+    let default = remotes
+        .iter()
+        .position(|remote| Some(key(remote)) == last)
+        .unwrap_or(0);
+    println!("Several remotes are configured:");
+    for (index, remote) in remotes.iter().enumerate() {
+        let marker = if index == default { "*" } else { " " };
+        println!("{} {}) {}", marker, index + 1, describe(remote));
+    }
+    let picked = loop {
+        let answer = init::read_answer(&format!(
+            "Remote (number or part of the name) [{}]:",
+            default + 1
+        ))?;
+        if answer.is_empty() {
+            break default;
+        }
+        if let Some(index) = answer
+            .parse::<usize>()
+            .ok()
+            .filter(|number| (1..=remotes.len()).contains(number))
+        {
+            break index - 1;
+        }
+        let matches: Vec<usize> = (0..remotes.len())
+            .filter(|&index| fuzzy_match(&answer, &describe(&remotes[index])))
+            .collect();
+        match matches.as_slice() {
+            [index] => break *index,
+            [] => println!("No remote matches {:?}.", answer),
+            _ => {
+                let names: Vec<String> = matches
+                    .iter()
+                    .map(|&index| describe(&remotes[index]))
+                    .collect();
+                println!(
+                    "{:?} matches {}, be more specific.",
+                    answer,
+                    names.join(", ")
+                )
+            }
+        }
+    };
+    let remote = remotes[picked].clone();
+    remember(project_dir, &remote)?;
+    Ok(remote)
+}
+
+fn describe(remote: &Remote) -> String {
+    if remote.name.is_empty() {
+        remote.host.clone()
+    } else {
+        format!("{} ({})", remote.name, remote.host)
+    }
+}
+
+/// Name of `remote` saved as the last used one, its host if it has no name.
+fn key(remote: &Remote) -> String {
+    if remote.name.is_empty() {
+        remote.host.clone()
+    } else {
+        remote.name.clone()
+    }
+}
+
+/// Whether the characters of `pattern` appear in `text` in the same order, ignoring case.
+fn fuzzy_match(pattern: &str, text: &str) -> bool {
+    let mut text = text.chars().flat_map(char::to_lowercase);
+    pattern
+        .chars()
+        .flat_map(char::to_lowercase)
+        .all(|c| text.any(|t| t == c))
+}
+
+fn last_used(project_dir: &Path) -> Option<String> {
+    let file = xdg::BaseDirectories::with_prefix("cargo-remote")
+        .ok()?
+        .find_cache_file(format!(
+            "{}/{}",
+            LAST_REMOTE_DIR,
+            sync::dir_name(project_dir)
+        ))?;
+    fs::read_to_string(file)
+        .ok()
+        .map(|name| name.trim().to_owned())
+}
+
+fn remember(project_dir: &Path, remote: &Remote) -> io::Result<()> {
+    let file = xdg::BaseDirectories::with_prefix("cargo-remote")?.place_cache_file(format!(
+        "{}/{}",
+        LAST_REMOTE_DIR,
+        sync::dir_name(project_dir)
+    ))?;
+    fs::write(file, key(remote))
+}
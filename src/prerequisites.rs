@@ -0,0 +1,86 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+
+/// Whether `program` can be run, either as a path or found in the `PATH`.
+pub fn is_installed(program: &str) -> bool {
+    if program.contains('/') {
+        return Path::new(program).is_file();
+    }
+    env::var_os("PATH")
+        .map(|path| env::split_paths(&path).any(|dir| dir.join(program).is_file()))
+        .unwrap_or(false)
+}
+
+/// How to install `tool` (`ssh` or `rsync`) on this machine.
+pub fn local_install_hint(tool: &str) -> String {
+    match env::consts::OS {
+        "macos" => format!("install it with `brew install {}`", package(tool, "brew")),
+        "linux" => linux_install_hint(tool, &local_distribution()),
+        "freebsd" => format!("install it with `pkg install {}`", package(tool, "pkg")),
+        "windows" => format!(
+            "install it in WSL or MSYS2 (`pacman -S {}`)",
+            package(tool, "pacman")
+        ),
+        _ => format!("install {} with the package manager of the system", tool),
+    }
+}
+
+/// How to install `tool` on a remote running `os` (the output of `uname -s`).
+pub fn remote_install_hint(tool: &str, os: Option<&str>) -> String {
+    match os {
+        Some("Darwin") => format!("install it with `brew install {}`", package(tool, "brew")),
+        Some("FreeBSD") => format!("install it with `pkg install {}`", package(tool, "pkg")),
+        // the distribution isn't probed, but every remote has nix
+        _ => format!(
+            "install it with `nix-env -iA nixpkgs.{}` or the package manager of the \
+             distribution (e.g. `apt install {}`)",
+            package(tool, "nix"),
+            package(tool, "apt")
+        ),
+    }
+}
+
+/// Package providing `tool` for the package `manager`.
+fn package(tool: &str, manager: &str) -> &'static str {
+    match (tool, manager) {
+        ("ssh", "apt") => "openssh-client",
+        ("ssh", _) => "openssh",
+        _ => "rsync",
+    }
+}
+
+fn linux_install_hint(tool: &str, distribution: &str) -> String {
+    let command = match distribution {
+        "debian" | "ubuntu" | "linuxmint" | "pop" => {
+            format!("sudo apt install {}", package(tool, "apt"))
+        }
+        "fedora" | "rhel" | "centos" | "rocky" | "almalinux" => {
+            format!("sudo dnf install {}", package(tool, "dnf"))
+        }
+        "arch" | "manjaro" | "endeavouros" => format!("sudo pacman -S {}", package(tool, "pacman")),
+        "opensuse-leap" | "opensuse-tumbleweed" => {
+            format!("sudo zypper install {}", package(tool, "zypper"))
+        }
+        "alpine" => format!("sudo apk add {}", package(tool, "apk")),
+        "nixos" => format!("nix-env -iA nixos.{}", package(tool, "nix")),
+        _ => {
+            return format!(
+                "install {} with the package manager of the distribution",
+                tool
+            )
+        }
+    };
+    format!("install it with `{}`", command)
+}
+
+/// `ID` of `/etc/os-release`, empty if it can't be read.
+fn local_distribution() -> String {
+    fs::read_to_string("/etc/os-release")
+        .unwrap_or_default()
+        .lines()
+        .find_map(|line| line.strip_prefix("ID="))
+        .unwrap_or_default()
+        .trim_matches('"')
+        .to_owned()
+}
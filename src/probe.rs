@@ -0,0 +1,317 @@
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::process::Command;
+use std::time::{Instant, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+use crate::backend::ExecOptions;
+use crate::config::{Link, Remote, Shell};
+use crate::process::{Input, Output};
+use crate::script;
+use crate::summary::unix_time;
+use crate::transport::Transport;
+use crate::windows;
+
+/// Data sent to measure the bandwidth to a remote.
+const BANDWIDTH_SAMPLE_BYTES: usize = 8 * 1024 * 1024;
+
+/// How long probed facts about a remote are trusted.
+pub const PROBE_TTL_SECS: u64 = 24 * 60 * 60;
+
+/// Compressors looked for on the remote, the preferred one first.
+pub const COMPRESSORS: &[&str] = &["zstd", "gzip"];
+
+/// Facts about a build server, cached locally so they don't have to be queried on every run.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RemoteFacts {
+    pub host: String,
+    pub ssh_port: u16,
+    /// Unix time of the probe.
+    pub probed_at: u64,
+    /// `release` of `rustc -vV`, `None` if rustc isn't available outside of `nix-shell`.
+    pub rustc_version: Option<String>,
+    /// Version of `cargo -V`, `None` if cargo isn't available outside of `nix-shell`.
+    pub cargo_version: Option<String>,
+    /// `host` of `rustc -vV`.
+    pub host_triple: Option<String>,
+    /// `uname -s`, e.g. `Linux`, `Darwin` or `FreeBSD`.
+    pub os: Option<String>,
+    /// First line of `rsync --version`.
+    pub rsync_version: Option<String>,
+    /// `nproc`, or `sysctl -n hw.ncpu` on macOS and BSD.
+    pub cpus: Option<u32>,
+    /// Which of [`COMPRESSORS`] are installed, in the same order.
+    #[serde(default)]
+    pub compressors: Vec<String>,
+    /// Measured upload bandwidth to the remote, only with `link = "auto"`.
+    pub bandwidth_mbit: Option<u64>,
+}
+
+impl RemoteFacts {
+    /// Queries the facts from `host` over ssh.
+    pub fn probe(transport: &Transport, remote: &Remote, host: &str) -> io::Result<Self> {
+        // the empty output of a dry run would be cached as a remote without any tools
+        if transport.dry_run() {
+            return Err(io::Error::other("the remote isn't probed in a dry run"));
+        }
+        let script = match remote.shell {
+            Shell::PowerShell => windows::probe_script(),
+            Shell::Sh => format!(
+                "{}echo os: $(uname -s); echo rsync: $({} --version 2>/dev/null | head -n 1); \
+                 echo cargo: $(cargo -V 2>/dev/null); \
+                 echo cpus: $(nproc 2>/dev/null || sysctl -n hw.ncpu 2>/dev/null); \
+                 echo compressors: $(for c in {}; do command -v $c >/dev/null && echo $c; done); \
+                 rustc -vV 2>/dev/null; true",
+                script::source_env(remote),
+                script::word(&remote.rsync_path_remote),
+                COMPRESSORS.join(" ")
+            ),
+        };
+        let output = transport.remote_output(remote, host, &script)?;
+        let field = |name: &str| {
+            output
+                .lines()
+                .find_map(|line| line.strip_prefix(name))
+                .map(|value| value.trim().to_owned())
+        };
+        Ok(RemoteFacts {
+            host: host.to_owned(),
+            ssh_port: remote.ssh_port,
+            probed_at: unix_time(SystemTime::now()),
+            rustc_version: field("release:"),
+            cargo_version: field("cargo:").and_then(|cargo| {
+                // e.g. `cargo 1.75.0 (1d8b05cdd 2023-11-20)`
+                cargo.split_whitespace().nth(1).map(str::to_owned)
+            }),
+            host_triple: field("host:"),
+            os: field("os:").filter(|os| !os.is_empty()),
+            rsync_version: field("rsync:").filter(|version| !version.is_empty()),
+            cpus: field("cpus:").and_then(|cpus| cpus.parse().ok()),
+            compressors: field("compressors:")
+                .unwrap_or_default()
+                .split_whitespace()
+                .map(str::to_owned)
+                .collect(),
+            bandwidth_mbit: match remote.link {
+                Link::Auto => measure_bandwidth(transport, remote, host),
+                Link::Lan | Link::Wan => None,
+            },
+        })
+    }
+
+    /// Loads the cached facts about `host`, regardless of their age.
+    pub fn load(remote: &Remote, host: &str) -> Option<Self> {
+        let contents = fs::read(cache_file(remote, host)?).ok()?;
+        serde_json::from_slice(&contents).ok()
+    }
+
+    pub fn save(&self, remote: &Remote) -> io::Result<()> {
+        let path = xdg::BaseDirectories::with_prefix("cargo-remote")?
+            .place_cache_file(format!("remotes/{}", cache_file_name(remote, &self.host)))?;
+        fs::write(path, serde_json::to_vec_pretty(self)?)
+    }
+
+    /// Deletes the cached facts about `host`. Returns whether there were any.
+    pub fn forget(remote: &Remote, host: &str) -> io::Result<bool> {
+        match cache_file(remote, host) {
+            Some(path) => fs::remove_file(path).map(|_| true),
+            None => Ok(false),
+        }
+    }
+
+    /// Seconds since the facts were probed.
+    pub fn age(&self) -> u64 {
+        unix_time(SystemTime::now()).saturating_sub(self.probed_at)
+    }
+
+    pub fn is_fresh(&self) -> bool {
+        self.age() < PROBE_TTL_SECS
+    }
+}
+
+/// Returns the cached facts about `host` or probes and caches them if they are missing or stale.
+/// A dry run takes cached facts of any age and probes nothing.
+pub fn cached_or_probe(
+    transport: &Transport,
+    remote: &Remote,
+    host: &str,
+) -> io::Result<RemoteFacts> {
+    let usable = |facts: &RemoteFacts| facts.is_fresh() || transport.dry_run();
+    if let Some(facts) = RemoteFacts::load(remote, host).filter(usable) {
+        return Ok(facts);
+    }
+    let facts = RemoteFacts::probe(transport, remote, host)?;
+    facts.save(remote)?;
+    Ok(facts)
+}
+
+/// Upload bandwidth to `host` in Mbit/s, measured by sending [`BANDWIDTH_SAMPLE_BYTES`] with
+/// the exec backend. The time of an empty connection is subtracted to leave out the ssh handshake.
+fn measure_bandwidth(transport: &Transport, remote: &Remote, host: &str) -> Option<u64> {
+    let time = |command: &str, input: Vec<u8>| {
+        let mut ssh = transport.remote_command_with(remote, host, command, ExecOptions::default());
+        let started = Instant::now();
+        let status = transport
+            .run_with(&mut ssh, "probe", Input::Bytes(input), Output::Inherit)
+            .ok()?;
+        status.success().then(|| started.elapsed())
+    };
+    let handshake = time("true", Vec::new())?;
+    let transfer =
+        time("cat > /dev/null", sample_bytes(BANDWIDTH_SAMPLE_BYTES))?.checked_sub(handshake)?;
+    let bandwidth =
+        (BANDWIDTH_SAMPLE_BYTES as f64 * 8.0 / 1_000_000.0) / transfer.as_secs_f64().max(0.001);
+    Some(bandwidth as u64)
+}
+
+/// `len` pseudo random bytes, so compression configured for ssh or rsync doesn't distort
+/// measurements.
+pub fn sample_bytes(len: usize) -> Vec<u8> {
+    let mut state: u32 = 0x2545_f491;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as u8
+        })
+        .collect()
+}
+
+/// Host triple of the local rustc.
+pub fn local_host_triple() -> Option<String> {
+    let output = Command::new("rustc").arg("-vV").output().ok()?;
+    String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .find_map(|line| line.strip_prefix("host:"))
+        .map(|triple| triple.trim().to_owned())
+}
+
+fn cache_file(remote: &Remote, host: &str) -> Option<PathBuf> {
+    xdg::BaseDirectories::with_prefix("cargo-remote")
+        .ok()?
+        .find_cache_file(format!("remotes/{}", cache_file_name(remote, host)))
+}
+
+fn cache_file_name(remote: &Remote, host: &str) -> String {
+    let host: String = host
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    format!("{}-{}.json", host, remote.ssh_port)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::executor::Scripted;
+    use crate::hostkey;
+
+    #[test]
+    fn probe_parses_the_output() {
+        let output = "os: Linux\n\
+                      rsync: rsync  version 3.2.7  protocol version 31\n\
+                      cargo: cargo 1.75.0 (1d8b05cdd 2023-11-20)\n\
+                      cpus: 16\n\
+                      compressors: zstd gzip\n\
+                      rustc 1.75.0 (82e1608df 2023-12-21)\n\
+                      binary: rustc\n\
+                      host: x86_64-unknown-linux-gnu\n\
+                      release: 1.75.0\n";
+        let executor = Arc::new(Scripted::new([(0, output)]));
+        let transport = Transport::with_executor(false, executor.clone());
+        let remote = Remote {
+            link: Link::Lan,
+            ..Remote::default()
+        };
+        let facts = RemoteFacts::probe(&transport, &remote, "builder").unwrap();
+        assert_eq!(facts.host, "builder");
+        assert_eq!(facts.os.as_deref(), Some("Linux"));
+        assert_eq!(
+            facts.rsync_version.as_deref(),
+            Some("rsync  version 3.2.7  protocol version 31")
+        );
+        assert_eq!(facts.cargo_version.as_deref(), Some("1.75.0"));
+        assert_eq!(facts.rustc_version.as_deref(), Some("1.75.0"));
+        assert_eq!(
+            facts.host_triple.as_deref(),
+            Some("x86_64-unknown-linux-gnu")
+        );
+        assert_eq!(facts.cpus, Some(16));
+        assert_eq!(facts.compressors, ["zstd", "gzip"]);
+        assert_eq!(facts.bandwidth_mbit, None);
+        assert_eq!(executor.commands().len(), 1);
+    }
+
+    #[test]
+    fn probe_leaves_missing_tools_empty() {
+        let output = "os: Linux\nrsync: \ncargo: \ncpus: \ncompressors: \n";
+        let executor = Arc::new(Scripted::new([(0, output)]));
+        let transport = Transport::with_executor(false, executor);
+        let remote = Remote {
+            link: Link::Wan,
+            ..Remote::default()
+        };
+        let facts = RemoteFacts::probe(&transport, &remote, "builder").unwrap();
+        assert_eq!(facts.rsync_version, None);
+        assert_eq!(facts.cargo_version, None);
+        assert_eq!(facts.rustc_version, None);
+        assert_eq!(facts.cpus, None);
+        assert!(facts.compressors.is_empty());
+    }
+
+    #[test]
+    fn probe_connects_with_the_pinned_host_key() {
+        let key = "AAAAC3NzaC1lZDI1NTE5AAAAIOMqqnkVzrm0SdG6UOoqKLsabgH5C9okWi0dh2l9GKJl";
+        let scanned = format!("builder.lan ssh-ed25519 {}\n", key);
+        let executor = Arc::new(Scripted::new([
+            (0, "hostname builder.lan\nport 22\n"),
+            (0, scanned.as_str()),
+            (0, "os: Linux\n"),
+        ]));
+        let transport = Transport::with_executor(false, executor.clone());
+        let remote = Remote {
+            name: "probe-pinned".to_owned(),
+            host: "builder".to_owned(),
+            hosts: vec!["builder".to_owned()],
+            host_key_fingerprint: Some(
+                "SHA256:+DiY3wvvV6TuJJhbpZisF/zLDA0zPMSvHdkr4UvCOqU".to_owned(),
+            ),
+            link: Link::Lan,
+            ..Remote::default()
+        };
+        RemoteFacts::probe(&transport, &remote, "builder").unwrap();
+        let known_hosts = hostkey::known_hosts(&remote);
+        let pinned = std::fs::read_to_string(&known_hosts).unwrap();
+        std::fs::remove_file(&known_hosts).unwrap();
+        assert_eq!(pinned, scanned);
+        let commands = executor.commands();
+        assert_eq!(commands[1][0], "ssh-keyscan");
+        let options = [
+            "StrictHostKeyChecking=yes".to_owned(),
+            format!("UserKnownHostsFile={}", known_hosts.display()),
+        ];
+        assert!(options.iter().all(|option| commands[2].contains(option)));
+    }
+
+    #[test]
+    fn probe_fails_on_a_failed_connection() {
+        let executor = Arc::new(Scripted::new([(255, "")]));
+        let transport = Transport::with_executor(false, executor);
+        let remote = Remote {
+            link: Link::Lan,
+            ..Remote::default()
+        };
+        assert!(RemoteFacts::probe(&transport, &remote, "builder").is_err());
+    }
+}
@@ -0,0 +1,153 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::process::{Command, ExitStatus, Stdio};
+use std::thread::{self, JoinHandle};
+
+/// Called with every chunk of bytes as soon as it was read from the stream.
+pub type ChunkHook = Box<dyn FnMut(&[u8]) -> io::Result<()> + Send>;
+/// Called with every line (without the line break) read from the stream.
+pub type LineHook = Box<dyn FnMut(&str) -> io::Result<()> + Send>;
+
+/// Where the output of a stream of a child process goes.
+pub enum Output {
+    /// Directly to the terminal, like the stream of this process.
+    Inherit,
+    /// Read in chunks and handed to a hook, useful for terminal output with control sequences.
+    Chunks(ChunkHook),
+    /// Read line by line and handed to a hook.
+    Lines(LineHook),
+}
+
+/// Where the stdin of a child process comes from.
+pub enum Input {
+    /// The terminal, like the stdin of this process.
+    Inherit,
+    /// Written to the process, which then sees the end of its stdin.
+    Bytes(Vec<u8>),
+}
+
+/// A child process whose output streams can be hooked into.
+///
+/// stdin is inherited by default, so interactive commands (ssh password prompts, `nix-shell`)
+/// keep working.
+pub struct Process<'a> {
+    command: &'a mut Command,
+    stdin: Input,
+    stdout: Output,
+    stderr: Output,
+}
+
+impl<'a> Process<'a> {
+    pub fn new(command: &'a mut Command) -> Self {
+        Process {
+            command,
+            stdin: Input::Inherit,
+            stdout: Output::Inherit,
+            stderr: Output::Inherit,
+        }
+    }
+
+    pub fn stdin(mut self, input: Input) -> Self {
+        self.stdin = input;
+        self
+    }
+
+    pub fn stdout(mut self, output: Output) -> Self {
+        self.stdout = output;
+        self
+    }
+
+    pub fn stderr(mut self, output: Output) -> Self {
+        self.stderr = output;
+        self
+    }
+
+    /// Spawns the process, streams its output into the hooks and waits for it to exit.
+    pub fn run(self) -> io::Result<ExitStatus> {
+        let Process {
+            command,
+            stdin,
+            stdout,
+            stderr,
+        } = self;
+        command
+            .stdin(match stdin {
+                Input::Inherit => Stdio::inherit(),
+                Input::Bytes(_) => Stdio::piped(),
+            })
+            .stdout(stdio(&stdout))
+            .stderr(stdio(&stderr));
+        let mut child = command.spawn()?;
+
+        let stdout = child.stdout.take().map(|stream| pump(stream, stdout));
+        let stderr = child.stderr.take().map(|stream| pump(stream, stderr));
+        let written = match (stdin, child.stdin.take()) {
+            // dropping the stream afterwards closes it
+            (Input::Bytes(bytes), Some(mut stream)) => match stream.write_all(&bytes) {
+                // the process exited without reading everything
+                Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+                written => written,
+            },
+            _ => Ok(()),
+        };
+
+        let status = written.and_then(|_| child.wait());
+        for reader in stdout.into_iter().chain(stderr) {
+            reader
+                .join()
+                .unwrap_or_else(|_| Err(io::Error::other("output reader panicked")))?;
+        }
+        status
+    }
+}
+
+fn stdio(output: &Output) -> Stdio {
+    match output {
+        Output::Inherit => Stdio::inherit(),
+        Output::Chunks(_) | Output::Lines(_) => Stdio::piped(),
+    }
+}
+
+/// Hands everything read from `stream` to the hook of `output` on a separate thread.
+///
+/// After a hook failed the stream is still drained, so the child never blocks on a full pipe.
+/// The first error is returned once the stream is closed.
+fn pump<R: Read + Send + 'static>(stream: R, output: Output) -> JoinHandle<io::Result<()>> {
+    thread::spawn(move || {
+        let mut result = Ok(());
+        match output {
+            Output::Inherit => {}
+            Output::Chunks(mut hook) => {
+                let mut stream = stream;
+                let mut buffer = [0u8; 4096];
+                loop {
+                    match stream.read(&mut buffer) {
+                        Ok(0) => break,
+                        Ok(read) if result.is_ok() => result = hook(&buffer[..read]),
+                        Ok(_) => {}
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+            Output::Lines(mut hook) => {
+                // remote output isn't necessarily UTF-8, invalid bytes mustn't stop the draining
+                let mut stream = BufReader::new(stream);
+                let mut line = Vec::new();
+                loop {
+                    line.clear();
+                    match stream.read_until(b'\n', &mut line) {
+                        Ok(0) => break,
+                        Ok(_) if result.is_ok() => {
+                            let text = String::from_utf8_lossy(&line);
+                            result = hook(text.trim_end_matches('\n').trim_end_matches('\r'));
+                        }
+                        Ok(_) => {}
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                        Err(e) => return Err(e),
+                    }
+                }
+            }
+        }
+        result
+    })
+}
@@ -0,0 +1,223 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+use tracing::{error, info};
+
+use crate::config::{Config, Remote};
+use crate::credentials;
+use crate::process::{Input, Output};
+use crate::script;
+use crate::sync;
+use crate::transport::Transport;
+
+/// A related local repository from the `[projects]` section of the config.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Project {
+    /// Location of the project, relative to the project containing the config.
+    pub path: PathBuf,
+    /// Names of the projects whose crates this project depends on.
+    #[serde(default)]
+    pub depends_on: Vec<String>,
+}
+
+/// Orders `projects` so every project comes after all projects it depends on.
+pub fn build_order(projects: &BTreeMap<String, Project>) -> Result<Vec<String>, String> {
+    fn visit(
+        name: &str,
+        projects: &BTreeMap<String, Project>,
+        visiting: &mut BTreeSet<String>,
+        order: &mut Vec<String>,
+    ) -> Result<(), String> {
+        if order.iter().any(|done| done == name) {
+            return Ok(());
+        }
+        if !visiting.insert(name.to_owned()) {
+            return Err(format!(
+                "projects have a dependency cycle involving `{}`",
+                name
+            ));
+        }
+        let project = projects
+            .get(name)
+            .ok_or_else(|| format!("unknown project `{}`", name))?;
+        for dependency in &project.depends_on {
+            visit(dependency, projects, visiting, order)?;
+        }
+        visiting.remove(name);
+        order.push(name.to_owned());
+        Ok(())
+    }
+
+    let mut order = Vec::new();
+    for name in projects.keys() {
+        visit(name, projects, &mut BTreeSet::new(), &mut order)?;
+    }
+    Ok(order)
+}
+
+/// A project resolved on the local machine and mapped to its build path.
+struct ResolvedProject {
+    root: PathBuf,
+    target_dir: PathBuf,
+    build_path: String,
+    /// Directories of all packages of the project, relative to `root`.
+    package_dirs: Vec<PathBuf>,
+}
+
+fn resolve(remote: &Remote, dir: &Path) -> Result<ResolvedProject, String> {
+    let metadata = cargo_metadata::MetadataCommand::new()
+        .manifest_path(dir.join("Cargo.toml"))
+        .no_deps()
+        .exec()
+        .map_err(|e| format!("failed to read metadata of {:?}: {}", dir, e))?;
+    let root = metadata.workspace_root;
+    let package_dirs = metadata
+        .packages
+        .iter()
+        .filter_map(|package| package.manifest_path.parent())
+        .filter_map(|package_dir| package_dir.strip_prefix(&root).ok())
+        .map(PathBuf::from)
+        .collect();
+    Ok(ResolvedProject {
+        build_path: sync::build_path(remote, &root),
+        target_dir: metadata.target_directory,
+        root,
+        package_dirs,
+    })
+}
+
+/// Path on the remote as it can be used inside double quotes in the remote shell.
+fn remote_path(build_path: &str, relative: &Path) -> String {
+    let build_path = match build_path.strip_prefix("~/") {
+        Some(in_home) => format!("$HOME/{}", in_home),
+        None => build_path.to_owned(),
+    };
+    format!("{}{}", build_path, relative.to_string_lossy())
+        .trim_end_matches('/')
+        .to_owned()
+}
+
+/// Syncs and builds all `[projects]` in dependency order with `commands` (`build` if empty).
+///
+/// Every project is built with cargo path overrides pointing at the remote copies of all the
+/// projects it depends on, so it uses their just-built sources instead of released versions.
+/// Stops at the first project that fails and returns whether all of them succeeded.
+pub fn build_all(
+    transport: &Transport,
+    conf: &Config,
+    remote: &Remote,
+    host: &str,
+    project_dir: &Path,
+    commands: &[String],
+    hidden: bool,
+) -> Result<bool, String> {
+    let projects = conf.projects();
+    if projects.is_empty() {
+        return Err("no [projects] are configured".to_owned());
+    }
+    let order = build_order(&projects)?;
+    info!("Build order: {}", order.join(" -> "));
+
+    let mut resolved = BTreeMap::new();
+    for name in &order {
+        let dir = project_dir.join(&projects[name].path);
+        resolved.insert(name.clone(), resolve(remote, &dir)?);
+    }
+
+    let commands: Vec<String> = if commands.is_empty() {
+        vec!["build".to_owned()]
+    } else {
+        commands.to_vec()
+    };
+    let forwarded = credentials::collect(remote).map_err(|e| e.to_string())?;
+
+    let mut results = Vec::new();
+    for name in &order {
+        let project = &resolved[name];
+        info!(
+            "Transferring {} ({:?}) to build server.",
+            name, project.root
+        );
+        let excludes = sync::source_excludes(conf, &project.root, &project.target_dir, hidden);
+        let status = sync::upload(
+            transport,
+            remote,
+            host,
+            &project.root,
+            &project.build_path,
+            &excludes,
+            false,
+        )
+        .map_err(|e| format!("failed to transfer {}: {}", name, e))?;
+        if !status.success() {
+            return Err(format!("failed to transfer {} ({})", name, status));
+        }
+
+        let overrides: Vec<String> = upstream(name, &projects)
+            .iter()
+            .flat_map(|upstream| {
+                let upstream = &resolved[upstream];
+                upstream
+                    .package_dirs
+                    .iter()
+                    .map(move |dir| format!("\\\"{}\\\"", remote_path(&upstream.build_path, dir)))
+            })
+            .collect();
+        let project_commands: Vec<String> = if overrides.is_empty() {
+            commands.clone()
+        } else {
+            let paths = format!("--config \"paths=[{}]\"", overrides.join(","));
+            commands
+                .iter()
+                .map(|command| format!("{} {}", paths, command))
+                .collect()
+        };
+
+        info!("Building {}.", name);
+        let mut build_command =
+            script::build_command(remote, &project.build_path, &project_commands);
+        let stdin = if forwarded.is_empty() {
+            Input::Inherit
+        } else {
+            build_command.insert_str(0, credentials::read_script());
+            credentials::input(&forwarded)
+        };
+        let mut ssh =
+            transport.remote_build_command(remote, host, &build_command, forwarded.is_empty());
+        let status = transport
+            .run_with(&mut ssh, "build", stdin, Output::Inherit)
+            .map_err(|e| format!("failed to build {}: {}", name, e))?;
+        results.push((name.clone(), status.success()));
+        if !status.success() {
+            error!(
+                "Building {} failed ({}), skipping the remaining projects.",
+                name, status
+            );
+            break;
+        }
+    }
+
+    println!();
+    println!("cargo remote build-all summary:");
+    for name in &order {
+        match results.iter().find(|(done, _)| done == name) {
+            Some((_, true)) => println!("  ok       {}", name),
+            Some((_, false)) => println!("  FAILED   {}", name),
+            None => println!("  skipped  {}", name),
+        }
+    }
+    Ok(results.iter().all(|(_, success)| *success) && results.len() == order.len())
+}
+
+/// All projects `name` depends on, directly or transitively.
+fn upstream(name: &str, projects: &BTreeMap<String, Project>) -> BTreeSet<String> {
+    let mut upstream = BTreeSet::new();
+    let mut pending = projects[name].depends_on.clone();
+    while let Some(dependency) = pending.pop() {
+        if upstream.insert(dependency.clone()) {
+            pending.extend(projects[&dependency].depends_on.iter().cloned());
+        }
+    }
+    upstream
+}
@@ -0,0 +1,105 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use crate::config::Config;
+
+/// Source of the settings nothing else sets.
+const DEFAULT: &str = "default";
+
+/// Where every setting of the effective config comes from: a config file, an environment
+/// variable, a flag or the default.
+#[derive(Debug, Default)]
+pub struct Provenance {
+    sources: BTreeMap<String, String>,
+}
+
+impl Provenance {
+    /// The sources of the top-level keys and of the keys of the remote called `name` (the
+    /// first remote without a name) in the config files of `project_dir`.
+    ///
+    /// Like the merged config, a later file with a `[[remote]]` list replaces the remotes of
+    /// the earlier ones.
+    pub fn of_files(project_dir: &Path, name: Option<&str>) -> Self {
+        let mut provenance = Provenance::default();
+        for file in Config::files(project_dir) {
+            let value: toml::Value = match fs::read_to_string(&file)
+                .ok()
+                .and_then(|contents| toml::from_str(&contents).ok())
+            {
+                Some(value) => value,
+                None => continue,
+            };
+            let source = file.to_string_lossy().into_owned();
+            for key in value.as_table().into_iter().flat_map(|table| table.keys()) {
+                if key != "remote" {
+                    provenance.set(key, &source);
+                }
+            }
+            let remotes = match value.get("remote").and_then(toml::Value::as_array) {
+                Some(remotes) => remotes,
+                None => continue,
+            };
+            provenance
+                .sources
+                .retain(|key, _| !key.starts_with("remote."));
+            let remote = remotes.iter().find(|remote| match name {
+                Some(name) => remote.get("name").and_then(toml::Value::as_str) == Some(name),
+                None => true,
+            });
+            for key in remote
+                .and_then(toml::Value::as_table)
+                .into_iter()
+                .flat_map(|table| table.keys())
+            {
+                provenance.set(&format!("remote.{}", key), &source);
+            }
+        }
+        provenance
+    }
+
+    /// Records that `key` (`remote.<key>` for keys of the remote) comes from `source`.
+    pub fn set(&mut self, key: &str, source: &str) {
+        self.sources.insert(key.to_owned(), source.to_owned());
+    }
+
+    pub fn source(&self, key: &str) -> &str {
+        self.sources.get(key).map_or(DEFAULT, String::as_str)
+    }
+
+    /// Appends the source to every `key = value` line of `description` in config file
+    /// syntax. Without `defaults`, settings nothing sets are left out.
+    pub fn annotate(&self, description: &str, defaults: bool) -> String {
+        let mut section = String::new();
+        let mut annotated = String::new();
+        for line in description.lines() {
+            if line.starts_with('[') {
+                section = match line.trim_matches(|c| c == '[' || c == ']') {
+                    "remote" => "remote.".to_owned(),
+                    _ => String::new(),
+                };
+                annotated.push_str(line);
+                annotated.push('\n');
+                continue;
+            }
+            let key = match line.split_once(" = ") {
+                Some((key, _)) => key,
+                None => {
+                    annotated.push_str(line);
+                    annotated.push('\n');
+                    continue;
+                }
+            };
+            // `hosts` is written as `host` when there is only one
+            let source = match self.source(&format!("{}{}", section, key)) {
+                DEFAULT if key == "host" => self.source(&format!("{}hosts", section)),
+                source => source,
+            };
+            if source == DEFAULT && !defaults {
+                continue;
+            }
+            annotated.push_str(&format!("{} # {}\n", line, source));
+        }
+        annotated
+    }
+}
@@ -0,0 +1,335 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use crate::backend::ExecOptions;
+use crate::config::{HostKeyChecking, Remote, Shell};
+use crate::process::{Input, Output};
+use crate::summary::unix_time;
+use crate::transport::Transport;
+
+/// Seconds a new instance has by default to accept ssh connections and finish cloud-init.
+const BOOT_TIMEOUT_SECS: u64 = 600;
+
+/// Minutes after which the built-in cloud-init powers the instance off by default, in case it
+/// isn't destroyed because `cargo remote` was killed.
+const MAX_LIFETIME_MINUTES: u64 = 240;
+
+/// Connect timeout of the attempts to reach a booting instance.
+const CONNECT_TIMEOUT_SECS: u64 = 10;
+
+/// Delay between the attempts to reach a booting instance.
+const RETRY_DELAY: Duration = Duration::from_secs(5);
+
+/// Public keys offered to new instances, the first existing one in `~/.ssh` is used.
+const PUBLIC_KEYS: &[&str] = &["id_ed25519.pub", "id_ecdsa.pub", "id_rsa.pub"];
+
+/// cloud-init of instances without a `cloud_init` template: rsync for the transfers and a
+/// multi-user nix, whose profile `/etc/profile` sources.
+const CLOUD_INIT: &str = "#cloud-config
+package_update: true
+packages:
+  - rsync
+  - curl
+  - xz-utils
+runcmd:
+  - shutdown -h +{max_lifetime}
+  - curl -fsSL https://nixos.org/nix/install | sh -s -- --daemon --yes
+";
+
+/// A cloud provider build servers are created at for a single build, from the `[provision]`
+/// section of the config. The provider is driven by local commands, usually its CLI.
+#[derive(Debug, Clone, Deserialize)]
+pub struct Provider {
+    /// Shell command creating an instance and printing `host=<user@address>` and optionally
+    /// `id=<instance id>` lines once it has an address.
+    pub create: String,
+    /// Shell command deleting the instance again.
+    pub destroy: String,
+    /// Instance type used if `--provision` names none, e.g. "c6i.8xlarge".
+    #[serde(default)]
+    pub instance_type: Option<String>,
+    /// Configured remote whose settings (temp_dir, env, ...) the instances get, the defaults
+    /// otherwise.
+    #[serde(default)]
+    pub remote: Option<String>,
+    /// Local cloud-init template, `{ssh_public_key}` and `{max_lifetime}` are replaced.
+    #[serde(default)]
+    pub cloud_init: Option<PathBuf>,
+    /// Seconds the instance has to accept ssh connections and finish cloud-init.
+    #[serde(default)]
+    pub boot_timeout: Option<u64>,
+    /// Minutes until the instance powers itself off, for templates using `{max_lifetime}`.
+    #[serde(default)]
+    pub max_lifetime: Option<u64>,
+}
+
+impl Provider {
+    pub fn check(&self) -> Result<(), &'static str> {
+        if self.create.trim().is_empty() || self.destroy.trim().is_empty() {
+            Err("`create` and `destroy` must be set")
+        } else {
+            Ok(())
+        }
+    }
+
+    /// The cloud-init user data of new instances.
+    fn user_data(&self) -> io::Result<String> {
+        let public_key = env::var_os("HOME").and_then(|home| {
+            PUBLIC_KEYS.iter().find_map(|key| {
+                fs::read_to_string(PathBuf::from(&home).join(".ssh").join(key)).ok()
+            })
+        });
+        let template = match &self.cloud_init {
+            Some(template) => fs::read_to_string(template)?,
+            None => match &public_key {
+                Some(_) => format!(
+                    "{}ssh_authorized_keys:\n  - {{ssh_public_key}}\n",
+                    CLOUD_INIT
+                ),
+                None => CLOUD_INIT.to_owned(),
+            },
+        };
+        if template.contains("{ssh_public_key}") && public_key.is_none() {
+            return Err(io::Error::other(format!(
+                "the cloud-init template needs a public key, but there is none of {} in ~/.ssh",
+                PUBLIC_KEYS.join(", ")
+            )));
+        }
+        Ok(template
+            .replace("{ssh_public_key}", public_key.unwrap_or_default().trim())
+            .replace(
+                "{max_lifetime}",
+                &self
+                    .max_lifetime
+                    .unwrap_or(MAX_LIFETIME_MINUTES)
+                    .to_string(),
+            ))
+    }
+}
+
+/// An instance created by a [`Provider`] for this build.
+#[derive(Debug, Clone)]
+pub struct Instance {
+    /// Name of the instance, for tagging it at the provider.
+    pub name: String,
+    /// The `id=` printed by `create`, if any.
+    pub id: Option<String>,
+    /// The `host=` printed by `create`, what ssh connects to.
+    pub host: String,
+    destroy: String,
+    /// known_hosts file of the instance alone, its address may have belonged to another host.
+    known_hosts: PathBuf,
+    cloud_init: Option<PathBuf>,
+}
+
+impl Instance {
+    /// Creates an instance of `instance_type` with the `create` command of `provider`.
+    ///
+    /// The command gets the instance type, a name for the instance and the file with the
+    /// cloud-init user data in `CARGO_REMOTE_INSTANCE_TYPE`, `CARGO_REMOTE_INSTANCE_NAME` and
+    /// `CARGO_REMOTE_CLOUD_INIT`. Once the command printed the instance it is destroyed at exit,
+    /// even if the command never finishes, and right away if the command fails.
+    pub fn create(
+        transport: &Transport,
+        provider: &Provider,
+        instance_type: Option<&str>,
+    ) -> io::Result<Self> {
+        let mut instance = Instance {
+            host: String::new(),
+            ..Instance::placeholder(provider)
+        };
+        let cloud_init = env::temp_dir().join(format!("{}-cloud-init.yaml", instance.name));
+        fs::write(&cloud_init, provider.user_data()?)?;
+        instance.cloud_init = Some(cloud_init.clone());
+
+        let mut create = Command::new("sh");
+        create
+            .arg("-c")
+            .arg(&provider.create)
+            .env("CARGO_REMOTE_INSTANCE_NAME", &instance.name)
+            .env("CARGO_REMOTE_CLOUD_INIT", &cloud_init);
+        if let Some(instance_type) = instance_type {
+            create.env("CARGO_REMOTE_INSTANCE_TYPE", instance_type);
+        }
+        // the instance as far as `create` printed it, destroyed at exit from its first line on
+        let created: Arc<Mutex<Option<Instance>>> = Arc::new(Mutex::new(None));
+        let destroyed = (transport.clone(), created.clone());
+        crate::at_exit(move || {
+            let (transport, created) = destroyed;
+            if let Some(instance) = created.lock().ok().and_then(|mut created| created.take()) {
+                instance.destroy(&transport);
+            }
+        });
+        let printed = created.clone();
+        let template = instance.clone();
+        let status = transport.run_with(
+            &mut create,
+            "provision",
+            Input::Inherit,
+            Output::Lines(Box::new(move |line| {
+                match line.trim().split_once('=') {
+                    Some((key @ ("host" | "id"), value)) => {
+                        if let Ok(mut printed) = printed.lock() {
+                            let instance = printed.get_or_insert_with(|| template.clone());
+                            match key {
+                                "host" => instance.host = value.trim().to_owned(),
+                                _ => instance.id = Some(value.trim().to_owned()),
+                            }
+                        }
+                    }
+                    _ => info!("{}", line),
+                }
+                Ok(())
+            })),
+        );
+        let printed = created.lock().ok().and_then(|created| created.clone());
+        let error = match (status, printed) {
+            (Ok(status), Some(printed)) if status.success() && !printed.host.is_empty() => {
+                return Ok(printed)
+            }
+            (Ok(status), _) if status.success() => {
+                io::Error::other("`create` printed no host= line")
+            }
+            (Ok(status), _) => io::Error::other(format!("`create` failed ({})", status)),
+            (Err(e), _) => e,
+        };
+        match created.lock().ok().and_then(|mut created| created.take()) {
+            Some(printed) => printed.destroy(transport),
+            None => instance.remove_files(),
+        }
+        Err(error)
+    }
+
+    /// An instance that isn't created, for dry runs.
+    pub fn placeholder(provider: &Provider) -> Self {
+        let started = unix_time(SystemTime::now());
+        let name = format!("cargo-remote-{}-{}", started, std::process::id());
+        Instance {
+            host: name.clone(),
+            known_hosts: env::temp_dir().join(format!("{}-known-hosts", name)),
+            name,
+            id: None,
+            destroy: provider.destroy.clone(),
+            cloud_init: None,
+        }
+    }
+
+    /// `template` building on the instance, accepting the new host key.
+    pub fn remote(&self, template: Remote) -> Remote {
+        Remote {
+            host: self.host.clone(),
+            hosts: vec![self.host.clone()],
+            known_hosts: Some(self.known_hosts.to_string_lossy().into_owned()),
+            strict_host_key_checking: Some(HostKeyChecking::AcceptNew),
+            ..template
+        }
+    }
+
+    /// Waits until the instance accepts ssh connections and cloud-init is done, at most
+    /// `boot_timeout` seconds.
+    pub fn wait(
+        &self,
+        transport: &Transport,
+        remote: &Remote,
+        boot_timeout: Option<u64>,
+    ) -> io::Result<()> {
+        let timeout = Duration::from_secs(boot_timeout.unwrap_or(BOOT_TIMEOUT_SECS));
+        let started = Instant::now();
+        info!("Waiting for {} to accept ssh connections.", self.host);
+        loop {
+            let mut ssh = transport.remote_command_with(
+                remote,
+                &self.host,
+                "true",
+                ExecOptions {
+                    connect_timeout: Some(CONNECT_TIMEOUT_SECS),
+                    ..ExecOptions::default()
+                },
+            );
+            ssh.stdout(Stdio::null());
+            if transport.output(&mut ssh, "provision")?.status.success() {
+                break;
+            }
+            if started.elapsed() > timeout {
+                return Err(io::Error::other(format!(
+                    "{} didn't accept ssh connections within {}s",
+                    self.host,
+                    timeout.as_secs()
+                )));
+            }
+            thread::sleep(RETRY_DELAY);
+        }
+        if remote.shell == Shell::Sh {
+            info!("Waiting for cloud-init to finish on {}.", self.host);
+            if let Err(e) = transport.remote_output(
+                remote,
+                &self.host,
+                "if command -v cloud-init >/dev/null; then cloud-init status --wait >/dev/null; fi",
+            ) {
+                warn!(
+                    "cloud-init didn't finish cleanly, the build may miss tools (error: {})",
+                    e
+                );
+            }
+        }
+        info!(
+            "{} is ready after {:.0}s.",
+            self.host,
+            started.elapsed().as_secs_f64()
+        );
+        Ok(())
+    }
+
+    /// Deletes the instance with the `destroy` command, which gets `CARGO_REMOTE_INSTANCE_ID`
+    /// (if `create` printed one), `CARGO_REMOTE_INSTANCE_HOST` and `CARGO_REMOTE_INSTANCE_NAME`.
+    /// Failures are only reported, the instance may then have to be deleted by hand.
+    pub fn destroy(&self, transport: &Transport) {
+        let instance = match (&self.id, self.host.is_empty()) {
+            (Some(id), true) => id,
+            _ => &self.host,
+        };
+        info!("Destroying the build server {}.", instance);
+        let mut destroy = Command::new("sh");
+        destroy
+            .arg("-c")
+            .arg(&self.destroy)
+            .env("CARGO_REMOTE_INSTANCE_NAME", &self.name)
+            .env("CARGO_REMOTE_INSTANCE_HOST", &self.host);
+        if let Some(id) = &self.id {
+            destroy.env("CARGO_REMOTE_INSTANCE_ID", id);
+        }
+        let manually = format!(
+            "delete {} ({}) manually",
+            self.id.as_deref().unwrap_or(&self.name),
+            self.host
+        );
+        match transport.run(&mut destroy, "provision") {
+            Ok(status) if status.success() => {}
+            Ok(status) => warn!(
+                "Failed to destroy the build server ({}), {}",
+                status, manually
+            ),
+            Err(e) => warn!(
+                "Failed to destroy the build server (error: {}), {}",
+                e, manually
+            ),
+        }
+        self.remove_files();
+    }
+
+    fn remove_files(&self) {
+        let _ = fs::remove_file(&self.known_hosts);
+        if let Some(cloud_init) = &self.cloud_init {
+            let _ = fs::remove_file(cloud_init);
+        }
+    }
+}
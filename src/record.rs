@@ -0,0 +1,74 @@
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Instant, SystemTime};
+
+use serde_json::json;
+
+use crate::process::Output;
+use crate::summary::unix_time;
+
+/// Directory inside the local target dir where session recordings are stored by default.
+const RECORD_DIR: &str = "remote-sessions";
+
+/// Default location of a recording started now, `<target_dir>/remote-sessions/<unix time>.cast`.
+pub fn default_path(target_dir: &Path) -> PathBuf {
+    target_dir
+        .join(RECORD_DIR)
+        .join(format!("{}.cast", unix_time(SystemTime::now())))
+}
+
+/// Creates a stdout handler that passes everything through to the terminal and additionally
+/// records it into `path` using the asciicast v2 format (replayable with `asciinema play`).
+///
+/// `title` and `remote_command` end up in the header of the recording so it can be tied to the
+/// build it belongs to.
+pub fn recorder(path: &Path, title: &str, remote_command: &str) -> io::Result<Output> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut cast = BufWriter::new(File::create(path)?);
+    let header = json!({
+        "version": 2,
+        "width": terminal_size("COLUMNS", 80),
+        "height": terminal_size("LINES", 24),
+        "timestamp": unix_time(SystemTime::now()),
+        "title": title,
+        "command": remote_command,
+    });
+    writeln!(cast, "{}", header)?;
+
+    let start = Instant::now();
+    // bytes of an utf-8 sequence that was split between two reads
+    let mut pending = Vec::new();
+    Ok(Output::Chunks(Box::new(move |chunk| {
+        {
+            let stdout = io::stdout();
+            let mut stdout = stdout.lock();
+            stdout.write_all(chunk)?;
+            stdout.flush()?;
+        }
+
+        pending.extend_from_slice(chunk);
+        let valid = match std::str::from_utf8(&pending) {
+            Ok(text) => text.len(),
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(_) => pending.len(),
+        };
+        let text = String::from_utf8_lossy(&pending[..valid]).into_owned();
+        pending.drain(..valid);
+        if !text.is_empty() {
+            let event = json!([start.elapsed().as_secs_f64(), "o", text]);
+            writeln!(cast, "{}", event)?;
+            cast.flush()?;
+        }
+        Ok(())
+    })))
+}
+
+fn terminal_size(variable: &str, default: u32) -> u32 {
+    std::env::var(variable)
+        .ok()
+        .and_then(|value| value.parse().ok())
+        .unwrap_or(default)
+}
@@ -0,0 +1,104 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::config::Remote;
+use crate::script;
+use crate::sync;
+use crate::transport::Transport;
+
+/// File in the config dir of cargo-remote mapping the remote build directories to the local
+/// projects they were built from.
+const REGISTRY_FILE: &str = "build-dirs.json";
+
+/// The local projects built from this machine, keyed by the name of their build directory in
+/// the `temp_dir` of the remotes.
+#[derive(Debug, Default)]
+pub struct Registry {
+    projects: BTreeMap<String, PathBuf>,
+}
+
+impl Registry {
+    pub fn load() -> io::Result<Self> {
+        let path =
+            xdg::BaseDirectories::with_prefix("cargo-remote")?.place_config_file(REGISTRY_FILE)?;
+        let projects = match fs::read(&path) {
+            Ok(contents) => serde_json::from_slice(&contents)?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => BTreeMap::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Registry { projects })
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        let path =
+            xdg::BaseDirectories::with_prefix("cargo-remote")?.place_config_file(REGISTRY_FILE)?;
+        fs::write(path, serde_json::to_vec_pretty(&self.projects)?)
+    }
+
+    /// Remembers that the build directory of `project_dir` belongs to it.
+    pub fn record(project_dir: &Path) -> io::Result<()> {
+        let mut registry = Self::load()?;
+        let dir_name = sync::dir_name(project_dir);
+        if registry.projects.get(&dir_name).map(PathBuf::as_path) == Some(project_dir) {
+            return Ok(());
+        }
+        registry.projects.insert(dir_name, project_dir.to_owned());
+        registry.save()
+    }
+
+    pub fn forget(&mut self, dir_name: &str) {
+        self.projects.remove(dir_name);
+    }
+}
+
+/// A build directory in the `temp_dir` of a remote that no local project uses anymore.
+#[derive(Debug)]
+pub enum Orphan {
+    /// The project was built from this machine, but its directory is gone.
+    Deleted { dir_name: String, project: PathBuf },
+    /// Not built from this machine, possibly by another user of the remote.
+    Unknown { dir_name: String },
+}
+
+/// Lists the build directories in the `temp_dir` of `remote` that don't belong to an existing
+/// local project.
+pub fn orphans(
+    transport: &Transport,
+    remote: &Remote,
+    host: &str,
+    registry: &Registry,
+) -> io::Result<Vec<Orphan>> {
+    let listing = transport.remote_output(
+        remote,
+        host,
+        &format!(
+            "find {} -mindepth 1 -maxdepth 1 -type d -name '[0-9]*' ! -name '*[!0-9]*' -print",
+            remote.temp_dir
+        ),
+    )?;
+    Ok(listing
+        .lines()
+        .filter_map(|path| path.rsplit('/').next())
+        .filter_map(|dir_name| match registry.projects.get(dir_name) {
+            Some(project) if project.exists() => None,
+            Some(project) => Some(Orphan::Deleted {
+                dir_name: dir_name.to_owned(),
+                project: project.clone(),
+            }),
+            None => Some(Orphan::Unknown {
+                dir_name: dir_name.to_owned(),
+            }),
+        })
+        .collect())
+}
+
+/// Script deleting the build directories called `dir_names` in the `temp_dir` of `remote`.
+pub fn delete_script(remote: &Remote, dir_names: &[&str]) -> String {
+    let paths: Vec<String> = dir_names
+        .iter()
+        .map(|dir_name| script::path(&format!("{}/{}", remote.temp_dir, dir_name)))
+        .collect();
+    format!("rm -rf {}", paths.join(" "))
+}
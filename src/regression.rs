@@ -0,0 +1,208 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use serde::{Deserialize, Serialize};
+
+use crate::summary::size;
+
+/// File in the local target dir keeping the last run of every command line.
+const HISTORY_FILE: &str = "remote-history.json";
+
+/// Build time changes below this share are noise and not reported.
+const TIME_NOISE: f64 = 0.05;
+
+/// Sizes of the copied back artifacts and the build time of one run.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Run {
+    pub host: String,
+    pub build_secs: f64,
+    /// Artifact paths relative to the copied back directory and their sizes in bytes.
+    pub artifacts: BTreeMap<String, u64>,
+}
+
+impl Run {
+    /// Measures the final artifacts (binaries and libraries, not `deps` or `build`) in the
+    /// profile directories of the copied back `destination`, which can be the target dir, a
+    /// target triple or a profile directory.
+    pub fn measure(host: &str, build_secs: f64, destination: &Path) -> io::Result<Self> {
+        let mut artifacts = BTreeMap::new();
+        for profile_dir in profile_dirs(destination)? {
+            for entry in fs::read_dir(&profile_dir)? {
+                let entry = entry?;
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if name.starts_with('.') || name.ends_with(".d") || !entry.file_type()?.is_file() {
+                    continue;
+                }
+                let path = entry.path();
+                let relative = path.strip_prefix(destination).unwrap_or(&path);
+                artifacts.insert(
+                    relative.to_string_lossy().into_owned(),
+                    entry.metadata()?.len(),
+                );
+            }
+        }
+        Ok(Run {
+            host: host.to_owned(),
+            build_secs,
+            artifacts,
+        })
+    }
+}
+
+/// Profile directories are the ones cargo keeps its `.fingerprint` in, at most two levels
+/// below `destination` (`<triple>/<profile>`).
+fn profile_dirs(destination: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut dirs = Vec::new();
+    let mut candidates = vec![(destination.to_path_buf(), 0)];
+    while let Some((dir, depth)) = candidates.pop() {
+        if dir.join(".fingerprint").is_dir() {
+            dirs.push(dir);
+            continue;
+        }
+        if depth == 2 {
+            continue;
+        }
+        let entries = match fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            // a single copied back file
+            Err(_) if depth == 0 => return Ok(dirs),
+            Err(e) => return Err(e),
+        };
+        for entry in entries {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                candidates.push((entry.path(), depth + 1));
+            }
+        }
+    }
+    dirs.sort();
+    Ok(dirs)
+}
+
+/// The last runs of the builds into a local target dir, keyed by their cargo commands.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct History {
+    runs: BTreeMap<String, Run>,
+}
+
+impl History {
+    pub fn load(target_dir: &Path) -> Self {
+        fs::read(target_dir.join(HISTORY_FILE))
+            .ok()
+            .and_then(|contents| serde_json::from_slice(&contents).ok())
+            .unwrap_or_default()
+    }
+
+    pub fn save(&self, target_dir: &Path) -> io::Result<()> {
+        fs::create_dir_all(target_dir)?;
+        fs::write(
+            target_dir.join(HISTORY_FILE),
+            serde_json::to_vec_pretty(self)?,
+        )
+    }
+
+    /// Replaces the last run of `commands` with `run`, returning the previous one.
+    pub fn record(&mut self, commands: &[String], run: Run) -> Option<Run> {
+        self.runs.insert(commands.join(" && "), run)
+    }
+}
+
+/// Changes of one artifact between two runs.
+#[derive(Debug)]
+struct SizeChange {
+    artifact: String,
+    before: Option<u64>,
+    after: Option<u64>,
+}
+
+impl SizeChange {
+    /// Growth in percent, `None` for added or removed artifacts.
+    fn increase(&self) -> Option<f64> {
+        match (self.before, self.after) {
+            (Some(before), Some(after)) if before > 0 => {
+                Some((after as f64 - before as f64) / before as f64 * 100.0)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// What changed between the previous run of the same commands and the current one.
+#[derive(Debug)]
+pub struct Report {
+    sizes: Vec<SizeChange>,
+    /// Build times before and after, only compared for builds on the same host.
+    build_secs: Option<(f64, f64)>,
+}
+
+impl Report {
+    pub fn new(previous: &Run, current: &Run) -> Self {
+        let artifacts: BTreeSet<&String> = previous
+            .artifacts
+            .keys()
+            .chain(current.artifacts.keys())
+            .collect();
+        let sizes = artifacts
+            .into_iter()
+            .map(|artifact| SizeChange {
+                artifact: artifact.clone(),
+                before: previous.artifacts.get(artifact).copied(),
+                after: current.artifacts.get(artifact).copied(),
+            })
+            .filter(|change| change.before != change.after)
+            .collect();
+        let build_secs = (previous.host == current.host
+            && previous.build_secs > 0.0
+            && ((current.build_secs - previous.build_secs) / previous.build_secs).abs()
+                >= TIME_NOISE)
+            .then_some((previous.build_secs, current.build_secs));
+        Report { sizes, build_secs }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sizes.is_empty() && self.build_secs.is_none()
+    }
+
+    /// The artifacts that grew by more than `percent` and by how much.
+    pub fn increases_over(&self, percent: f64) -> Vec<(&str, f64)> {
+        self.sizes
+            .iter()
+            .filter_map(|change| Some((change.artifact.as_str(), change.increase()?)))
+            .filter(|(_, increase)| *increase > percent)
+            .collect()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut lines = Vec::new();
+        if let Some((before, after)) = self.build_secs {
+            lines.push(format!(
+                "  build time: {:.1}s -> {:.1}s ({:+.1}%)",
+                before,
+                after,
+                (after - before) / before * 100.0
+            ));
+        }
+        for change in &self.sizes {
+            lines.push(match (change.before, change.after) {
+                (Some(before), Some(after)) => format!(
+                    "  {}: {} -> {} ({:+.1}%)",
+                    change.artifact,
+                    size(before),
+                    size(after),
+                    change.increase().unwrap_or_default()
+                ),
+                (None, Some(after)) => format!("  {}: new, {}", change.artifact, size(after)),
+                (Some(before), None) => {
+                    format!("  {}: gone, was {}", change.artifact, size(before))
+                }
+                (None, None) => continue,
+            });
+        }
+        write!(f, "{}", lines.join("\n"))
+    }
+}
@@ -0,0 +1,139 @@
+use std::fmt;
+use std::str::FromStr;
+
+use serde::{Deserialize, Deserializer};
+
+/// How a comparator compares a version with its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Op {
+    Greater,
+    GreaterEq,
+    Less,
+    LessEq,
+    Exact,
+    /// `^1.70` and a bare `1.70`, like in Cargo.toml: compatible versions.
+    Caret,
+    /// `~1.70`: only patch versions.
+    Tilde,
+}
+
+/// One part of a requirement, e.g. `>=1.70`. `parts` is how many of major, minor and patch were
+/// given, missing ones are 0.
+#[derive(Debug, Clone)]
+struct Comparator {
+    op: Op,
+    version: [u64; 3],
+    parts: usize,
+}
+
+impl Comparator {
+    fn matches(&self, version: [u64; 3]) -> bool {
+        let own = self.version;
+        match self.op {
+            Op::Greater => version > own,
+            Op::GreaterEq => version >= own,
+            Op::Less => version < own,
+            Op::LessEq => version <= own,
+            Op::Exact => version[..self.parts] == own[..self.parts],
+            Op::Caret => {
+                // the first non-zero part given has to stay the same
+                let fixed = own[..self.parts]
+                    .iter()
+                    .position(|&part| part != 0)
+                    .unwrap_or(self.parts - 1);
+                version >= own && version[..=fixed] == own[..=fixed]
+            }
+            Op::Tilde => {
+                let fixed = if self.parts == 1 { 0 } else { 1 };
+                version >= own && version[..=fixed] == own[..=fixed]
+            }
+        }
+    }
+}
+
+/// A version requirement for the toolchain of the remote like `>=1.70` or `>=1.70, <1.80`,
+/// with the comparators of Cargo.toml. Pre-release tags like `-nightly` are ignored, so a
+/// nightly counts as its version.
+#[derive(Debug, Clone)]
+pub struct Requirement {
+    text: String,
+    comparators: Vec<Comparator>,
+}
+
+impl Requirement {
+    /// Whether `version`, e.g. `1.75.0` or `1.80.0-nightly`, satisfies the requirement. Versions
+    /// that can't be parsed never do.
+    pub fn matches(&self, version: &str) -> bool {
+        match parse_version(version.split('-').next().unwrap_or_default()) {
+            Some((version, _)) => self.comparators.iter().all(|c| c.matches(version)),
+            None => false,
+        }
+    }
+}
+
+impl FromStr for Requirement {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let comparators = text
+            .split(',')
+            .map(|comparator| {
+                let comparator = comparator.trim();
+                let (op, version) = [
+                    (">=", Op::GreaterEq),
+                    ("<=", Op::LessEq),
+                    (">", Op::Greater),
+                    ("<", Op::Less),
+                    ("=", Op::Exact),
+                    ("^", Op::Caret),
+                    ("~", Op::Tilde),
+                ]
+                .iter()
+                .find_map(|(prefix, op)| Some((*op, comparator.strip_prefix(prefix)?)))
+                .unwrap_or((Op::Caret, comparator));
+                let (version, parts) = parse_version(version.trim()).ok_or_else(|| {
+                    format!(
+                        "{:?} isn't a version requirement like \">=1.70\"",
+                        comparator
+                    )
+                })?;
+                Ok(Comparator { op, version, parts })
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Requirement {
+            text: text.to_owned(),
+            comparators,
+        })
+    }
+}
+
+impl fmt::Display for Requirement {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+impl<'de> Deserialize<'de> for Requirement {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Major, minor and patch of `version` and how many of them it has.
+fn parse_version(version: &str) -> Option<([u64; 3], usize)> {
+    let mut parsed = [0; 3];
+    let mut parts = 0;
+    for part in version.split('.') {
+        if parts == 3 {
+            return None;
+        }
+        parsed[parts] = part.parse().ok()?;
+        parts += 1;
+    }
+    Some((parsed, parts))
+}
@@ -0,0 +1,508 @@
+use std::collections::BTreeMap;
+
+use crate::config::{Remote, Shell};
+use crate::windows;
+
+/// Quotes `value` so the remote shell passes it on as a single word.
+pub fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', r"'\''"))
+}
+
+/// Quotes the remote path `value` for the shell, leaving a leading `~/` outside of the quotes
+/// so it still expands to the home directory.
+pub fn path(value: &str) -> String {
+    match value.strip_prefix("~/") {
+        Some(rest) => format!("~/{}", quote(rest)),
+        None if value == "~" => value.to_owned(),
+        None => quote(value),
+    }
+}
+
+/// Script deleting the remote directory `dir` with everything in it, in the shell of `remote`.
+pub fn remove_dir(remote: &Remote, dir: &str) -> String {
+    match remote.shell {
+        Shell::Sh => format!("rm -rf {}", path(dir)),
+        Shell::PowerShell => windows::remove_dir(dir),
+    }
+}
+
+/// Quotes `value` for the shell only if it contains anything but plain characters.
+pub fn word(value: &str) -> String {
+    let plain = !value.is_empty()
+        && value
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_=./:,+@%".contains(c));
+    if plain {
+        value.to_owned()
+    } else {
+        quote(value)
+    }
+}
+
+/// Joins `args` into a single command line for the remote shell, quoting only where needed.
+pub fn join_args(args: &[String]) -> String {
+    args.iter()
+        .map(|arg| word(arg))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Cargo subcommands building only the packages selected with `-p`.
+const PACKAGE_COMMANDS: &[&str] = &[
+    "build",
+    "b",
+    "check",
+    "c",
+    "clippy",
+    "test",
+    "t",
+    "bench",
+    "run",
+    "r",
+    "doc",
+    "d",
+    "rustc",
+    "rustdoc",
+    "fix",
+    "tree",
+    "tarpaulin",
+    "package",
+    "publish",
+];
+
+/// Cargo plugins with subcommands of their own, e.g. `nextest run`, which take `-p` after those.
+const PACKAGE_PLUGINS: &[&str] = &["nextest", "miri", "llvm-cov"];
+
+/// Index in the cargo command `words` where `-p <package>` goes to build only that package,
+/// `None` if the command doesn't select packages or already does so itself.
+pub fn package_position<S: AsRef<str>>(words: &[S]) -> Option<usize> {
+    let words: Vec<&str> = words.iter().map(AsRef::as_ref).collect();
+    let selects_packages = words.iter().take_while(|word| **word != "--").any(|word| {
+        ["--workspace", "--all", "--package", "--manifest-path"]
+            .iter()
+            .any(|option| word == option || word.starts_with(&format!("{}=", option)))
+            || (word.starts_with("-p") && !word.starts_with("--"))
+    });
+    if selects_packages {
+        return None;
+    }
+    match words.first() {
+        Some(first) if PACKAGE_COMMANDS.contains(first) => Some(1),
+        Some(first) if PACKAGE_PLUGINS.contains(first) => match words.get(1) {
+            Some(second) if !second.is_empty() && !second.starts_with('-') => Some(2),
+            _ => Some(1),
+        },
+        _ => None,
+    }
+}
+
+/// Adds `-p <package>` to the cargo command line `command`, see [`package_position`].
+pub fn select_package(command: &str, package: &str) -> String {
+    let words: Vec<&str> = command.split(' ').collect();
+    match package_position(&words) {
+        Some(position) => {
+            let mut words = words;
+            let selection = format!("-p {}", word(package));
+            words.insert(position, &selection);
+            words.join(" ")
+        }
+        None => command.to_owned(),
+    }
+}
+
+/// File in the remote target dir the exit code of every cargo command is written to, one
+/// `<exit code> <command>` line each.
+pub const RESULTS_FILE: &str = "cargo-remote-results";
+
+/// SHA-256 program of the remote, `sha256sum` is missing on macOS and BSD.
+pub const SHA256: &str =
+    "$(command -v sha256sum >/dev/null 2>&1 && echo sha256sum || echo 'shasum -a 256')";
+
+/// File in the remote target dir locked while a build uses the target dir.
+const TARGET_LOCK_FILE: &str = "cargo-remote.lock";
+
+/// Builds the script running every cargo command in `commands` one after another.
+///
+/// All commands are executed even if one of them fails. With more than one command a summary
+/// of the individual results is printed at the end. The script exits with the exit code of the
+/// last failed command, or 0 if all of them succeeded. The results are also written to
+/// [`RESULTS_FILE`].
+pub fn cargo_commands(commands: &[String]) -> String {
+    let mut script = start_results();
+    for (i, command) in commands.iter().enumerate() {
+        script.push(format!("cargo {}", command));
+        script.push(format!("cargo_remote_rc_{}=$?", i));
+        script.push(record_result(i, command));
+    }
+    script.extend(finish_results(commands));
+    script.join("; ")
+}
+
+/// Like [`cargo_commands`], but runs all commands at the same time, each with its own target
+/// dir below the common one.
+///
+/// The output of every command goes to a log file, the logs are printed one after another
+/// once all commands finished.
+pub fn parallel_cargo_commands(commands: &[String]) -> String {
+    let mut script = start_results();
+    let file =
+        |i: usize, extension: &str| format!("\"$CARGO_TARGET_DIR\"/matrix-{}.{}", i, extension);
+    let jobs: Vec<String> = commands
+        .iter()
+        .enumerate()
+        .map(|(i, command)| {
+            format!(
+                "( CARGO_TARGET_DIR=\"$CARGO_TARGET_DIR\"/matrix-{i} cargo {command} > {log} 2>&1; \
+                 echo $? > {status} )",
+                i = i,
+                command = command,
+                log = file(i, "log"),
+                status = file(i, "status")
+            )
+        })
+        .collect();
+    script.push(format!("{} & wait", jobs.join(" & ")));
+    for (i, command) in commands.iter().enumerate() {
+        script.push(format!("echo {}", quote(&format!("==> cargo {}", command))));
+        script.push(format!("cat {}", file(i, "log")));
+        script.push(format!(
+            "cargo_remote_rc_{}=$(cat {})",
+            i,
+            file(i, "status")
+        ));
+        script.push(record_result(i, command));
+    }
+    script.extend(finish_results(commands));
+    script.join("; ")
+}
+
+/// Cargo commands of a feature matrix: `command` once for every set of features.
+///
+/// An empty set builds without `--features`.
+pub fn matrix_commands(
+    command: &str,
+    features_sets: &[String],
+    no_default_features: bool,
+) -> Vec<String> {
+    features_sets
+        .iter()
+        .map(|features| {
+            let mut matrix_command = command.to_owned();
+            if no_default_features {
+                matrix_command.push_str(" --no-default-features");
+            }
+            if !features.is_empty() {
+                matrix_command.push_str(&format!(" --features {}", word(features)));
+            }
+            matrix_command
+        })
+        .collect()
+}
+
+/// Start of a script recording the results of cargo commands in [`RESULTS_FILE`].
+fn start_results() -> Vec<String> {
+    vec![
+        "cargo_remote_status=0".to_owned(),
+        format!(
+            "mkdir -p \"$CARGO_TARGET_DIR\"; : > \"$CARGO_TARGET_DIR\"/{}",
+            RESULTS_FILE
+        ),
+    ]
+}
+
+/// Appends the exit code of command `i` to [`RESULTS_FILE`].
+fn record_result(i: usize, command: &str) -> String {
+    format!(
+        "echo $cargo_remote_rc_{} {} >> \"$CARGO_TARGET_DIR\"/{}",
+        i,
+        quote(command),
+        RESULTS_FILE
+    )
+}
+
+/// Prints the summary of `commands` and exits with the code of the last failed one.
+fn finish_results(commands: &[String]) -> Vec<String> {
+    let mut script = Vec::new();
+    if commands.len() > 1 {
+        script.push("echo".to_owned());
+        script.push(format!("echo {}", quote("cargo remote summary:")));
+        for (i, command) in commands.iter().enumerate() {
+            script.push(format!(
+                "if [ $cargo_remote_rc_{i} -eq 0 ]; then echo {ok}; \
+                 else echo {failed}\" (exit $cargo_remote_rc_{i})\"; fi",
+                i = i,
+                ok = quote(&format!("  ok      cargo {}", command)),
+                failed = quote(&format!("  FAILED  cargo {}", command)),
+            ));
+        }
+    }
+    for i in 0..commands.len() {
+        script.push(format!(
+            "[ $cargo_remote_rc_{i} -eq 0 ] || cargo_remote_status=$cargo_remote_rc_{i}",
+            i = i
+        ));
+    }
+    script.push("exit $cargo_remote_status".to_owned());
+    script
+}
+
+/// The command executed over ssh to build the project in `build_path`.
+///
+/// Without `commands` an interactive `nix-shell` is opened, otherwise the cargo commands are
+/// run inside of it.
+pub fn build_command(remote: &Remote, build_path: &str, commands: &[String]) -> String {
+    let script = (!commands.is_empty()).then(|| cargo_commands(commands));
+    build_script(remote, build_path, script.as_deref())
+}
+
+/// Like [`build_command`], but runs `script` inside the `nix-shell`, or opens an interactive one
+/// without it.
+pub fn build_script(remote: &Remote, build_path: &str, script: Option<&str>) -> String {
+    let rust_env = rust_env(remote);
+    let nix_shell = match script {
+        Some(script) => format!("nix-shell --run {}", quote(&(rust_env + script))),
+        None if rust_env.is_empty() => "nix-shell".to_owned(),
+        // `return` leaves the interactive shell open after the exports
+        None => format!("nix-shell --command {}", quote(&(rust_env + "return"))),
+    };
+    let jobs = match remote.jobs {
+        Some(jobs) => format!("export CARGO_BUILD_JOBS={}; ", jobs),
+        None => String::new(),
+    };
+    // an interactive shell would hold the lock for as long as it is open
+    let (lock, locked) = match script {
+        Some(_) => (target_lock(), "$cargo_remote_lock "),
+        None => (String::new(), ""),
+    };
+    format!(
+        "{}{}cd {}; {}export CARGO_TARGET_DIR={}{}; {}{}{}{}{};",
+        umask(remote),
+        source_env(remote),
+        build_path,
+        shared_target_dir(remote),
+        build_path,
+        crate::REMOTE_TARGET_DIR,
+        jobs,
+        lock,
+        locked,
+        priority(remote),
+        nix_shell
+    )
+}
+
+/// Exports the `rustflags`, `linker` and `target` settings of `remote` as the environment
+/// variables of the cargo config, which take precedence over the config files of the project.
+/// Runs in the nix-shell, where the toolchain telling the host triple is available.
+pub fn rust_env(remote: &Remote) -> String {
+    let mut exports = String::new();
+    if !remote.rustflags.is_empty() {
+        exports.push_str(&format!(
+            "export CARGO_BUILD_RUSTFLAGS={}; ",
+            quote(&remote.rustflags.join(" "))
+        ));
+    }
+    if let Some(linker) = &remote.linker {
+        exports.push_str(&format!(
+            "cargo_remote_host=$(rustc -vV | sed -n 's/^host: //p' | tr 'a-z.-' 'A-Z__'); \
+             export \"CARGO_TARGET_${{cargo_remote_host}}_LINKER\"={}; ",
+            quote(linker)
+        ));
+    }
+    for (triple, target) in &remote.target {
+        let variable = format!(
+            "CARGO_TARGET_{}",
+            triple.to_ascii_uppercase().replace(['-', '.'], "_")
+        );
+        if let Some(linker) = &target.linker {
+            exports.push_str(&format!("export {}_LINKER={}; ", variable, quote(linker)));
+        }
+        if !target.rustflags.is_empty() {
+            exports.push_str(&format!(
+                "export {}_RUSTFLAGS={}; ",
+                variable,
+                quote(&target.rustflags.join(" "))
+            ));
+        }
+    }
+    exports
+}
+
+/// Points the target dir of the build directory at the `shared_target_dir` of `remote`, so
+/// every path into the target dir keeps working. Empty without one.
+fn shared_target_dir(remote: &Remote) -> String {
+    match &remote.shared_target_dir {
+        Some(shared) => format!(
+            "mkdir -p {shared}; if [ \"$(readlink {target})\" != {shared} ]; then \
+             rm -rf {target}; ln -s {shared} {target}; fi; ",
+            shared = shared,
+            target = crate::REMOTE_TARGET_DIR
+        ),
+        None => String::new(),
+    }
+}
+
+/// Sets `$cargo_remote_lock` to a `flock` prefix holding [`TARGET_LOCK_FILE`] while the
+/// build runs, so builds using the same target dir on the remote (from other machines or with
+/// a `shared_target_dir`) wait for each other. Left empty where `flock` isn't installed, cargo
+/// still locks its own files there.
+fn target_lock() -> String {
+    format!(
+        "mkdir -p {target}; if command -v flock >/dev/null 2>&1; then \
+         cargo_remote_lock='flock -o {target}/{lock}'; \
+         flock -n {target}/{lock} true || echo {waiting} >&2; fi; ",
+        target = crate::REMOTE_TARGET_DIR,
+        lock = TARGET_LOCK_FILE,
+        waiting = quote("Waiting for another build using the target directory on the remote.")
+    )
+}
+
+/// `nice` and `ionice` prefix lowering the priority of the build as configured for `remote`.
+///
+/// `ionice` only exists on Linux, elsewhere the I/O class is silently ignored.
+fn priority(remote: &Remote) -> String {
+    let mut prefix = String::new();
+    if let Some(nice) = remote.nice {
+        prefix.push_str(&format!("nice -n {} ", nice));
+    }
+    if let Some(class) = remote.ionice_class {
+        prefix.push_str(&format!(
+            "$(command -v ionice >/dev/null 2>&1 && echo ionice -c {}) ",
+            class.number()
+        ));
+    }
+    prefix
+}
+
+/// Commands exporting `variables` to the following commands.
+pub fn exports(variables: &BTreeMap<String, String>) -> String {
+    variables
+        .iter()
+        .map(|(name, value)| format!("export {}={}; ", name, quote(value)))
+        .collect()
+}
+
+/// Command loading the environment profile of `remote`.
+///
+/// Uses `.` instead of `source`, which isn't available in every POSIX shell (e.g. BSD `sh`).
+pub fn source_env(remote: &Remote) -> String {
+    format!(". {}; ", remote.env)
+}
+
+/// `sudo_command` prefix switching to the `remote_user` of `remote`, or to root with
+/// `use_sudo`. Empty if everything runs as the ssh user.
+pub fn sudo(remote: &Remote) -> String {
+    let user = match (&remote.remote_user, remote.use_sudo) {
+        (Some(user), _) => user.as_str(),
+        (None, true) => "root",
+        (None, false) => return String::new(),
+    };
+    format!("{} ", remote.sudo_command.replace("{user}", &word(user)))
+}
+
+/// `command` run by the build user of `remote` (see [`sudo`]), unchanged without one.
+pub fn as_build_user(remote: &Remote, command: &str) -> String {
+    match sudo(remote).as_str() {
+        "" => command.to_owned(),
+        sudo => format!("{}sh -c {}", sudo, quote(command)),
+    }
+}
+
+/// The rsync on the remote, started as the build user so it owns the files it writes.
+pub fn remote_rsync(remote: &Remote) -> String {
+    format!("{}{}", sudo(remote), word(&remote.rsync_path_remote))
+}
+
+/// `umask` command applying the configured umask of `remote`, empty if there is none.
+pub fn umask(remote: &Remote) -> String {
+    match &remote.umask {
+        Some(umask) => format!("umask {}; ", quote(umask)),
+        None => String::new(),
+    }
+}
+
+/// Remote rsync invocation for the source upload, which also prepares the build directory.
+///
+/// If a shared `group` is configured the build directory is handed to it and marked setgid, so
+/// everything created inside it later belongs to that group, too.
+pub fn rsync_path(remote: &Remote, build_path: &str) -> String {
+    let mut rsync_path = format!("mkdir -p rust && {}", umask(remote));
+    if let Some(group) = &remote.group {
+        let shared = format!(
+            "mkdir -p {path} && chgrp {group} {path} && chmod g+s {path}",
+            path = build_path,
+            group = quote(group)
+        );
+        rsync_path.push_str(&format!("{} && ", as_build_user(remote, &shared)));
+    }
+    rsync_path.push_str(&remote_rsync(remote));
+    rsync_path
+}
+
+/// Deletes (or with `dry_run` only lists) the build directories in the `temp_dir` of `remote`
+/// that weren't modified for `older_than` days.
+///
+/// Build directories are named after the hash of the project path, so other directories in a
+/// shared `temp_dir` are never touched.
+pub fn gc(remote: &Remote, older_than: u32, dry_run: bool) -> String {
+    format!(
+        "find {} -mindepth 1 -maxdepth 1 -type d -name '[0-9]*' ! -name '*[!0-9]*' -mtime +{} -print{}",
+        remote.temp_dir,
+        older_than,
+        if dry_run { "" } else { " -exec rm -rf {} +" }
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::process::Command;
+
+    use super::*;
+
+    /// Runs `script` with a `cargo` exiting with its second argument and returns the exit
+    /// code, stdout and the results file.
+    fn run(name: &str, script: &str) -> (Option<i32>, String, String) {
+        let target_dir =
+            std::env::temp_dir().join(format!("cargo-remote-test-{}-{}", name, std::process::id()));
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(format!("cargo() {{ return $2; }}; {}", script))
+            .env("CARGO_TARGET_DIR", &target_dir)
+            .output()
+            .unwrap();
+        let results = fs::read_to_string(target_dir.join(RESULTS_FILE)).unwrap();
+        fs::remove_dir_all(&target_dir).unwrap();
+        (
+            output.status.code(),
+            String::from_utf8_lossy(&output.stdout).into_owned(),
+            results,
+        )
+    }
+
+    #[test]
+    fn cargo_commands_run_all_and_exit_with_the_last_failure() {
+        let commands = ["build 0", "test 3", "check 0", "clippy 5"].map(str::to_owned);
+        let (code, stdout, results) = run("all", &cargo_commands(&commands));
+        assert_eq!(code, Some(5));
+        assert_eq!(results, "0 build 0\n3 test 3\n0 check 0\n5 clippy 5\n");
+        assert!(stdout.contains("cargo remote summary:"));
+        assert!(stdout.contains("  FAILED  cargo test 3 (exit 3)"));
+        assert!(stdout.contains("  ok      cargo check 0"));
+    }
+
+    #[test]
+    fn cargo_commands_succeed_without_summary_for_one_command() {
+        let (code, stdout, results) = run("one", &cargo_commands(&["build 0".to_owned()]));
+        assert_eq!(code, Some(0));
+        assert_eq!(results, "0 build 0\n");
+        assert!(!stdout.contains("cargo remote summary:"));
+    }
+
+    #[test]
+    fn cargo_commands_quote_the_recorded_commands() {
+        let commands = ["test 0 --features 'a b'".to_owned()];
+        let (code, _, results) = run("quoted", &cargo_commands(&commands));
+        assert_eq!(code, Some(0));
+        assert_eq!(results, "0 test 0 --features 'a b'\n");
+    }
+}
@@ -0,0 +1,173 @@
+use std::env;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+/// Service the secrets are saved under in the system keyring.
+const KEYRING_SERVICE: &str = "cargo-remote";
+
+/// Environment variable with the age identity file decrypting `age:` secrets.
+const AGE_IDENTITY_VAR: &str = "CARGO_REMOTE_AGE_IDENTITY";
+
+/// Age identity file in the config dir of cargo-remote, used without [`AGE_IDENTITY_VAR`].
+const AGE_IDENTITY_FILE: &str = "age-identity.txt";
+
+/// Where the value of a secret in the `secrets` of a remote comes from. The config only holds
+/// the reference, so it can be committed.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Reference<'a> {
+    /// `keyring:<name>`, saved with `cargo remote secret set <name>`.
+    Keyring(&'a str),
+    /// `age:<ciphertext>`, the ASCII armored output of `age --encrypt --armor`.
+    Age(&'a str),
+    /// `sops:<file>#<key>`, a value of a file encrypted with sops.
+    Sops { file: &'a str, key: &'a str },
+}
+
+impl<'a> Reference<'a> {
+    pub fn parse(value: &'a str) -> Option<Self> {
+        let (scheme, rest) = value.split_once(':')?;
+        match scheme {
+            "keyring" if !rest.is_empty() => Some(Reference::Keyring(rest)),
+            "age" if rest.contains("BEGIN AGE ENCRYPTED FILE") => Some(Reference::Age(rest)),
+            "sops" => match rest.rsplit_once('#') {
+                Some((file, key)) if !file.is_empty() && !key.is_empty() => {
+                    Some(Reference::Sops { file, key })
+                }
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Looks up or decrypts the value with the local tools.
+    pub fn resolve(&self) -> io::Result<String> {
+        let (mut command, input) = match self {
+            Reference::Keyring(name) => (keyring_lookup(name), None),
+            Reference::Age(ciphertext) => {
+                let mut age = Command::new("age");
+                age.arg("--decrypt").arg("--identity").arg(age_identity()?);
+                (age, Some(ciphertext.trim_start()))
+            }
+            Reference::Sops { file, key } => {
+                let mut sops = Command::new("sops");
+                sops.args(["--decrypt", "--extract"])
+                    .arg(format!("[\"{}\"]", key))
+                    .arg(expand_home(file));
+                (sops, None)
+            }
+        };
+        let value = output(&mut command, input)?;
+        let value = value.strip_suffix('\n').unwrap_or(&value);
+        Ok(value.strip_suffix('\r').unwrap_or(value).to_owned())
+    }
+}
+
+/// Saves a secret under `name` in the system keyring, the keyring tool asks for the value.
+pub fn set_keyring(name: &str) -> io::Result<()> {
+    let mut command = if cfg!(target_os = "macos") {
+        let mut security = Command::new("security");
+        security
+            .args(["add-generic-password", "-U", "-s", KEYRING_SERVICE, "-a"])
+            .arg(name)
+            // the value is prompted for when -w is the last option
+            .arg("-w");
+        security
+    } else {
+        let mut secret_tool = Command::new("secret-tool");
+        secret_tool
+            .arg("store")
+            .arg(format!("--label=cargo-remote {}", name))
+            .args(["service", KEYRING_SERVICE, "account", name]);
+        secret_tool
+    };
+    let status = command.status().map_err(|e| spawn_error(&command, e))?;
+    if status.success() {
+        Ok(())
+    } else {
+        Err(io::Error::other(format!(
+            "{} failed ({})",
+            program(&command),
+            status
+        )))
+    }
+}
+
+/// Encrypts `value` for the age `recipient`, returns the armored ciphertext.
+pub fn encrypt_age(recipient: &str, value: &str) -> io::Result<String> {
+    let mut age = Command::new("age");
+    age.args(["--encrypt", "--armor", "--recipient", recipient]);
+    output(&mut age, Some(value))
+}
+
+fn keyring_lookup(name: &str) -> Command {
+    if cfg!(target_os = "macos") {
+        let mut security = Command::new("security");
+        security
+            .args(["find-generic-password", "-s", KEYRING_SERVICE, "-a"])
+            .arg(name)
+            .arg("-w");
+        security
+    } else {
+        let mut secret_tool = Command::new("secret-tool");
+        secret_tool.args(["lookup", "service", KEYRING_SERVICE, "account", name]);
+        secret_tool
+    }
+}
+
+fn age_identity() -> io::Result<PathBuf> {
+    if let Some(identity) = env::var_os(AGE_IDENTITY_VAR) {
+        return Ok(identity.into());
+    }
+    let identity =
+        xdg::BaseDirectories::with_prefix("cargo-remote")?.get_config_file(AGE_IDENTITY_FILE);
+    if identity.exists() {
+        Ok(identity)
+    } else {
+        Err(io::Error::other(format!(
+            "no age identity, set {} or create {:?}",
+            AGE_IDENTITY_VAR, identity
+        )))
+    }
+}
+
+fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix("~/"), env::var_os("HOME")) {
+        (Some(rest), Some(home)) => PathBuf::from(home).join(rest),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// Runs `command` with `input` on stdin and returns its stdout. stderr is left to the terminal,
+/// where the tools ask for passphrases.
+fn output(command: &mut Command, input: Option<&str>) -> io::Result<String> {
+    command
+        .stdin(if input.is_some() {
+            Stdio::piped()
+        } else {
+            Stdio::inherit()
+        })
+        .stdout(Stdio::piped());
+    let mut child = command.spawn().map_err(|e| spawn_error(command, e))?;
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        stdin.write_all(input.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "{} failed ({})",
+            program(command),
+            output.status
+        )));
+    }
+    String::from_utf8(output.stdout)
+        .map_err(|_| io::Error::other(format!("{} printed invalid UTF-8", program(command))))
+}
+
+fn spawn_error(command: &Command, error: io::Error) -> io::Error {
+    io::Error::other(format!("can't run {} ({})", program(command), error))
+}
+
+fn program(command: &Command) -> String {
+    command.get_program().to_string_lossy().into_owned()
+}
@@ -0,0 +1,131 @@
+use std::io;
+use std::path::PathBuf;
+
+use tracing::info;
+
+use crate::backend::Ssh;
+use crate::config::Remote;
+use crate::transport::Transport;
+
+/// Directory in the XDG runtime dir with the sockets of the ssh master connections.
+const SESSIONS_DIR: &str = "sessions";
+
+/// Opens a persistent ssh master connection to `host` of `remote` in the background. Until
+/// [`disconnect`], all ssh and rsync invocations for the remote reuse it instead of
+/// authenticating again.
+///
+/// Returns `false` if a session was already open.
+pub fn connect(transport: &Transport, remote: &Remote, host: &str) -> io::Result<bool> {
+    if !remote.exec_command.is_empty() {
+        return Err(io::Error::other(
+            "sessions are ssh connections, the remote is reached with its exec_command",
+        ));
+    }
+    let control_path = control_path(remote, true)?;
+    if is_open(transport, remote, host, &control_path) {
+        return Ok(false);
+    }
+    let mut ssh = Ssh::connection(transport, remote);
+    ssh.args(["-o", "ControlMaster=yes", "-o", "ControlPersist=yes"])
+        .arg("-o")
+        .arg(format!("ControlPath={}", control_path))
+        // authenticate in the foreground, then keep the connection open in the background
+        .args(["-N", "-f"])
+        .arg(host);
+    let status = transport.run(&mut ssh, "connect")?;
+    if status.success() {
+        Ok(true)
+    } else {
+        Err(io::Error::other(format!("ssh failed ({})", status)))
+    }
+}
+
+/// Closes the sessions to all hosts of `remote` and returns the hosts that had one.
+pub fn disconnect(transport: &Transport, remote: &Remote) -> io::Result<Vec<String>> {
+    let control_path = control_path(remote, false)?;
+    let mut closed = Vec::new();
+    for host in &remote.hosts {
+        if !is_open(transport, remote, host, &control_path) {
+            continue;
+        }
+        let mut ssh = Ssh::connection(transport, remote);
+        ssh.args(["-O", "exit", "-o"])
+            .arg(format!("ControlPath={}", control_path))
+            .arg(host);
+        let output = transport.output(&mut ssh, "disconnect")?;
+        if !output.status.success() {
+            return Err(io::Error::other(format!(
+                "ssh failed ({}): {}",
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        info!("Closed the session to {}.", host);
+        closed.push(host.clone());
+    }
+    Ok(closed)
+}
+
+/// ssh options reusing a session of `remote` opened with [`connect`], empty without one.
+///
+/// Hosts of the remote without a session of their own are still connected to directly.
+pub fn ssh_options(remote: &Remote) -> Vec<String> {
+    let prefix = format!("{}-", key(remote));
+    let has_session = xdg::BaseDirectories::with_prefix("cargo-remote")
+        .map(|base| base.list_runtime_files(SESSIONS_DIR))
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|socket| socket.file_name()?.to_str().map(str::to_owned))
+        .any(|name| name.starts_with(&prefix));
+    match control_path(remote, false) {
+        Ok(control_path) if has_session => {
+            vec!["-o".to_owned(), format!("ControlPath={}", control_path)]
+        }
+        _ => Vec::new(),
+    }
+}
+
+fn is_open(transport: &Transport, remote: &Remote, host: &str, control_path: &str) -> bool {
+    let mut ssh = Ssh::connection(transport, remote);
+    ssh.args(["-O", "check", "-o"])
+        .arg(format!("ControlPath={}", control_path))
+        .arg(host);
+    transport
+        .output(&mut ssh, "session-check")
+        .map(|output| output.status.success())
+        .unwrap_or(false)
+}
+
+/// `ControlPath` of the sessions of `remote`, ssh puts in the user, host and port so every
+/// host of the remote gets a socket of its own.
+fn control_path(remote: &Remote, create: bool) -> io::Result<String> {
+    let base = xdg::BaseDirectories::with_prefix("cargo-remote")?;
+    let dir: PathBuf = if create {
+        base.create_runtime_directory(SESSIONS_DIR)?
+    } else {
+        base.get_runtime_file(SESSIONS_DIR)?
+    };
+    Ok(format!(
+        "{}/{}-%r@%h:%p",
+        dir.to_string_lossy(),
+        key(remote)
+    ))
+}
+
+/// Name or host of `remote`, usable in a file name.
+fn key(remote: &Remote) -> String {
+    let key = if remote.name.is_empty() {
+        remote.host.as_str()
+    } else {
+        remote.name.as_str()
+    };
+    key.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "-_.".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
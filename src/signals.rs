@@ -0,0 +1,63 @@
+use std::fs::File;
+use std::io::{self, Read};
+use std::os::unix::io::FromRawFd;
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::thread;
+
+/// Signals ending the process through the callback of [`install`]: Ctrl-C, a cancelled CI job
+/// and a closed terminal.
+const SIGNALS: [libc::c_int; 3] = [libc::SIGINT, libc::SIGTERM, libc::SIGHUP];
+
+/// Write end of the pipe the signal handler reports to, -1 before [`install`].
+static PIPE: AtomicI32 = AtomicI32::new(-1);
+
+extern "C" fn report(signal: libc::c_int) {
+    let byte = signal as u8;
+    // write is async-signal-safe, everything else happens on the thread reading the pipe
+    unsafe {
+        libc::write(
+            PIPE.load(Ordering::Relaxed),
+            &byte as *const u8 as *const libc::c_void,
+            1,
+        );
+    }
+}
+
+/// Calls `on_signal` with the number of the first of [`SIGNALS`] the process receives, on a
+/// separate thread so it can do anything. Signals which were ignored when the process started,
+/// e.g. SIGHUP under `nohup`, stay ignored. A second signal ends the process right away, in
+/// case `on_signal` hangs.
+pub fn install(on_signal: fn(i32)) -> io::Result<()> {
+    let mut fds = [0; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    PIPE.store(fds[1], Ordering::Relaxed);
+    let mut reader = unsafe { File::from_raw_fd(fds[0]) };
+    thread::Builder::new()
+        .name("signals".to_owned())
+        .spawn(move || {
+            let mut signal = [0];
+            if reader.read_exact(&mut signal).is_ok() {
+                for signal in SIGNALS {
+                    unsafe { libc::signal(signal, libc::SIG_DFL) };
+                }
+                on_signal(signal[0] as i32);
+            }
+        })?;
+    for signal in SIGNALS {
+        let previous = unsafe {
+            libc::signal(
+                signal,
+                report as extern "C" fn(libc::c_int) as libc::sighandler_t,
+            )
+        };
+        if previous == libc::SIG_ERR {
+            return Err(io::Error::last_os_error());
+        }
+        if previous == libc::SIG_IGN {
+            unsafe { libc::signal(signal, libc::SIG_IGN) };
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,172 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use serde::{Deserialize, Serialize};
+
+use crate::config::Remote;
+use crate::script;
+use crate::transport::Transport;
+
+/// Default location of the summary of the last build inside the local target dir.
+const SUMMARY_FILE: &str = "remote-summary.json";
+
+/// Marks the pull request comment written by `cargo remote comment`, so it can be updated.
+pub const COMMENT_MARKER: &str = "<!-- cargo-remote-summary -->";
+
+/// Result of a single cargo command.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CommandResult {
+    pub command: String,
+    pub exit_code: i32,
+}
+
+/// Time spent in the phases of a remote build and the bytes transferred, to tell whether the
+/// network or the build dominates.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Timings {
+    pub sync_secs: f64,
+    /// `None` if the sources were transferred with tar.
+    pub sync_bytes: Option<u64>,
+    pub build_secs: f64,
+    /// `None` if nothing was copied back.
+    pub copy_back_secs: Option<f64>,
+    pub copy_back_bytes: Option<u64>,
+}
+
+impl fmt::Display for Timings {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "sync {:.1}s", self.sync_secs)?;
+        if let Some(bytes) = self.sync_bytes {
+            write!(f, " ({})", size(bytes))?;
+        }
+        write!(f, ", build {:.1}s", self.build_secs)?;
+        if let Some(secs) = self.copy_back_secs {
+            write!(f, ", copy back {:.1}s", secs)?;
+            if let Some(bytes) = self.copy_back_bytes {
+                write!(f, " ({})", size(bytes))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub fn size(bytes: u64) -> String {
+    const UNITS: &[&str] = &["B", "KiB", "MiB", "GiB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit + 1 < UNITS.len() {
+        value /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", value, UNITS[unit])
+    }
+}
+
+/// Seconds since the epoch at `time`, 0 for times before it.
+pub fn unix_time(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+/// Machine readable summary of a remote build, written by `--json-summary`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Summary {
+    pub host: String,
+    pub build_path: String,
+    /// Unix time the build started.
+    pub started_at: u64,
+    pub duration_secs: u64,
+    /// Exit code of the whole build, `None` if it was killed by a signal.
+    pub exit_code: Option<i32>,
+    pub commands: Vec<CommandResult>,
+    #[serde(default)]
+    pub timings: Timings,
+}
+
+pub fn default_path(target_dir: &Path) -> PathBuf {
+    target_dir.join(SUMMARY_FILE)
+}
+
+/// Reads the results of the cargo commands of the last build in `build_path` from the remote.
+pub fn fetch_results(
+    transport: &Transport,
+    remote: &Remote,
+    host: &str,
+    build_path: &str,
+) -> io::Result<Vec<CommandResult>> {
+    let output = transport.remote_output(
+        remote,
+        host,
+        &format!(
+            "cat {}{}/{}",
+            build_path,
+            crate::REMOTE_TARGET_DIR,
+            script::RESULTS_FILE
+        ),
+    )?;
+    Ok(output
+        .lines()
+        .filter_map(|line| {
+            let (exit_code, command) = line.split_once(' ')?;
+            Some(CommandResult {
+                command: command.to_owned(),
+                exit_code: exit_code.parse().ok()?,
+            })
+        })
+        .collect())
+}
+
+impl Summary {
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(path, serde_json::to_vec_pretty(self)?)
+    }
+
+    pub fn load(path: &Path) -> io::Result<Self> {
+        Ok(serde_json::from_slice(&fs::read(path)?)?)
+    }
+
+    /// The summary as a markdown table for a pull request comment.
+    pub fn markdown(&self) -> String {
+        let passed = self.exit_code == Some(0);
+        let mut markdown = format!(
+            "{}\n### {} cargo remote build on `{}`\n\n",
+            COMMENT_MARKER,
+            if passed { "✅" } else { "❌" },
+            self.host
+        );
+        if !self.commands.is_empty() {
+            markdown.push_str("| Command | Result |\n|---|---|\n");
+            for result in &self.commands {
+                let outcome = if result.exit_code == 0 {
+                    "✅ passed".to_owned()
+                } else {
+                    format!("❌ failed (exit {})", result.exit_code)
+                };
+                markdown.push_str(&format!(
+                    "| `cargo {}` | {} |\n",
+                    result.command.replace('|', "\\|"),
+                    outcome
+                ));
+            }
+            markdown.push('\n');
+        }
+        markdown.push_str(&format!(
+            "Finished in {}s, exit code {}.\n",
+            self.duration_secs,
+            self.exit_code
+                .map(|code| code.to_string())
+                .unwrap_or_else(|| "none (killed)".to_owned())
+        ));
+        markdown
+    }
+}
@@ -0,0 +1,553 @@
+use std::collections::hash_map::DefaultHasher;
+use std::env;
+use std::fs;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::path::Path;
+use std::process::ExitStatus;
+
+use tracing::{debug, info, warn};
+
+use crate::backend;
+use crate::cargo_config;
+use crate::config::{Config, Link, Remote, Shell, Transfer};
+use crate::manifest;
+use crate::registry::Registry;
+use crate::script;
+use crate::transport::Transport;
+use crate::windows;
+use crate::REMOTE_TARGET_DIR;
+
+/// Files uploaded by `--fast` builds: directories, sources, manifests, the cargo config, the nix
+/// environment and the configs of cargo-audit and cargo-deny.
+pub const FAST_INCLUDES: &[&str] = &[
+    "*/",
+    "*.rs",
+    "Cargo.toml",
+    "Cargo.lock",
+    "/.cargo/config.toml",
+    "/.cargo/config",
+    "rust-toolchain",
+    "rust-toolchain.toml",
+    "*.nix",
+    "deny.toml",
+    "audit.toml",
+];
+
+/// Directories `temp_dir` and `shared_target_dir` must never be, `gc`, `clean` and
+/// `rsync --delete` would delete files in them that have nothing to do with the builds.
+const PROTECTED_DIRS: &[&str] = &[
+    "/",
+    "~",
+    "$HOME",
+    "${HOME}",
+    "/Applications",
+    "/Library",
+    "/System",
+    "/Users",
+    "/bin",
+    "/boot",
+    "/dev",
+    "/etc",
+    "/home",
+    "/lib",
+    "/lib64",
+    "/nix",
+    "/nix/store",
+    "/opt",
+    "/proc",
+    "/root",
+    "/run",
+    "/sbin",
+    "/srv",
+    "/sys",
+    "/tmp",
+    "/usr",
+    "/usr/local",
+    "/var",
+];
+
+/// Characters the shell would interpret in the unquoted paths of the scripts. `\` only on sh
+/// remotes, Windows paths are made of them.
+const SHELL_METACHARACTERS: &str = "$`\"'\\;&|<>(){}[]*?!#~";
+
+/// Directories holding the home directories of the users, which `temp_dir` must not be either.
+const HOME_PARENTS: &[&str] = &["/home", "/Users"];
+
+/// `path` without empty and `.` components, e.g. `/` for `//./`.
+fn normalize(path: &str) -> String {
+    let components: Vec<&str> = path
+        .split('/')
+        .filter(|component| !component.is_empty() && *component != ".")
+        .collect();
+    match (path.starts_with('/'), components.join("/")) {
+        (true, joined) => format!("/{}", joined),
+        (false, joined) => joined,
+    }
+}
+
+/// Refuses a remote path containing whitespace or characters of the shell other than a leading
+/// `~/`, the scripts interpolate it unquoted.
+fn check_characters(remote: &Remote, key: &str, path: &str) -> Result<(), String> {
+    let plain = path.strip_prefix("~/").unwrap_or(path);
+    let special = plain.chars().find(|&c| {
+        c.is_whitespace()
+            || (SHELL_METACHARACTERS.contains(c) && (c != '\\' || remote.shell == Shell::Sh))
+    });
+    if let Some(special) = special {
+        return Err(format!(
+            "`{}` is {:?}, which contains {:?} that the remote shell would interpret",
+            key, path, special
+        ));
+    }
+    Ok(())
+}
+
+/// Refuses a remote path that is a protected or home directory, escapes with `..` or contains
+/// whitespace or characters of the shell other than a leading `~/`.
+fn check_dir(remote: &Remote, key: &str, path: &str) -> Result<(), String> {
+    check_characters(remote, key, path)?;
+    let normalized = normalize(path);
+    if normalized.is_empty() {
+        return Err(format!(
+            "`{}` is empty, the builds would end up in the root or home directory",
+            key
+        ));
+    }
+    // on Windows remotes the root of a drive, e.g. `C:`
+    let drive_root = normalized.len() == 2 && normalized.ends_with(':');
+    let home_dir = HOME_PARENTS.iter().any(|parent| {
+        normalized
+            .strip_prefix(parent)
+            .and_then(|rest| rest.strip_prefix('/'))
+            .is_some_and(|user| !user.contains('/'))
+    });
+    if PROTECTED_DIRS.contains(&normalized.as_str()) || drive_root || home_dir {
+        return Err(format!(
+            "`{}` is {:?}, files of the remote that have nothing to do with the builds could be deleted",
+            key, path
+        ));
+    }
+    if normalized.split('/').any(|component| component == "..") {
+        return Err(format!(
+            "`{}` is {:?}, `..` could lead anywhere on the remote",
+            key, path
+        ));
+    }
+    Ok(())
+}
+
+/// Checks that `build_path` and the directories of `remote` that get deleted in are safe
+/// places: `temp_dir` and `shared_target_dir` are no system or home directory and
+/// `build_path` is a directory of its own inside `temp_dir`.
+pub fn check_remote_paths(remote: &Remote, build_path: &str) -> Result<(), String> {
+    check_dir(remote, "temp_dir", &remote.temp_dir)?;
+    if let Some(shared_target_dir) = &remote.shared_target_dir {
+        check_dir(remote, "shared_target_dir", shared_target_dir)?;
+    }
+    let temp_dir = normalize(&remote.temp_dir);
+    let inside = normalize(build_path)
+        .strip_prefix(&temp_dir)
+        .and_then(|rest| rest.strip_prefix('/'))
+        .is_some_and(|rest| !rest.is_empty() && !rest.contains('/') && rest != "..");
+    if inside {
+        Ok(())
+    } else {
+        Err(format!(
+            "the build path {:?} isn't a directory of its own in the temp_dir {:?}",
+            build_path, remote.temp_dir
+        ))
+    }
+}
+
+/// Checks only that `temp_dir` and `shared_target_dir` of `remote` contain nothing the remote
+/// shell would interpret, which even `--i-know-what-i-am-doing` doesn't skip.
+pub fn check_remote_characters(remote: &Remote) -> Result<(), String> {
+    check_characters(remote, "temp_dir", &remote.temp_dir)?;
+    if let Some(shared_target_dir) = &remote.shared_target_dir {
+        check_characters(remote, "shared_target_dir", shared_target_dir)?;
+    }
+    Ok(())
+}
+
+/// A unique build path on the remote machine, using the hashed project dir as folder name.
+pub fn build_path(remote: &Remote, project_dir: &Path) -> String {
+    format!("{}/{}/", remote.temp_dir, dir_name(project_dir))
+}
+
+/// Name of the build directory of `project_dir` in the `temp_dir` of the remotes.
+pub fn dir_name(project_dir: &Path) -> String {
+    let mut hasher = DefaultHasher::new();
+    project_dir.hash(&mut hasher);
+    hasher.finish().to_string()
+}
+
+/// All rsync exclude patterns applied when uploading the project sources.
+pub fn source_excludes(
+    conf: &Config,
+    project_dir: &Path,
+    target_dir: &Path,
+    hidden: bool,
+) -> Vec<String> {
+    let mut excludes = vec![REMOTE_TARGET_DIR.to_owned()];
+
+    // a custom local target dir inside the project must not be uploaded either
+    if let Ok(relative_target_dir) = target_dir.strip_prefix(project_dir) {
+        if relative_target_dir != Path::new(REMOTE_TARGET_DIR) {
+            excludes.push(format!("/{}", relative_target_dir.to_string_lossy()));
+        }
+    }
+
+    if !hidden {
+        excludes.extend(hidden_excludes(project_dir));
+    }
+
+    excludes.extend(conf.excludes());
+    excludes
+}
+
+/// Excludes of the hidden files and directories of `project_dir`, except for the cargo config
+/// in `.cargo`, which the build needs. The rest of `.cargo`, e.g. `credentials.toml`, stays
+/// local.
+fn hidden_excludes(project_dir: &Path) -> Vec<String> {
+    let entries = |dir: &Path| -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| entry.ok()?.file_name().into_string().ok())
+            .collect();
+        names.sort();
+        names
+    };
+    // hidden entries below the top level, top-level ones are listed one by one
+    let mut excludes = vec!["*/.*".to_owned()];
+    for name in entries(project_dir) {
+        if name.starts_with('.') && name != ".cargo" {
+            excludes.push(format!("/{}", name));
+        }
+    }
+    for name in entries(&project_dir.join(".cargo")) {
+        if !cargo_config::FILE_NAMES.contains(&name.as_str()) {
+            excludes.push(format!("/.cargo/{}", name));
+        }
+    }
+    excludes
+}
+
+/// Transfers the project in `project_dir` to `build_path` on `host` with the
+/// [`backend::SyncBackend`] of the `transfer` of `remote`.
+///
+/// With the `per-remote` lockfile strategy the local `Cargo.lock.<remote>` is uploaded as the
+/// Cargo.lock instead, the shared one is only used to seed it. With `fast` only the files
+/// matching [`FAST_INCLUDES`] are transferred, without progress output.
+pub fn upload(
+    transport: &Transport,
+    remote: &Remote,
+    host: &str,
+    project_dir: &Path,
+    build_path: &str,
+    excludes: &[String],
+    fast: bool,
+) -> io::Result<ExitStatus> {
+    // `gc --orphans` tells the build directories of deleted projects apart with the registry
+    if let Err(e) = Registry::record(project_dir) {
+        warn!(
+            "Failed to record the build directory of {:?} (error: {})",
+            project_dir, e
+        );
+    }
+    backend::sync_backend(remote).upload(
+        transport,
+        remote,
+        host,
+        project_dir,
+        build_path,
+        excludes,
+        fast,
+    )
+}
+
+/// Transfers `file_name` from the remote target dir in `build_path` (the whole target dir if it
+/// is empty) to the local `destination`.
+///
+/// Directories (`file_name` ending with `/`) are first compared with the local files to print
+/// what has to be downloaded. Unless the remote is on a LAN, where everything is sent whole
+/// anyway, new and small files are then sent as a whole and only the larger changed files as
+/// deltas.
+pub fn copy_back(
+    transport: &Transport,
+    remote: &Remote,
+    host: &str,
+    build_path: &str,
+    file_name: &str,
+    destination: &str,
+    delete: bool,
+) -> io::Result<ExitStatus> {
+    let source = format!("{}{}/{}", build_path, REMOTE_TARGET_DIR, file_name);
+    let backend = backend::sync_backend(remote);
+    if backend.incremental() && source.ends_with('/') {
+        match manifest::list(transport, remote, host, &source) {
+            // an empty listing is also what a dry run returns
+            Ok(entries) if !entries.is_empty() => {
+                let plan = manifest::Plan::new(entries, Path::new(destination));
+                info!("Copy back: {}.", plan);
+                if remote.link != Link::Lan && !plan.whole.is_empty() {
+                    let status = fetch_whole(transport, remote, host, &source, destination, &plan)?;
+                    if !status.success() {
+                        return Ok(status);
+                    }
+                }
+            }
+            Ok(_) => {}
+            Err(e) => debug!("No listing of {} to plan the copy back ({})", source, e),
+        }
+    }
+    backend.fetch(
+        transport,
+        remote,
+        host,
+        &source,
+        destination,
+        delete,
+        "copy-back",
+    )
+}
+
+/// Like [`copy_back`] into the `--output-dir` `destination`, but a directory `file_name` only
+/// brings back the files directly in it, flat: the executables and libraries without the
+/// `deps`, `build` and fingerprint directories next to them. Files already in `destination`
+/// are never deleted.
+pub fn copy_back_flat(
+    transport: &Transport,
+    remote: &Remote,
+    host: &str,
+    build_path: &str,
+    file_name: &str,
+    destination: &str,
+) -> io::Result<ExitStatus> {
+    if !file_name.is_empty() && !file_name.ends_with('/') {
+        return copy_back(
+            transport,
+            remote,
+            host,
+            build_path,
+            file_name,
+            destination,
+            false,
+        );
+    }
+    let source = format!("{}{}/{}", build_path, REMOTE_TARGET_DIR, file_name);
+    let list = match remote.shell {
+        Shell::Sh => format!(
+            "cd {} && for f in * .[!.]*; do if [ -f \"$f\" ]; then printf '%s\\n' \"$f\"; fi; done",
+            source
+        ),
+        Shell::PowerShell => windows::list_files(&source),
+    };
+    let output = transport.remote_output(remote, host, &list)?;
+    let files: Vec<&str> = output.lines().filter(|file| !file.is_empty()).collect();
+    if files.is_empty() {
+        info!("There are no files in {} to copy back.", source);
+        return Ok(ExitStatus::default());
+    }
+    if backend::sync_backend(remote).incremental() {
+        return fetch_listed(transport, remote, host, &source, destination, &files, false);
+    }
+    for file in files {
+        let status = fetch(
+            transport,
+            remote,
+            host,
+            &format!("{}{}", source, file),
+            destination,
+            false,
+            "copy-back",
+        )?;
+        if !status.success() {
+            return Ok(status);
+        }
+    }
+    Ok(ExitStatus::default())
+}
+
+/// Transfers the files sent as a whole in `plan` from the remote directory `source`, without
+/// computing deltas. The remaining files are left to the regular transfer.
+fn fetch_whole(
+    transport: &Transport,
+    remote: &Remote,
+    host: &str,
+    source: &str,
+    destination: &str,
+    plan: &manifest::Plan,
+) -> io::Result<ExitStatus> {
+    let files: Vec<&str> = plan.whole.iter().map(|entry| entry.path.as_str()).collect();
+    fetch_listed(transport, remote, host, source, destination, &files, true)
+}
+
+/// Transfers `files`, relative to the remote directory `source`, with a single rsync, with
+/// `whole_file` without computing deltas.
+fn fetch_listed(
+    transport: &Transport,
+    remote: &Remote,
+    host: &str,
+    source: &str,
+    destination: &str,
+    files: &[&str],
+    whole_file: bool,
+) -> io::Result<ExitStatus> {
+    let files_from = env::temp_dir().join(format!("cargo-remote-copy-back-{}", std::process::id()));
+    fs::write(&files_from, files.join("\n"))?;
+    let (mut rsync_back, source) = match transport.rsyncd_url(remote, host, source) {
+        Some(url) if remote.transfer == Transfer::Rsyncd => (transport.rsyncd(remote, false), url),
+        _ => {
+            let mut rsync = transport.rsync(remote, false);
+            rsync.arg("--rsync-path").arg(script::remote_rsync(remote));
+            (rsync, format!("{}:{}", host, source))
+        }
+    };
+    if whole_file {
+        rsync_back.arg("--whole-file");
+    }
+    rsync_back
+        .arg(format!("--files-from={}", files_from.to_string_lossy()))
+        .args(transport.progress_flag(remote))
+        .arg(source)
+        .arg(destination);
+    let status = transport.transfer(remote, &mut rsync_back, "copy-back");
+    let _ = fs::remove_file(&files_from);
+    status
+}
+
+/// Transfers the remote `source` to the local `destination` like `rsync <host>:<source>
+/// <destination>`, with the configured [`Transfer`] of `remote`.
+pub fn fetch(
+    transport: &Transport,
+    remote: &Remote,
+    host: &str,
+    source: &str,
+    destination: &str,
+    delete: bool,
+    phase: &str,
+) -> io::Result<ExitStatus> {
+    backend::sync_backend(remote).fetch(transport, remote, host, source, destination, delete, phase)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn remote(temp_dir: &str) -> Remote {
+        Remote {
+            temp_dir: temp_dir.to_string(),
+            ..Remote::default()
+        }
+    }
+
+    #[test]
+    fn a_directory_of_its_own_in_the_temp_dir_is_a_safe_build_path() {
+        for temp_dir in ["~/remote-builds", "/srv/builds", "/tmp/cargo-remote/"] {
+            let remote = remote(temp_dir);
+            let build_path = build_path(&remote, Path::new("/p"));
+            assert_eq!(
+                check_remote_paths(&remote, &build_path),
+                Ok(()),
+                "{}",
+                temp_dir
+            );
+        }
+    }
+
+    #[test]
+    fn system_and_home_directories_are_refused() {
+        for temp_dir in [
+            "/",
+            "//./",
+            "",
+            "~",
+            "$HOME",
+            "${HOME}",
+            "/home",
+            "/home/alice",
+            "/Users/bob/",
+            "/tmp",
+            "/usr/local",
+            "C:",
+        ] {
+            let remote = remote(temp_dir);
+            let build_path = build_path(&remote, Path::new("/p"));
+            assert!(
+                check_remote_paths(&remote, &build_path).is_err(),
+                "{}",
+                temp_dir
+            );
+        }
+        let remote = Remote {
+            shared_target_dir: Some("/".to_string()),
+            ..remote("~/remote-builds")
+        };
+        let build_path = build_path(&remote, Path::new("/p"));
+        assert!(check_remote_paths(&remote, &build_path).is_err());
+    }
+
+    #[test]
+    fn shell_metacharacters_and_parent_directories_are_refused() {
+        for temp_dir in [
+            "/srv/builds;rm",
+            "/srv/my builds",
+            "/srv/$(x)",
+            "/srv/`x`",
+            "/srv/a&b",
+            "~/~",
+            "/srv/../etc",
+        ] {
+            let remote = remote(temp_dir);
+            let build_path = build_path(&remote, Path::new("/p"));
+            assert!(
+                check_remote_paths(&remote, &build_path).is_err(),
+                "{}",
+                temp_dir
+            );
+        }
+    }
+
+    #[test]
+    fn only_the_characters_are_checked_of_overridden_remotes() {
+        for temp_dir in ["/", "/home/alice", "/srv/../etc"] {
+            assert_eq!(
+                check_remote_characters(&remote(temp_dir)),
+                Ok(()),
+                "{}",
+                temp_dir
+            );
+        }
+        for temp_dir in ["/srv/builds;rm", "/srv/$(x)"] {
+            assert!(
+                check_remote_characters(&remote(temp_dir)).is_err(),
+                "{}",
+                temp_dir
+            );
+        }
+        let remote = Remote {
+            shared_target_dir: Some("/srv/a b".to_string()),
+            ..remote("~/remote-builds")
+        };
+        assert!(check_remote_characters(&remote).is_err());
+    }
+
+    #[test]
+    fn the_build_path_must_be_inside_the_temp_dir() {
+        let remote = remote("~/remote-builds");
+        for build_path in [
+            "~/remote-builds/",
+            "~/remote-builds/a/b/",
+            "~/remote-builds/../",
+            "/srv/a/",
+        ] {
+            assert!(
+                check_remote_paths(&remote, build_path).is_err(),
+                "{}",
+                build_path
+            );
+        }
+    }
+}
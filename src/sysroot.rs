@@ -0,0 +1,204 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::script::quote;
+use crate::tools;
+
+/// Directory in the build directory the target spec files from outside of the project are
+/// written to.
+const SPECS_DIR: &str = ".cargo-remote-targets";
+
+/// A custom target spec file (`--target <file>.json`) or one found in the local
+/// `RUST_TARGET_PATH`, which the remote needs a copy of.
+#[derive(Debug)]
+struct Spec {
+    /// `--target` argument as given, empty for the specs of `RUST_TARGET_PATH`.
+    argument: String,
+    file_name: String,
+    contents: String,
+}
+
+/// What `-Z build-std` builds and custom targets need on the remote beyond the sources.
+#[derive(Debug, Default)]
+pub struct Sysroot {
+    specs: Vec<Spec>,
+    /// Whether the local `RUST_TARGET_PATH` is replaced by [`SPECS_DIR`] on the remote.
+    target_path: bool,
+}
+
+impl Sysroot {
+    /// Collects the target spec files of `commands` outside of `project_dir`, those inside are
+    /// uploaded with the sources, and the specs in the local `RUST_TARGET_PATH`.
+    pub fn prepare(commands: &[String], project_dir: &Path) -> io::Result<Self> {
+        let mut sysroot = Sysroot::default();
+        let current_dir = env::current_dir()?;
+        for argument in commands.iter().filter_map(|command| target(command)) {
+            if !argument.ends_with(".json") || sysroot.specs.iter().any(|s| s.argument == argument)
+            {
+                continue;
+            }
+            let path = fs::canonicalize(current_dir.join(argument)).map_err(|e| {
+                io::Error::other(format!("can't find the target spec {} ({})", argument, e))
+            })?;
+            // the remote build runs in the project root, relative paths only work from there
+            if path.starts_with(project_dir) && current_dir == project_dir {
+                continue;
+            }
+            sysroot.specs.push(Spec {
+                argument: argument.to_owned(),
+                file_name: file_name(&path)?,
+                contents: read(&path)?,
+            });
+        }
+        if let Some(target_path) = env::var_os("RUST_TARGET_PATH") {
+            for dir in env::split_paths(&target_path) {
+                let entries = match fs::read_dir(&dir) {
+                    Ok(entries) => entries,
+                    Err(_) => continue,
+                };
+                for path in entries.filter_map(|entry| Some(entry.ok()?.path())) {
+                    if path
+                        .extension()
+                        .is_some_and(|extension| extension == "json")
+                    {
+                        sysroot.specs.push(Spec {
+                            argument: String::new(),
+                            file_name: file_name(&path)?,
+                            contents: read(&path)?,
+                        });
+                    }
+                }
+            }
+            sysroot.target_path = true;
+        }
+        Ok(sysroot)
+    }
+
+    /// `commands` with the `--target` arguments of spec files outside of the project pointing
+    /// at their copies on the remote.
+    pub fn rewrite(&self, commands: Vec<String>) -> Vec<String> {
+        commands
+            .into_iter()
+            .map(|command| {
+                let spec = match target(&command)
+                    .and_then(|argument| self.specs.iter().find(|s| s.argument == argument))
+                {
+                    Some(spec) => spec,
+                    None => return command,
+                };
+                let remote = format!("{}/{}", SPECS_DIR, spec.file_name);
+                command
+                    .split(' ')
+                    .map(|word| {
+                        if word == spec.argument {
+                            remote.clone()
+                        } else if word.strip_prefix("--target=") == Some(&spec.argument) {
+                            format!("--target={}", remote)
+                        } else {
+                            word.to_owned()
+                        }
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            })
+            .collect()
+    }
+
+    /// Script run in the build directory before `commands`: writes the spec files, points
+    /// `RUST_TARGET_PATH` at them and, with `install`, adds `rust-src` to the toolchains of
+    /// `-Z build-std` builds.
+    pub fn setup_script(&self, commands: &[String], install: bool) -> String {
+        let mut script = String::new();
+        if !self.specs.is_empty() {
+            script.push_str(&format!("mkdir -p {}; ", SPECS_DIR));
+        }
+        for spec in &self.specs {
+            script.push_str(&format!(
+                "printf '%s' {} > {}/{}; ",
+                quote(&spec.contents),
+                SPECS_DIR,
+                quote(&spec.file_name)
+            ));
+        }
+        if self.target_path {
+            script.push_str(&format!("export RUST_TARGET_PATH=\"$PWD\"/{}; ", SPECS_DIR));
+        }
+        for command in commands.iter().filter(|command| builds_std(command)) {
+            let toolchain = match tools::toolchain(command) {
+                Some(toolchain) => format!(" --toolchain {}", quote(toolchain)),
+                None => String::new(),
+            };
+            let rustc = match tools::toolchain(command) {
+                Some(toolchain) => format!("rustc +{}", quote(toolchain)),
+                None => "rustc".to_owned(),
+            };
+            let step = format!(
+                "if ! {rustc} -V 2>/dev/null | grep -q -e nightly -e dev; then \
+                 echo {message} >&2; fi; ",
+                rustc = rustc,
+                message = quote("-Z build-std needs a nightly toolchain on the remote, e.g. `cargo remote +nightly build -Z build-std`.")
+            );
+            if !script.contains(&step) {
+                script.push_str(&step);
+            }
+            if install {
+                let step = format!(
+                    "if command -v rustup >/dev/null 2>&1 && ! rustup component list --installed{toolchain} 2>/dev/null | grep -q '^rust-src'; then \
+                     echo 'Installing rust-src on the remote.'; rustup component add{toolchain} rust-src; fi; ",
+                    toolchain = toolchain
+                );
+                if !script.contains(&step) {
+                    script.push_str(&step);
+                }
+            }
+        }
+        script
+    }
+}
+
+/// Whether the cargo `command` builds the standard library itself, `-Z build-std` or
+/// `-Zbuild-std`.
+fn builds_std(command: &str) -> bool {
+    let mut previous = "";
+    for word in command.split_whitespace() {
+        if word == "--" {
+            break;
+        }
+        if word.starts_with("-Zbuild-std") || (previous == "-Z" && word.starts_with("build-std")) {
+            return true;
+        }
+        previous = word;
+    }
+    false
+}
+
+/// The `--target` argument of the cargo `command`.
+pub fn target(command: &str) -> Option<&str> {
+    let mut words = command.split_whitespace();
+    while let Some(word) = words.next() {
+        if word == "--" {
+            break;
+        }
+        if word == "--target" {
+            return words.next();
+        }
+        if let Some(target) = word.strip_prefix("--target=") {
+            return Some(target);
+        }
+    }
+    None
+}
+
+fn file_name(path: &Path) -> io::Result<String> {
+    path.file_name()
+        .and_then(|name| name.to_str())
+        .map(str::to_owned)
+        .ok_or_else(|| io::Error::other(format!("{:?} isn't a file name", path)))
+}
+
+fn read(path: &Path) -> io::Result<String> {
+    fs::read_to_string(path)
+        .map_err(|e| io::Error::other(format!("can't read the target spec {:?} ({})", path, e)))
+}
@@ -0,0 +1,215 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+
+use crate::config::Remote;
+use crate::script::{self, quote};
+use crate::transport::Transport;
+
+/// Separates the sections of the probe output.
+const SECTION_SEPARATOR: &str = "--- cargo-remote ---";
+
+/// Tools that can cross compile without a target specific linker.
+const CROSS_TOOLS: &[&str] = &["cross", "cargo-zigbuild", "zig", "cargo-xwin", "clang"];
+
+/// Cross compilation capabilities of a remote.
+#[derive(Debug, Default)]
+pub struct TargetSupport {
+    pub host_triple: String,
+    /// Installed rustup targets, including the host.
+    pub targets: BTreeSet<String>,
+    /// Available [`CROSS_TOOLS`].
+    pub cross_tools: BTreeSet<String>,
+    /// Linker found for each installed target that needs one.
+    pub linkers: BTreeMap<String, String>,
+}
+
+/// How well a requested `--target` is supported by a remote.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Support {
+    Ready,
+    /// The target is installed but no linker for it was found, it may still work with one of
+    /// the cross tools.
+    MissingLinker,
+    MissingTarget,
+}
+
+impl std::fmt::Display for Support {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Support::Ready => write!(f, "ok"),
+            Support::MissingLinker => write!(f, "no linker"),
+            Support::MissingTarget => write!(f, "not installed"),
+        }
+    }
+}
+
+/// Conventional name of the gcc cross linker for `target`, e.g. `aarch64-linux-gnu-gcc`.
+fn gcc_linker(target: &str) -> String {
+    let parts: Vec<&str> = target.split('-').collect();
+    match parts.as_slice() {
+        [arch, _vendor, os, env] => format!("{}-{}-{}-gcc", arch, os, env),
+        _ => format!("{}-gcc", target),
+    }
+}
+
+/// Targets linked with the `rust-lld` shipped with the toolchain.
+fn has_bundled_linker(target: &str) -> bool {
+    target.starts_with("wasm") || target.contains("-none")
+}
+
+/// Architecture and operating system of a target triple.
+fn arch_and_os(target: &str) -> (&str, &str) {
+    let mut parts = target.split('-');
+    let arch = parts.next().unwrap_or_default();
+    let os = parts.nth(1).unwrap_or_default();
+    (arch, os)
+}
+
+/// Collects the installed targets and the available cross tools and linkers of the remote.
+pub fn probe(transport: &Transport, remote: &Remote, host: &str) -> io::Result<TargetSupport> {
+    let output = transport.remote_output(
+        remote,
+        host,
+        &format!(
+            "{}rustc -vV; echo {sep}; rustup target list --installed 2>/dev/null; echo {sep}; \
+             for tool in {tools}; do command -v $tool >/dev/null && echo $tool; done; echo {sep}; \
+             for target in $(rustup target list --installed 2>/dev/null); do \
+             echo $target $(command -v $(echo $target | awk -F- 'NF == 4 {{ print $1 \"-\" $3 \"-\" $4 }}')-gcc); \
+             done; true",
+            script::source_env(remote),
+            sep = quote(SECTION_SEPARATOR),
+            tools = CROSS_TOOLS.join(" "),
+        ),
+    )?;
+    Ok(parse_probe(&output))
+}
+
+fn parse_probe(output: &str) -> TargetSupport {
+    let mut sections = output.split(SECTION_SEPARATOR).map(str::lines);
+    let mut support = TargetSupport::default();
+
+    for line in sections.next().into_iter().flatten() {
+        if let Some(host) = line.strip_prefix("host: ") {
+            support.host_triple = host.trim().to_owned();
+        }
+    }
+    for line in sections.next().into_iter().flatten() {
+        let target = line.trim();
+        if !target.is_empty() {
+            support.targets.insert(target.to_owned());
+        }
+    }
+    if !support.host_triple.is_empty() {
+        support.targets.insert(support.host_triple.clone());
+    }
+    for line in sections.next().into_iter().flatten() {
+        let tool = line.trim();
+        if !tool.is_empty() {
+            support.cross_tools.insert(tool.to_owned());
+        }
+    }
+    for line in sections.next().into_iter().flatten() {
+        let mut parts = line.split_whitespace();
+        if let (Some(target), Some(linker)) = (parts.next(), parts.next()) {
+            support.linkers.insert(target.to_owned(), linker.to_owned());
+        }
+    }
+    support
+}
+
+impl TargetSupport {
+    /// Whether `target` can be built on the remote.
+    pub fn support(&self, target: &str) -> Support {
+        if !self.targets.contains(target) {
+            return Support::MissingTarget;
+        }
+        // the host cc can link other environments of the same platform, e.g. musl
+        let native = arch_and_os(target) == arch_and_os(&self.host_triple);
+        if native || has_bundled_linker(target) || self.linkers.contains_key(target) {
+            Support::Ready
+        } else {
+            Support::MissingLinker
+        }
+    }
+
+    /// Human readable summary, marking how well each of `requested` is supported.
+    pub fn describe(&self, requested: &[String]) -> String {
+        let mut description = format!("  host: {}\n", self.host_triple);
+        description.push_str(&format!(
+            "  targets: {}\n",
+            self.targets.iter().cloned().collect::<Vec<_>>().join(", ")
+        ));
+        description.push_str(&format!(
+            "  cross tools: {}\n",
+            if self.cross_tools.is_empty() {
+                "none".to_owned()
+            } else {
+                self.cross_tools
+                    .iter()
+                    .cloned()
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            }
+        ));
+        for target in self.targets.iter().filter(|t| **t != self.host_triple) {
+            if let Some(linker) = self.linkers.get(target) {
+                description.push_str(&format!("  linker for {}: {}\n", target, linker));
+            } else if self.support(target) == Support::MissingLinker {
+                description.push_str(&format!(
+                    "  linker for {}: {} not found\n",
+                    target,
+                    gcc_linker(target)
+                ));
+            }
+        }
+        for target in requested {
+            description.push_str(&format!(
+                "  --target {}: {}\n",
+                target,
+                self.support(target)
+            ));
+        }
+        description
+    }
+}
+
+/// Prints the target support of every remote and where each of `requested` can be built.
+///
+/// Returns whether every requested target can be built on at least one remote.
+pub fn report(transport: &Transport, remotes: &[Remote], requested: &[String]) -> bool {
+    let mut ready: BTreeMap<&str, Vec<&str>> = requested
+        .iter()
+        .map(|target| (target.as_str(), Vec::new()))
+        .collect();
+    for remote in remotes {
+        println!("remote {} ({})", remote.name, remote.hosts.join(", "));
+        let support = transport
+            .select_host(remote)
+            .ok_or_else(|| io::Error::other("not reachable"))
+            .and_then(|host| probe(transport, remote, &host));
+        match support {
+            Ok(support) => {
+                print!("{}", support.describe(requested));
+                for target in requested {
+                    if support.support(target) == Support::Ready {
+                        ready.entry(target).or_default().push(&remote.name);
+                    }
+                }
+            }
+            Err(e) => println!("  error: {}", e),
+        }
+    }
+
+    if requested.is_empty() {
+        return true;
+    }
+    println!();
+    for (target, remotes) in &ready {
+        if remotes.is_empty() {
+            println!("{}: no remote can build it", target);
+        } else {
+            println!("{}: {}", target, remotes.join(", "));
+        }
+    }
+    ready.values().all(|remotes| !remotes.is_empty())
+}
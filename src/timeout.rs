@@ -0,0 +1,187 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use tracing::{error, warn};
+
+use crate::config::Remote;
+use crate::sync;
+use crate::transport::Transport;
+
+/// File in the remote target dir with the process id of the shell running the build, which
+/// leads the process group of everything the build started.
+const PID_FILE: &str = "cargo-remote-build.pid";
+
+/// Local file in the target dir with the processes that were still running at the timeout.
+const PROCESSES_FILE: &str = "remote-timeout.log";
+
+/// Local directory in the target dir the logs of the build scripts are copied to after a
+/// timeout, and its name in the remote target dir.
+const LOGS_DIR: &str = "remote-timeout-logs";
+
+/// Time the build gets to exit after SIGTERM before it is killed.
+const GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+/// Parses a duration like `90`, `90s`, `30m`, `2h` or `1h30m`, plain numbers are seconds.
+pub fn parse_duration(value: &str) -> Result<Duration, String> {
+    let mut secs = 0;
+    let mut number = String::new();
+    for c in value.trim().chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        let unit = match c {
+            's' => 1,
+            'm' => 60,
+            'h' => 60 * 60,
+            'd' => 24 * 60 * 60,
+            _ => return Err(format!("unknown unit {:?}, use s, m, h or d", c)),
+        };
+        let amount: u64 = number
+            .parse()
+            .map_err(|_| format!("{:?} is missing a number before {:?}", value, c))?;
+        secs += amount * unit;
+        number.clear();
+    }
+    if !number.is_empty() {
+        secs += number
+            .parse::<u64>()
+            .map_err(|e| format!("invalid duration {:?} ({})", value, e))?;
+    }
+    if secs == 0 {
+        return Err("the duration has to be at least one second".to_owned());
+    }
+    Ok(Duration::from_secs(secs))
+}
+
+/// Command recording the process id of the remote shell in the target dir of `build_path`,
+/// put in front of the build command so the watchdog can find its process group.
+pub fn record_pid(build_path: &str) -> String {
+    format!(
+        "mkdir -p {build_path}{target} && echo $$ > {build_path}{target}/{pid_file}; ",
+        build_path = build_path,
+        target = crate::REMOTE_TARGET_DIR,
+        pid_file = PID_FILE
+    )
+}
+
+/// Stops the build on the remote once its time is up, by terminating the whole process group
+/// there. The local ssh then ends on its own.
+pub struct Watchdog {
+    stop: Sender<()>,
+    thread: JoinHandle<bool>,
+}
+
+impl Watchdog {
+    pub fn start(
+        transport: &Transport,
+        remote: &Remote,
+        host: &str,
+        build_path: &str,
+        target_dir: &Path,
+        timeout: Duration,
+    ) -> Self {
+        let (stop, stopped) = mpsc::channel();
+        let (transport, remote, host) = (transport.clone(), remote.clone(), host.to_owned());
+        let pid_file = format!("{}{}/{}", build_path, crate::REMOTE_TARGET_DIR, PID_FILE);
+        let processes_file = target_dir.join(PROCESSES_FILE);
+        let thread = thread::spawn(move || match stopped.recv_timeout(timeout) {
+            Err(RecvTimeoutError::Timeout) => {
+                warn!(
+                    "The build is running for more than {}s, stopping it.",
+                    timeout.as_secs()
+                );
+                if let Err(e) = kill(&transport, &remote, &host, &pid_file, &processes_file) {
+                    error!("Failed to stop the build on {} (error: {})", host, e);
+                }
+                true
+            }
+            Ok(()) | Err(RecvTimeoutError::Disconnected) => false,
+        });
+        Watchdog { stop, thread }
+    }
+
+    /// Ends the watchdog after the build finished, returns whether it stopped the build.
+    pub fn finish(self) -> bool {
+        // fails if the watchdog already fired and ended
+        let _ = self.stop.send(());
+        self.thread.join().unwrap_or(false)
+    }
+}
+
+/// Terminates the process group of the build, and kills it if it is still there after the
+/// grace period. The processes running at that time are saved in `processes_file`.
+fn kill(
+    transport: &Transport,
+    remote: &Remote,
+    host: &str,
+    pid_file: &str,
+    processes_file: &Path,
+) -> io::Result<()> {
+    let script = format!(
+        "cargo_remote_pid=$(cat {pid_file}) || exit 1; \
+         ps -A -o pgid,pid,etime,args | awk -v pgid=$cargo_remote_pid 'NR == 1 || $1 == pgid'; \
+         kill -TERM -$cargo_remote_pid 2>/dev/null || kill -TERM $cargo_remote_pid; \
+         cargo_remote_waited=0; \
+         while [ $cargo_remote_waited -lt {grace} ] && kill -0 -$cargo_remote_pid 2>/dev/null; \
+         do sleep 1; cargo_remote_waited=$((cargo_remote_waited + 1)); done; \
+         kill -KILL -$cargo_remote_pid 2>/dev/null; exit 0",
+        pid_file = pid_file,
+        grace = GRACE_PERIOD.as_secs()
+    );
+    let processes = transport.remote_output(remote, host, &script)?;
+    warn!(
+        "Processes of the build at the timeout:\n{}",
+        processes.trim_end()
+    );
+    if let Some(parent) = processes_file.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    fs::write(processes_file, processes)?;
+    warn!("Saved the process list in {:?}.", processes_file);
+    Ok(())
+}
+
+/// Copies the `output` and `stderr` logs of the build scripts in the remote target dir of
+/// `build_path` into `remote-timeout-logs` in `target_dir`, keeping their paths, so a hanging
+/// build script can be looked into. Returns the local directory.
+pub fn fetch_logs(
+    transport: &Transport,
+    remote: &Remote,
+    host: &str,
+    build_path: &str,
+    target_dir: &Path,
+) -> io::Result<PathBuf> {
+    let target = format!("{}{}", build_path, crate::REMOTE_TARGET_DIR);
+    let collect = format!(
+        "cd {target} && rm -rf {logs} && mkdir {logs} && \
+         for log in */build/*/output */build/*/stderr */*/build/*/output */*/build/*/stderr; do \
+         if [ -s \"$log\" ]; then mkdir -p {logs}/\"$(dirname \"$log\")\" && \
+         cp \"$log\" {logs}/\"$log\"; fi; done",
+        target = target,
+        logs = LOGS_DIR
+    );
+    transport.remote_output(remote, host, &collect)?;
+    let destination = target_dir.join(LOGS_DIR);
+    fs::create_dir_all(&destination)?;
+    let status = sync::fetch(
+        transport,
+        remote,
+        host,
+        &format!("{}/{}/", target, LOGS_DIR),
+        &format!("{}/", destination.to_string_lossy()),
+        true,
+        "timeout",
+    )?;
+    if !status.success() {
+        return Err(io::Error::other(format!(
+            "the transfer of the logs failed ({})",
+            status
+        )));
+    }
+    Ok(destination)
+}
@@ -0,0 +1,155 @@
+use crate::config::Remote;
+use crate::script::{quote, word};
+
+/// Cargo subcommands provided by a plugin from the `cargo-<subcommand>` crate, installed
+/// automatically when a command needs them.
+const KNOWN_TOOLS: &[&str] = &[
+    "nextest",
+    "llvm-cov",
+    "expand",
+    "audit",
+    "deny",
+    "udeps",
+    "machete",
+    "outdated",
+    "hack",
+    "insta",
+    "tarpaulin",
+    "bloat",
+    "semver-checks",
+    "sort",
+];
+
+/// Cargo subcommands shipped as rustup components, with all components they need.
+const KNOWN_COMPONENTS: &[(&str, &[&str])] = &[("miri", &["miri", "rust-src"])];
+
+/// Local environment variables configuring a cargo subcommand, forwarded to the build whenever
+/// the subcommand runs.
+const KNOWN_VARIABLES: &[(&str, &str)] = &[("miri", "MIRIFLAGS")];
+
+/// A cargo plugin needed by one of the commands.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Tool {
+    /// Crate with the `cargo-<subcommand>` binary.
+    pub package: String,
+    /// Pinned version, any version if `None`.
+    pub version: Option<String>,
+}
+
+/// The plugins needed by `commands`, either known ones or ones listed in the `tools` of
+/// `remote`.
+pub fn required(remote: &Remote, commands: &[String]) -> Vec<Tool> {
+    let mut tools: Vec<Tool> = Vec::new();
+    for command in commands {
+        let subcommand = subcommand(command);
+        let version = remote.tools.get(subcommand);
+        if version.is_none() && !KNOWN_TOOLS.contains(&subcommand) {
+            continue;
+        }
+        let tool = Tool {
+            package: format!("cargo-{}", subcommand),
+            version: version.filter(|version| *version != "*").cloned(),
+        };
+        if !tools.contains(&tool) {
+            tools.push(tool);
+        }
+    }
+    tools
+}
+
+/// The subcommand of the cargo `command`, skipping a toolchain override like `+nightly`.
+fn subcommand(command: &str) -> &str {
+    command
+        .split_whitespace()
+        .find(|word| !word.starts_with('+'))
+        .unwrap_or_default()
+}
+
+/// The toolchain override of the cargo `command`, e.g. `nightly` for `+nightly miri test`.
+pub fn toolchain(command: &str) -> Option<&str> {
+    command.split_whitespace().next()?.strip_prefix('+')
+}
+
+/// The local environment variables read by the subcommands of `commands`.
+pub fn variables(commands: &[String]) -> Vec<String> {
+    let mut variables: Vec<String> = Vec::new();
+    for command in commands {
+        for (_, variable) in KNOWN_VARIABLES
+            .iter()
+            .filter(|(name, _)| *name == subcommand(command))
+        {
+            if !variables.iter().any(|known| known == variable) {
+                variables.push((*variable).to_owned());
+            }
+        }
+    }
+    variables
+}
+
+/// Script adding the rustup components needed by `commands`, e.g. miri, to the toolchain they
+/// run with.
+///
+/// Toolchains without rustup, like the ones of nixpkgs, are left alone, there the component
+/// has to come with the nix environment.
+pub fn component_script(commands: &[String]) -> String {
+    let mut script = String::new();
+    for command in commands {
+        let components = match KNOWN_COMPONENTS
+            .iter()
+            .find(|(name, _)| *name == subcommand(command))
+        {
+            Some((_, components)) => components,
+            None => continue,
+        };
+        let toolchain = match toolchain(command) {
+            Some(toolchain) => format!(" --toolchain {}", quote(toolchain)),
+            None => String::new(),
+        };
+        let step = format!(
+            "if command -v rustup >/dev/null 2>&1 && ! rustup component list --installed{toolchain} 2>/dev/null | grep -q {pattern}; then \
+             echo {message}; rustup component add{toolchain} {components}; fi; ",
+            toolchain = toolchain,
+            pattern = quote(&format!("^{}", components[0])),
+            message = quote(&format!("Installing {} on the remote.", components.join(", "))),
+            components = components.join(" ")
+        );
+        if !script.contains(&step) {
+            script.push_str(&step);
+        }
+    }
+    script
+}
+
+/// Script installing every one of `tools` with `cargo install` unless it is already there.
+///
+/// Pinned versions are compared with `cargo install --list`, other tools may also come from
+/// the `PATH`, e.g. the nix environment.
+pub fn install_script(tools: &[Tool]) -> String {
+    tools
+        .iter()
+        .map(|tool| {
+            let installed = match &tool.version {
+                Some(version) => format!(
+                    "cargo install --list | grep -q {}",
+                    quote(&format!("^{} v{}:", tool.package, version))
+                ),
+                None => format!(
+                    "command -v {package} >/dev/null 2>&1 || cargo install --list | grep -q {pattern}",
+                    package = word(&tool.package),
+                    pattern = quote(&format!("^{} v", tool.package))
+                ),
+            };
+            let version = match &tool.version {
+                Some(version) => format!(" --version {}", quote(version)),
+                None => String::new(),
+            };
+            format!(
+                "{{ {installed}; }} || {{ echo {message}; cargo install --locked {package}{version}; }}; ",
+                installed = installed,
+                message = quote(&format!("Installing {} on the remote.", tool.package)),
+                package = word(&tool.package),
+                version = version
+            )
+        })
+        .collect()
+}
@@ -0,0 +1,740 @@
+use std::collections::HashMap;
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use tracing::{error, info, info_span, warn};
+
+use crate::backend::{self, ExecOptions};
+use crate::ci::Ci;
+use crate::compat;
+use crate::config::{Link, Remote, Symlinks};
+use crate::executor::{self, Executor};
+use crate::hostkey;
+use crate::process::{Input, Output};
+use crate::summary::size;
+
+/// Connect timeout of the reachability check done before choosing one of several hosts.
+const PREFLIGHT_TIMEOUT_SECS: u64 = 10;
+
+/// rsync exit codes caused by a broken connection, transfers failing with them are resumed.
+/// 10: socket I/O, 12: protocol data stream, 30: data timeout, 35: daemon timeout,
+/// 255: ssh connection
+const RESUMABLE_EXIT_CODES: &[i32] = &[10, 12, 30, 35, 255];
+
+/// Whether `status` is that of an ssh or rsync connection that broke, see
+/// [`RESUMABLE_EXIT_CODES`].
+pub fn connection_lost(status: ExitStatus) -> bool {
+    status
+        .code()
+        .is_some_and(|code| RESUMABLE_EXIT_CODES.contains(&code))
+}
+
+/// Delay before an interrupted transfer is resumed.
+#[cfg(not(test))]
+const RESUME_DELAY: Duration = Duration::from_secs(2);
+#[cfg(test)]
+const RESUME_DELAY: Duration = Duration::ZERO;
+
+/// Where the output of rsync is passed through to, captured by the tests.
+#[cfg(not(test))]
+fn passed_through() -> io::Stdout {
+    io::stdout()
+}
+#[cfg(test)]
+fn passed_through() -> tests::PassedThrough {
+    tests::PassedThrough
+}
+
+/// Placeholder for anything in the transport logs that looks like a private key path.
+const REDACTED: &str = "<redacted>";
+
+/// Progress output of rsync 3.1 and newer, summarizing the whole transfer.
+const PROGRESS_FLAG: &str = "--info=progress2";
+/// Progress output supported by older rsync versions (e.g. the one shipped with macOS) and
+/// openrsync.
+const LEGACY_PROGRESS_FLAG: &str = "--progress";
+
+/// How often the combined progress of parallel transfers is redrawn.
+const PARALLEL_PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+/// First line of the `--stats` rsync prints after all other output.
+const STATS_MARKER: &[u8] = b"Number of files:";
+/// Lines of the `--stats` with the bytes rsync sent and received over the connection.
+const STATS_BYTES: &[&str] = &["Total bytes sent:", "Total bytes received:"];
+
+/// Settings shared by every ssh and rsync invocation.
+#[derive(Debug, Clone)]
+pub struct Transport {
+    /// Make ssh and rsync verbose and route their stderr into the trace log.
+    pub debug: bool,
+    /// Whether stdout is a terminal rsync can draw its progress on.
+    progress: bool,
+    /// Format the output for a CI system.
+    pub ci: Option<Ci>,
+    executor: Arc<dyn Executor>,
+    /// Bytes sent and received by all rsync transfers so far.
+    transferred: Arc<AtomicU64>,
+    /// Concurrent rsync processes the sources are uploaded with.
+    pub transfer_jobs: usize,
+    /// Compressor installed locally and on the remote that tar transfers over slow links are
+    /// piped through.
+    pub tar_compressor: Option<&'static str>,
+    /// Whether the key of a host could be pinned, by remote name and host.
+    pinned: Arc<Mutex<HashMap<(String, String), bool>>>,
+}
+
+impl Transport {
+    pub fn new(debug: bool) -> Self {
+        Self::with_executor(debug, Arc::new(executor::System { debug }))
+    }
+
+    /// A transport running its commands with `executor` instead of spawning them.
+    pub fn with_executor(debug: bool, executor: Arc<dyn Executor>) -> Self {
+        Transport {
+            debug,
+            progress: io::stdout().is_terminal(),
+            ci: None,
+            executor,
+            transferred: Arc::new(AtomicU64::new(0)),
+            transfer_jobs: 1,
+            tar_compressor: None,
+            pinned: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Whether the commands only pretend to run, see [`Executor::dry_run`].
+    pub fn dry_run(&self) -> bool {
+        self.executor.dry_run()
+    }
+
+    /// Pins the key of `host` to the `host_key_fingerprint` of `remote` with [`hostkey::pin`]
+    /// the first time the host is connected to, later calls return whether that worked. A
+    /// failure is reported once, ssh then refuses every connection to the host.
+    pub fn pin_host_key(&self, remote: &Remote, host: &str) -> bool {
+        if remote.host_key_fingerprint.is_none() || self.dry_run() {
+            return true;
+        }
+        let Ok(mut pinned) = self.pinned.lock() else {
+            return false;
+        };
+        if let Some(&outcome) = pinned.get(&(remote.name.clone(), host.to_owned())) {
+            return outcome;
+        }
+        if !pinned.keys().any(|(name, _)| *name == remote.name) {
+            let known_hosts = hostkey::known_hosts(remote);
+            crate::at_exit(move || {
+                let _ = fs::remove_file(known_hosts);
+            });
+        }
+        let outcome = match hostkey::pin(self, remote, host) {
+            Ok(()) => true,
+            Err(e) => {
+                error!("Refusing to connect to {} (error: {})", host, e);
+                false
+            }
+        };
+        pinned.insert((remote.name.clone(), host.to_owned()), outcome);
+        outcome
+    }
+
+    /// Pins the keys of all hosts of `remote` like [`Transport::pin_host_key`], before any
+    /// connection to them.
+    pub fn pin_host_keys(&self, remote: &Remote) {
+        for host in &remote.hosts {
+            self.pin_host_key(remote, host);
+        }
+    }
+
+    /// The same transport, but never showing transfer progress.
+    pub fn without_progress(&self) -> Self {
+        Transport {
+            progress: false,
+            ..self.clone()
+        }
+    }
+
+    /// Bytes sent and received by all rsync transfers of this transport (and its clones) so
+    /// far, the difference before and after a transfer is its size.
+    pub fn transferred_bytes(&self) -> u64 {
+        self.transferred.load(Ordering::Relaxed)
+    }
+
+    /// Progress flag the local rsync of `remote` understands, none in CI mode or without a
+    /// terminal to draw it on.
+    pub fn progress_flag(&self, remote: &Remote) -> Option<&'static str> {
+        if !self.progress || self.ci.is_some() {
+            None
+        } else if compat::supports_info_flag(&compat::local_rsync_version(&remote.rsync_path_local))
+        {
+            Some(PROGRESS_FLAG)
+        } else {
+            Some(LEGACY_PROGRESS_FLAG)
+        }
+    }
+
+    /// Progress flag for the commands of [`Transport::transfer_parallel`], which can only
+    /// combine the overall progress of rsync 3.1 and newer.
+    pub fn total_progress_flag(&self, remote: &Remote) -> Option<&'static str> {
+        self.progress_flag(remote)
+            .filter(|&flag| flag == PROGRESS_FLAG)
+    }
+
+    /// Command running the shell `script` as the build user on `host`, with the
+    /// [`backend::ExecBackend`] of `remote`.
+    pub fn remote_command(&self, remote: &Remote, host: &str, script: &str) -> Command {
+        backend::exec_backend(remote).command(self, remote, host, script)
+    }
+
+    /// Command running the build `script` on `host`, see [`backend::ExecBackend::build_command`].
+    pub fn remote_build_command(
+        &self,
+        remote: &Remote,
+        host: &str,
+        script: &str,
+        tty: bool,
+    ) -> Command {
+        backend::exec_backend(remote).build_command(self, remote, host, script, tty)
+    }
+
+    /// Command running `script` on `host` as is, with the `options` of the
+    /// [`backend::ExecBackend`] of `remote`.
+    pub fn remote_command_with(
+        &self,
+        remote: &Remote,
+        host: &str,
+        script: &str,
+        options: ExecOptions,
+    ) -> Command {
+        backend::exec_backend(remote).command_with(self, remote, host, script, options)
+    }
+
+    /// Runs the local `command` without stdin and returns its output, see [`Executor::output`].
+    pub fn output(&self, command: &mut Command, phase: &str) -> io::Result<std::process::Output> {
+        self.executor.output(command, phase)
+    }
+
+    /// Runs `command` on `host` non-interactively and returns its stdout.
+    ///
+    /// Fails if the command exits unsuccessfully, the error then contains its stderr.
+    pub fn remote_output(&self, remote: &Remote, host: &str, command: &str) -> io::Result<String> {
+        let mut ssh = self.remote_command(remote, host, command);
+        let output = self.executor.output(&mut ssh, "probe")?;
+        if !output.status.success() {
+            return Err(io::Error::other(format!(
+                "`{}` failed ({}): {}",
+                command,
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+    }
+
+    /// Picks the first host of `remote` that accepts ssh connections.
+    ///
+    /// Remotes with a single host are returned as is without probing them.
+    pub fn select_host(&self, remote: &Remote) -> Option<String> {
+        self.select_host_except(remote, &[])
+    }
+
+    /// Picks the first host of `remote` that accepts ssh connections and isn't one of
+    /// `failed`, for failing over after a host lost the connection.
+    pub fn select_host_except(&self, remote: &Remote, failed: &[String]) -> Option<String> {
+        if remote.hosts.len() <= 1 {
+            return (!failed.contains(&remote.host)).then(|| remote.host.clone());
+        }
+
+        let mut candidates = remote.hosts.iter().filter(|host| !failed.contains(host));
+        candidates.find_map(|host| {
+            info!("Checking if {} is reachable.", host);
+            let mut ssh = self.remote_command_with(
+                remote,
+                host,
+                "true",
+                ExecOptions {
+                    connect_timeout: Some(PREFLIGHT_TIMEOUT_SECS),
+                    ..ExecOptions::default()
+                },
+            );
+            ssh.stdout(Stdio::null());
+            match self
+                .executor
+                .run(&mut ssh, "preflight", Input::Inherit, Output::Inherit)
+            {
+                Ok(status) if status.success() => Some(host.clone()),
+                Ok(status) => {
+                    warn!(
+                        "{} is not reachable ({}), trying the next host.",
+                        host, status
+                    );
+                    None
+                }
+                Err(e) => {
+                    warn!("{} is not reachable ({}), trying the next host.", host, e);
+                    None
+                }
+            }
+        })
+    }
+
+    /// A `rsync` command with the flags shared by all transfers, to be run with
+    /// [`Transport::transfer`].
+    ///
+    /// With `delete` files missing on the sending side are removed from the receiving side.
+    pub fn rsync(&self, remote: &Remote, delete: bool) -> Command {
+        let mut rsync = self.rsync_command(remote, delete);
+        rsync
+            .arg("-e")
+            .arg(backend::exec_backend(remote).remote_shell(self, remote));
+        rsync
+    }
+
+    /// Like [`Transport::rsync`], but talking to the rsync daemon of `remote` instead of
+    /// starting rsync over ssh, for paths from [`Transport::rsyncd_url`].
+    pub fn rsyncd(&self, remote: &Remote, delete: bool) -> Command {
+        let mut rsync = self.rsync_command(remote, delete);
+        rsync.arg(format!("--port={}", remote.rsyncd_port));
+        if let Some(password_file) = &remote.rsyncd_password_file {
+            rsync.arg(format!("--password-file={}", password_file));
+        }
+        rsync
+    }
+
+    /// `rsync://` URL of `path` on `host` in the daemon module of `remote`, `None` if `path`
+    /// isn't in the `temp_dir` the module points at.
+    pub fn rsyncd_url(&self, remote: &Remote, host: &str, path: &str) -> Option<String> {
+        let module = remote.rsyncd_module.as_deref()?;
+        let relative = path
+            .strip_prefix(remote.temp_dir.trim_end_matches('/'))?
+            .strip_prefix('/')?;
+        // the user of the ssh destination isn't necessarily known to the daemon
+        let address = host.rsplit_once('@').map_or(host, |(_, address)| address);
+        let address = if address.contains(':') && !address.starts_with('[') {
+            format!("[{}]", address)
+        } else {
+            address.to_owned()
+        };
+        let user = remote
+            .rsyncd_user
+            .as_ref()
+            .map(|user| format!("{}@", user))
+            .unwrap_or_default();
+        Some(format!(
+            "rsync://{}{}/{}/{}",
+            user, address, module, relative
+        ))
+    }
+
+    fn rsync_command(&self, remote: &Remote, delete: bool) -> Command {
+        let mut rsync = Command::new(&remote.rsync_path_local);
+        // read by the transfer to count the bytes, not shown
+        rsync.arg("-a").arg("--stats");
+        if self.debug {
+            rsync.arg("-vv");
+        }
+        if delete {
+            rsync.arg("--delete");
+        }
+        if remote.checksum {
+            rsync.arg("--checksum");
+        }
+        if !remote.partial_dir.is_empty() {
+            rsync
+                .arg("--partial")
+                .arg(format!("--partial-dir={}", remote.partial_dir));
+        }
+        match remote.symlinks {
+            Symlinks::Preserve => {}
+            Symlinks::Copy => {
+                rsync.arg("--copy-links");
+            }
+            Symlinks::CopyUnsafe => {
+                rsync.arg("--copy-unsafe-links");
+            }
+        }
+        if !remote.preserve_permissions {
+            rsync.arg("--no-perms");
+        }
+        if !remote.preserve_owner {
+            rsync.arg("--no-owner").arg("--no-group");
+        }
+        if let Some(chmod) = &remote.chmod {
+            rsync.arg(format!("--chmod={}", chmod));
+        }
+        // rsync 3.2 and newer negotiate the compression, preferring zstd
+        match remote.link {
+            Link::Lan => rsync.arg("--whole-file"),
+            Link::Wan | Link::Auto => rsync.arg("--compress"),
+        };
+        rsync
+    }
+
+    /// Runs `command` with inherited stdio and waits for it to finish, inside a span for
+    /// `phase` which logs its duration.
+    ///
+    /// In debug mode stderr is captured instead and every line is written to the trace log,
+    /// tagged with `phase` and with key paths redacted.
+    pub fn run(&self, command: &mut Command, phase: &str) -> io::Result<ExitStatus> {
+        self.run_with(command, phase, Input::Inherit, Output::Inherit)
+    }
+
+    /// Like [`Transport::run`], but feeds `stdin` to `command` and hands its stdout to `stdout`.
+    pub fn run_with(
+        &self,
+        command: &mut Command,
+        phase: &str,
+        stdin: Input,
+        stdout: Output,
+    ) -> io::Result<ExitStatus> {
+        let _span = info_span!("phase", name = %phase).entered();
+        self.in_group(phase, || self.executor.run(command, phase, stdin, stdout))
+    }
+
+    /// Runs the rsync `command` like [`Transport::run`] and resumes it up to
+    /// `remote.transfer_retries` times if the connection broke.
+    pub fn transfer(
+        &self,
+        remote: &Remote,
+        command: &mut Command,
+        phase: &str,
+    ) -> io::Result<ExitStatus> {
+        let _span = info_span!("phase", name = %phase).entered();
+        self.in_group(phase, || {
+            self.resume(remote, command, phase, || self.count_stats())
+        })
+    }
+
+    /// Runs the rsync `commands` at the same time, each like [`Transport::transfer`], and
+    /// draws one line with their combined progress instead of theirs. Returns the first
+    /// failure, or success if all of them succeeded.
+    pub fn transfer_parallel(
+        &self,
+        remote: &Remote,
+        commands: Vec<Command>,
+        phase: &str,
+    ) -> io::Result<ExitStatus> {
+        let _span = info_span!("phase", name = %phase).entered();
+        let progress: Vec<Arc<AtomicU64>> = commands
+            .iter()
+            .map(|_| Arc::new(AtomicU64::new(0)))
+            .collect();
+        let done = AtomicBool::new(false);
+        let jobs = commands.len();
+        self.in_group(phase, || {
+            thread::scope(|scope| {
+                if self.progress && self.ci.is_none() {
+                    scope.spawn(|| {
+                        while !done.load(Ordering::Relaxed) {
+                            let bytes: u64 =
+                                progress.iter().map(|p| p.load(Ordering::Relaxed)).sum();
+                            print!("\r{:>12} by {} transfers", size(bytes), jobs);
+                            let _ = io::stdout().flush();
+                            thread::sleep(PARALLEL_PROGRESS_INTERVAL);
+                        }
+                        println!();
+                    });
+                }
+                let handles: Vec<_> = commands
+                    .into_iter()
+                    .zip(&progress)
+                    .map(|(mut command, progress)| {
+                        scope.spawn(move || {
+                            self.resume(remote, &mut command, phase, || {
+                                self.count_progress(progress)
+                            })
+                        })
+                    })
+                    .collect();
+                let statuses: Vec<io::Result<ExitStatus>> = handles
+                    .into_iter()
+                    .map(|handle| {
+                        handle
+                            .join()
+                            .unwrap_or_else(|_| Err(io::Error::other("transfer thread panicked")))
+                    })
+                    .collect();
+                done.store(true, Ordering::Relaxed);
+                let mut first_failure = None;
+                let mut success = None;
+                for status in statuses {
+                    match status? {
+                        status if status.success() => success = success.or(Some(status)),
+                        status => first_failure = first_failure.or(Some(status)),
+                    }
+                }
+                first_failure
+                    .or(success)
+                    .ok_or_else(|| io::Error::other("no transfers to run"))
+            })
+        })
+    }
+
+    /// Opens a collapsible log group for `phase` around `run` in CI mode.
+    fn in_group<T>(&self, phase: &str, run: impl FnOnce() -> T) -> T {
+        if let Some(ci) = self.ci {
+            ci.start_group(phase);
+        }
+        let result = run();
+        if let Some(ci) = self.ci {
+            ci.end_group(phase);
+        }
+        result
+    }
+
+    /// Runs `command` again after connection failures, see [`Transport::transfer`].
+    fn resume(
+        &self,
+        remote: &Remote,
+        command: &mut Command,
+        phase: &str,
+        stdout: impl Fn() -> Output,
+    ) -> io::Result<ExitStatus> {
+        let mut attempt = 0;
+        loop {
+            let status = self
+                .executor
+                .run(command, phase, Input::Inherit, stdout())?;
+            if !connection_lost(status) || attempt >= remote.transfer_retries {
+                return Ok(status);
+            }
+            attempt += 1;
+            warn!(
+                "Transfer interrupted ({}), resuming ({}/{}).",
+                status, attempt, remote.transfer_retries
+            );
+            thread::sleep(RESUME_DELAY);
+        }
+    }
+
+    /// Keeps the output of rsync from stdout, storing the bytes of its `--info=progress2`
+    /// lines in `progress` and adding the bytes of its `--stats` to
+    /// [`Transport::transferred_bytes`].
+    fn count_progress(&self, progress: &Arc<AtomicU64>) -> Output {
+        let transferred = Arc::clone(&self.transferred);
+        let progress = Arc::clone(progress);
+        let mut pending = Vec::new();
+        Output::Chunks(Box::new(move |chunk| {
+            for &byte in chunk {
+                if byte != b'\n' && byte != b'\r' {
+                    pending.push(byte);
+                    continue;
+                }
+                let line = String::from_utf8_lossy(&pending);
+                let digits = |text: &str| -> Option<u64> {
+                    text.chars()
+                        .filter(char::is_ascii_digit)
+                        .collect::<String>()
+                        .parse()
+                        .ok()
+                };
+                if STATS_BYTES.iter().any(|prefix| line.starts_with(prefix)) {
+                    transferred.fetch_add(digits(&line).unwrap_or(0), Ordering::Relaxed);
+                } else if let Some(bytes) = line
+                    .split_whitespace()
+                    .next()
+                    .filter(|_| line.contains('%'))
+                    .and_then(digits)
+                {
+                    progress.store(bytes, Ordering::Relaxed);
+                }
+                pending.clear();
+            }
+            Ok(())
+        }))
+    }
+
+    /// Passes the output of rsync through to stdout, except for its `--stats`, from which the
+    /// bytes sent and received are added to [`Transport::transferred_bytes`].
+    fn count_stats(&self) -> Output {
+        let transferred = Arc::clone(&self.transferred);
+        // output after the last line break that wasn't written yet
+        let mut pending = Vec::new();
+        let mut line_start = true;
+        let mut in_stats = false;
+        Output::Chunks(Box::new(move |chunk| {
+            let mut stdout = passed_through();
+            for &byte in chunk {
+                pending.push(byte);
+                let line_end = byte == b'\n' || byte == b'\r';
+                if in_stats {
+                    if line_end {
+                        let line = String::from_utf8_lossy(&pending);
+                        if STATS_BYTES.iter().any(|prefix| line.starts_with(prefix)) {
+                            let digits: String =
+                                line.chars().filter(char::is_ascii_digit).collect();
+                            let bytes = digits.parse().unwrap_or(0);
+                            transferred.fetch_add(bytes, Ordering::Relaxed);
+                        }
+                        pending.clear();
+                    }
+                    continue;
+                }
+                // the stats are preceded by an empty line, which is kept back with them
+                let line = pending.strip_prefix(b"\n").unwrap_or(&pending);
+                if line_start && line == STATS_MARKER {
+                    in_stats = true;
+                } else if line_end && pending != b"\n" {
+                    stdout.write_all(&pending)?;
+                    pending.clear();
+                    line_start = true;
+                }
+            }
+            // progress updates are shown right away, only a possible start of the stats waits
+            let line = pending.strip_prefix(b"\n").unwrap_or(&pending);
+            let held_back = in_stats || (line_start && STATS_MARKER.starts_with(line));
+            if !held_back {
+                stdout.write_all(&pending)?;
+                pending.clear();
+                line_start = false;
+            }
+            stdout.flush()
+        }))
+    }
+}
+
+/// Replaces every word that looks like the path of a private key or ssh identity.
+pub fn redact_key_paths(line: &str) -> String {
+    line.split(' ')
+        .map(|word| if is_key_path(word) { REDACTED } else { word })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+fn is_key_path(word: &str) -> bool {
+    let word = word.trim_matches(|c| c == '"' || c == '\'' || c == ',');
+    let file_name = word.rsplit('/').next().unwrap_or(word);
+    word.contains('/')
+        && (word.contains(".ssh/")
+            || file_name.starts_with("id_")
+            || file_name.ends_with(".pem")
+            || file_name.ends_with(".key"))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+
+    use super::*;
+    use crate::executor::Scripted;
+
+    thread_local! {
+        /// What [`passed_through`] got on this thread.
+        static PASSED_THROUGH: RefCell<Vec<u8>> = const { RefCell::new(Vec::new()) };
+    }
+
+    pub struct PassedThrough;
+
+    impl Write for PassedThrough {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            PASSED_THROUGH.with(|passed_through| passed_through.borrow_mut().extend(buf));
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn transport(executor: &Arc<Scripted>) -> Transport {
+        Transport::with_executor(false, Arc::clone(executor) as Arc<dyn Executor>)
+    }
+
+    fn remote(hosts: &[&str]) -> Remote {
+        Remote {
+            host: hosts[0].to_owned(),
+            hosts: hosts.iter().map(|host| (*host).to_owned()).collect(),
+            transfer_retries: 2,
+            ..Remote::default()
+        }
+    }
+
+    #[test]
+    fn transfer_resumes_after_connection_errors() {
+        let executor = Arc::new(Scripted::new([(255, ""), (12, ""), (0, "")]));
+        let status = transport(&executor)
+            .transfer(&remote(&["a"]), &mut Command::new("rsync"), "sync")
+            .unwrap();
+        assert!(status.success());
+        assert_eq!(executor.commands().len(), 3);
+    }
+
+    #[test]
+    fn transfer_gives_up_after_the_retries() {
+        let executor = Arc::new(Scripted::new([(30, ""), (30, ""), (30, ""), (0, "")]));
+        let status = transport(&executor)
+            .transfer(&remote(&["a"]), &mut Command::new("rsync"), "sync")
+            .unwrap();
+        assert_eq!(status.code(), Some(30));
+        assert_eq!(executor.commands().len(), 3);
+    }
+
+    #[test]
+    fn transfer_doesnt_resume_other_failures() {
+        let executor = Arc::new(Scripted::new([(23, ""), (0, "")]));
+        let status = transport(&executor)
+            .transfer(&remote(&["a"]), &mut Command::new("rsync"), "sync")
+            .unwrap();
+        assert_eq!(status.code(), Some(23));
+        assert_eq!(executor.commands().len(), 1);
+    }
+
+    #[test]
+    fn transfer_counts_the_stats() {
+        let stats = "sent\n\nNumber of files: 3\nTotal bytes sent: 1,234\n\
+                     Total bytes received: 56\n";
+        let executor = Arc::new(Scripted::new([(0, stats)]));
+        let transport = transport(&executor);
+        transport
+            .transfer(&remote(&["a"]), &mut Command::new("rsync"), "sync")
+            .unwrap();
+        assert_eq!(transport.transferred_bytes(), 1290);
+        let passed_through = PASSED_THROUGH.with(|passed_through| passed_through.take());
+        assert_eq!(passed_through, b"sent\n");
+    }
+
+    #[test]
+    fn select_host_doesnt_probe_a_single_host() {
+        let executor = Arc::new(Scripted::new([(255, "")]));
+        let host = transport(&executor).select_host(&remote(&["a"]));
+        assert_eq!(host.as_deref(), Some("a"));
+        assert!(executor.commands().is_empty());
+    }
+
+    #[test]
+    fn select_host_skips_unreachable_hosts() {
+        let executor = Arc::new(Scripted::new([(255, ""), (0, "")]));
+        let host = transport(&executor).select_host(&remote(&["a", "b", "c"]));
+        assert_eq!(host.as_deref(), Some("b"));
+        let probed: Vec<_> = executor
+            .commands()
+            .iter()
+            .map(|command| command[command.len() - 2].clone())
+            .collect();
+        assert_eq!(probed, ["a", "b"]);
+    }
+
+    #[test]
+    fn select_host_except_skips_failed_hosts() {
+        let executor = Arc::new(Scripted::new([(0, "")]));
+        let transport = transport(&executor);
+        let remote = remote(&["a", "b"]);
+        let host = transport.select_host_except(&remote, &["a".to_owned()]);
+        assert_eq!(host.as_deref(), Some("b"));
+        assert_eq!(executor.commands().len(), 1);
+        let failed = ["a".to_owned(), "b".to_owned()];
+        assert_eq!(transport.select_host_except(&remote, &failed), None);
+        let single = self::remote(&["a"]);
+        assert_eq!(transport.select_host_except(&single, &failed[..1]), None);
+    }
+
+    #[test]
+    fn select_host_fails_without_reachable_hosts() {
+        let executor = Arc::new(Scripted::new([(255, ""), (255, "")]));
+        assert_eq!(transport(&executor).select_host(&remote(&["a", "b"])), None);
+    }
+}
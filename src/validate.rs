@@ -0,0 +1,149 @@
+use std::fs;
+use std::path::Path;
+
+use crate::config::{Config, CONFIG_KEYS, DEPLOY_KEYS, PROFILE_KEYS, PROJECT_KEYS, REMOTE_KEYS};
+
+/// A problem found in a config file.
+#[derive(Debug)]
+pub struct Problem {
+    pub file: String,
+    /// 1-based line, if it could be determined.
+    pub line: Option<usize>,
+    pub message: String,
+}
+
+impl std::fmt::Display for Problem {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}: {}", self.file, line, self.message),
+            None => write!(f, "{}: {}", self.file, self.message),
+        }
+    }
+}
+
+/// Checks every config file on its own for syntax errors, type errors, missing fields and
+/// unknown keys.
+pub fn validate_files(project_dir: &Path) -> Vec<Problem> {
+    Config::files(project_dir)
+        .iter()
+        .flat_map(|file| validate_file(file))
+        .collect()
+}
+
+fn validate_file(path: &Path) -> Vec<Problem> {
+    let file = path.to_string_lossy().into_owned();
+    let problem = |line, message| Problem {
+        file: file.clone(),
+        line,
+        message,
+    };
+
+    let contents = match fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) => return vec![problem(None, e.to_string())],
+    };
+    let value: toml::Value = match toml::from_str(&contents) {
+        Ok(value) => value,
+        Err(e) => {
+            return vec![problem(
+                e.line_col().map(|(line, _)| line + 1),
+                e.to_string(),
+            )]
+        }
+    };
+
+    let mut problems = Vec::new();
+    if let Err(e) = toml::from_str::<Config>(&contents) {
+        problems.push(problem(
+            e.line_col().map(|(line, _)| line + 1),
+            e.to_string(),
+        ));
+    }
+    problems.extend(unknown_keys_in(&file, &contents, &value));
+    problems
+}
+
+/// The unknown keys in all config files, which are ignored when the config is loaded, e.g.
+/// because of a typo. Files that can't be parsed are left to [`validate_files`].
+pub fn unknown_keys(project_dir: &Path) -> Vec<Problem> {
+    Config::files(project_dir)
+        .iter()
+        .filter_map(|path| {
+            let contents = fs::read_to_string(path).ok()?;
+            let value: toml::Value = toml::from_str(&contents).ok()?;
+            Some(unknown_keys_in(&path.to_string_lossy(), &contents, &value))
+        })
+        .flatten()
+        .collect()
+}
+
+fn unknown_keys_in(file: &str, contents: &str, value: &toml::Value) -> Vec<Problem> {
+    let mut problems = Vec::new();
+    let mut unknown = |section: &str, key: &str| {
+        problems.push(Problem {
+            file: file.to_owned(),
+            line: find_key(contents, key),
+            message: format!("unknown key `{}` in {}", key, section),
+        })
+    };
+    if let Some(table) = value.as_table() {
+        for (key, value) in table {
+            if !CONFIG_KEYS.contains(&key.as_str()) {
+                unknown("the top level", key);
+            }
+            match (key.as_str(), value) {
+                ("remote", toml::Value::Array(remotes)) => {
+                    for remote in remotes.iter().filter_map(toml::Value::as_table) {
+                        for key in remote.keys() {
+                            if !REMOTE_KEYS.contains(&key.as_str()) {
+                                unknown("[[remote]]", key);
+                            }
+                        }
+                    }
+                }
+                ("projects", toml::Value::Table(projects)) => {
+                    for (name, project) in projects {
+                        for key in project.as_table().into_iter().flat_map(|p| p.keys()) {
+                            if !PROJECT_KEYS.contains(&key.as_str()) {
+                                unknown(&format!("[projects.{}]", name), key);
+                            }
+                        }
+                    }
+                }
+                ("profiles", toml::Value::Table(profiles)) => {
+                    for (name, profile) in profiles {
+                        for key in profile.as_table().into_iter().flat_map(|p| p.keys()) {
+                            if !PROFILE_KEYS.contains(&key.as_str()) {
+                                unknown(&format!("[profiles.{}]", name), key);
+                            }
+                        }
+                    }
+                }
+                ("deploy", toml::Value::Table(targets)) => {
+                    for (name, target) in targets {
+                        for key in target.as_table().into_iter().flat_map(|t| t.keys()) {
+                            if !DEPLOY_KEYS.contains(&key.as_str()) {
+                                unknown(&format!("[deploy.{}]", name), key);
+                            }
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+    problems
+}
+
+/// Line of the first assignment to `key`, toml values don't keep track of their position.
+fn find_key(contents: &str, key: &str) -> Option<usize> {
+    contents
+        .lines()
+        .position(|line| {
+            line.trim_start()
+                .strip_prefix(key)
+                .map(|rest| rest.trim_start().starts_with('='))
+                .unwrap_or(false)
+        })
+        .map(|index| index + 1)
+}
@@ -0,0 +1,105 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+
+use tracing::info;
+
+use crate::backend;
+use crate::config::Remote;
+use crate::tools;
+use crate::transport::Transport;
+
+/// Directory in the build directory the vendored dependencies are uploaded to. Excluded from
+/// the upload of the sources, so it isn't deleted by it.
+pub const REMOTE_DIR: &str = ".cargo-remote-vendor";
+
+/// Directory in the local target dir `cargo vendor` writes to.
+const LOCAL_DIR: &str = "remote-vendor";
+
+/// Cargo config replacing the sources with the vendored ones, written next to the vendored
+/// crates.
+const CONFIG_FILE: &str = "config.toml";
+
+/// Vendors the dependencies of `project_dir` into `target_dir` with the local cargo, unless
+/// they were vendored after the last change of the Cargo.lock. Returns the vendor directory.
+pub fn prepare(
+    transport: &Transport,
+    project_dir: &Path,
+    target_dir: &Path,
+) -> io::Result<PathBuf> {
+    let vendor_dir = target_dir.join(LOCAL_DIR);
+    let config = vendor_dir.join(CONFIG_FILE);
+    let modified = |path: &Path| {
+        fs::metadata(path)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+    };
+    if let (Some(vendored), Some(locked)) =
+        (modified(&config), modified(&project_dir.join("Cargo.lock")))
+    {
+        if vendored >= locked {
+            info!("Reusing the vendored dependencies in {:?}.", vendor_dir);
+            return Ok(vendor_dir);
+        }
+    }
+    info!("Vendoring the dependencies into {:?}.", vendor_dir);
+    let mut cargo = Command::new("cargo");
+    cargo
+        .current_dir(project_dir)
+        .arg("vendor")
+        .arg("--respect-source-config")
+        .arg(&vendor_dir);
+    let output = transport.output(&mut cargo, "vendor")?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "cargo vendor failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    // cargo vendor prints the config using the vendored sources, with the local directory
+    let source_config = String::from_utf8_lossy(&output.stdout).replace(
+        &format!("{:?}", vendor_dir.to_string_lossy()),
+        &format!("{:?}", REMOTE_DIR),
+    );
+    fs::create_dir_all(&vendor_dir)?;
+    fs::write(config, source_config)?;
+    Ok(vendor_dir)
+}
+
+/// Uploads the `vendor_dir` of [`prepare`] into [`REMOTE_DIR`] of `build_path`, with the sync
+/// backend of `remote` like the sources.
+pub fn upload(
+    transport: &Transport,
+    remote: &Remote,
+    host: &str,
+    vendor_dir: &Path,
+    build_path: &str,
+) -> io::Result<ExitStatus> {
+    backend::sync_backend(remote).upload(
+        transport,
+        remote,
+        host,
+        vendor_dir,
+        &format!("{}{}/", build_path, REMOTE_DIR),
+        &[],
+        false,
+    )
+}
+
+/// `commands` building offline with the vendored sources. The options go before the
+/// subcommand, after the toolchain if there is one.
+pub fn rewrite(commands: Vec<String>) -> Vec<String> {
+    let options = format!("--offline --config {}/{}", REMOTE_DIR, CONFIG_FILE);
+    commands
+        .into_iter()
+        .map(|command| match tools::toolchain(&command) {
+            Some(_) => match command.split_once(' ') {
+                Some((toolchain, rest)) => format!("{} {} {}", toolchain, options, rest),
+                None => format!("{} {}", command, options),
+            },
+            None => format!("{} {}", options, command),
+        })
+        .collect()
+}
@@ -0,0 +1,71 @@
+use clap::ValueEnum;
+
+use crate::script::quote;
+
+/// Directory in the target dir the JavaScript package is written to and copied back from.
+pub const PKG_DIR: &str = "pkg";
+
+const WASM_TRIPLE: &str = "wasm32-unknown-unknown";
+
+/// Kind of JavaScript module generated by wasm-bindgen.
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Target {
+    /// ES module loaded directly by the browser
+    Web,
+    /// ES module for bundlers like webpack
+    Bundler,
+    /// CommonJS module for Node.js
+    Nodejs,
+    /// Script without modules defining a global
+    NoModules,
+}
+
+impl Target {
+    fn name(self) -> &'static str {
+        match self {
+            Target::Web => "web",
+            Target::Bundler => "bundler",
+            Target::Nodejs => "nodejs",
+            Target::NoModules => "no-modules",
+        }
+    }
+}
+
+/// Cargo command building the library for wasm and generating the bindings of every wasm file
+/// for `target` in [`PKG_DIR`] of the remote target dir. `args` are passed on to cargo.
+///
+/// The bindings only run if the build succeeded, both share the exit code of the command.
+pub fn commands(target: Target, dev: bool, args: &str) -> Vec<String> {
+    let (profile, profile_dir) = if dev {
+        ("", "debug")
+    } else {
+        ("--release ", "release")
+    };
+    vec![format!(
+        "build --lib --target {triple} {profile}{args} && \
+         ( for wasm in \"$CARGO_TARGET_DIR\"/{triple}/{profile_dir}/*.wasm; do \
+         wasm-bindgen --target {target} --out-dir \"$CARGO_TARGET_DIR\"/{pkg} \"$wasm\" || exit 1; done )",
+        triple = WASM_TRIPLE,
+        profile = profile,
+        args = args,
+        profile_dir = profile_dir,
+        target = target.name(),
+        pkg = PKG_DIR
+    )]
+}
+
+/// Script adding the wasm target to a rustup toolchain and installing the wasm-bindgen CLI in
+/// the version of the `wasm-bindgen` crate in Cargo.lock, which the bindings have to match.
+pub fn setup_script() -> String {
+    format!(
+        "if command -v rustup >/dev/null 2>&1 && ! rustup target list --installed | grep -q {triple}; then \
+         rustup target add {triple}; fi; \
+         cargo_remote_bindgen=$(cargo pkgid wasm-bindgen 2>/dev/null | sed 's/.*[@#:]//'); \
+         if [ -n \"$cargo_remote_bindgen\" ] && \
+         [ \"$(wasm-bindgen --version 2>/dev/null)\" != \"wasm-bindgen $cargo_remote_bindgen\" ]; then \
+         echo {message}\"$cargo_remote_bindgen on the remote.\"; \
+         cargo install --locked wasm-bindgen-cli --version \"$cargo_remote_bindgen\"; fi; ",
+        triple = quote(WASM_TRIPLE),
+        message = quote("Installing wasm-bindgen-cli "),
+    )
+}
@@ -0,0 +1,118 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use tracing::{info, warn};
+
+use crate::config::Remote;
+use crate::sync;
+use crate::transport::Transport;
+
+/// How often the project is checked for changes.
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Modification time and size of every file of the project.
+type Fingerprint = BTreeMap<PathBuf, (Option<SystemTime>, u64)>;
+
+/// Whether `path` (relative to the project) is matched by one of the rsync `excludes`.
+///
+/// Only understands the patterns used for uploads: anchored paths, plain names, `.*` and
+/// `*.suffix`, so anything fancier is watched (and possibly synced for nothing).
+fn is_excluded(path: &Path, excludes: &[String]) -> bool {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy())
+        .unwrap_or_default();
+    excludes.iter().any(|exclude| {
+        if let Some(anchored) = exclude.strip_prefix('/') {
+            path == Path::new(anchored)
+        } else if let Some(suffix) = exclude.strip_prefix('*') {
+            name.ends_with(suffix)
+        } else {
+            *exclude == name
+        }
+    })
+}
+
+fn fingerprint(project_dir: &Path, excludes: &[String]) -> Fingerprint {
+    fn walk(project_dir: &Path, dir: &Path, excludes: &[String], files: &mut Fingerprint) {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let relative = path.strip_prefix(project_dir).unwrap_or(&path);
+            if is_excluded(relative, excludes) {
+                continue;
+            }
+            match entry.metadata() {
+                Ok(metadata) if metadata.is_dir() => walk(project_dir, &path, excludes, files),
+                Ok(metadata) => {
+                    files.insert(path, (metadata.modified().ok(), metadata.len()));
+                }
+                Err(_) => {}
+            }
+        }
+    }
+
+    let mut files = Fingerprint::new();
+    walk(project_dir, project_dir, excludes, &mut files);
+    files
+}
+
+/// Keeps the remote copy of the project up to date while it is edited.
+///
+/// Changes are synced once the project was left alone for `idle`, so a build started later
+/// finds the sources already on the server and only has to transfer what changed since.
+/// Files changed during a sync are picked up by the next one, so interrupting it at any time
+/// is safe. Runs until the process is killed.
+pub fn run(
+    transport: &Transport,
+    remote: &Remote,
+    host: &str,
+    project_dir: &Path,
+    build_path: &str,
+    excludes: &[String],
+    idle: Duration,
+) -> ! {
+    let mut synced = Fingerprint::new();
+    let mut current = fingerprint(project_dir, excludes);
+    let mut last_change = SystemTime::UNIX_EPOCH;
+    info!("Watching {:?} for changes.", project_dir);
+    loop {
+        let idle_for = last_change.elapsed().unwrap_or_default();
+        if current != synced && idle_for >= idle {
+            let snapshot = current.clone();
+            info!("Pre-syncing changes to {}.", host);
+            match sync::upload(
+                transport,
+                remote,
+                host,
+                project_dir,
+                build_path,
+                excludes,
+                false,
+            ) {
+                Ok(status) if status.success() => synced = snapshot,
+                Ok(status) => {
+                    warn!("Pre-sync failed ({}), trying again later.", status);
+                    last_change = SystemTime::now();
+                }
+                Err(e) => {
+                    warn!("Pre-sync failed ({}), trying again later.", e);
+                    last_change = SystemTime::now();
+                }
+            }
+        }
+
+        thread::sleep(POLL_INTERVAL);
+        let latest = fingerprint(project_dir, excludes);
+        if latest != current {
+            current = latest;
+            last_change = SystemTime::now();
+        }
+    }
+}
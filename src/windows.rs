@@ -0,0 +1,208 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+use crate::config::Remote;
+
+/// Quotes `value` as a PowerShell string without expansions.
+pub fn quote(value: &str) -> String {
+    format!("'{}'", value.replace('\'', "''"))
+}
+
+/// A remote path for PowerShell. A leading `~` is the home directory of the ssh user, like in
+/// the POSIX shell.
+fn path(path: &str) -> String {
+    match path.strip_prefix('~') {
+        Some(rest) => format!("(Join-Path $HOME {})", quote(rest.trim_start_matches('/'))),
+        None => quote(path),
+    }
+}
+
+/// A remote path for cmd.exe, with backslashes and `%USERPROFILE%` for a leading `~`. Trailing
+/// separators are removed, a backslash before the closing quote would escape it.
+fn cmd_path(path: &str) -> String {
+    let path = match path.strip_prefix('~') {
+        Some(rest) => format!("%USERPROFILE%{}", rest),
+        None => path.to_owned(),
+    };
+    format!("\"{}\"", path.replace('/', "\\").trim_end_matches('\\'))
+}
+
+/// Command line running the PowerShell `script` from cmd.exe, the default shell of OpenSSH on
+/// Windows. The script is passed encoded, so it needs no quoting for cmd.exe.
+pub fn powershell(script: &str, interactive: bool) -> String {
+    let utf16: Vec<u8> = script.encode_utf16().flat_map(u16::to_le_bytes).collect();
+    format!(
+        "powershell -NoLogo{} -EncodedCommand {}",
+        if interactive {
+            " -NoExit"
+        } else {
+            " -NoProfile -NonInteractive"
+        },
+        STANDARD.encode(utf16)
+    )
+}
+
+/// The words of a cargo command joined for the POSIX shell by [`crate::script::join_args`].
+fn words(command: &str) -> Vec<String> {
+    let mut words = Vec::new();
+    let mut word = String::new();
+    let mut in_word = false;
+    let mut chars = command.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\'' => {
+                in_word = true;
+                for quoted in chars.by_ref() {
+                    if quoted == '\'' {
+                        break;
+                    }
+                    word.push(quoted);
+                }
+            }
+            '\\' => {
+                in_word = true;
+                word.extend(chars.next());
+            }
+            ' ' => {
+                if in_word {
+                    words.push(std::mem::take(&mut word));
+                    in_word = false;
+                }
+            }
+            c => {
+                in_word = true;
+                word.push(c);
+            }
+        }
+    }
+    if in_word {
+        words.push(word);
+    }
+    words
+}
+
+/// The command executed over ssh to build the project in `build_path` on a Windows remote, the
+/// counterpart of [`crate::script::build_command`]. Every command runs even if one fails, the
+/// exit code is the one of the last failed command. Without `commands` an interactive
+/// PowerShell is opened in the build directory.
+pub fn build_command(remote: &Remote, build_path: &str, commands: &[String]) -> String {
+    let mut script = vec![
+        format!(
+            "Set-Location -LiteralPath {} -ErrorAction Stop",
+            path(build_path)
+        ),
+        format!(
+            "$env:CARGO_TARGET_DIR = {}",
+            path(&format!("{}{}", build_path, crate::REMOTE_TARGET_DIR))
+        ),
+    ];
+    if remote.env != Remote::default().env {
+        script.push(format!(". {}", path(&remote.env)));
+    }
+    if let Some(jobs) = remote.jobs {
+        script.push(format!("$env:CARGO_BUILD_JOBS = '{}'", jobs));
+    }
+    if !remote.rustflags.is_empty() {
+        script.push(format!(
+            "$env:CARGO_BUILD_RUSTFLAGS = {}",
+            quote(&remote.rustflags.join(" "))
+        ));
+    }
+    if let Some(linker) = &remote.linker {
+        script.push(format!(
+            "$cargo_remote_host = ((rustc -vV) -match '^host: ') -replace '^host: ', ''; \
+             Set-Item -LiteralPath \"env:CARGO_TARGET_$($cargo_remote_host.ToUpper().Replace('-', '_').Replace('.', '_'))_LINKER\" {}",
+            quote(linker)
+        ));
+    }
+    for (triple, target) in &remote.target {
+        let variable = triple.to_ascii_uppercase().replace(['-', '.'], "_");
+        if let Some(linker) = &target.linker {
+            script.push(format!(
+                "$env:CARGO_TARGET_{}_LINKER = {}",
+                variable,
+                quote(linker)
+            ));
+        }
+        if !target.rustflags.is_empty() {
+            script.push(format!(
+                "$env:CARGO_TARGET_{}_RUSTFLAGS = {}",
+                variable,
+                quote(&target.rustflags.join(" "))
+            ));
+        }
+    }
+    if commands.is_empty() {
+        return powershell(&script.join("; "), true);
+    }
+    script.push("$cargo_remote_status = 0".to_owned());
+    for command in commands {
+        let args: Vec<String> = words(command).iter().map(|word| quote(word)).collect();
+        script.push(format!(
+            "& cargo {}; if ($LASTEXITCODE -ne 0) {{ $cargo_remote_status = $LASTEXITCODE }}",
+            args.join(" ")
+        ));
+    }
+    script.push("exit $cargo_remote_status".to_owned());
+    powershell(&script.join("; "), false)
+}
+
+/// Script probing a Windows remote for the facts of [`crate::probe::RemoteFacts`], in the
+/// same format as on other remotes. rsync and the compressors are never used there.
+pub fn probe_script() -> String {
+    powershell(
+        "'os: Windows'; \
+         \"cargo: $(cargo -V 2>$null)\"; \
+         \"cpus: $env:NUMBER_OF_PROCESSORS\"; \
+         rustc -vV 2>$null; exit 0",
+        false,
+    )
+}
+
+/// Command printing the names of the files directly in `dir`, one per line.
+pub fn list_files(dir: &str) -> String {
+    powershell(
+        &format!(
+            "Get-ChildItem -LiteralPath {} -File -Force -Name",
+            path(dir)
+        ),
+        false,
+    )
+}
+
+/// Command printing the SHA-256 of the file at `path` in hex.
+pub fn file_hash(path: &str) -> String {
+    powershell(
+        &format!(
+            "(Get-FileHash -Algorithm SHA256 -LiteralPath {}).Hash",
+            self::path(path)
+        ),
+        false,
+    )
+}
+
+/// Command deleting the directory `dir` with everything in it, if it exists.
+pub fn remove_dir(dir: &str) -> String {
+    powershell(
+        &format!(
+            "if (Test-Path -LiteralPath {dir}) {{ Remove-Item -Recurse -Force -LiteralPath {dir} }}",
+            dir = path(dir)
+        ),
+        false,
+    )
+}
+
+/// cmd.exe command extracting the tar archive on stdin into `build_path`. cmd.exe passes the
+/// archive on unchanged, PowerShell would decode it as text.
+pub fn extract(build_path: &str) -> String {
+    let dir = cmd_path(build_path);
+    format!(
+        "(if not exist {dir} mkdir {dir}) && tar -C {dir} -xf -",
+        dir = dir
+    )
+}
+
+/// cmd.exe command writing a tar archive of `entry` in `dir` to stdout.
+pub fn archive(dir: &str, entry: &str) -> String {
+    format!("tar -C {} -cf - {}", cmd_path(dir), cmd_path(entry))
+}